@@ -0,0 +1,214 @@
+//! A template of an agent deployed in Kubernetes next to the HAProxy ingress.
+//!
+//! - the `/healthz` and `/readyz` HTTP endpoints are wired to the health of the runtime;
+//! - on SIGTERM, the agent stops being ready and drains the connections before exiting;
+//! - the configuration is read from the environment, and the logs are written as JSON.
+//!
+//! | Variable              | Default         | Description                                        |
+//! |-----------------------|-----------------|----------------------------------------------------|
+//! | `SPOA_ADDR`           | `0.0.0.0:12345` | The address to accept the SPOP connections on.     |
+//! | `SPOA_HTTP_ADDR`      | `0.0.0.0:8080`  | The address to serve the probe endpoints on.       |
+//! | `SPOA_MAX_FRAME_SIZE` | `16380`         | The max size of the frames.                        |
+//! | `SPOA_DRAIN_TIMEOUT`  | `10s`           | How long to drain the connections on SIGTERM.      |
+//! | `SPOA_LOG_LEVEL`      | `info`          | The max level of the logs.                         |
+//!
+//! ```sh
+//! docker build -f haproxy/examples/k8s-agent/Dockerfile -t spoa-k8s-agent .
+//! kubectl apply -f haproxy/examples/k8s-agent/deployment.yaml
+//! ```
+
+use std::convert::Infallible;
+use std::env;
+use std::net::TcpListener as StdTcpListener;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context as _, Result};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::{TcpListener, TcpStream},
+    select,
+    signal::unix::{signal, SignalKind},
+    time::{sleep, timeout, Instant},
+};
+use tower::{make::Shared, service_fn};
+use tracing::{debug, info, warn, Level};
+
+use haproxy::{
+    agent::{
+        req::Args,
+        runtime::{self, HealthState, LoadSnapshot},
+        Agent,
+    },
+    proto::{Action, Message, Scope, MAX_FRAME_SIZE},
+};
+
+#[derive(Debug)]
+struct Config {
+    addr: String,
+    http_addr: String,
+    max_frame_size: usize,
+    drain_timeout: Duration,
+    log_level: Level,
+}
+
+impl Config {
+    fn from_env() -> Result<Self> {
+        Ok(Config {
+            addr: var("SPOA_ADDR", "0.0.0.0:12345".to_string())?,
+            http_addr: var("SPOA_HTTP_ADDR", "0.0.0.0:8080".to_string())?,
+            max_frame_size: var("SPOA_MAX_FRAME_SIZE", MAX_FRAME_SIZE)?,
+            drain_timeout: var(
+                "SPOA_DRAIN_TIMEOUT",
+                humantime::Duration::from(Duration::from_secs(10)),
+            )?
+            .into(),
+            log_level: var("SPOA_LOG_LEVEL", Level::INFO)?,
+        })
+    }
+}
+
+fn var<T>(name: &str, default: T) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    match env::var(name) {
+        Ok(value) => value.parse().with_context(|| format!("invalid {name}")),
+        Err(_) => Ok(default),
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = Config::from_env()?;
+
+    tracing_subscriber::fmt()
+        .json()
+        .with_max_level(config.log_level)
+        .init();
+
+    info!(?config, "starting");
+
+    let runtime = runtime::Builder::new()
+        .max_frame_size(config.max_frame_size)
+        .make_service(Shared::new(service_fn(check)), ());
+
+    let listener = StdTcpListener::bind(&config.addr)?;
+    listener.set_nonblocking(true)?;
+
+    let agent = Arc::new(Agent::new(runtime, listener)?);
+    let shutdown = agent.shutdown();
+
+    let probes = TcpListener::bind(&config.http_addr).await?;
+    tokio::spawn(serve_probes(probes, agent.clone()));
+
+    tokio::spawn({
+        let agent = agent.clone();
+        let drain_timeout = config.drain_timeout;
+
+        async move {
+            let mut sigterm = signal(SignalKind::terminate()).expect("SIGTERM handler");
+
+            select! {
+                _ = sigterm.recv() => info!("received SIGTERM"),
+                _ = tokio::signal::ctrl_c() => info!("received Ctrl+C"),
+            }
+
+            drain(&agent, drain_timeout).await;
+            shutdown.cancel();
+        }
+    });
+
+    agent.serve().await?;
+
+    info!("stopped");
+
+    Ok(())
+}
+
+/// Stop being ready, and wait for HAProxy to close the connections.
+async fn drain<S, T>(agent: &Agent<S, T>, drain_timeout: Duration) {
+    agent.drain(true);
+
+    let deadline = Instant::now() + drain_timeout;
+
+    while agent.load().connections > 0 {
+        if Instant::now() >= deadline {
+            warn!(connections = agent.load().connections, "drain timed out");
+            return;
+        }
+
+        sleep(Duration::from_millis(100)).await;
+    }
+
+    info!("drained");
+}
+
+/// Score the client IP address, a placeholder of the real processing.
+async fn check(msgs: Vec<Message>) -> Result<Vec<Action>, Infallible> {
+    let actions = msgs
+        .iter()
+        .filter_map(|msg| match Args::new(msg).src() {
+            Ok(src) => Some(Action::set_var(
+                Scope::Session,
+                "ip_score",
+                if src.is_loopback() { 100 } else { 50 },
+            )),
+            Err(err) => {
+                debug!(%err, "ignored");
+                None
+            }
+        })
+        .collect();
+
+    Ok(actions)
+}
+
+/// Serve the `/healthz` and `/readyz` endpoints for the kubelet probes.
+async fn serve_probes<S, T>(listener: TcpListener, agent: Arc<Agent<S, T>>)
+where
+    S: Send + Sync + 'static,
+    T: Send + Sync + 'static,
+{
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let agent = agent.clone();
+
+                tokio::spawn(async move {
+                    if let Err(err) = probe(stream, &agent.health().state, &agent.load()).await {
+                        debug!(%err, "probe failed");
+                    }
+                });
+            }
+            Err(err) => warn!(%err, "failed to accept probe"),
+        }
+    }
+}
+
+async fn probe(mut stream: TcpStream, health: &HealthState, load: &LoadSnapshot) -> Result<()> {
+    let mut buf = [0; 1024];
+    let n = timeout(Duration::from_secs(1), stream.read(&mut buf)).await??;
+    let req = String::from_utf8_lossy(&buf[..n]);
+    let path = req.split_whitespace().nth(1).unwrap_or_default();
+
+    let healthy = *health == HealthState::Healthy;
+    let (status, body) = match path {
+        "/healthz" if healthy => ("200 OK", "ok"),
+        "/healthz" => ("503 Service Unavailable", "unhealthy"),
+        "/readyz" if load.draining => ("503 Service Unavailable", "draining"),
+        "/readyz" if healthy => ("200 OK", "ready"),
+        "/readyz" => ("503 Service Unavailable", "unhealthy"),
+        _ => ("404 Not Found", "not found"),
+    };
+
+    let resp = format!(
+        "HTTP/1.1 {status}\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(resp.as_bytes()).await?;
+
+    Ok(())
+}
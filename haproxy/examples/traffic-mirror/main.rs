@@ -9,21 +9,20 @@ use core::str;
 use std::env;
 use std::fs::create_dir_all;
 use std::io;
-use std::net::IpAddr;
 use std::path::PathBuf;
 use std::{convert::Infallible, fs::File};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use bytes::Buf;
 use clap::Parser;
 use daemonize::Daemonize;
-use haproxy_spop::Scope;
 use humantime::Duration;
 use rand::{thread_rng, Rng};
 use reqwest::{
     header::HeaderMap, Body, Client, ClientBuilder, Method, RequestBuilder, Url, Version,
 };
 use rlimit::{getrlimit, setrlimit, Resource};
+use serde::Deserialize;
 use tokio::signal;
 use tokio::task::JoinSet;
 use tower::service_fn;
@@ -32,7 +31,7 @@ use tracing_subscriber::prelude::*;
 
 use haproxy::{
     agent::{req, runtime, Agent},
-    proto::{Action, Capability, Message, Typed, MAX_FRAME_SIZE},
+    proto::{Action, Capability, Message, Scope, MAX_FRAME_SIZE},
 };
 
 #[derive(Debug, Parser)]
@@ -229,61 +228,59 @@ async fn process_request(client: Client, base: Url, msgs: Vec<Message>) -> Resul
 }
 
 fn iprep(msg: Message) -> Result<Action> {
-    let addr = msg
-        .args
-        .into_iter()
-        .find(|(name, _)| name == "ip")
-        .and_then(|(_, value)| match value {
-            Typed::Ipv4(addr) => Some(IpAddr::from(addr)),
-            Typed::Ipv6(addr) => Some(IpAddr::from(addr)),
-            _ => None,
-        });
+    let addr = req::Args::new(&msg).ip("ip")?;
+    let score = thread_rng().gen_range(0..=100u32);
 
-    if let Some(addr) = addr {
-        let score = thread_rng().gen_range(0..=100u32);
+    trace!(%addr, score, "IP reputation");
 
-        trace!(%addr, score, "IP reputation");
+    Ok(Action::set_var(Scope::Session, "ip_score", score))
+}
 
-        Ok(Action::set_var(Scope::Session, "ip_score", score))
-    } else {
-        bail!("missing `ip` argument");
-    }
+/// The arguments of the `mirror` message, see `config/spoe.cfg`.
+#[derive(Debug, Deserialize)]
+struct MirrorArgs {
+    #[serde(rename = "arg_method")]
+    method: String,
+    #[serde(rename = "arg_path")]
+    path: String,
+    #[serde(rename = "arg_query")]
+    query: Option<String>,
+    #[serde(rename = "arg_ver")]
+    version: Option<String>,
+    #[serde(rename = "arg_hdrs")]
+    hdrs: Option<Vec<u8>>,
+    #[serde(rename = "arg_body")]
+    body: Option<Vec<u8>>,
 }
 
 fn mirror(tasks: &mut JoinSet<Action>, client: &Client, base: &Url, msg: Message) -> Result<()> {
-    for (arg, value) in msg.args {
-        let mut builder = Builder::new(base.clone());
+    let args = msg
+        .parse_args::<MirrorArgs>()
+        .with_context(|| format!("parse `{}` message", msg.name))?;
+    let mut builder = Builder::new(base.clone());
 
-        match (arg.as_str(), value) {
-            ("arg_method", Typed::String(method)) => {
-                builder.method(method);
-            }
-            ("arg_path", Typed::String(path)) => {
-                builder.path(path);
-            }
-            ("arg_query", Typed::String(query)) if !query.is_empty() => {
-                builder.query(query);
-            }
-            ("arg_ver", Typed::String(version)) => {
-                builder.version(version);
-            }
-            ("arg_hdrs", Typed::Binary(hdrs)) => {
-                builder.headers(hdrs);
-            }
-            ("arg_body", Typed::Binary(body)) if !body.is_empty() => {
-                builder.body(body);
-            }
-            _ => trace!(%arg, "ignored"),
-        }
+    builder.method(args.method).path(args.path);
+
+    if let Some(query) = args.query.filter(|query| !query.is_empty()) {
+        builder.query(query);
+    }
+    if let Some(version) = args.version {
+        builder.version(version);
+    }
+    if let Some(hdrs) = args.hdrs {
+        builder.headers(&hdrs[..]);
+    }
+    if let Some(body) = args.body.filter(|body| !body.is_empty()) {
+        builder.body(body);
+    }
 
-        let req = builder.build(client.clone());
+    let _req = builder.build(client.clone());
 
-        tasks.build_task().name("mirror").spawn(async {
-            // let res = req.send().await?;
+    tasks.build_task().name("mirror").spawn(async {
+        // let res = req.send().await?;
 
-            Action::set_var(Scope::Session, "foo", "bar")
-        })?;
-    }
+        Action::set_var(Scope::Session, "foo", "bar")
+    })?;
 
     Ok(())
 }
@@ -335,7 +332,7 @@ impl<T> Builder<T> {
     }
 
     pub fn headers<B: Buf>(&mut self, b: B) -> &mut Self {
-        if let Some(hdrs) = req::hdrs_bin(b).ok() {
+        if let Ok(hdrs) = req::hdrs_bin(b) {
             self.headers.extend(hdrs);
         }
         self
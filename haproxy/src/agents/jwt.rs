@@ -0,0 +1,716 @@
+//! Validate the bearer tokens of the requests, offloading the authentication to the agent.
+//!
+//! The token is passed as the `token` argument, with or without the `Bearer` prefix,
+//! and the result of the validation is returned in the transaction variables:
+//!
+//! | Variable         | Description                                            |
+//! |------------------|--------------------------------------------------------|
+//! | `txn.auth_ok`    | `true` if the token is valid, `false` otherwise.       |
+//! | `txn.auth_sub`   | The `sub` claim of the valid token.                    |
+//! | `txn.auth_scope` | The `scope` or `scp` claims, separated by spaces.      |
+//!
+//! ```text
+//! [spoe]
+//! spoe-message check-auth
+//!     args token=req.hdr(authorization)
+//!     event on-frontend-http-request
+//!
+//! frontend www
+//!     http-request deny if !{ var(txn.auth_ok) -m bool }
+//! ```
+//!
+//! The tokens are signed with a HS256 secret, a RS256 public key,
+//! or the RS256 keys of a JWKS endpoint, fetched and cached by their `kid`:
+//!
+//! ```no_run
+//! # use std::time::Duration;
+//! # use haproxy::agents::jwt::JwtAuth;
+//! let auth = JwtAuth::jwks("https://example.com/.well-known/jwks.json")
+//!     .issuer("https://example.com/")
+//!     .leeway(Duration::from_secs(30));
+//! ```
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::{
+    decode, decode_header, errors::Error as JwtError, jwk::JwkSet, Algorithm, DecodingKey,
+    Validation,
+};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::sync::{Mutex, RwLock};
+use tower::Service;
+use tracing::{debug, trace};
+
+use crate::proto::{Action, Message, Scope, Typed};
+
+/// The name of the argument carrying the token.
+pub const TOKEN_ARG: &str = "token";
+/// The variable set to `true` if the token is valid.
+pub const AUTH_OK: &str = "auth_ok";
+/// The variable set to the subject of the token.
+pub const AUTH_SUB: &str = "auth_sub";
+/// The variable set to the scopes of the token.
+pub const AUTH_SCOPE: &str = "auth_scope";
+
+/// The default clock skew tolerated when checking the expiration of the tokens.
+pub const LEEWAY: Duration = Duration::from_secs(60);
+/// How long the keys fetched from a JWKS endpoint are cached.
+pub const JWKS_TTL: Duration = Duration::from_secs(300);
+/// The min interval between two fetches of a JWKS endpoint, when a token has an unknown `kid`
+/// or the previous fetch failed.
+pub const JWKS_MIN_REFRESH: Duration = Duration::from_secs(10);
+/// The max time to fetch the keys of a JWKS endpoint, well within the `timeout processing` of HAProxy.
+pub const JWKS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The reason a token was rejected.
+#[derive(Debug, Error)]
+pub enum AuthError {
+    #[error("no token")]
+    Missing,
+
+    #[error(transparent)]
+    Invalid(#[from] JwtError),
+
+    #[error("unknown key {0:?}")]
+    UnknownKey(Option<String>),
+
+    #[error("fetch JWKS, {0}")]
+    Fetch(#[from] reqwest::Error),
+
+    #[error("parse JWKS, {0}")]
+    Jwks(#[from] serde_json::Error),
+}
+
+/// The claims of a valid token.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+pub struct Claims {
+    /// The subject of the token.
+    pub sub: Option<String>,
+    /// The scopes of the token, separated by spaces.
+    pub scope: Option<String>,
+    /// The scopes of the token, as a list.
+    pub scp: Option<Vec<String>>,
+}
+
+impl Claims {
+    /// Returns the scopes, from the `scope` or `scp` claims.
+    pub fn scopes(&self) -> Option<String> {
+        self.scope
+            .clone()
+            .or_else(|| self.scp.as_ref().map(|scp| scp.join(" ")))
+    }
+}
+
+/// A service validating the tokens of the messages.
+#[derive(Clone, Debug)]
+pub struct JwtAuth(Arc<Inner>);
+
+#[derive(Debug)]
+struct Inner {
+    keys: Keys,
+    validation: Validation,
+    token_arg: String,
+}
+
+enum Keys {
+    Static(DecodingKey),
+    Jwks(Box<Jwks>),
+}
+
+impl fmt::Debug for Keys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Keys::Static(_) => f.write_str("Static"),
+            Keys::Jwks(jwks) => f.debug_tuple("Jwks").field(&jwks.url).finish(),
+        }
+    }
+}
+
+impl JwtAuth {
+    /// Validate the tokens signed with the HS256 secret.
+    pub fn hs256<K: AsRef<[u8]>>(secret: K) -> Self {
+        JwtAuth::new(
+            Keys::Static(DecodingKey::from_secret(secret.as_ref())),
+            Algorithm::HS256,
+        )
+    }
+
+    /// Validate the tokens signed with the RS256 key, the public key in PEM format.
+    pub fn rs256_pem<K: AsRef<[u8]>>(pem: K) -> Result<Self, AuthError> {
+        Ok(JwtAuth::new(
+            Keys::Static(DecodingKey::from_rsa_pem(pem.as_ref())?),
+            Algorithm::RS256,
+        ))
+    }
+
+    /// Validate the tokens signed with the RS256 keys of the JWKS endpoint.
+    pub fn jwks<U: Into<String>>(url: U) -> Self {
+        JwtAuth::new(
+            Keys::Jwks(Box::new(Jwks::new(url.into()))),
+            Algorithm::RS256,
+        )
+    }
+
+    fn new(keys: Keys, alg: Algorithm) -> Self {
+        let mut validation = Validation::new(alg);
+        validation.leeway = LEEWAY.as_secs();
+
+        JwtAuth(Arc::new(Inner {
+            keys,
+            validation,
+            token_arg: TOKEN_ARG.to_string(),
+        }))
+    }
+
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.0).expect("configure before cloning")
+    }
+
+    /// Read the token from the argument, `token` by default.
+    pub fn token_arg<S: Into<String>>(mut self, name: S) -> Self {
+        self.inner_mut().token_arg = name.into();
+        self
+    }
+
+    /// Tolerate the clock skew when checking the expiration, 60 seconds by default.
+    pub fn leeway(mut self, skew: Duration) -> Self {
+        self.inner_mut().validation.leeway = skew.as_secs();
+        self
+    }
+
+    /// Check the `exp` claim, enabled by default.
+    pub fn validate_exp(mut self, enable: bool) -> Self {
+        let validation = &mut self.inner_mut().validation;
+        validation.validate_exp = enable;
+        if !enable {
+            validation.required_spec_claims.remove("exp");
+        }
+        self
+    }
+
+    /// Check the `nbf` claim, disabled by default.
+    pub fn validate_nbf(mut self, enable: bool) -> Self {
+        self.inner_mut().validation.validate_nbf = enable;
+        self
+    }
+
+    /// Accept only the tokens issued by the issuer.
+    pub fn issuer<S: ToString>(mut self, iss: S) -> Self {
+        self.inner_mut().validation.set_issuer(&[iss]);
+        self
+    }
+
+    /// Accept only the tokens intended for the audience.
+    pub fn audience<S: ToString>(mut self, aud: S) -> Self {
+        self.inner_mut().validation.set_audience(&[aud]);
+        self
+    }
+
+    /// Cache the keys of the JWKS endpoint for the duration, 5 minutes by default.
+    pub fn jwks_ttl(mut self, ttl: Duration) -> Self {
+        if let Keys::Jwks(jwks) = &mut self.inner_mut().keys {
+            jwks.ttl = ttl;
+        }
+        self
+    }
+
+    /// Fetch the keys of the JWKS endpoint at most once per interval, 10 seconds by default,
+    /// when a token has an unknown `kid` or the previous fetch failed.
+    pub fn jwks_min_refresh(mut self, interval: Duration) -> Self {
+        if let Keys::Jwks(jwks) = &mut self.inner_mut().keys {
+            jwks.min_refresh = interval;
+        }
+        self
+    }
+
+    /// Give up fetching the keys of the JWKS endpoint after the timeout, 2 seconds by default.
+    pub fn jwks_timeout(mut self, timeout: Duration) -> Self {
+        if let Keys::Jwks(jwks) = &mut self.inner_mut().keys {
+            jwks.client = client(timeout);
+        }
+        self
+    }
+
+    /// Validate the token, with or without the `Bearer` prefix.
+    pub async fn verify(&self, token: &str) -> Result<Claims, AuthError> {
+        let token = strip_bearer(token).ok_or(AuthError::Missing)?;
+        let inner = &self.0;
+
+        let data = match &inner.keys {
+            Keys::Static(key) => decode::<Claims>(token, key, &inner.validation)?,
+            Keys::Jwks(jwks) => {
+                let kid = decode_header(token)?.kid;
+                let key = jwks.key(kid.as_deref()).await?;
+
+                decode::<Claims>(token, &key, &inner.validation)?
+            }
+        };
+
+        Ok(data.claims)
+    }
+
+    /// Returns the actions of the validation of the message, `None` if it has no token.
+    async fn check(&self, msg: &Message) -> Option<Vec<Action>> {
+        let token = msg
+            .args
+            .iter()
+            .find(|(name, _)| *name == self.0.token_arg)
+            .map(|(_, value)| value)?;
+        let res = match token {
+            Typed::String(token) => self.verify(token).await,
+            Typed::Binary(token) => match std::str::from_utf8(token) {
+                Ok(token) => self.verify(token).await,
+                Err(_) => Err(AuthError::Missing),
+            },
+            _ => Err(AuthError::Missing),
+        };
+
+        let actions = match res {
+            Ok(claims) => {
+                trace!(msg = msg.name, ?claims, "authenticated");
+
+                [
+                    Some(Action::set_var(Scope::Transaction, AUTH_OK, true)),
+                    claims
+                        .sub
+                        .clone()
+                        .map(|sub| Action::set_var(Scope::Transaction, AUTH_SUB, sub)),
+                    claims
+                        .scopes()
+                        .map(|scope| Action::set_var(Scope::Transaction, AUTH_SCOPE, scope)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect()
+            }
+            Err(err) => {
+                debug!(msg = msg.name, %err, "unauthenticated");
+
+                vec![Action::set_var(Scope::Transaction, AUTH_OK, false)]
+            }
+        };
+
+        Some(actions)
+    }
+}
+
+impl Service<Vec<Message>> for JwtAuth {
+    type Response = Vec<Action>;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, msgs: Vec<Message>) -> Self::Future {
+        let auth = self.clone();
+
+        Box::pin(async move {
+            let mut actions = vec![];
+
+            for msg in &msgs {
+                if let Some(checked) = auth.check(msg).await {
+                    actions.extend(checked);
+                }
+            }
+
+            Ok(actions)
+        })
+    }
+}
+
+const BEARER: &str = "bearer";
+
+fn strip_bearer(token: &str) -> Option<&str> {
+    let token = token.trim();
+    let token = match token.split_at_checked(BEARER.len()) {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case(BEARER)
+                && (rest.is_empty() || rest.starts_with(char::is_whitespace)) =>
+        {
+            rest.trim_start()
+        }
+        _ => token,
+    };
+
+    Some(token).filter(|token| !token.is_empty())
+}
+
+/// The keys of a JWKS endpoint, cached by their `kid`.
+///
+/// The keys are fetched outside of the cache lock, by one task at a time,
+/// and the cached keys are still served while they are refreshed or when the refresh failed.
+#[derive(Debug)]
+struct Jwks {
+    url: String,
+    client: reqwest::Client,
+    ttl: Duration,
+    min_refresh: Duration,
+    cached: RwLock<Option<Cached>>,
+    refreshing: Mutex<()>,
+}
+
+#[derive(Debug)]
+struct Cached {
+    keys: JwkSet,
+    /// When the keys were fetched, `None` until the first fetch succeeded.
+    fetched: Option<Instant>,
+    /// When the keys were last fetched or failed to.
+    attempted: Instant,
+}
+
+impl Cached {
+    /// Returns `true` if the keys should be fetched again to find the key.
+    fn is_stale(&self, jwks: &Jwks, kid: Option<&str>) -> bool {
+        (self
+            .fetched
+            .is_none_or(|fetched| fetched.elapsed() >= jwks.ttl)
+            || find(&self.keys, kid).is_none())
+            && self.attempted.elapsed() >= jwks.min_refresh
+    }
+}
+
+fn client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .expect("JWKS client")
+}
+
+impl Jwks {
+    fn new(url: String) -> Self {
+        Jwks {
+            url,
+            client: client(JWKS_TIMEOUT),
+            ttl: JWKS_TTL,
+            min_refresh: JWKS_MIN_REFRESH,
+            cached: RwLock::new(None),
+            refreshing: Mutex::new(()),
+        }
+    }
+
+    /// Returns the key, fetching the keys again when expired or the `kid` is unknown.
+    async fn key(&self, kid: Option<&str>) -> Result<DecodingKey, AuthError> {
+        let refreshing = match &*self.cached.read().await {
+            Some(cached) if !cached.is_stale(self, kid) => return lookup(&cached.keys, kid),
+            // another task is refreshing the keys, the cached one is used meanwhile
+            Some(cached) => match self.refreshing.try_lock() {
+                Ok(refreshing) => Some(refreshing),
+                Err(_) if find(&cached.keys, kid).is_some() => return lookup(&cached.keys, kid),
+                Err(_) => None,
+            },
+            None => None,
+        };
+        let _refreshing = match refreshing {
+            Some(refreshing) => refreshing,
+            None => self.refreshing.lock().await,
+        };
+
+        // another task may have fetched the keys while waiting for the lock
+        if let Some(cached) = &*self.cached.read().await {
+            if !cached.is_stale(self, kid) {
+                return lookup(&cached.keys, kid);
+            }
+        }
+
+        let fetched = self.fetch().await;
+        let mut cached = self.cached.write().await;
+        let now = Instant::now();
+
+        match (fetched, cached.as_mut()) {
+            (Ok(keys), _) => {
+                *cached = Some(Cached {
+                    keys,
+                    fetched: Some(now),
+                    attempted: now,
+                })
+            }
+            (Err(err), Some(cached)) => {
+                debug!(url = self.url, %err, "failed to refresh JWKS, serving the cached keys");
+
+                cached.attempted = now;
+            }
+            // the failed fetch is not retried before the min refresh interval
+            (Err(err), None) => {
+                *cached = Some(Cached {
+                    keys: JwkSet { keys: vec![] },
+                    fetched: None,
+                    attempted: now,
+                });
+
+                return Err(err);
+            }
+        }
+
+        lookup(&cached.as_ref().expect("fetched").keys, kid)
+    }
+
+    /// Fetch the keys, within the timeout of the client.
+    async fn fetch(&self) -> Result<JwkSet, AuthError> {
+        let body = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let keys = serde_json::from_slice::<JwkSet>(&body)?;
+
+        debug!(url = self.url, keys = keys.keys.len(), "JWKS fetched");
+
+        Ok(keys)
+    }
+}
+
+/// Returns the decoding key of the `kid`.
+fn lookup(keys: &JwkSet, kid: Option<&str>) -> Result<DecodingKey, AuthError> {
+    find(keys, kid)
+        .map(DecodingKey::from_jwk)
+        .transpose()?
+        .ok_or_else(|| AuthError::UnknownKey(kid.map(str::to_string)))
+}
+
+/// Find the key by its `kid`, or the only key when the token has no `kid`.
+fn find<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+    use std::sync::Mutex as StdMutex;
+
+    use jsonwebtoken::{encode, get_current_timestamp, EncodingKey, Header};
+    use serde_json::json;
+    use tokio::{
+        io::{AsyncReadExt as _, AsyncWriteExt as _},
+        net::TcpListener,
+    };
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const PRIVATE_KEY: &[u8] = include_bytes!("../../testdata/jwt.key.pem");
+    const PUBLIC_KEY: &[u8] = include_bytes!("../../testdata/jwt.pub.pem");
+    /// The modulus of the test RSA key, encoded in base64url.
+    const MODULUS: &str = "tDU_JRxL9U_6qJ9jRaT96Nmb91SYF7OMxig1Bw787NWge6hiFX7grTdAhe2zzc98DcCaeE2AKStV1HrkbD-JU72LMA6NeqkHU47Gm4mdFIA0p0pZTZFlyIXBDZtKBri-_2TSwhy6p1rAhY74WqdbnG85dfJPOBi7dRngql8MlTSPaJ5L7kw49lGWoSWQlJkcCjx2KnWf796-2Y9D-zOPUv70gkEAokNOzAczGYjn_2Grwe6nJgWAd_Q3k9nts-iifqICzUffldnS9CR5CFe0PRnv2sX3eHwmdEy1iX4oO-iBbHZS2-B1980Gcy6q4ZqpULst7oElCG8YsNDLuUhq7w";
+
+    fn rs256_token(kid: Option<&str>, exp: u64) -> String {
+        encode(
+            &Header {
+                kid: kid.map(str::to_string),
+                ..Header::new(Algorithm::RS256)
+            },
+            &json!({ "sub": "bob", "scope": "admin", "exp": exp }),
+            &EncodingKey::from_rsa_pem(PRIVATE_KEY).unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// How the JWKS endpoint answers.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    enum Endpoint {
+        Keys,
+        Failing,
+        Hung,
+    }
+
+    /// Serve the JWKS of the test RSA key as `k1`, returning its URL and the number of fetches.
+    async fn jwks_endpoint(endpoint: Arc<StdMutex<Endpoint>>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/jwks.json", listener.local_addr().unwrap());
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let body = json!({
+            "keys": [{ "kty": "RSA", "kid": "k1", "alg": "RS256", "use": "sig", "n": MODULUS, "e": "AQAB" }]
+        })
+        .to_string();
+
+        tokio::spawn({
+            let fetches = fetches.clone();
+
+            async move {
+                loop {
+                    let (mut stream, _) = listener.accept().await.unwrap();
+                    let mut buf = vec![0; 4096];
+                    let _ = stream.read(&mut buf).await;
+                    fetches.fetch_add(1, SeqCst);
+
+                    let res = match *endpoint.lock().unwrap() {
+                        Endpoint::Keys => format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                            body.len()
+                        ),
+                        Endpoint::Failing => "HTTP/1.1 500 Internal Server Error\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+                        Endpoint::Hung => {
+                            // the connection is kept open without answering
+                            tokio::spawn(async move {
+                                let _ = stream.read(&mut buf).await;
+                            });
+                            continue;
+                        }
+                    };
+                    let _ = stream.write_all(res.as_bytes()).await;
+                }
+            }
+        });
+
+        (url, fetches)
+    }
+
+    fn token(exp: u64) -> String {
+        encode(
+            &Header::default(),
+            &json!({ "sub": "alice", "scp": ["read", "write"], "exp": exp }),
+            &EncodingKey::from_secret(SECRET),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_jwt_auth() {
+        let now = get_current_timestamp();
+        let auth = JwtAuth::hs256(SECRET).leeway(Duration::from_secs(30));
+
+        let claims = auth
+            .verify(&format!("Bearer {}", token(now + 60)))
+            .await
+            .unwrap();
+        assert_eq!(claims.sub.as_deref(), Some("alice"));
+        assert_eq!(claims.scopes().as_deref(), Some("read write"));
+
+        // expired, but within the clock skew
+        assert!(auth.verify(&token(now - 10)).await.is_ok());
+        assert!(auth.verify(&token(now - 60)).await.is_err());
+        assert!(JwtAuth::hs256("other")
+            .verify(&token(now + 60))
+            .await
+            .is_err());
+        assert!(matches!(
+            auth.verify("Bearer ").await,
+            Err(AuthError::Missing)
+        ));
+
+        let actions = auth
+            .oneshot(vec![
+                Message::new(
+                    "check-auth",
+                    [(TOKEN_ARG, format!("bearer {}", token(now + 60)))],
+                ),
+                Message::new("check-auth", [(TOKEN_ARG, "garbage")]),
+                Message::new("log", [("path", "/")]),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            actions,
+            vec![
+                Action::set_var(Scope::Transaction, AUTH_OK, true),
+                Action::set_var(Scope::Transaction, AUTH_SUB, "alice"),
+                Action::set_var(Scope::Transaction, AUTH_SCOPE, "read write"),
+                Action::set_var(Scope::Transaction, AUTH_OK, false),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rs256() {
+        let now = get_current_timestamp();
+        let auth = JwtAuth::rs256_pem(PUBLIC_KEY).unwrap();
+
+        let claims = auth.verify(&rs256_token(None, now + 60)).await.unwrap();
+        assert_eq!(claims.sub.as_deref(), Some("bob"));
+        assert_eq!(claims.scopes().as_deref(), Some("admin"));
+
+        // expired, or signed with another algorithm
+        assert!(auth.verify(&rs256_token(None, now - 120)).await.is_err());
+        assert!(auth.verify(&token(now + 60)).await.is_err());
+        assert!(JwtAuth::rs256_pem("not a key").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_jwks_refresh() {
+        let now = get_current_timestamp();
+        let endpoint = Arc::new(StdMutex::new(Endpoint::Keys));
+        let (url, fetches) = jwks_endpoint(endpoint.clone()).await;
+        let auth = JwtAuth::jwks(&url);
+
+        // the keys are fetched once, then cached
+        for _ in 0..2 {
+            let claims = auth
+                .verify(&rs256_token(Some("k1"), now + 60))
+                .await
+                .unwrap();
+            assert_eq!(claims.sub.as_deref(), Some("bob"));
+        }
+        assert_eq!(fetches.load(SeqCst), 1);
+
+        // the unknown keys are not fetched again before the min refresh interval
+        assert!(matches!(
+            auth.verify(&rs256_token(Some("k2"), now + 60)).await,
+            Err(AuthError::UnknownKey(Some(kid))) if kid == "k2"
+        ));
+        assert_eq!(fetches.load(SeqCst), 1);
+
+        // the expired keys are still served when the refresh failed
+        let auth = JwtAuth::jwks(&url)
+            .jwks_ttl(Duration::ZERO)
+            .jwks_min_refresh(Duration::ZERO);
+        assert!(auth
+            .verify(&rs256_token(Some("k1"), now + 60))
+            .await
+            .is_ok());
+        *endpoint.lock().unwrap() = Endpoint::Failing;
+        assert!(auth
+            .verify(&rs256_token(Some("k1"), now + 60))
+            .await
+            .is_ok());
+        assert!(matches!(
+            auth.verify(&rs256_token(Some("k2"), now + 60)).await,
+            Err(AuthError::UnknownKey(_))
+        ));
+        assert_eq!(fetches.load(SeqCst), 4);
+
+        // without cached keys, the failed fetch is not retried before the min refresh interval
+        let auth = JwtAuth::jwks(&url);
+        assert!(matches!(
+            auth.verify(&rs256_token(Some("k1"), now + 60)).await,
+            Err(AuthError::Fetch(_))
+        ));
+        assert!(matches!(
+            auth.verify(&rs256_token(Some("k1"), now + 60)).await,
+            Err(AuthError::UnknownKey(_))
+        ));
+        assert_eq!(fetches.load(SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_jwks_timeout() {
+        let now = get_current_timestamp();
+        let (url, fetches) = jwks_endpoint(Arc::new(StdMutex::new(Endpoint::Hung))).await;
+        let auth = JwtAuth::jwks(url).jwks_timeout(Duration::from_millis(100));
+
+        // the hung endpoint fails the verification within the timeout
+        let started = Instant::now();
+        assert!(matches!(
+            auth.verify(&rs256_token(Some("k1"), now + 60)).await,
+            Err(AuthError::Fetch(err)) if err.is_timeout()
+        ));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(fetches.load(SeqCst), 1);
+    }
+}
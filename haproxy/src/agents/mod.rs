@@ -0,0 +1,7 @@
+//! Reference agents, ready to be served or composed with the other services.
+//!
+//! - `jwt`: validate the bearer tokens of the requests, offloading the authentication
+//!   (requires the `jwt` feature).
+
+#[cfg(feature = "jwt")]
+pub mod jwt;
@@ -0,0 +1,151 @@
+//! Scaffold a new agent project.
+//!
+//! ```sh
+//! cargo install haproxy --features cli
+//! cargo haproxy-agent new ip-score
+//! ```
+//!
+//! The project contains the runtime wiring, a configuration file, a sample message handler
+//! with its tests over the in-memory transport, and the HAProxy and SPOE configuration snippets.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(name = "cargo", bin_name = "cargo")]
+enum Cargo {
+    /// Scaffold the agents built on the haproxy crate.
+    #[command(name = "haproxy-agent", subcommand)]
+    HaproxyAgent(Command),
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Create a new agent project.
+    New(New),
+}
+
+#[derive(Debug, Parser)]
+struct New {
+    /// The name of the agent, used as the package name.
+    name: String,
+
+    /// The directory of the project, defaults to the name of the agent.
+    #[arg(long)]
+    dir: Option<PathBuf>,
+
+    /// Depend on a local checkout of the haproxy crate instead of crates.io.
+    #[arg(long)]
+    haproxy_path: Option<PathBuf>,
+}
+
+/// The files of the project and their templates.
+const TEMPLATES: &[(&str, &str)] = &[
+    ("Cargo.toml", include_str!("templates/Cargo.toml.tmpl")),
+    ("agent.toml", include_str!("templates/agent.toml.tmpl")),
+    ("src/lib.rs", include_str!("templates/lib.rs.tmpl")),
+    ("src/main.rs", include_str!("templates/main.rs.tmpl")),
+    ("src/config.rs", include_str!("templates/config.rs.tmpl")),
+    ("src/handler.rs", include_str!("templates/handler.rs.tmpl")),
+    ("tests/handler.rs", include_str!("templates/test.rs.tmpl")),
+    (
+        "haproxy/haproxy.cfg",
+        include_str!("templates/haproxy.cfg.tmpl"),
+    ),
+    ("haproxy/spoe.cfg", include_str!("templates/spoe.cfg.tmpl")),
+];
+
+fn main() -> Result<()> {
+    let Cargo::HaproxyAgent(cmd) = Cargo::parse();
+
+    match cmd {
+        Command::New(new) => {
+            let dir = new.dir.clone().unwrap_or_else(|| PathBuf::from(&new.name));
+
+            scaffold(&new, &dir)?;
+
+            println!("Created agent `{}` in {}", new.name, dir.display());
+        }
+    }
+
+    Ok(())
+}
+
+fn scaffold(new: &New, dir: &Path) -> Result<()> {
+    if !new
+        .name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        || !new.name.starts_with(|c: char| c.is_ascii_alphabetic())
+    {
+        bail!("invalid agent name `{}`", new.name);
+    }
+    if dir.exists() {
+        bail!("{} already exists", dir.display());
+    }
+
+    let crate_name = new.name.replace('-', "_");
+    let haproxy = match &new.haproxy_path {
+        Some(path) => format!("{{ path = {:?} }}", path.display().to_string()),
+        None => format!("\"{}\"", env!("CARGO_PKG_VERSION")),
+    };
+    let vars = [
+        ("{{name}}", new.name.as_str()),
+        ("{{crate_name}}", crate_name.as_str()),
+        ("{{prefix}}", crate_name.as_str()),
+        ("{{haproxy}}", haproxy.as_str()),
+    ];
+
+    for (file, template) in TEMPLATES {
+        let path = dir.join(file);
+        let content = vars.iter().fold(template.to_string(), |s, (var, value)| {
+            s.replace(var, value)
+        });
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create {}", parent.display()))?;
+        }
+        fs::write(&path, content).with_context(|| format!("write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_scaffold() {
+        let dir = env::temp_dir().join(format!("haproxy-agent-{}", std::process::id()));
+        let new = New {
+            name: "ip-score".to_string(),
+            dir: None,
+            haproxy_path: None,
+        };
+
+        scaffold(&new, &dir).unwrap();
+
+        for (file, _) in TEMPLATES {
+            let content = fs::read_to_string(dir.join(file)).unwrap();
+            assert!(!content.contains("{{"), "{file} not rendered");
+        }
+        assert!(fs::read_to_string(dir.join("src/main.rs"))
+            .unwrap()
+            .contains("use ip_score::{handler, Config};"));
+        assert!(scaffold(&new, &dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        let invalid = New {
+            name: "../agent".to_string(),
+            ..new
+        };
+        assert!(scaffold(&invalid, &dir).is_err());
+    }
+}
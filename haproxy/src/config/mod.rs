@@ -0,0 +1,546 @@
+//! Lint the SPOE sections of the HAProxy configuration against the agent.
+//!
+//! The `spoe-agent`, `spoe-message` and `spoe-group` sections are parsed from the SPOE
+//! configuration file, or the scoped `[engine]` sections of a `haproxy.cfg`, and cross-checked
+//! against the messages and capabilities of the agent, so the mismatches are caught in CI
+//! rather than by the silently missing variables in production.
+//!
+//! ```
+//! use haproxy::config::{lint, AgentSpec, Lint, MessageSchema};
+//!
+//! let spec = AgentSpec::new().message(MessageSchema::new("check-client-ip", ["ip"]));
+//! let cfg = r#"
+//! [ip-score]
+//! spoe-agent ip-score-agent
+//!     messages check-client-ip
+//!
+//! spoe-message check-client-ip
+//!     args src=src
+//!     event on-client-session
+//! "#;
+//!
+//! let diags = lint(cfg, &spec);
+//! assert!(diags.iter().any(|diag| matches!(diag.lint, Lint::UnknownArg { ref arg, .. } if arg == "src")));
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{agent::NotifyContext, proto::Capability};
+
+/// The schema of a message processed by the agent.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MessageSchema {
+    /// The name of the message.
+    pub name: String,
+    /// The arguments the agent expects.
+    pub args: Vec<String>,
+    /// The arguments the agent accepts but doesn't require.
+    pub optional: Vec<String>,
+}
+
+impl MessageSchema {
+    pub fn new<S, I, A>(name: S, args: I) -> Self
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        MessageSchema {
+            name: name.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            optional: vec![],
+        }
+    }
+
+    /// Accept the optional arguments.
+    pub fn optional<I, A>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = A>,
+        A: Into<String>,
+    {
+        self.optional.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    fn accepts(&self, arg: &str) -> bool {
+        self.args
+            .iter()
+            .chain(&self.optional)
+            .any(|name| name == arg)
+            || arg == NotifyContext::GROUP_ARG
+            || arg == NotifyContext::EVENT_ARG
+    }
+}
+
+/// The messages and capabilities of the agent, checked against the configuration.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AgentSpec {
+    /// The messages processed by the agent.
+    pub messages: Vec<MessageSchema>,
+    /// The capabilities supported by the agent.
+    pub capabilities: Vec<Capability>,
+}
+
+impl AgentSpec {
+    pub fn new() -> Self {
+        AgentSpec::default()
+    }
+
+    pub fn message(mut self, schema: MessageSchema) -> Self {
+        self.messages.push(schema);
+        self
+    }
+
+    pub fn capability(mut self, cap: Capability) -> Self {
+        self.capabilities.push(cap);
+        self
+    }
+}
+
+/// A mismatch between the configuration and the agent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Lint {
+    /// An agent or a group references a message not defined in its engine.
+    UndefinedMessage { section: String, message: String },
+    /// An agent references a group not defined in its engine.
+    UndefinedGroup { agent: String, group: String },
+    /// The message is not processed by the agent.
+    UnregisteredMessage { message: String },
+    /// The message passes an argument the agent doesn't expect.
+    UnknownArg { message: String, arg: String },
+    /// The message doesn't pass an argument the agent expects.
+    MissingArg { message: String, arg: String },
+    /// The message is neither triggered by an event nor sent by a group, HAProxy never sends it.
+    NeverSent { message: String },
+    /// The group has no message.
+    EmptyGroup { group: String },
+    /// Several agents set their variables with the same prefix, overwriting each other.
+    VarPrefixCollision { prefix: String, agents: Vec<String> },
+    /// The agent section enables a capability the agent doesn't support.
+    UnsupportedCapability {
+        agent: String,
+        capability: Capability,
+    },
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::UndefinedMessage { section, message } => {
+                write!(f, "`{section}` references undefined message `{message}`")
+            }
+            Lint::UndefinedGroup { agent, group } => {
+                write!(f, "`{agent}` references undefined group `{group}`")
+            }
+            Lint::UnregisteredMessage { message } => {
+                write!(f, "message `{message}` is not processed by the agent")
+            }
+            Lint::UnknownArg { message, arg } => {
+                write!(f, "message `{message}` passes unknown argument `{arg}`")
+            }
+            Lint::MissingArg { message, arg } => {
+                write!(f, "message `{message}` misses argument `{arg}`")
+            }
+            Lint::NeverSent { message } => {
+                write!(
+                    f,
+                    "message `{message}` has no event and belongs to no group"
+                )
+            }
+            Lint::EmptyGroup { group } => write!(f, "group `{group}` has no message"),
+            Lint::VarPrefixCollision { prefix, agents } => {
+                write!(f, "agents {agents:?} share the var-prefix `{prefix}`")
+            }
+            Lint::UnsupportedCapability { agent, capability } => {
+                write!(f, "`{agent}` enables unsupported capability `{capability}`")
+            }
+        }
+    }
+}
+
+/// A lint found at a line of the configuration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The line of the configuration, starting from 1.
+    pub line: usize,
+    /// The mismatch.
+    pub lint: Lint,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.lint)
+    }
+}
+
+/// Parse the SPOE sections of the configuration and check them against the agent.
+///
+/// The diagnostics are sorted by line.
+pub fn lint(cfg: &str, spec: &AgentSpec) -> Vec<Diagnostic> {
+    let sections = parse(cfg);
+    let mut diags = vec![];
+    let mut report = |line, lint| diags.push(Diagnostic { line, lint });
+
+    let defined = |kind, engine: &Option<String>, name: &str| {
+        sections
+            .iter()
+            .any(|s| s.kind == kind && &s.engine == engine && s.name == name)
+    };
+    let grouped = sections
+        .iter()
+        .filter(|s| s.kind == Kind::Group)
+        .flat_map(|s| s.messages.iter().map(move |(m, _)| (&s.engine, m.as_str())))
+        .collect::<HashSet<_>>();
+    let mut prefixes = HashMap::<String, Vec<(&str, usize)>>::new();
+
+    for section in &sections {
+        match section.kind {
+            Kind::Agent => {
+                for (message, line) in &section.messages {
+                    if !defined(Kind::Message, &section.engine, message) {
+                        report(
+                            *line,
+                            Lint::UndefinedMessage {
+                                section: section.name.clone(),
+                                message: message.clone(),
+                            },
+                        );
+                    }
+                }
+                for (group, line) in &section.groups {
+                    if !defined(Kind::Group, &section.engine, group) {
+                        report(
+                            *line,
+                            Lint::UndefinedGroup {
+                                agent: section.name.clone(),
+                                group: group.clone(),
+                            },
+                        );
+                    }
+                }
+                for (capability, line) in &section.capabilities {
+                    if !spec.capabilities.contains(capability) {
+                        report(
+                            *line,
+                            Lint::UnsupportedCapability {
+                                agent: section.name.clone(),
+                                capability: *capability,
+                            },
+                        );
+                    }
+                }
+
+                let (prefix, line) = section
+                    .var_prefix
+                    .clone()
+                    .unwrap_or((section.name.clone(), section.line));
+                prefixes
+                    .entry(prefix)
+                    .or_default()
+                    .push((&section.name, line));
+            }
+            Kind::Message => {
+                match spec
+                    .messages
+                    .iter()
+                    .find(|schema| schema.name == section.name)
+                {
+                    Some(schema) => {
+                        for (arg, line) in &section.args {
+                            if !schema.accepts(arg) {
+                                report(
+                                    *line,
+                                    Lint::UnknownArg {
+                                        message: section.name.clone(),
+                                        arg: arg.clone(),
+                                    },
+                                );
+                            }
+                        }
+                        for arg in &schema.args {
+                            if !section.args.iter().any(|(name, _)| name == arg) {
+                                report(
+                                    section.line,
+                                    Lint::MissingArg {
+                                        message: section.name.clone(),
+                                        arg: arg.clone(),
+                                    },
+                                );
+                            }
+                        }
+                    }
+                    None => report(
+                        section.line,
+                        Lint::UnregisteredMessage {
+                            message: section.name.clone(),
+                        },
+                    ),
+                }
+
+                if !section.event && !grouped.contains(&(&section.engine, section.name.as_str())) {
+                    report(
+                        section.line,
+                        Lint::NeverSent {
+                            message: section.name.clone(),
+                        },
+                    );
+                }
+            }
+            Kind::Group => {
+                if section.messages.is_empty() {
+                    report(
+                        section.line,
+                        Lint::EmptyGroup {
+                            group: section.name.clone(),
+                        },
+                    );
+                }
+                for (message, line) in &section.messages {
+                    if !defined(Kind::Message, &section.engine, message) {
+                        report(
+                            *line,
+                            Lint::UndefinedMessage {
+                                section: section.name.clone(),
+                                message: message.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for (prefix, agents) in prefixes {
+        if let [_, .., (_, line)] = agents[..] {
+            report(
+                line,
+                Lint::VarPrefixCollision {
+                    prefix,
+                    agents: agents.iter().map(|(name, _)| name.to_string()).collect(),
+                },
+            );
+        }
+    }
+
+    diags.sort_by_key(|diag| diag.line);
+    diags
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Agent,
+    Message,
+    Group,
+}
+
+/// A `spoe-agent`, `spoe-message` or `spoe-group` section, with the lines of its directives.
+#[derive(Debug)]
+struct Section {
+    kind: Kind,
+    name: String,
+    line: usize,
+    /// The scope of the section, `[engine]` in the configuration.
+    engine: Option<String>,
+    messages: Vec<(String, usize)>,
+    groups: Vec<(String, usize)>,
+    args: Vec<(String, usize)>,
+    event: bool,
+    var_prefix: Option<(String, usize)>,
+    capabilities: Vec<(Capability, usize)>,
+}
+
+/// The keywords starting the other sections of a `haproxy.cfg`.
+const SECTIONS: &[&str] = &[
+    "global",
+    "defaults",
+    "frontend",
+    "backend",
+    "listen",
+    "resolvers",
+    "peers",
+    "userlist",
+    "cache",
+    "program",
+    "http-errors",
+    "ring",
+    "mailers",
+];
+
+fn parse(cfg: &str) -> Vec<Section> {
+    let mut sections = vec![];
+    let mut engine = None;
+    let mut current: Option<Section> = None;
+
+    for (i, line) in cfg.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+
+        let Some(&keyword) = tokens.first() else {
+            continue;
+        };
+
+        if let Some(scope) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            sections.extend(current.take());
+            engine = Some(scope.trim().to_string());
+            continue;
+        }
+
+        let kind = match keyword {
+            "spoe-agent" => Some(Kind::Agent),
+            "spoe-message" => Some(Kind::Message),
+            "spoe-group" => Some(Kind::Group),
+            _ if SECTIONS.contains(&keyword) => {
+                sections.extend(current.take());
+                continue;
+            }
+            _ => None,
+        };
+
+        if let Some(kind) = kind {
+            sections.extend(current.take());
+            current = tokens.get(1).map(|name| Section {
+                kind,
+                name: name.to_string(),
+                line: line_no,
+                engine: engine.clone(),
+                messages: vec![],
+                groups: vec![],
+                args: vec![],
+                event: false,
+                var_prefix: None,
+                capabilities: vec![],
+            });
+            continue;
+        }
+
+        let Some(section) = current.as_mut() else {
+            continue;
+        };
+        let values = tokens[1..].iter().map(|s| (s.to_string(), line_no));
+
+        match (keyword, tokens.get(1).copied()) {
+            ("messages", _) => section.messages.extend(values),
+            ("groups", _) => section.groups.extend(values),
+            ("args", _) => section.args.extend(
+                tokens[1..]
+                    .iter()
+                    .filter_map(|arg| arg.split_once('='))
+                    .map(|(name, _)| (name.to_string(), line_no)),
+            ),
+            ("event", _) => section.event = true,
+            ("option", Some("var-prefix")) => {
+                section.var_prefix = tokens.get(2).map(|prefix| (prefix.to_string(), line_no))
+            }
+            ("option", Some("pipelining")) => {
+                section.capabilities.push((Capability::Pipelining, line_no))
+            }
+            ("option", Some("async")) => section.capabilities.push((Capability::Async, line_no)),
+            _ => {}
+        }
+    }
+
+    sections.extend(current);
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The agent serving the configurations of the corpus.
+    fn spec() -> AgentSpec {
+        AgentSpec::new()
+            .message(MessageSchema::new("check-client-ip", ["ip"]))
+            .message(MessageSchema::new("check-request", ["path"]).optional(["method"]))
+            .capability(Capability::Pipelining)
+    }
+
+    #[test]
+    fn test_valid() {
+        for cfg in [
+            include_str!("corpus/spoe.cfg"),
+            include_str!("corpus/groups.cfg"),
+            include_str!("corpus/haproxy.cfg"),
+        ] {
+            assert_eq!(lint(cfg, &spec()), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let lints = |line: usize| {
+            lint(include_str!("corpus/mismatches.cfg"), &spec())
+                .into_iter()
+                .filter(move |diag| diag.line == line)
+                .map(|diag| diag.lint)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            lints(3),
+            vec![Lint::UndefinedMessage {
+                section: "broken-agent".into(),
+                message: "undefined".into()
+            }]
+        );
+        assert_eq!(
+            lints(4),
+            vec![Lint::UndefinedGroup {
+                agent: "broken-agent".into(),
+                group: "missing".into()
+            }]
+        );
+        assert_eq!(
+            lints(6),
+            vec![Lint::UnsupportedCapability {
+                agent: "broken-agent".into(),
+                capability: Capability::Async
+            }]
+        );
+        assert_eq!(
+            lints(8),
+            vec![Lint::MissingArg {
+                message: "check-client-ip".into(),
+                arg: "ip".into()
+            }]
+        );
+        assert_eq!(
+            lints(9),
+            vec![Lint::UnknownArg {
+                message: "check-client-ip".into(),
+                arg: "src".into()
+            }]
+        );
+        assert_eq!(
+            lints(12),
+            vec![
+                Lint::UnregisteredMessage {
+                    message: "unknown".into()
+                },
+                Lint::NeverSent {
+                    message: "unknown".into()
+                }
+            ]
+        );
+        assert_eq!(
+            lints(15),
+            vec![Lint::EmptyGroup {
+                group: "empty".into()
+            }]
+        );
+        assert_eq!(
+            lints(19),
+            vec![Lint::VarPrefixCollision {
+                prefix: "shared".into(),
+                agents: vec!["broken-agent".into(), "other-agent".into()]
+            }]
+        );
+        assert_eq!(
+            lint(include_str!("corpus/mismatches.cfg"), &spec())[0].to_string(),
+            "line 3: `broken-agent` references undefined message `undefined`"
+        );
+    }
+}
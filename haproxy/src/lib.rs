@@ -1,3 +1,107 @@
-pub use haproxy_spoa as agent;
-pub use haproxy_spoe as engine;
-pub use haproxy_spop as proto;
+//! HAProxy Stream Processing Offload.
+//!
+//! - [`proto`]: the Stream Processing Offload Protocol, frames and data types.
+//! - [`agent`]: the Stream Processing Offload Agent, serving HAProxy connections.
+//! - [`client`]: the Stream Processing Offload Engine, connecting to the agents.
+//! - [`testing`]: the in-memory transport to test the agents without binding sockets.
+//! - [`agents`]: the reference agents, e.g. the JWT authentication.
+//! - [`config`]: lint the SPOE sections of the HAProxy configuration against the agent.
+//!
+//! The public paths of the facade:
+//!
+//! ```
+//! # #![allow(unused_imports, deprecated)]
+//! use haproxy::proto::{Action, Frame, Message, Scope};
+//!
+//! use haproxy::agent::{
+//!     accept, budget, defaults, fault, history, logger, metrics, normalize, req, router, runtime,
+//!     spop, tenant, verdict, ActionSink, Agent, Connection, Context, Error, ErrorKind, FrameHook,
+//!     Frames, HandshakeHook, IoInfo, ListenerOptions, Negotiated, NotifyContext, Reason,
+//!     SpopServer, State, StreamLogger, Streaming, Ulid, DEFAULT_LISTENER, DISCONNECT_LINGER,
+//!     SHUTDOWN_REASON,
+//! };
+//! #[cfg(feature = "ipnet")]
+//! use haproxy::agent::ip;
+//! #[cfg(feature = "proxy-protocol")]
+//! use haproxy::agent::proxy;
+//! #[cfg(feature = "quic")]
+//! use haproxy::agent::quic;
+//! #[cfg(feature = "tls-fingerprint")]
+//! use haproxy::agent::tls;
+//!
+//! use haproxy::client::{
+//!     failover, ids, pool, Connection as _, Connector, Error as _, Failover, Pool, PoolStats,
+//!     Pooled, Result,
+//! };
+//!
+//! use haproxy::testing;
+//!
+//! // the deprecated paths
+//! use haproxy::agent::Runtime;
+//! use haproxy::engine::Connector as _;
+//! use haproxy::spop::Frame as _;
+//! ```
+
+pub mod proto {
+    //! Stream Processing Offload Protocol, used by SPOEs to talk to SPOA servers.
+
+    pub use haproxy_spop::*;
+}
+
+pub mod agent {
+    //! Stream Processing Offload Agent.
+
+    pub use haproxy_spoa::{
+        accept, budget, defaults, fault, history, logger, metrics, normalize, req, router, runtime,
+        spop, tenant, verdict, ActionSink, Agent, Connection, Context, Error, ErrorKind, FrameHook,
+        Frames, HandshakeHook, IoInfo, ListenerOptions, Negotiated, NotifyContext, Reason,
+        SpopServer, State, StreamLogger, Streaming, Ulid, DEFAULT_LISTENER, DISCONNECT_LINGER,
+        SHUTDOWN_REASON,
+    };
+
+    #[cfg(feature = "ipnet")]
+    pub use haproxy_spoa::ip;
+
+    #[cfg(feature = "proxy-protocol")]
+    pub use haproxy_spoa::proxy;
+
+    #[cfg(feature = "quic")]
+    pub use haproxy_spoa::quic;
+
+    #[cfg(feature = "tls-fingerprint")]
+    pub use haproxy_spoa::tls;
+
+    #[deprecated(note = "use `haproxy::agent::runtime::Runtime` instead")]
+    pub type Runtime<S, T> = runtime::Runtime<S, T>;
+}
+
+pub mod client {
+    //! Stream Processing Offload Engine, the client side of the protocol.
+
+    pub use haproxy_spoe::{
+        failover, ids, pool, Connection, Connector, Error, Failover, Pool, PoolStats, Pooled,
+        Result,
+    };
+
+    #[cfg(feature = "quic")]
+    pub use haproxy_spoe::quic;
+}
+
+#[deprecated(note = "use `haproxy::client` instead")]
+pub mod engine {
+    //! Stream Processing Offload Engine.
+
+    pub use haproxy_spoe::*;
+}
+
+#[deprecated(note = "use `haproxy::proto` instead")]
+pub mod spop {
+    //! Stream Processing Offload Protocol.
+
+    pub use haproxy_spop::*;
+}
+
+pub mod agents;
+pub mod config;
+
+pub use haproxy_spoa::testing;
@@ -0,0 +1,85 @@
+//! Compare the shared and per-connection dispatchers under concurrent connections.
+//!
+//! ```sh
+//! cargo run --release -p haproxy-spoa --example dispatch -- 64 1000 8
+//! ```
+
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{future::join_all, StreamExt as _};
+use tokio::sync::mpsc::unbounded_channel;
+
+use haproxy_spoa::{
+    runtime::{DispatchMode, Dispatcher, Processor},
+    spop::{HaproxyNotify, Message},
+};
+
+#[tokio::main]
+async fn main() {
+    let mut args = env::args().skip(1).map(|arg| arg.parse().expect("number"));
+    let conns = args.next().unwrap_or(64);
+    let frames = args.next().unwrap_or(1000);
+    let fragments = args.next().unwrap_or(8);
+
+    for mode in [DispatchMode::Shared, DispatchMode::PerConnection] {
+        let elapsed = run(mode, conns, frames, fragments).await;
+        let total = conns * frames * fragments;
+
+        println!(
+            "{mode:?}: {total} fragments in {elapsed:?}, {:.0} fragments/s",
+            total as f64 / elapsed.as_secs_f64()
+        );
+    }
+}
+
+async fn run(mode: DispatchMode, conns: u64, frames: u64, fragments: u64) -> Duration {
+    let (sender, receiver) = unbounded_channel();
+    let dispatcher = Arc::new(Dispatcher::new(sender));
+    let mut processor = Processor(receiver);
+
+    let consumer = tokio::spawn(async move {
+        while let Some((mut acker, msgs)) = processor.next().await {
+            tokio::spawn(async move {
+                msgs.count().await;
+                let _ = acker.complete().await;
+            });
+        }
+    });
+
+    let started = Instant::now();
+
+    let conns = (0..conns).map(|conn| {
+        let dispatcher = dispatcher.clone();
+
+        tokio::spawn(async move {
+            let mut dispatch = dispatcher.dispatch(mode);
+            let mut acked = vec![];
+
+            for frame in 0..frames {
+                for i in 0..fragments {
+                    let notify = HaproxyNotify {
+                        fragmented: i + 1 < fragments,
+                        stream_id: conn * frames + frame,
+                        frame_id: 1,
+                        messages: vec![Message::new("fragment", [("i", i as i32)])],
+                    };
+
+                    acked.extend(dispatch.recieve_messages(notify).unwrap());
+                }
+            }
+
+            join_all(acked).await;
+        })
+    });
+
+    join_all(conns).await;
+
+    let elapsed = started.elapsed();
+
+    drop(dispatcher);
+    consumer.await.unwrap();
+
+    elapsed
+}
@@ -0,0 +1,37 @@
+//! Filter the accepted connections before the handshake.
+//!
+//! The filter is called with the addresses of the connection, after the PROXY protocol header
+//! has been read if enabled, so the original peer address can be checked against the pod CIDRs.
+//! It may also tag the connection with the identity of the HAProxy instance (e.g. its node or pod),
+//! recorded as the `origin` field of the connection span to tell the ingress fleets apart.
+//!
+//! ```
+//! # use std::net::IpAddr;
+//! # use haproxy_spoa::{accept::Accept, IoInfo};
+//! fn pod_network(info: &IoInfo) -> Accept {
+//!     match info.peer_addr.map(|addr| addr.ip()) {
+//!         Some(IpAddr::V4(ip)) if ip.octets()[..2] == [10, 244] => {
+//!             Accept::Tagged(format!("node-{}", ip.octets()[2]))
+//!         }
+//!         _ => Accept::Reject,
+//!     }
+//! }
+//! ```
+
+use std::sync::Arc;
+
+use crate::IoInfo;
+
+/// The decision of the [`AcceptFilter`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Accept {
+    /// Serve the connection.
+    Allow,
+    /// Serve the connection, tagged with the identity of the HAProxy instance.
+    Tagged(String),
+    /// Close the connection.
+    Reject,
+}
+
+/// A callback deciding whether to serve the accepted connections.
+pub type AcceptFilter = Arc<dyn Fn(&IoInfo) -> Accept + Send + Sync>;
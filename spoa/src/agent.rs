@@ -1,44 +1,187 @@
 use std::error::Error as StdError;
-use std::fmt;
-use std::net::TcpListener as StdTcpListener;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener as StdUnixListener;
 use std::sync::Arc;
 
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
-    net::TcpListener,
+    runtime::Handle,
     select,
+    task::{JoinError, JoinHandle, JoinSet},
+    time::{sleep, timeout},
 };
 use tokio_util::{sync::CancellationToken, task::TaskTracker};
-use tower::{MakeService, Service};
-use tracing::{debug, instrument, trace};
+use tower::{BoxError, MakeService, Service};
+use tracing::{debug, debug_span, error, info, trace, Instrument as _};
 
+#[cfg(feature = "proxy-protocol")]
+use crate::proxy;
 use crate::{
+    accept::Accept,
     error::Result,
-    spop::{Action, Error::*, Message},
-    Connection, Runtime,
+    listener::{self, Listener, ListenerOptions},
+    metrics,
+    runtime::{
+        ConnectionEvent, ConnectionHandle, ConnectionId, HandshakePhase, HealthReport, Load,
+        LoadSnapshot, Runtime, RuntimeHandle, Watchdog,
+    },
+    spop::{Action, Error as Status, Message},
+    task, Connection, IoInfo, DISCONNECT_LINGER,
 };
 
 #[derive(Debug)]
 pub struct Agent<S, T> {
     runtime: Arc<Runtime<S, T>>,
-    listener: TcpListener,
+    listeners: Vec<Listener>,
     shutdown: Shutdown,
 }
 
 impl<S, T> Agent<S, T> {
     pub fn new(runtime: Arc<Runtime<S, T>>, listener: StdTcpListener) -> Result<Self> {
-        let listener = TcpListener::from_std(listener)?;
+        let listener = Listener::new(listener, ListenerOptions::default())?;
 
         Ok(Agent {
             runtime,
-            listener,
+            listeners: vec![listener],
             shutdown: Shutdown::default(),
         })
     }
 
+    /// Accept the connections from a unix domain socket,
+    /// e.g. for the HAProxy backends configured with `server agent /var/run/agent.sock`.
+    ///
+    /// ```no_run
+    /// # use std::os::unix::net::UnixListener;
+    /// # use haproxy_spoa::{Agent, runtime::Runtime};
+    /// # fn bind<S, T>(runtime: std::sync::Arc<Runtime<S, T>>) -> Result<(), haproxy_spoa::Error> {
+    /// let agent = Agent::from_unix(runtime, UnixListener::bind("/var/run/agent.sock")?)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub fn from_unix(runtime: Arc<Runtime<S, T>>, listener: StdUnixListener) -> Result<Self> {
+        let listener = Listener::unix(listener, ListenerOptions::default())?;
+
+        Ok(Agent {
+            runtime,
+            listeners: vec![listener],
+            shutdown: Shutdown::default(),
+        })
+    }
+
+    /// Accept the connections from another listener, with its own protocol options.
+    ///
+    /// The connections of all the listeners share the runtime and are shut down together.
+    pub fn with_listener(
+        mut self,
+        listener: StdTcpListener,
+        options: ListenerOptions,
+    ) -> Result<Self> {
+        self.listeners.push(Listener::new(listener, options)?);
+
+        Ok(self)
+    }
+
+    /// Accept the connections from another unix domain socket, with its own protocol options.
+    #[cfg(unix)]
+    pub fn with_unix_listener(
+        mut self,
+        listener: StdUnixListener,
+        options: ListenerOptions,
+    ) -> Result<Self> {
+        self.listeners.push(Listener::unix(listener, options)?);
+
+        Ok(self)
+    }
+
+    /// Returns the local addresses of the TCP listeners.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
     pub fn shutdown(&self) -> CancellationToken {
         self.shutdown.token.clone()
     }
+
+    /// Returns a handle to manage the runtime while serving.
+    pub fn runtime_handle(&self) -> RuntimeHandle<S, T> {
+        self.runtime.handle()
+    }
+
+    /// Returns a snapshot of the current load.
+    pub fn load(&self) -> LoadSnapshot {
+        self.runtime.load()
+    }
+
+    /// Returns the health of the dependencies.
+    pub fn health(&self) -> HealthReport {
+        self.runtime.health.report()
+    }
+
+    /// Returns the handles of the active connections.
+    pub fn connections(&self) -> Vec<ConnectionHandle> {
+        self.runtime.registry.connections()
+    }
+
+    /// Returns the handle of the active connection.
+    pub fn connection(&self, id: ConnectionId) -> Option<ConnectionHandle> {
+        self.runtime.registry.get(id)
+    }
+
+    /// Subscribe to the connect, handshake and disconnect events of the connections.
+    pub fn subscribe(&self) -> tokio::sync::broadcast::Receiver<ConnectionEvent> {
+        self.runtime.registry.subscribe()
+    }
+
+    /// Stop or resume accepting new connections.
+    pub fn drain(&self, enable: bool) {
+        self.runtime.load.drain(enable)
+    }
+
+    /// Spawn the processing workers, restarted with exponential backoff when they died.
+    ///
+    /// The workers are cancelled and waited for when the agent shuts down,
+    /// their health is reported by [`Agent::load`].
+    pub fn supervise<F, Fut>(&self, workers: usize, f: F) -> Result<()>
+    where
+        F: Fn(usize) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.runtime.supervisor.spawn(
+            workers,
+            &self.shutdown.tracker,
+            self.shutdown.token.child_token(),
+            f,
+        )
+    }
+}
+
+/// Spawn the connection task.
+fn spawn_conn<F>(conns: &mut JoinSet<Result<()>>, task: F) -> Result<()>
+where
+    F: Future<Output = Result<()>> + Send + 'static,
+{
+    task::spawn_in(conns, "conn", task)?;
+
+    Ok(())
+}
+
+/// Log the result of a finished connection task, counting the panics.
+fn reap(load: &Load, res: std::result::Result<Result<()>, JoinError>) {
+    match res {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => debug!(?err, "connection closed"),
+        Err(err) if err.is_panic() => {
+            load.panic();
+
+            error!(?err, "connection panicked");
+        }
+        Err(err) => debug!(?err, "connection cancelled"),
+    }
 }
 
 #[derive(Clone, Debug, Default)]
@@ -54,38 +197,1092 @@ where
     <S::Service as Service<Vec<Message>>>::Future: Send + 'static,
     S::MakeError: StdError + Send + Sync + 'static,
     S::Future: Send,
-    S::Error: fmt::Display + Send + Sync + 'static,
+    S::Error: Into<BoxError>,
     T: Clone + Send + Sync + 'static,
 {
+    /// Serve on the runtime, as a subsystem of a larger service, e.g. next to an HTTP server.
+    ///
+    /// Returns the task of the agent, resolved once it has shut down,
+    /// and the token to shut it down, e.g. on the signals handled by the service.
+    ///
+    /// The agent leaves the process-level concerns, e.g. the signals, the resource limits
+    /// or the global tracing subscriber, to the service. Its tasks log with the tracing
+    /// subscriber current when spawned, e.g. the one set with `tracing::subscriber::with_default`.
+    ///
+    /// ```no_run
+    /// # use std::net::TcpListener;
+    /// # use haproxy_spoa::{Agent, runtime::Runtime};
+    /// # async fn embed<S, T>(runtime: std::sync::Arc<Runtime<S, T>>) -> Result<(), haproxy_spoa::Error>
+    /// # where
+    /// #     S: tower::MakeService<T, Vec<haproxy_spoa::spop::Message>, Response = Vec<haproxy_spoa::spop::Action>> + Send + Sync + 'static,
+    /// #     S::Service: Send,
+    /// #     <S::Service as tower::Service<Vec<haproxy_spoa::spop::Message>>>::Future: Send + 'static,
+    /// #     S::MakeError: std::error::Error + Send + Sync + 'static,
+    /// #     S::Future: Send,
+    /// #     S::Error: Into<tower::BoxError>,
+    /// #     T: Clone + Send + Sync + 'static,
+    /// # {
+    /// let listener = TcpListener::bind("127.0.0.1:12345")?;
+    /// listener.set_nonblocking(true)?;
+    ///
+    /// let agent = Agent::new(runtime, listener)?;
+    /// let (serving, shutdown) = agent.spawn_on(&tokio::runtime::Handle::current())?;
+    ///
+    /// // ... serve the HTTP requests until the service shuts down
+    ///
+    /// shutdown.cancel();
+    /// serving.await.unwrap()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn spawn_on(self, handle: &Handle) -> Result<(JoinHandle<Result<()>>, CancellationToken)> {
+        let shutdown = self.shutdown();
+        let serving = task::spawn_on("agent", async move { self.serve().await }, handle)?;
+
+        Ok((serving, shutdown))
+    }
+
     pub async fn serve(&self) -> Result<()> {
+        if self.runtime.health.has_probes() {
+            self.spawn_health_checks()?;
+        }
+        if let Some(watchdog) = self.runtime.watchdog {
+            self.spawn_watchdog(watchdog)?;
+        }
+
+        let mut conns = JoinSet::new();
+
         loop {
+            let overloaded = self.runtime.is_overloaded();
+
             select! {
                 _ = self.shutdown.token.cancelled() => {
                     debug!("shutting down");
                     break
                 }
 
-                Ok((stream, peer)) = self.listener.accept() => {
-                    trace!(?peer, "accepted connection");
+                _ = self.runtime.load.changed(), if overloaded => {
+                    trace!(load = ?self.runtime.load(), "load changed");
+                }
+
+                Some(res) = conns.join_next() => {
+                    reap(&self.runtime.load, res);
+                }
+
+                Ok((stream, peer, options)) = listener::accept(&self.listeners), if !overloaded => {
+                    trace!(?peer, listener = options.name, "accepted connection");
+                    metrics::listener_counter(metrics::LISTENER_CONNECTIONS, &options.name, 1);
+
+                    let info = IoInfo {
+                        peer_addr: peer,
+                        local_addr: stream.local_addr(),
+                        proxy_addr: None,
+                    };
+                    let mut conn = Connection::new(self.runtime.clone(), stream, self.shutdown.token.child_token())
+                        .with_io_info(info)
+                        .with_listener(options);
+                    #[cfg(feature = "proxy-protocol")]
+                    let proxy_protocol = self.runtime.proxy_protocol;
+                    let accept_filter = self.runtime.accept_filter.clone();
+
+                    spawn_conn(&mut conns, async move {
+                        #[cfg(feature = "proxy-protocol")]
+                        if proxy_protocol {
+                            match timeout(proxy::HEADER_TIMEOUT, conn.read_proxy_header()).await {
+                                Ok(Ok(_)) => {}
+                                Ok(Err(err)) => {
+                                    debug!(?peer, ?err, "rejected connection");
+
+                                    conn.reject(HandshakePhase::ProxyHeader, &err);
+                                    return Err(err);
+                                }
+                                Err(_) => {
+                                    debug!(?peer, "PROXY protocol header timed out");
+
+                                    let err = Status::Timeout.into();
+                                    conn.reject(HandshakePhase::ProxyHeader, &err);
+                                    return Err(err);
+                                }
+                            }
+                        }
+
+                        if let Some(filter) = accept_filter {
+                            match filter(conn.io_info()) {
+                                Accept::Allow => {}
+                                Accept::Tagged(origin) => conn = conn.with_origin(origin),
+                                Accept::Reject => {
+                                    debug!(?peer, info = ?conn.io_info(), "connection rejected by filter");
+
+                                    conn.reject(HandshakePhase::Accept, &Status::ResourceAllocErr.into());
+                                    return Ok(());
+                                }
+                            }
+                        }
+
+                        let span = debug_span!(
+                            "conn",
+                            ulid = %conn.ulid(),
+                            peer = ?conn.io_info().peer_addr,
+                            origin = conn.origin(),
+                            listener = conn.listener().map(|listener| listener.name.as_str()),
+                        );
+                        let stalled = conn.stalled();
 
-                    let mut conn = Connection::new(self.runtime.clone(), stream, self.shutdown.token.child_token());
+                        let res = select! {
+                            res = conn.serve().instrument(span) => Some(res),
+                            _ = stalled.cancelled() => None,
+                        };
 
-                    tokio::task::Builder::new().name("conn").spawn(self.shutdown.tracker.track_future(async move {
-                        conn.serve().await
-                    }))?;
+                        match res {
+                            Some(res) => res,
+                            None => {
+                                let _ = timeout(DISCONNECT_LINGER, conn.disconnect(Status::Timeout, "stalled")).await;
+
+                                Err(Status::Timeout.into())
+                            }
+                        }
+                    })?;
                 }
             }
         }
 
+        if !conns.is_empty() {
+            debug!(conns = conns.len(), "waiting for connections");
+
+            while let Some(res) = conns.join_next().await {
+                reap(&self.runtime.load, res);
+            }
+        }
+
         if self.shutdown.tracker.close() && !self.shutdown.tracker.is_empty() {
             debug!(
-                conns = self.shutdown.tracker.len(),
+                tasks = self.shutdown.tracker.len(),
                 "waiting for shutting down"
             );
 
             self.shutdown.tracker.wait().await;
         }
 
+        if let Some(size) = self
+            .runtime
+            .suggested_max_frame_size()
+            .filter(|&size| size != self.runtime.max_frame_size)
+        {
+            info!(
+                max_frame_size = self.runtime.max_frame_size,
+                "suggested max-frame-size: {size}"
+            );
+        }
+
         Ok(())
     }
+
+    /// Close the stalled connections periodically until the agent shuts down.
+    fn spawn_watchdog(&self, watchdog: Watchdog) -> Result<()> {
+        let runtime = self.runtime.clone();
+        let token = self.shutdown.token.child_token();
+
+        task::spawn(
+            "watchdog",
+            self.shutdown.tracker.track_future(async move {
+                loop {
+                    select! {
+                        _ = token.cancelled() => break,
+                        _ = sleep(watchdog.interval()) => {}
+                    }
+
+                    let stalled = watchdog.check(&runtime.registry, &runtime.load);
+
+                    if !stalled.is_empty() {
+                        debug!(?stalled, "stalled connections closed");
+                    }
+                }
+            }),
+        )?;
+
+        Ok(())
+    }
+
+    /// Check the dependencies periodically until the agent shuts down.
+    fn spawn_health_checks(&self) -> Result<()> {
+        let runtime = self.runtime.clone();
+        let token = self.shutdown.token.child_token();
+
+        task::spawn(
+            "health",
+            self.shutdown.tracker.track_future(async move {
+                loop {
+                    let state = runtime.health.check().await;
+
+                    trace!(?state, "dependencies checked");
+
+                    select! {
+                        _ = token.cancelled() => break,
+                        _ = sleep(runtime.health.interval) => {}
+                    }
+                }
+            }),
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use futures::future::join_all;
+    use haproxy_spoe::Connector;
+    use tokio::time::{sleep, timeout};
+    use tower::{make::Shared, service_fn};
+
+    use crate::{
+        fixture,
+        runtime::{Builder, PAUSED_VAR},
+        spop::{Message, Scope},
+    };
+
+    use super::*;
+
+    const CLIENTS: usize = 8;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_shutdown_during_processing() {
+        let (entered, mut entering) = tokio::sync::mpsc::unbounded_channel();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn({
+                let gate = gate.clone();
+
+                move |msgs: Vec<Message>| {
+                    let entered = entered.clone();
+                    let gate = gate.clone();
+
+                    async move {
+                        entered.send(()).unwrap();
+                        gate.acquire().await.unwrap().forget();
+
+                        Ok::<_, Infallible>(vec![Action::set_var(
+                            Scope::Transaction,
+                            "messages",
+                            msgs.len() as i32,
+                        )])
+                    }
+                }
+            })),
+            (),
+        );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let clients = (0..CLIENTS).map(|_| async move {
+            let conn = Connector::new(addr.to_string()).connect().await?;
+
+            conn.notify([Message::new("check", [("n", 1)])]).await
+        });
+        let clients = tokio::spawn(join_all(clients));
+
+        // cancel while the messages are being processed
+        for _ in 0..CLIENTS {
+            entering.recv().await.unwrap();
+        }
+        shutdown.cancel();
+        gate.add_permits(CLIENTS);
+
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("shutdown timed out")
+            .unwrap()
+            .unwrap();
+
+        for res in clients.await.unwrap() {
+            assert_eq!(
+                res.unwrap(),
+                vec![Action::set_var(Scope::Transaction, "messages", 1)]
+            );
+        }
+
+        assert_eq!(runtime.load().connections, 0);
+        assert_eq!(runtime.load().in_flight, 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_on_shutdown() {
+        let (entered, mut entering) = tokio::sync::mpsc::unbounded_channel();
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let runtime = Builder::new()
+            .drain_timeout(Duration::from_secs(1))
+            .make_service(
+                Shared::new(service_fn({
+                    let gate = gate.clone();
+
+                    move |_: Vec<Message>| {
+                        let entered = entered.clone();
+                        let gate = gate.clone();
+
+                        async move {
+                            entered.send(()).unwrap();
+                            gate.acquire().await.unwrap().forget();
+
+                            Ok::<_, Infallible>(vec![Action::set_var(
+                                Scope::Transaction,
+                                "score",
+                                10,
+                            )])
+                        }
+                    }
+                })),
+                (),
+            );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        let notifying = tokio::spawn(async move {
+            let res = conn.notify([Message::new("check", [("n", 1)])]).await;
+
+            (conn, res)
+        });
+
+        // cancel while the frame is being processed
+        entering.recv().await.unwrap();
+        shutdown.cancel();
+        gate.add_permits(1);
+
+        let (conn, res) = notifying.await.unwrap();
+        assert_eq!(
+            res.unwrap(),
+            vec![Action::set_var(Scope::Transaction, "score", 10)]
+        );
+
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("shutdown timed out")
+            .unwrap()
+            .unwrap();
+
+        // the connection was closed with a `Normal` AGENT-DISCONNECT frame
+        assert!(conn.is_closed());
+        assert_eq!(runtime.load().disconnects, 1);
+        assert_eq!(
+            runtime
+                .load()
+                .frames
+                .get(crate::spop::FrameType::AgentDisconnect),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let (entered, mut entering) = tokio::sync::mpsc::unbounded_channel();
+        let runtime = Builder::new()
+            .drain_timeout(Duration::from_millis(100))
+            .max_process_time(Duration::from_secs(30))
+            .make_service(
+                Shared::new(service_fn(move |_: Vec<Message>| {
+                    let _ = entered.send(());
+
+                    async {
+                        futures::future::pending::<std::result::Result<Vec<Action>, Infallible>>()
+                            .await
+                    }
+                })),
+                (),
+            );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        let notifying = tokio::spawn(async move {
+            let res = conn.notify([Message::new("check", [("n", 1)])]).await;
+
+            (conn, res)
+        });
+
+        // cancel while the frame is being processed, it never completes
+        entering.recv().await.unwrap();
+        shutdown.cancel();
+
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("shutdown timed out")
+            .unwrap()
+            .unwrap();
+
+        // the connection was closed with a `Normal` AGENT-DISCONNECT frame, without the ACK frame
+        let (conn, res) = notifying.await.unwrap();
+        assert!(res.is_err());
+        assert!(conn.is_closed());
+        assert_eq!(
+            runtime
+                .load()
+                .frames
+                .get(crate::spop::FrameType::AgentDisconnect),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_accept_filter() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+        use crate::accept::Accept;
+
+        let accepted = AtomicUsize::new(0);
+        let runtime = fixture::runtime(Builder::new().accept_filter(move |_| {
+            match accepted.fetch_add(1, Relaxed) {
+                0 => Accept::Reject,
+                1 => Accept::Allow,
+                _ => Accept::Tagged("node-1".to_string()),
+            }
+        }));
+
+        let mut events = runtime.registry.subscribe();
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        // the rejected connection is closed before the handshake, and reported
+        assert!(Connector::new(addr.to_string()).connect().await.is_err());
+        match events.recv().await.unwrap() {
+            ConnectionEvent::Rejected(failure) => {
+                assert_eq!(failure.phase, HandshakePhase::Accept);
+            }
+            event => panic!("unexpected event: {event:?}"),
+        }
+        assert_eq!(runtime.load().handshake_rejections, 1);
+
+        let allowed = Connector::new(addr.to_string()).connect().await.unwrap();
+        let tagged = Connector::new(addr.to_string()).connect().await.unwrap();
+        for conn in [&allowed, &tagged] {
+            conn.notify([Message::new("check", [("n", 1)])])
+                .await
+                .unwrap();
+        }
+
+        // the origin is recorded on the connection, not as a metrics label
+        let mut origins = runtime
+            .registry
+            .connections()
+            .iter()
+            .map(|conn| conn.origin().map(str::to_string))
+            .collect::<Vec<_>>();
+        origins.sort();
+        assert_eq!(origins, [None, Some("node-1".to_string())]);
+
+        shutdown.cancel();
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("shutdown timed out")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_by_handle() {
+        let runtime = fixture::runtime(Builder::new());
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+        let mut events = runtime.registry.subscribe();
+
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        conn.notify([Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+
+        let handles = runtime.registry.connections();
+        assert_eq!(handles.len(), 1);
+        assert!(handles[0].negotiated().is_some());
+        assert_eq!(handles[0].frames(), 2);
+        assert!(handles[0].last_activity() > handles[0].connected_at());
+
+        let id = handles[0].id();
+        assert!(matches!(events.recv().await, Ok(ConnectionEvent::Connected(h)) if h.id() == id));
+        assert!(matches!(events.recv().await, Ok(ConnectionEvent::Handshaked(h)) if h.id() == id));
+        handles[0]
+            .disconnect(crate::spop::Error::Normal, "admin")
+            .unwrap();
+
+        match conn.notify([Message::new("check", [("n", 2)])]).await {
+            Err(haproxy_spoe::Error::Disconnected(disconnect)) => {
+                assert_eq!(disconnect.message, "admin")
+            }
+            // the AGENT-DISCONNECT frame was received before sending the NOTIFY frame
+            Err(haproxy_spoe::Error::Closed) => {}
+            res => panic!("unexpected {res:?}"),
+        }
+
+        // HAProxy closes the connection on the AGENT-DISCONNECT frame
+        drop(conn);
+        sleep(Duration::from_millis(50)).await;
+        assert!(handles[0].is_closed());
+        assert!(runtime.registry.connections().is_empty());
+        assert!(
+            matches!(events.recv().await, Ok(ConnectionEvent::Disconnected(closed)) if closed == id)
+        );
+
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watchdog() {
+        let runtime = Builder::new()
+            .max_process_time(Duration::from_secs(10))
+            .watchdog(Duration::from_millis(100))
+            .make_service(
+                Shared::new(service_fn(|_: Vec<Message>| async {
+                    // a wedged dependency
+                    sleep(Duration::from_secs(10)).await;
+
+                    Ok::<_, Infallible>(vec![])
+                })),
+                (),
+            );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        let res = timeout(
+            Duration::from_secs(5),
+            conn.notify([Message::new("check", [("n", 1)])]),
+        )
+        .await
+        .expect("watchdog timed out");
+
+        match res {
+            Err(haproxy_spoe::Error::Disconnected(disconnect)) => {
+                assert_eq!(disconnect.status_code, crate::spop::Error::Timeout as u32);
+                assert_eq!(disconnect.message, "stalled");
+            }
+            res => panic!("unexpected {res:?}"),
+        }
+        assert_eq!(runtime.load().stalled, 1);
+
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_user_status() {
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                let forbidden = crate::spop::UserStatus::new(403).unwrap();
+
+                Err::<Vec<Action>, _>(crate::spop::Disconnect::user(forbidden, "banned"))
+            })),
+            (),
+        );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        match conn.notify([Message::new("check", [("n", 1)])]).await {
+            Err(haproxy_spoe::Error::Disconnected(disconnect)) => {
+                assert_eq!(disconnect.status_code, 403);
+                assert_eq!(disconnect.status(), crate::spop::Error::Unknown);
+                assert_eq!(disconnect.message, "banned");
+            }
+            res => panic!("unexpected {res:?}"),
+        }
+        assert_eq!(runtime.load().disconnects, 1);
+
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_engine_connector() {
+        use crate::spop::Capability;
+
+        let runtime = Builder::new().fragmentation().pipelining().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+            })),
+            (),
+        );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let connector = Connector::new(addr.to_string())
+            .max_frame_size(256)
+            .capabilities([Capability::Fragmentation, Capability::Pipelining]);
+
+        let hello = connector.healthcheck().await.unwrap();
+        assert_eq!(hello.max_frame_size, 256);
+
+        let conn = connector.connect().await.unwrap();
+        assert!(conn
+            .hello()
+            .capabilities
+            .contains(&Capability::Fragmentation));
+        assert_eq!(
+            conn.notify([Message::new("check", [("n", 1)])])
+                .await
+                .unwrap(),
+            vec![Action::set_var(Scope::Transaction, "score", 10)]
+        );
+        // the healthcheck connection is not served as a connection
+        assert_eq!(runtime.registry.connections().len(), 1);
+
+        let frames = runtime.load().frames;
+        assert_eq!(frames.get(crate::spop::FrameType::HaproxyHello), 2);
+        assert_eq!(frames.get(crate::spop::FrameType::AgentAck), 1);
+
+        drop(conn);
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_engine_fragmentation() {
+        use crate::spop::Capability;
+
+        let action =
+            |i: usize| Action::set_var(Scope::Transaction, format!("v{i}"), "x".repeat(64));
+        let runtime = Builder::new()
+            .max_frame_size(256)
+            .fragmentation()
+            .make_service(
+                Shared::new(service_fn(move |msgs: Vec<Message>| async move {
+                    Ok::<_, Infallible>((0..msgs.len()).map(action).collect::<Vec<_>>())
+                })),
+                (),
+            );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+
+        let conn = Connector::new(addr.to_string())
+            .max_frame_size(256)
+            .capabilities([Capability::Fragmentation])
+            .connect()
+            .await
+            .unwrap();
+
+        // both the NOTIFY and the ACK frames are over the max frame size
+        let messages = (0..8).map(|i| Message::new("check", [("data", "x".repeat(64 + i))]));
+        assert_eq!(
+            conn.notify(messages).await.unwrap(),
+            (0..8).map(action).collect::<Vec<_>>()
+        );
+
+        // the fragments are received as NOTIFY frames, and the ACK frame is only split when written
+        let frames = runtime.load().frames;
+        assert!(frames.get(crate::spop::FrameType::HaproxyNotify) > 1);
+        assert_eq!(frames.get(crate::spop::FrameType::AgentAck), 1);
+
+        drop(conn);
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_engine_failover() {
+        use haproxy_spoe::{Failover, Pool};
+
+        // the primary agent is down until its address is listened again
+        let primary_addr = fixture::listener().1;
+        let (backup_addr, backup_shutdown, backup_serving) =
+            fixture::serve(fixture::runtime(Builder::new()));
+
+        let primary = Pool::builder(Connector::new(primary_addr.to_string())).build();
+        let backup = Pool::builder(Connector::new(backup_addr.to_string())).build();
+        let failover = Failover::builder()
+            .primary(primary.clone())
+            .backup(backup.clone())
+            .fall(1)
+            .rise(1)
+            .check_interval(Duration::from_millis(10))
+            .build();
+
+        // redispatched to the backup agent
+        let pooled = failover
+            .checkout_timeout(Duration::from_secs(5))
+            .await
+            .unwrap();
+        pooled
+            .notify([Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+        drop(pooled);
+        assert!(!failover.is_primary_up());
+        assert_eq!(backup.stats().checkouts, 1);
+
+        let listener = StdTcpListener::bind(primary_addr).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let agent = Agent::new(fixture::runtime(Builder::new()), listener).unwrap();
+        let primary_shutdown = agent.shutdown();
+        let primary_serving = tokio::spawn(async move { agent.serve().await });
+
+        // back to the primary agent once it passes the check
+        timeout(Duration::from_secs(5), async {
+            while primary.stats().checkouts == 0 {
+                sleep(Duration::from_millis(10)).await;
+
+                drop(
+                    failover
+                        .checkout_timeout(Duration::from_secs(5))
+                        .await
+                        .unwrap(),
+                );
+            }
+        })
+        .await
+        .unwrap();
+        assert!(failover.is_primary_up());
+        assert_eq!(failover.up(), 2);
+
+        let checkouts = backup.stats().checkouts;
+        drop(
+            failover
+                .checkout_timeout(Duration::from_secs(5))
+                .await
+                .unwrap(),
+        );
+        assert_eq!(backup.stats().checkouts, checkouts);
+
+        drop((failover, primary, backup));
+        for (shutdown, serving) in [
+            (primary_shutdown, primary_serving),
+            (backup_shutdown, backup_serving),
+        ] {
+            shutdown.cancel();
+            serving.await.unwrap().unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_processing() {
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+            })),
+            (),
+        );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+        let handle = runtime.handle();
+
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        let check = || conn.notify([Message::new("check", [("n", 1)])]);
+
+        handle.pause_processing();
+        assert!(runtime.load().paused);
+        assert_eq!(
+            check().await.unwrap(),
+            vec![Action::set_var(Scope::Transaction, PAUSED_VAR, true)]
+        );
+
+        // the connection is kept open while paused
+        handle.resume_processing();
+        assert_eq!(
+            check().await.unwrap(),
+            vec![Action::set_var(Scope::Transaction, "score", 10)]
+        );
+        assert_eq!(runtime.load().connections, 1);
+
+        drop(conn);
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_renegotiate() {
+        let runtime = fixture::runtime(Builder::new());
+
+        let (addr, shutdown, serving) = fixture::serve(runtime.clone());
+        let handle = runtime.handle();
+
+        let mut conns = vec![];
+        for _ in 0..3 {
+            let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+            conn.notify([Message::new("check", [("n", 1)])])
+                .await
+                .unwrap();
+            conns.push(conn);
+        }
+
+        // the disconnects are staggered over the window
+        let started = std::time::Instant::now();
+        let drained = handle.renegotiate(Duration::from_millis(150)).unwrap();
+        assert_eq!(drained.await.unwrap(), 3);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        for conn in &conns {
+            match conn.notify([Message::new("check", [("n", 2)])]).await {
+                Err(haproxy_spoe::Error::Disconnected(disconnect)) => {
+                    assert_eq!(disconnect.status(), crate::spop::Error::Normal);
+                    assert_eq!(disconnect.message, crate::runtime::RENEGOTIATE_REASON);
+                }
+                Err(haproxy_spoe::Error::Closed) => {}
+                res => panic!("unexpected {res:?}"),
+            }
+        }
+
+        // HAProxy reconnects and negotiates again
+        drop(conns);
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        conn.notify([Message::new("check", [("n", 3)])])
+            .await
+            .unwrap();
+
+        drop(conn);
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_seeded_stream_ids() {
+        use haproxy_spoe::ids::RandomIds;
+
+        use crate::NotifyContext;
+
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async move {
+                sleep(Duration::from_millis(50)).await;
+
+                let cx = NotifyContext::current().unwrap();
+
+                Ok::<_, Infallible>(vec![Action::set_var(
+                    Scope::Transaction,
+                    "stream_id",
+                    cx.stream_id as i64,
+                )])
+            })),
+            (),
+        );
+
+        let (addr, shutdown, serving) = fixture::serve(runtime);
+
+        let stream_ids = |seed| async move {
+            let conn = Connector::new(addr.to_string())
+                .id_generator(RandomIds::seeded(seed))
+                .connect()
+                .await
+                .unwrap();
+            let mut ids = vec![];
+
+            for _ in 0..3 {
+                ids.extend(
+                    conn.notify([Message::new("check", [("n", 1)])])
+                        .await
+                        .unwrap(),
+                );
+            }
+
+            ids
+        };
+
+        assert_eq!(stream_ids(42).await, stream_ids(42).await);
+
+        // the same frame of a stream can't be in flight twice
+        let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+        let msgs = || [Message::new("check", [("n", 1)])];
+        let (first, second) = tokio::join!(
+            conn.notify_stream(7, 1, msgs()),
+            conn.notify_stream(7, 1, msgs())
+        );
+        assert!(first.is_ok());
+        assert!(matches!(
+            second,
+            Err(haproxy_spoe::Error::DuplicateId(7, 1))
+        ));
+
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_multiple_listeners() {
+        use std::collections::HashSet;
+
+        use crate::spop::Capability;
+
+        let runtime = Builder::new().fragmentation().pipelining().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+            })),
+            (),
+        );
+
+        let agent = Agent::new(runtime.clone(), fixture::listener().0)
+            .unwrap()
+            .with_listener(
+                fixture::listener().0,
+                ListenerOptions::new("public")
+                    .max_frame_size(4096)
+                    .capabilities([Capability::Pipelining]),
+            )
+            .unwrap();
+        let addrs = agent.local_addrs();
+        assert_eq!(addrs.len(), 2);
+        let shutdown = agent.shutdown();
+        let serving = tokio::spawn(async move { agent.serve().await });
+
+        let mut conns = vec![];
+        for addr in &addrs {
+            let conn = Connector::new(addr.to_string())
+                .capabilities([Capability::Fragmentation, Capability::Pipelining])
+                .connect()
+                .await
+                .unwrap();
+            assert_eq!(
+                conn.notify([Message::new("check", [("n", 1)])])
+                    .await
+                    .unwrap(),
+                vec![Action::set_var(Scope::Transaction, "score", 10)]
+            );
+            conns.push(conn);
+        }
+
+        let mut negotiated = runtime
+            .registry
+            .connections()
+            .into_iter()
+            .map(|handle| handle.negotiated().unwrap().clone())
+            .collect::<Vec<_>>();
+        negotiated.sort_by_key(|negotiated| negotiated.max_frame_size);
+        assert_eq!(negotiated[0].max_frame_size, 4096);
+        assert_eq!(
+            negotiated[0].capabilities,
+            HashSet::from([Capability::Pipelining])
+        );
+        assert!(negotiated[1].max_frame_size > 4096);
+        assert!(negotiated[1].supports_fragmentation());
+
+        // the connections of all the listeners are shut down together
+        drop(conns);
+        shutdown.cancel();
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("shutdown timed out")
+            .unwrap()
+            .unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_listener() {
+        use std::os::unix::net::UnixListener;
+
+        use haproxy_spoe::Connection as SpoeConnection;
+        use tokio::net::UnixStream;
+
+        use crate::spop::{Capability, HaproxyHello, Version, MAX_FRAME_SIZE};
+
+        let runtime = Builder::new().pipelining().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+            })),
+            (),
+        );
+
+        let path = std::env::temp_dir().join(format!("spoa-{}.sock", ulid::Ulid::new()));
+        let agent = Agent::from_unix(runtime.clone(), UnixListener::bind(&path).unwrap()).unwrap();
+        assert!(agent.local_addrs().is_empty());
+        let shutdown = agent.shutdown();
+        let serving = tokio::spawn(async move { agent.serve().await });
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let conn = SpoeConnection::handshake(
+            stream,
+            HaproxyHello {
+                supported_versions: vec![Version::V2_0],
+                max_frame_size: MAX_FRAME_SIZE as u32,
+                capabilities: vec![Capability::Pipelining],
+                healthcheck: None,
+                engine_id: None,
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            conn.notify([Message::new("check", [("n", 1)])])
+                .await
+                .unwrap(),
+            vec![Action::set_var(Scope::Transaction, "score", 10)]
+        );
+
+        // the peers of the unix domain sockets have no address
+        let conns = runtime.registry.connections();
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].io_info().peer_addr, None);
+
+        drop(conn);
+        shutdown.cancel();
+        timeout(Duration::from_secs(5), serving)
+            .await
+            .expect("shutdown timed out")
+            .unwrap()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_spawn_on() {
+        // the runtime of the embedding service
+        let service = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+            })),
+            (),
+        );
+
+        let (listener, addr) = fixture::listener();
+
+        let agent = {
+            let _guard = service.enter();
+
+            Agent::new(runtime, listener).unwrap()
+        };
+        let (serving, shutdown) = agent.spawn_on(service.handle()).unwrap();
+
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                let conn = Connector::new(addr.to_string()).connect().await.unwrap();
+                assert_eq!(
+                    conn.notify([Message::new("check", [("n", 1)])])
+                        .await
+                        .unwrap(),
+                    vec![Action::set_var(Scope::Transaction, "score", 10)]
+                );
+            });
+
+        shutdown.cancel();
+        service.block_on(serving).unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reap_panics() {
+        let load = Load::default();
+        let mut conns = JoinSet::new();
+
+        spawn_conn(&mut conns, async { Ok(()) }).unwrap();
+        spawn_conn(&mut conns, async { panic!("boom") }).unwrap();
+        spawn_conn(&mut conns, async {
+            sleep(Duration::from_secs(60)).await;
+            Ok(())
+        })
+        .unwrap();
+
+        for _ in 0..2 {
+            reap(&load, conns.join_next().await.unwrap());
+        }
+        assert_eq!(load.snapshot().panics, 1);
+
+        conns.abort_all();
+        while let Some(res) = conns.join_next().await {
+            reap(&load, res);
+        }
+        assert_eq!(load.snapshot().panics, 1);
+    }
 }
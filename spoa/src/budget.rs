@@ -0,0 +1,127 @@
+//! Split the processing deadline of a frame across the backends called by the service.
+//!
+//! When a service calls several backends in sequence, e.g. a GeoIP lookup, a reputation check
+//! and a cache write, every step reserves a share of the time left, so the ACK frame is still
+//! sent before HAProxy gives up on the frame:
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use haproxy_spoa::budget::Budget;
+//! # async fn geoip() {}
+//! # async fn reputation() {}
+//! # async fn check() -> Result<(), tokio::time::error::Elapsed> {
+//! let budget = Budget::current().unwrap_or_else(|| Budget::new(Duration::from_millis(100)));
+//!
+//! // reserve half of the time left to the lookup, leaving the rest to the next steps
+//! let country = budget.reserve(0.5).run(geoip()).await?;
+//! let score = budget.run(reputation()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The budget also builds the [`TimeoutLayer`] of the tower clients of the backends.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use tokio::time::{error::Elapsed, timeout_at};
+use tower::timeout::TimeoutLayer;
+
+use crate::NotifyContext;
+
+/// The time left to process a frame, derived from its deadline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Budget {
+    deadline: Instant,
+}
+
+impl Budget {
+    /// Returns a budget of the duration, starting now.
+    pub fn new(timeout: Duration) -> Self {
+        Budget::until(Instant::now() + timeout)
+    }
+
+    /// Returns a budget ending at the deadline.
+    pub fn until(deadline: Instant) -> Self {
+        Budget { deadline }
+    }
+
+    /// Returns the budget of the NOTIFY frame being processed by the current task.
+    pub fn current() -> Option<Self> {
+        NotifyContext::current()
+            .and_then(|cx| cx.deadline)
+            .map(Budget::until)
+    }
+
+    /// Returns the deadline of the budget.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns the time left before the deadline.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Returns `true` if the deadline has passed.
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Keep the margin for the work after the budget, e.g. encoding and writing the ACK frame.
+    pub fn with_margin(self, margin: Duration) -> Self {
+        Budget::until(self.deadline.checked_sub(margin).unwrap_or(self.deadline))
+    }
+
+    /// Reserve the fraction of the time left for a step, clamped to `0.0..=1.0`.
+    pub fn reserve(&self, fraction: f64) -> Self {
+        let share = self.remaining().mul_f64(fraction.clamp(0.0, 1.0));
+
+        Budget::until(Instant::now() + share).min(*self)
+    }
+
+    /// Reserve at most the duration for a step, bounded by the time left.
+    pub fn reserve_at_most(&self, timeout: Duration) -> Self {
+        Budget::new(timeout).min(*self)
+    }
+
+    /// Run the future until the deadline.
+    pub async fn run<F: Future>(&self, fut: F) -> Result<F::Output, Elapsed> {
+        timeout_at(self.deadline.into(), fut).await
+    }
+
+    /// Returns a tower layer timing out the requests after the time left.
+    pub fn timeout_layer(&self) -> TimeoutLayer {
+        TimeoutLayer::new(self.remaining())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::sleep;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_budget() {
+        let budget = Budget::new(Duration::from_millis(200));
+
+        let step = budget.reserve(0.25);
+        assert!(step < budget);
+        assert!(step.remaining() <= Duration::from_millis(50));
+        assert!(step.run(sleep(Duration::from_millis(100))).await.is_err());
+        assert!(step.is_exhausted());
+
+        // the next steps share the time left
+        assert!(!budget.is_exhausted());
+        assert!(budget.reserve(2.0) <= budget);
+        assert_eq!(budget.reserve_at_most(Duration::from_secs(1)), budget);
+        assert_eq!(budget.run(async { 42 }).await, Ok(42));
+
+        let margin = budget.with_margin(Duration::from_millis(100));
+        assert_eq!(
+            budget.deadline() - margin.deadline(),
+            Duration::from_millis(100)
+        );
+    }
+}
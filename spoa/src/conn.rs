@@ -1,32 +1,100 @@
+use std::collections::VecDeque;
 use std::error::Error as StdError;
 use std::fmt;
+use std::future;
 use std::mem;
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
+use derive_more::Debug;
+use pin_project::pin_project;
 use tokio::{
-    io::{AsyncRead, AsyncWrite},
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt as _, ReadBuf},
     select,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        OwnedSemaphorePermit, Semaphore,
+    },
+    time::{sleep, sleep_until, timeout, timeout_at},
 };
 use tokio_util::sync::CancellationToken;
-use tower::MakeService;
-use tracing::instrument;
+use tower::{BoxError, MakeService, Service};
+use tracing::{debug, debug_span, instrument, trace, warn, Instrument as _};
+use ulid::Ulid;
 
-use crate::runtime::Runtime;
+#[cfg(feature = "proxy-protocol")]
+use crate::proxy::{self, ProxyHeader};
+use crate::runtime::{
+    busy, AckLane, Command, ConnectionHandle, ConnectionId, DropReason, HalfOpenGuard,
+    HandshakeFailure, HandshakePhase, LiveState, Route, Runtime, TimeSlice,
+};
+#[cfg(feature = "debug-protocol")]
+use crate::spop::debug::Monitor;
 use crate::{
-    error::Result,
-    spop::{Action, BufCodec, Codec, Error as Status, Frame, Framer, Message},
-    state::AsyncHandler,
+    error::{Context as _, Error, Result},
+    history::{History, Record},
+    listener::ListenerOptions,
+    spop::{
+        split_fragments, Action, AgentAck, BufCodec, Codec, Error as Status, Frame, FrameId,
+        Framer, HaproxyNotify, Message, StreamId, WireSize,
+    },
+    state::{AsyncHandler, Negotiated},
     State,
 };
 
+/// The addresses of the connection.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IoInfo {
+    /// The address of the HAProxy peer, as reported by the PROXY protocol header if any,
+    /// or `None` for the unix domain sockets.
+    pub peer_addr: Option<SocketAddr>,
+    /// The local address of the connection.
+    pub local_addr: Option<SocketAddr>,
+    /// The address of the L4 proxy which sent the PROXY protocol header.
+    pub proxy_addr: Option<SocketAddr>,
+}
+
+/// How long to drain the frames crossing the AGENT-DISCONNECT frame before closing the connection.
+pub const DISCONNECT_LINGER: Duration = Duration::from_millis(500);
+
+/// The reason of the AGENT-DISCONNECT frames sent when draining the connections on shutdown.
+pub const SHUTDOWN_REASON: &str = "shutdown";
+
+/// A hook invoked with every frame received from HAProxy.
+///
+/// The returned frame, if any, is sent to HAProxy after the reply of the state machine.
+pub type FrameHook = Box<dyn FnMut(&Frame) -> Option<Frame> + Send>;
+
 #[derive(Debug)]
 pub struct Connection<IO, S, T>
 where
     S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
 {
-    codec: BufCodec<IO>,
+    runtime: Arc<Runtime<S, T>>,
+    codec: BufCodec<Metered<IO>>,
     state: State<S, T>,
     tok: CancellationToken,
+    id: ConnectionId,
+    ulid: Ulid,
+    seq: u64,
+    commands: (UnboundedSender<Command>, UnboundedReceiver<Command>),
+    info: IoInfo,
+    origin: Option<String>,
+    listener: Option<Arc<ListenerOptions>>,
+    /// The frames waiting to be written, in order.
+    outbox: VecDeque<Frame>,
+    slice: Option<TimeSlice>,
+    history: Option<History>,
+    half_open: Option<HalfOpenGuard>,
+    lane: Option<AckLane>,
+    live: Arc<LiveState>,
+    #[cfg(feature = "debug-protocol")]
+    monitor: Monitor,
+    #[debug(skip)]
+    hook: Option<FrameHook>,
 }
 
 impl<IO, S, T> Connection<IO, S, T>
@@ -35,56 +103,569 @@ where
     S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
 {
     pub fn new(runtime: Arc<Runtime<S, T>>, io: IO, tok: CancellationToken) -> Self {
-        let framer = Framer::new(runtime.max_frame_size);
-        let codec = Codec::buffered(io, framer);
-        let state = State::new(runtime);
+        let mut framer = Framer::new(runtime.max_frame_size.min(runtime.handshake.max_hello_size));
+        framer.set_offload_size(runtime.offload_size);
+        framer.set_limits(runtime.decode_limits);
+        let codec = Codec::buffered(Metered { io, read: 0 }, framer);
+        let state = State::new(runtime.clone());
+
+        runtime.load.connect();
+        let id = runtime.registry.next_id();
+        let slice = runtime
+            .time_slice
+            .map(|(budget, interval)| TimeSlice::new(budget, interval));
+        let history = runtime.history.map(History::new);
+
+        Connection {
+            runtime,
+            codec,
+            state,
+            tok,
+            id,
+            ulid: Ulid::new(),
+            seq: 0,
+            commands: unbounded_channel(),
+            info: IoInfo::default(),
+            origin: None,
+            listener: None,
+            outbox: VecDeque::new(),
+            slice,
+            history,
+            half_open: None,
+            lane: None,
+            live: Arc::default(),
+            #[cfg(feature = "debug-protocol")]
+            monitor: Monitor::default(),
+            hook: None,
+        }
+    }
+
+    /// Returns the identifier of the connection.
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Returns the stable identifier of the connection, to correlate the observability outputs.
+    pub fn ulid(&self) -> Ulid {
+        self.ulid
+    }
+
+    /// Returns a handle to the connection, registered in the runtime while serving.
+    pub fn handle(&self) -> ConnectionHandle {
+        ConnectionHandle::new(
+            self.id,
+            self.ulid,
+            self.info,
+            self.origin.clone(),
+            self.commands.0.clone(),
+            self.history.clone(),
+            self.live.clone(),
+        )
+    }
+
+    /// Set the addresses of the connection.
+    pub fn with_io_info(mut self, info: IoInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Returns the addresses of the connection.
+    pub fn io_info(&self) -> &IoInfo {
+        &self.info
+    }
+
+    /// Tag the connection with the identity of the HAProxy instance.
+    pub fn with_origin<O: Into<String>>(mut self, origin: O) -> Self {
+        self.origin = Some(origin.into());
+        self
+    }
+
+    /// Returns the identity of the HAProxy instance, as tagged by the accept filter.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Negotiate the handshake with the protocol options of the listener.
+    pub fn with_listener(mut self, options: Arc<ListenerOptions>) -> Self {
+        if let State::Connecting(ref mut connecting) = self.state {
+            connecting.listener = Some(options.clone());
+        }
+        self.listener = Some(options);
+        self
+    }
+
+    /// Returns the options of the listener which accepted the connection.
+    pub fn listener(&self) -> Option<&ListenerOptions> {
+        self.listener.as_deref()
+    }
+
+    /// Read the PROXY protocol header before the handshake,
+    /// replacing the peer address with the address of the original peer.
+    #[cfg(feature = "proxy-protocol")]
+    #[instrument(skip(self), ret, err, level = "trace")]
+    pub async fn read_proxy_header(&mut self) -> Result<ProxyHeader> {
+        let hdr = proxy::read_header(self.codec.get_mut()).await?;
+
+        if let Some(source) = hdr.source {
+            self.info.proxy_addr = self.info.peer_addr.replace(source);
+        }
+
+        Ok(hdr)
+    }
+
+    /// Install a hook invoked with every frame received on this connection.
+    ///
+    /// This is an advanced API intended for experimenting with protocol extensions,
+    /// the frames returned by the hook are validated like [`Connection::send_frame`].
+    pub fn with_hook<F>(mut self, hook: F) -> Self
+    where
+        F: FnMut(&Frame) -> Option<Frame> + Send + 'static,
+    {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Returns the negotiated parameters once the handshake has completed.
+    pub fn negotiated(&self) -> Option<&Negotiated> {
+        self.state.negotiated()
+    }
+
+    /// Send an agent-initiated frame on this connection.
+    ///
+    /// This is an advanced API, the frame is validated against the current state
+    /// and the negotiated capabilities to protect the protocol invariants.
+    ///
+    /// Returns the number of bytes written, including the queued frames written before it.
+    /// No frame may be sent after an AGENT-DISCONNECT frame.
+    #[instrument(skip(self), err, level = "trace")]
+    pub async fn send_frame(&mut self, frame: Frame) -> Result<usize> {
+        self.state.validate(&frame)?;
+
+        if let Frame::AgentDisconnect(_) = frame {
+            self.state = State::Disconnecting;
+        }
+        self.outbox.push_back(frame);
+
+        self.flush().await
+    }
+
+    /// Write the queued frames, in order.
+    async fn flush(&mut self) -> Result<usize> {
+        let mut written = 0;
+
+        while let Some(frame) = self.outbox.pop_front() {
+            #[cfg(feature = "debug-protocol")]
+            self.monitor.observe(&frame);
+
+            written += self.write(frame).await?;
+        }
+
+        Ok(written)
+    }
+
+    /// Write an ACK frame routed by another connection of the engine, after the queued frames.
+    ///
+    /// The routed ACK frame is not observed by the monitor, which never saw its NOTIFY frame.
+    async fn write_routed(&mut self, ack: Frame) -> Result<usize> {
+        trace!(id = self.id, "writing routed ACK");
+
+        let res = match self.flush().await {
+            Ok(n) => self.write(ack).await.map(|m| n + m),
+            Err(err) => Err(err),
+        };
+
+        if let Some(lane) = &self.lane {
+            lane.written();
+        }
+
+        res
+    }
+
+    /// Write the ACK frames already routed to the connection, without waiting for more.
+    async fn flush_routed(&mut self) -> Result<usize> {
+        let mut written = 0;
+
+        while let Some(ack) = self
+            .lane
+            .as_mut()
+            .and_then(|lane| lane.acks.try_recv().ok())
+        {
+            written += self.write_routed(ack).await?;
+        }
+
+        Ok(written)
+    }
+
+    /// Write the frame, within the write timeout if any.
+    async fn write(&mut self, frame: Frame) -> Result<usize> {
+        self.runtime.load.frame(frame.frame_type());
 
-        Connection { codec, state, tok }
+        match frame {
+            Frame::AgentAck(_) => self.runtime.load.send_ack(frame.size()),
+            Frame::AgentDisconnect(ref disconnect) => self.runtime.load.send_disconnect(disconnect),
+            _ => {}
+        }
+
+        let n = match self.runtime.write_timeout {
+            Some(d) => match timeout(d, self.codec.write_frame(frame)).await {
+                Ok(res) => res?,
+                Err(_) => {
+                    debug!(id = self.id, timeout = ?d, "write timed out");
+
+                    self.runtime.load.write_timeout();
+                    return Err(Status::Timeout.into());
+                }
+            },
+            None => self.codec.write_frame(frame).await?,
+        };
+
+        self.live.written();
+
+        Ok(n)
+    }
+
+    /// Returns a token cancelled by the watchdog when the connection stalled.
+    pub(crate) fn stalled(&self) -> CancellationToken {
+        self.live.stalled()
     }
 
+    /// Report the connection rejected before completing the handshake.
+    pub(crate) fn reject(&self, phase: HandshakePhase, err: &Error) {
+        let failure = HandshakeFailure::new(self.id, self.info.peer_addr, phase, err);
+
+        self.runtime.load.reject_handshake(phase, failure.kind);
+        self.runtime.registry.reject(failure);
+    }
+
+    /// Send an AGENT-DISCONNECT frame, after which no frame is processed on the connection.
     #[instrument(skip(self), err, level = "trace")]
     pub async fn disconnect<M>(&mut self, status: Status, msg: M) -> Result<()>
     where
         M: Into<String> + fmt::Debug,
     {
-        let disconnect = Frame::agent_disconnect(status, msg);
-        self.codec.write_frame(disconnect).await?;
+        self.state = State::Disconnecting;
+        self.outbox.push_back(Frame::agent_disconnect(status, msg));
+        self.flush().await?;
         Ok(())
     }
 }
 
+impl<IO, S, T> Drop for Connection<IO, S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+{
+    fn drop(&mut self) {
+        self.runtime.registry.deregister(self.id);
+        self.runtime.load.disconnect();
+
+        if let Some(lane) = &self.lane {
+            self.runtime.scheduler.leave(&lane.engine_id, self.id);
+        }
+    }
+}
+
 impl<IO, S, T> Connection<IO, S, T>
 where
     IO: AsyncRead + AsyncWrite + Unpin,
-    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
     S::MakeError: StdError + Send + Sync + 'static,
-    S::Error: fmt::Display + Send + Sync + 'static,
-    T: Clone,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
 {
     pub async fn serve(&mut self) -> Result<()> {
+        let limits = self.runtime.handshake;
+
+        if let (Some(max), Some(peer)) = (limits.max_half_open_per_ip, self.info.peer_addr) {
+            self.half_open = self.runtime.half_open.acquire(peer.ip(), max);
+
+            if self.half_open.is_none() {
+                debug!(id = self.id, ?peer, "too many half-open connections");
+
+                let err = Status::ResourceAllocErr.into();
+                self.reject(HandshakePhase::Accept, &err);
+                return Err(err);
+            }
+        }
+
+        self.runtime.registry.register(self.handle());
+
+        let handshake_timeout = sleep(limits.timeout);
+        tokio::pin!(handshake_timeout);
+
+        // the next frame is read once the deadline elapsed, when the time slice was used up
+        let mut throttled = None;
+        let mut delayed = false;
+        // when the connection started draining on shutdown
+        let mut draining = None;
+
         loop {
-            let state = mem::replace(&mut self.state, State::Disconnecting);
+            let mut state = mem::replace(&mut self.state, State::Disconnecting);
             if matches!(state, State::Disconnecting) {
                 break;
             }
+            let connecting = matches!(state, State::Connecting(_));
+
+            if let (Some(slice), None, false, false) =
+                (self.slice.as_mut(), throttled, delayed, connecting)
+            {
+                if let Some(delay) = slice.delay(Instant::now()) {
+                    // the frame is delayed once, before HAProxy times it out
+                    let delay = delay.min(self.runtime.haproxy_timeout() / 2);
+                    trace!(id = self.id, ?delay, used = ?slice.used(), "time slice exceeded");
+
+                    self.runtime.load.throttle(delay);
+                    throttled = Some(Instant::now() + delay);
+                }
+            }
 
             select! {
                 _ = self.tok.cancelled() => {
+                    if let (Some(drain_timeout), false) = (self.runtime.drain_timeout, connecting) {
+                        let started = *draining.get_or_insert_with(Instant::now);
+
+                        self.drain(started + drain_timeout).await;
+                    }
                     break;
                 }
 
-                frame = self.codec.read_frame() => {
-                    match state.handle_frame(frame?).await {
-                        Ok((next, reply)) => {
-                            if let Some(frame) = reply {
-                                self.codec.write_frame(frame).await?;
+                _ = &mut handshake_timeout, if connecting => {
+                    debug!(id = self.id, timeout = ?limits.timeout, "handshake timed out");
+
+                    let err = Status::Timeout.into();
+                    self.reject(HandshakePhase::Handshake, &err);
+                    let _ = self.disconnect(Status::Timeout, "handshake timed out").await;
+                    return Err(err);
+                }
+
+                Some(cmd) = self.commands.1.recv() => {
+                    match cmd {
+                        Command::Disconnect(disconnect) => {
+                            debug!(id = self.id, status = disconnect.status_code, reason = disconnect.message, "disconnecting by handle");
+
+                            self.close(Frame::AgentDisconnect(disconnect), false).await;
+                            break;
+                        }
+                    }
+                }
+
+                Some(ack) = recv_routed(&mut self.lane) => {
+                    self.state = state;
+                    self.write_routed(ack).await?;
+                }
+
+                _ = throttle(throttled) => {
+                    self.state = state;
+                    throttled = None;
+                    delayed = true;
+                }
+
+                frame = self.codec.next_timed_frame(), if throttled.is_none() => {
+                    delayed = false;
+                    let frame = match frame {
+                        Ok(None) if !connecting => match self.runtime.half_close_linger {
+                            Some(linger) => {
+                                debug!(id = self.id, ?linger, "half-closed by HAProxy");
+
+                                self.half_close(linger).await?;
+                                break;
                             }
+                            None => Err(Status::Io),
+                        },
+                        Ok(frame) => frame.ok_or(Status::Io),
+                        Err(err) => Err(err),
+                    };
+                    let (frame, timing) = match frame {
+                        Ok(frame) => frame,
+                        Err(err) if connecting => {
+                            debug!(id = self.id, ?err, "handshake failed");
+
+                            let err = err.into();
+                            self.reject(HandshakePhase::Handshake, &err);
+                            return Err(err);
+                        }
+                        Err(Status::Io) => return Err(Status::Io.into()),
+                        Err(err) => {
+                            debug!(id = self.id, %err, "invalid frame");
+
+                            // the stream is out of sync, the frames crossing the DISCONNECT frame can't be drained
+                            self.close(Frame::agent_disconnect(err, err.to_string()), true).await;
+                            return Err(err.into());
+                        }
+                    };
+
+                    if connecting && self.codec.get_mut().get_ref().read > limits.max_bytes {
+                        debug!(id = self.id, read = self.codec.get_mut().get_ref().read, "too many bytes before handshake");
+
+                        let err = Status::TooBig.into();
+                        self.reject(HandshakePhase::Handshake, &err);
+                        return Err(err);
+                    }
+
+                    let peer_closed = matches!(frame, Frame::HaproxyDisconnect(_));
+                    self.seq += 1;
+                    let seq = self.seq;
+                    self.live.received();
+                    self.runtime.load.frame(frame.frame_type());
+
+                    if let Frame::HaproxyNotify(_) = frame {
+                        self.runtime.load.receive_notify(frame.size());
+                    }
+
+                    #[cfg(feature = "debug-protocol")]
+                    self.monitor.observe(&frame);
+
+                    let extra = self.hook.as_mut().and_then(|hook| hook(&frame));
+
+                    let notified = match (&self.history, &frame) {
+                        (Some(_), Frame::HaproxyNotify(notify)) => Some((
+                            seq,
+                            notify.stream_id,
+                            notify.frame_id,
+                            notify.messages.iter().map(|msg| msg.name.clone()).collect(),
+                        )),
+                        _ => None,
+                    };
+
+                    let engine_id = match (&self.runtime.engine_stats, &state, &frame) {
+                        (Some(_), State::Processing(processing), Frame::HaproxyNotify(_)) => {
+                            processing.negotiated.engine_id.clone()
+                        }
+                        _ => None,
+                    };
+
+                    if let State::Processing(processing) = &mut state {
+                        processing.conn = Some(self.ulid);
+                        processing.seq = seq;
+                        processing.timing = Some(timing);
+                    }
+
+                    // the NOTIFY frame counts in the load of the connection until its ACK frame is written
+                    let notify = match (&frame, &self.lane) {
+                        (Frame::HaproxyNotify(_), Some(lane)) => {
+                            lane.received();
+                            true
+                        }
+                        _ => false,
+                    };
+
+                    let started = Instant::now();
+                    // the frame keeps being processed on shutdown, until the drain timeout elapsed
+                    let drain_timeout = self.runtime.drain_timeout.filter(|_| !connecting);
+                    let (res, busy) = select! {
+                        res = busy(state.handle_frame(frame).instrument(debug_span!("frame", seq))) => res,
+                        _ = drained(&self.tok, drain_timeout, &mut draining) => {
+                            debug!(id = self.id, "drain timeout elapsed while processing a frame");
+
+                            self.close(Frame::agent_disconnect(Status::Normal, SHUTDOWN_REASON), false).await;
+                            break;
+                        }
+                    };
+                    let latency = started.elapsed();
+
+                    // only the time spent processing the frame is charged, not the time waiting for the services
+                    if let Some(slice) = self.slice.as_mut() {
+                        slice.consume(busy);
+                    }
+
+                    if let (Some(stats), Some(engine_id)) = (&self.runtime.engine_stats, engine_id) {
+                        let actions = match &res {
+                            Ok((_, Some(Frame::AgentAck(ack)))) => ack.actions.len(),
+                            _ => 0,
+                        };
+
+                        stats.record(&engine_id, actions, res.is_err(), latency);
+                    }
+
+                    if let (Some(history), Some((seq, stream_id, frame_id, messages))) = (&self.history, notified) {
+                        history.record(Record {
+                            seq,
+                            stream_id,
+                            frame_id,
+                            messages,
+                            actions: match &res {
+                                Ok((_, Some(Frame::AgentAck(ack)))) => ack.actions.len(),
+                                _ => 0,
+                            },
+                            latency,
+                            error: res.as_ref().err().map(ToString::to_string),
+                        });
+                    }
+
+                    // the ACK frame waits for a slot in the outgoing queue until it is written
+                    let _outgoing = match res {
+                        Ok((_, Some(Frame::AgentAck(_)))) => outgoing(self.runtime.dispatcher.outgoing.clone()).await?,
+                        _ => None,
+                    };
+
+                    match res {
+                        Ok((next, reply)) => {
+                            let reply = match (reply, &self.lane) {
+                                (Some(Frame::AgentAck(ack)), Some(lane)) => {
+                                    match self.runtime.scheduler.route(&lane.engine_id, self.id, ack) {
+                                        Route::Local(frame) => Some(frame),
+                                        Route::Remote(conn) => {
+                                            trace!(id = self.id, conn, "ACK routed");
+                                            None
+                                        }
+                                        Route::Duplicate => {
+                                            self.runtime.load.dropped(DropReason::DuplicateAck, 1);
+                                            None
+                                        }
+                                    }
+                                }
+                                (reply, _) => reply,
+                            };
+                            self.outbox.extend(reply);
                             self.state = next;
+
+                            if connecting && !matches!(self.state, State::Connecting(_)) {
+                                self.handshaked();
+                            }
+
+                            // the DISCONNECT frame of the hook closes the connection once the reply is written
+                            let disconnect = match extra {
+                                Some(frame) => {
+                                    self.state.validate(&frame)?;
+
+                                    match frame {
+                                        Frame::AgentDisconnect(_) => Some(frame),
+                                        frame => {
+                                            self.outbox.push_back(frame);
+                                            None
+                                        }
+                                    }
+                                }
+                                None => None,
+                            };
+
+                            self.flush().await?;
+                            self.live.handled();
+
+                            if let (true, Some(lane)) = (notify, &self.lane) {
+                                lane.acked();
+                            }
+
+                            if let Some(disconnect) = disconnect {
+                                debug!(id = self.id, "disconnecting by hook");
+
+                                self.close(disconnect, peer_closed).await;
+                                break;
+                            }
                         }
                         Err(err) => {
-                            let frame = Frame::AgentDisconnect(err.into());
-                            self.codec.write_frame(frame).await?;
-                            self.tok.cancel();
+                            if connecting {
+                                debug!(id = self.id, %err, "handshake rejected");
+
+                                self.reject(HandshakePhase::Handshake, &err);
+                            }
+                            if let Some(history) = &self.history {
+                                warn!(id = self.id, %err, history = ?history.records(), "connection closed on error");
+                            }
+
+                            self.close(Frame::AgentDisconnect(err.into()), peer_closed).await;
                             break;
                         }
                     }
@@ -94,4 +675,1513 @@ where
 
         Ok(())
     }
+
+    /// Lift the limits of the half-open connections once the handshake has completed,
+    /// and join the connections of the engine in the `async` mode.
+    fn handshaked(&mut self) {
+        self.half_open = None;
+        let max_frame_size = self
+            .listener
+            .as_ref()
+            .and_then(|listener| listener.max_frame_size)
+            .unwrap_or(self.runtime.max_frame_size);
+        self.codec.framer_mut().set_max_frame_size(max_frame_size);
+
+        if let Some(negotiated) = self.state.negotiated() {
+            // the oversized ACK frames are split in fragments the engine reassembles, or rejected
+            self.codec
+                .framer_mut()
+                .set_max_write_size(Some(negotiated.max_frame_size as usize));
+            self.codec.framer_mut().set_fragmentation(
+                negotiated
+                    .supports_fragmentation()
+                    .then_some(negotiated.max_frame_size as usize),
+            );
+            self.live.handshaked(negotiated);
+            self.runtime.registry.handshaked(self.id);
+
+            if let (Some(stats), Some(engine_id)) =
+                (&self.runtime.engine_stats, negotiated.engine_id.as_deref())
+            {
+                stats.connect(engine_id);
+            }
+
+            if let Some(engine_id) = negotiated
+                .engine_id
+                .as_deref()
+                .filter(|_| negotiated.supports_async())
+            {
+                self.lane = Some(self.runtime.scheduler.join(engine_id, self.id));
+            }
+        }
+    }
+
+    /// Send the AGENT-DISCONNECT frame and close the connection.
+    ///
+    /// Both sides may send their DISCONNECT frame concurrently, so a failure to send ours is ignored,
+    /// and the frames crossing it are drained until HAProxy closes the connection
+    /// or the linger period elapsed.
+    async fn close(&mut self, disconnect: Frame, peer_closed: bool) {
+        self.state = State::Disconnecting;
+        self.outbox.push_back(disconnect);
+
+        if let Err(err) = self.flush().await {
+            debug!(id = self.id, ?err, "failed to send the DISCONNECT frame");
+        } else if !peer_closed {
+            let draining = async {
+                loop {
+                    match self.codec.read_frame().await {
+                        Ok(Frame::HaproxyDisconnect(_)) | Err(_) => break,
+                        Ok(frame) => {
+                            trace!(id = self.id, ty = ?frame.frame_type(), "frame ignored after disconnect");
+
+                            self.runtime.load.dropped(DropReason::AfterDisconnect, 1);
+                        }
+                    }
+                }
+            };
+
+            if timeout(DISCONNECT_LINGER, draining).await.is_err() {
+                debug!(id = self.id, "linger period elapsed");
+            }
+        }
+
+        self.tok.cancel();
+    }
+
+    /// Write the ACK frames still in flight after HAProxy shut down its side of the connection,
+    /// until the linger period elapsed, then shut down ours.
+    ///
+    /// In the `async` mode, the connection leaves the connections of its engine,
+    /// so the ACK frames are no longer routed to it, but the ones already routed are written.
+    async fn half_close(&mut self, linger: Duration) -> Result<()> {
+        match timeout(linger, self.flush_in_flight()).await {
+            Ok(res) => {
+                res?;
+            }
+            Err(_) => debug!(id = self.id, "half-close linger period elapsed"),
+        }
+
+        if let Err(err) = self.codec.get_mut().shutdown().await {
+            debug!(id = self.id, ?err, "failed to shut down the connection");
+        }
+
+        self.tok.cancel();
+
+        Ok(())
+    }
+
+    /// Drain the connection on shutdown, without reading the new NOTIFY frames:
+    /// the ACK frames in flight are written until the deadline,
+    /// then the connection is closed with a `Normal` AGENT-DISCONNECT frame.
+    ///
+    /// The frame being processed when the shutdown started was completed first,
+    /// within the same deadline.
+    async fn drain(&mut self, deadline: Instant) {
+        debug!(id = self.id, remaining = ?deadline.saturating_duration_since(Instant::now()), "draining on shutdown");
+
+        match timeout_at(deadline.into(), self.flush_in_flight()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                debug!(id = self.id, ?err, "failed to drain the connection");
+                return;
+            }
+            Err(_) => debug!(id = self.id, "drain timeout elapsed"),
+        }
+
+        self.close(
+            Frame::agent_disconnect(Status::Normal, SHUTDOWN_REASON),
+            false,
+        )
+        .await;
+    }
+
+    /// Write the queued frames and, in the `async` mode, the ACK frames already routed
+    /// to the connection, once it left the connections of its engine.
+    async fn flush_in_flight(&mut self) -> Result<usize> {
+        if let Some(lane) = &self.lane {
+            self.runtime.scheduler.leave(&lane.engine_id, self.id);
+        }
+
+        let mut written = 0;
+
+        while let Some(ack) = match self.lane.as_mut() {
+            Some(lane) => lane.acks.recv().await,
+            None => None,
+        } {
+            written += self.write_routed(ack).await?;
+        }
+
+        Ok(written + self.flush().await?)
+    }
+
+    /// Returns the NOTIFY frames received on the connection, for a manual control loop.
+    ///
+    /// The handshake and the HAPROXY-DISCONNECT frame are handled by the connection,
+    /// the NOTIFY frames are passed as is to the caller, which replies with [`Frames::reply`]
+    /// instead of the service of the runtime.
+    pub fn frames(&mut self) -> Frames<'_, IO, S, T> {
+        self.runtime.registry.register(self.handle());
+
+        Frames { conn: self }
+    }
+}
+
+/// The NOTIFY frames received on a connection, see [`Connection::frames`].
+#[derive(Debug)]
+pub struct Frames<'a, IO, S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+{
+    conn: &'a mut Connection<IO, S, T>,
+}
+
+impl<IO, S, T> Frames<'_, IO, S, T>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
+{
+    /// Returns the next NOTIFY frame, or `None` once the connection is closed.
+    ///
+    /// With [`half_close_linger`](crate::runtime::Builder::half_close_linger),
+    /// `None` is also returned once HAProxy shut down its side of the connection,
+    /// and the frames received before are still replied.
+    ///
+    /// In the `async` mode, the ACK frames routed to the connection by the other connections
+    /// of its engine are written while waiting for the next frame.
+    ///
+    /// The fragmented frames are not reassembled.
+    pub async fn next(&mut self) -> Option<Result<HaproxyNotify>> {
+        loop {
+            let conn = &mut *self.conn;
+            let connecting = match conn.state {
+                State::Disconnecting => return None,
+                State::Connecting(_) => true,
+                State::Processing(_) => false,
+            };
+
+            let frame = if connecting {
+                match timeout(conn.runtime.handshake.timeout, conn.codec.read_frame()).await {
+                    Ok(frame) => frame,
+                    Err(_) => {
+                        conn.reject(HandshakePhase::Handshake, &Status::Timeout.into());
+                        conn.close(
+                            Frame::agent_disconnect(Status::Timeout, "handshake timed out"),
+                            false,
+                        )
+                        .await;
+
+                        return Some(Err(Status::Timeout.into()));
+                    }
+                }
+            } else {
+                // the ACK frames routed by the other connections of the engine are written while waiting
+                let frame = select! {
+                    Some(ack) = recv_routed(&mut conn.lane) => {
+                        if let Err(err) = conn.write_routed(ack).await {
+                            conn.state = State::Disconnecting;
+
+                            return Some(Err(err));
+                        }
+                        continue;
+                    }
+                    frame = conn.codec.next_timed_frame() => frame,
+                };
+
+                match frame {
+                    Ok(Some((frame, _))) => Ok(frame),
+                    Ok(None) => match conn.runtime.half_close_linger {
+                        Some(linger) => {
+                            debug!(id = conn.id, ?linger, "half-closed by HAProxy");
+
+                            // the ACK frames already routed to the connection are still written
+                            match timeout(linger, conn.flush_in_flight()).await {
+                                Ok(Ok(_)) => {}
+                                Ok(Err(err)) => return Some(Err(err)),
+                                Err(_) => debug!(id = conn.id, "half-close linger period elapsed"),
+                            }
+
+                            return None;
+                        }
+                        None => Err(Status::Io),
+                    },
+                    Err(err) => Err(err),
+                }
+            };
+
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(err) => {
+                    let err = err.into();
+                    if connecting {
+                        conn.reject(HandshakePhase::Handshake, &err);
+                    }
+                    conn.state = State::Disconnecting;
+
+                    return Some(Err(err));
+                }
+            };
+
+            conn.seq += 1;
+            // the frames are handled by the caller, out of reach of the watchdog
+            conn.live.received();
+            conn.live.handled();
+            conn.runtime.load.frame(frame.frame_type());
+
+            #[cfg(feature = "debug-protocol")]
+            conn.monitor.observe(&frame);
+
+            match frame {
+                Frame::HaproxyNotify(_) if !connecting => {
+                    conn.runtime.load.receive_notify(frame.size());
+
+                    if let Frame::HaproxyNotify(notify) = frame {
+                        // the frame counts in the load of the connection until the caller sends its ACK frame
+                        if let (false, Some(lane)) = (notify.fragmented, &conn.lane) {
+                            lane.received();
+                        }
+
+                        return Some(Ok(notify));
+                    }
+                }
+                Frame::HaproxyDisconnect(_) if !connecting => {
+                    conn.close(Frame::agent_disconnect(Status::Normal, "bye"), true)
+                        .await;
+
+                    return None;
+                }
+                frame => {
+                    let state = mem::replace(&mut conn.state, State::Disconnecting);
+
+                    match state.handle_frame(frame).await {
+                        Ok((next, reply)) => {
+                            conn.outbox.extend(reply);
+                            conn.state = next;
+
+                            if connecting && !matches!(conn.state, State::Connecting(_)) {
+                                conn.handshaked();
+                            }
+
+                            if let Err(err) = conn.flush().await {
+                                conn.state = State::Disconnecting;
+
+                                return Some(Err(err));
+                            }
+                        }
+                        Err(err) => {
+                            if connecting {
+                                conn.reject(HandshakePhase::Handshake, &err);
+                            }
+
+                            let status = err.status().unwrap_or(Status::Unknown);
+                            conn.close(Frame::AgentDisconnect(err.into()), false).await;
+
+                            return Some(Err(status.into()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the reply of a NOTIFY frame, usually an ACK frame, validated like [`Connection::send_frame`].
+    pub async fn reply(&mut self, frame: Frame) -> Result<usize> {
+        let acked = matches!(frame, Frame::AgentAck(ref ack) if !ack.fragmented);
+        let _outgoing = match frame {
+            Frame::AgentAck(_) => outgoing(self.conn.runtime.dispatcher.outgoing.clone()).await?,
+            _ => None,
+        };
+        let res = self.conn.send_frame(frame).await;
+
+        if let (true, Some(lane)) = (acked, &self.conn.lane) {
+            lane.acked();
+        }
+
+        res
+    }
+
+    /// Send the ACK frame of a NOTIFY frame, like the service of the runtime would do.
+    ///
+    /// The variable values are checked by the `VarGuard` of the runtime, if any.
+    /// The ACK frame over the negotiated max frame size is split in fragments
+    /// when the fragmentation was negotiated, or rejected before anything is written.
+    /// In the `async` mode, the ACK frame is scheduled on the connections of the engine,
+    /// and the duplicate ACK frames are dropped.
+    ///
+    /// Returns the number of bytes written, including the queued frames written before it.
+    pub async fn send_ack(
+        &mut self,
+        stream_id: StreamId,
+        frame_id: FrameId,
+        actions: Vec<Action>,
+    ) -> Result<usize> {
+        let conn = &mut *self.conn;
+        let Some(negotiated) = conn.state.negotiated() else {
+            return Err(Status::Invalid).context("ACK frame sent before the handshake");
+        };
+        let actions = match conn.runtime.var_guard {
+            Some(ref guard) => guard.apply(actions),
+            None => actions,
+        };
+        let mut acks = split_ack(
+            AgentAck {
+                actions,
+                ..AgentAck::new(stream_id, frame_id)
+            },
+            negotiated.max_frame_size as usize,
+            negotiated.supports_fragmentation(),
+        )?;
+
+        for ack in &acks {
+            conn.state.validate(&Frame::AgentAck(ack.clone()))?;
+        }
+        let _outgoing = outgoing(conn.runtime.dispatcher.outgoing.clone()).await?;
+
+        // the fragments must be written in order on the same connection
+        let frames = match (&conn.lane, acks.len()) {
+            (Some(lane), 1) => {
+                let ack = acks.pop().expect("ACK frame");
+
+                match conn.runtime.scheduler.route(&lane.engine_id, conn.id, ack) {
+                    Route::Local(frame) => vec![frame],
+                    Route::Remote(_) => vec![],
+                    Route::Duplicate => {
+                        conn.runtime.load.dropped(DropReason::DuplicateAck, 1);
+                        vec![]
+                    }
+                }
+            }
+            _ => acks.into_iter().map(Frame::AgentAck).collect(),
+        };
+
+        conn.outbox.extend(frames);
+        let res = match conn.flush().await {
+            Ok(n) => conn.flush_routed().await.map(|m| n + m),
+            Err(err) => Err(err),
+        };
+
+        if let Some(lane) = &conn.lane {
+            lane.acked();
+        }
+
+        res
+    }
+
+    /// Returns the negotiated parameters, once the handshake has completed.
+    pub fn negotiated(&self) -> Option<&Negotiated> {
+        self.conn.negotiated()
+    }
+}
+
+/// Split the ACK frame in fragments fitting the max frame size, if the fragmentation is negotiated.
+fn split_ack(ack: AgentAck, max_frame_size: usize, fragmentation: bool) -> Result<Vec<AgentAck>> {
+    let (stream_id, frame_id) = (ack.stream_id, ack.frame_id);
+    let empty = || AgentAck::new(stream_id, frame_id);
+    let base = Frame::AgentAck(empty()).size();
+
+    if base + ack.actions.iter().map(WireSize::size).sum::<usize>() <= max_frame_size {
+        return Ok(vec![ack]);
+    }
+    if !fragmentation {
+        return Err(Status::TooBig).context("ACK frame exceeds the max frame size");
+    }
+
+    let chunks = split_fragments(ack.actions, base, max_frame_size)
+        .context("action exceeds the max frame size")?;
+    let n = chunks.len();
+
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, actions)| AgentAck {
+            fragmented: i + 1 < n,
+            actions,
+            ..empty()
+        })
+        .collect())
+}
+
+/// Wait for a slot in the outgoing queue of the runtime before writing an ACK frame,
+/// when bounded by [`max_outgoing_acks`](crate::runtime::Builder::max_outgoing_acks).
+///
+/// The slot is released once the ACK frame is written, or routed to another connection
+/// of the engine, where the reorder window bounds it instead.
+async fn outgoing(queue: Option<Arc<Semaphore>>) -> Result<Option<OwnedSemaphorePermit>> {
+    match queue {
+        Some(queue) => {
+            let permit = queue.acquire_owned().await.map_err(|_| Error::Closed)?;
+
+            Ok(Some(permit))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Receive the ACK frames routed by the other connections of the engine, if any.
+async fn recv_routed(lane: &mut Option<AckLane>) -> Option<Frame> {
+    match lane {
+        Some(lane) => lane.acks.recv().await,
+        None => future::pending().await,
+    }
+}
+
+/// Wait for the drain timeout to elapse once the connection is shut down,
+/// recording when the drain started.
+async fn drained(
+    tok: &CancellationToken,
+    drain_timeout: Option<Duration>,
+    draining: &mut Option<Instant>,
+) {
+    match drain_timeout {
+        Some(drain_timeout) => {
+            tok.cancelled().await;
+
+            let started = *draining.get_or_insert_with(Instant::now);
+            sleep_until((started + drain_timeout).into()).await
+        }
+        None => future::pending().await,
+    }
+}
+
+/// Wait for the deadline of the throttled connection, if any.
+async fn throttle(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => sleep_until(deadline.into()).await,
+        None => future::pending().await,
+    }
+}
+
+/// Count the bytes read from the underlying stream.
+#[pin_project]
+#[derive(Debug)]
+struct Metered<IO> {
+    #[pin]
+    io: IO,
+    read: usize,
+}
+
+impl<IO: AsyncRead> AsyncRead for Metered<IO> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let res = this.io.poll_read(cx, buf);
+
+        *this.read += buf.filled().len() - filled;
+
+        res
+    }
+}
+
+impl<IO: AsyncWrite> AsyncWrite for Metered<IO> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().io.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().io.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.project().io.poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<std::io::Result<usize>> {
+        self.project().io.poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{make::Shared, service_fn};
+
+    use crate::{
+        fixture,
+        runtime::{Builder, ConnectionEvent},
+        spop::{Capability, Error::*, HaproxyHello, Scope, Version},
+        testing,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send_ack() {
+        const MAX_FRAME_SIZE: usize = 256;
+
+        let actions = (0..40)
+            .map(|i| Action::set_var(Scope::Transaction, format!("v{i}"), "x".repeat(20)))
+            .collect::<Vec<_>>();
+
+        let err = split_ack(
+            AgentAck {
+                actions: actions.clone(),
+                ..AgentAck::new(1, 1)
+            },
+            MAX_FRAME_SIZE,
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(err.status(), Some(TooBig));
+
+        let runtime = fixture::runtime(Builder::new().fragmentation());
+        let (mut client, mut conn) = testing::pair(runtime);
+
+        let sending = actions.clone();
+        tokio::spawn(async move {
+            let mut frames = conn.frames();
+
+            while let Some(Ok(notify)) = frames.next().await {
+                frames
+                    .send_ack(notify.stream_id, notify.frame_id, sending.clone())
+                    .await
+                    .unwrap();
+            }
+        });
+
+        client
+            .handshake(HaproxyHello {
+                supported_versions: vec![Version::V2_0],
+                max_frame_size: MAX_FRAME_SIZE as u32,
+                capabilities: vec![Capability::Fragmentation],
+                healthcheck: None,
+                engine_id: None,
+            })
+            .await
+            .unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+
+        let mut received = vec![];
+        loop {
+            let frame = client.recv().await.unwrap();
+            assert!(frame.size() <= MAX_FRAME_SIZE);
+
+            match frame {
+                Frame::AgentAck(ack) => {
+                    received.extend(ack.actions);
+
+                    if !ack.fragmented {
+                        break;
+                    }
+                }
+                frame => panic!("unexpected frame: {frame:?}"),
+            }
+        }
+        assert_eq!(received, actions);
+    }
+
+    #[tokio::test]
+    async fn test_route_acks() {
+        use tokio::sync::{mpsc, Semaphore};
+
+        let gate = Arc::new(Semaphore::new(0));
+        let (entered, mut slow) = mpsc::unbounded_channel();
+        let runtime = Builder::new().asynchronous().make_service(
+            Shared::new(service_fn({
+                let gate = gate.clone();
+
+                move |msgs: Vec<Message>| {
+                    let gate = gate.clone();
+                    let entered = entered.clone();
+
+                    async move {
+                        if msgs[0].name == "slow" {
+                            entered.send(()).unwrap();
+                            gate.acquire().await.unwrap().forget();
+                        }
+
+                        Ok::<_, Infallible>(vec![])
+                    }
+                }
+            })),
+            (),
+        );
+        let hello = HaproxyHello {
+            supported_versions: vec![Version::V2_0],
+            max_frame_size: 16384,
+            capabilities: vec![Capability::Async],
+            healthcheck: None,
+            engine_id: Some("engine".to_string()),
+        };
+
+        let mut clients = vec![];
+        for _ in 0..2 {
+            let (mut client, mut conn) = testing::pair(runtime.clone());
+            tokio::spawn(async move { conn.serve().await });
+
+            client.handshake(hello.clone()).await.unwrap();
+            clients.push(client);
+        }
+        let [mut first, mut second] = clients.try_into().ok().unwrap();
+        let check = || [Message::new("check", [("n", 1)])];
+        let acked = |frame: Frame, stream_id| match frame {
+            Frame::AgentAck(ack) => assert_eq!(ack.stream_id, stream_id),
+            frame => panic!("unexpected frame: {frame:?}"),
+        };
+
+        // the second connection is idle, the ACK frame is written on it
+        first.send(Frame::notify(1, 1, check())).await.unwrap();
+        acked(second.recv().await.unwrap(), 1);
+        assert_eq!(runtime.scheduler.stats().rerouted, 1);
+
+        // the second connection is processing a NOTIFY frame, as loaded as the first one
+        second
+            .send(Frame::notify(2, 1, [Message::new("slow", [("n", 1)])]))
+            .await
+            .unwrap();
+        slow.recv().await.unwrap();
+        first.send(Frame::notify(3, 1, check())).await.unwrap();
+        acked(first.recv().await.unwrap(), 3);
+        assert_eq!(runtime.scheduler.stats().rerouted, 1);
+
+        // the slow frame is done, and acknowledged on the idle first connection
+        gate.add_permits(1);
+        acked(first.recv().await.unwrap(), 2);
+        assert_eq!(runtime.scheduler.stats().rerouted, 2);
+        assert_eq!(runtime.load().acks_rerouted, 2);
+    }
+
+    #[tokio::test]
+    async fn test_route_acks_to_frames() {
+        let runtime = fixture::runtime(Builder::new().asynchronous());
+        let hello = HaproxyHello {
+            supported_versions: vec![Version::V2_0],
+            max_frame_size: 16384,
+            capabilities: vec![Capability::Async],
+            healthcheck: None,
+            engine_id: Some("engine".to_string()),
+        };
+
+        let (mut served, mut conn) = testing::pair(runtime.clone());
+        tokio::spawn(async move { conn.serve().await });
+        served.handshake(hello.clone()).await.unwrap();
+
+        // the manual control loop is waiting for the next frame
+        let (mut manual, mut conn) = testing::pair(runtime.clone());
+        tokio::spawn(async move {
+            let mut frames = conn.frames();
+
+            while let Some(Ok(notify)) = frames.next().await {
+                frames
+                    .send_ack(notify.stream_id, notify.frame_id, vec![])
+                    .await
+                    .unwrap();
+            }
+        });
+        manual.handshake(hello).await.unwrap();
+        assert_eq!(runtime.scheduler.connections("engine"), 2);
+
+        // the idle manual connection writes the ACK frame of the served one
+        served
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+        match manual.recv().await.unwrap() {
+            Frame::AgentAck(ack) => assert_eq!(ack.stream_id, 1),
+            frame => panic!("unexpected frame: {frame:?}"),
+        }
+        assert_eq!(runtime.scheduler.stats().rerouted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_outgoing_acks() {
+        let runtime = fixture::runtime(Builder::new().max_outgoing_acks(1));
+        let outgoing = runtime.dispatcher.outgoing.clone().unwrap();
+        let (mut client, mut conn) = testing::pair(runtime);
+        tokio::spawn(async move { conn.serve().await });
+        client.hello().await.unwrap();
+
+        // the outgoing queue is full, the ACK frame is held back
+        let full = outgoing.clone().acquire_owned().await.unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+        assert!(timeout(Duration::from_millis(50), client.recv())
+            .await
+            .is_err());
+
+        drop(full);
+        match client.recv().await.unwrap() {
+            Frame::AgentAck(ack) => assert_eq!(ack.stream_id, 1),
+            frame => panic!("unexpected frame: {frame:?}"),
+        }
+
+        // the slot is released once the ACK frame is written
+        let ack = client
+            .notify(2, 1, [Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+        assert_eq!(ack.stream_id, 2);
+        assert_eq!(outgoing.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hook_after_reply() {
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+            })),
+            (),
+        );
+        let (mut client, conn) = testing::pair(runtime);
+        // the hook is only called through `&mut`, its state doesn't need to be `Sync`
+        let seen = std::cell::Cell::new(0);
+        let mut conn = conn.with_hook(move |frame| {
+            seen.set(seen.get() + 1);
+
+            match frame {
+                Frame::HaproxyNotify(_) => Some(Frame::agent_disconnect(
+                    Status::Normal,
+                    format!("bye after {} frames", seen.get()),
+                )),
+                _ => None,
+            }
+        });
+
+        let serving = tokio::spawn(async move {
+            let res = conn.serve().await;
+
+            (conn, res)
+        });
+
+        client.hello().await.unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+
+        // the frame of the hook is written after the ACK frame, never before it
+        assert!(matches!(client.recv().await.unwrap(), Frame::AgentAck(_)));
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(_)
+        ));
+
+        // then the connection is closed
+        client
+            .send(Frame::haproxy_disconnect(Normal, "bye"))
+            .await
+            .unwrap();
+
+        let (mut conn, res) = serving.await.unwrap();
+        res.unwrap();
+        assert!(matches!(
+            conn.send_frame(Frame::AgentAck(AgentAck::new(2, 1))).await,
+            Err(crate::Error::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let runtime = fixture::runtime(Builder::new());
+        let (mut client, mut conn) = testing::pair(runtime);
+
+        let serving = tokio::spawn(async move {
+            let mut frames = conn.frames();
+            let notify = frames.next().await.unwrap()?;
+            frames
+                .send_ack(notify.stream_id, notify.frame_id, vec![])
+                .await?;
+
+            conn.disconnect(Normal, "bye").await?;
+
+            // no frame is processed after the DISCONNECT frame
+            assert!(conn.frames().next().await.is_none());
+            conn.serve().await?;
+            assert!(matches!(
+                conn.send_frame(Frame::AgentAck(AgentAck::new(2, 1))).await,
+                Err(crate::Error::Closed)
+            ));
+
+            Ok::<_, crate::Error>(())
+        });
+
+        client.hello().await.unwrap();
+        client
+            .notify(1, 1, [Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(_)
+        ));
+
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_send_frame() {
+        let runtime = fixture::runtime(Builder::new());
+        let (mut client, mut conn) = testing::pair(runtime);
+
+        // no ACK frame before the handshake
+        let err = conn
+            .send_frame(Frame::AgentAck(AgentAck::new(1, 1)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(Invalid));
+
+        let serving = tokio::spawn(async move {
+            let mut frames = conn.frames();
+            let notify = frames.next().await.unwrap()?;
+
+            // the fragmentation was not negotiated
+            let fragmented = Frame::AgentAck(AgentAck {
+                fragmented: true,
+                ..AgentAck::new(notify.stream_id, notify.frame_id)
+            });
+            let err = frames.reply(fragmented).await.unwrap_err();
+            assert_eq!(err.status(), Some(FragmentNotSupported));
+
+            let err = frames.reply(Frame::Unset).await.unwrap_err();
+            assert_eq!(err.status(), Some(FragmentNotSupported));
+
+            frames
+                .reply(Frame::AgentAck(AgentAck::new(
+                    notify.stream_id,
+                    notify.frame_id,
+                )))
+                .await?;
+            frames.reply(Frame::agent_disconnect(Normal, "bye")).await?;
+
+            // no frame after the DISCONNECT frame
+            assert!(matches!(
+                frames.reply(Frame::AgentAck(AgentAck::new(2, 1))).await,
+                Err(crate::Error::Closed)
+            ));
+            assert!(frames.next().await.is_none());
+
+            Ok::<_, crate::Error>(())
+        });
+
+        client.hello().await.unwrap();
+        let ack = client
+            .notify(1, 1, [Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+        assert_eq!(ack.stream_id, 1);
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(_)
+        ));
+
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_peer_disconnect_and_close() {
+        let runtime = fixture::runtime(Builder::new());
+        let (mut client, mut conn) = testing::pair(runtime);
+
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .send(Frame::haproxy_disconnect(Normal, "bye"))
+            .await
+            .unwrap();
+        drop(client);
+
+        serving.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_continue_on_error() {
+        use crate::{runtime::ERROR_VAR, spop::Disconnect};
+
+        let runtime = |builder: Builder| {
+            builder.make_service(
+                Shared::new(service_fn(|msgs: Vec<Message>| async move {
+                    match msgs[0].name.as_str() {
+                        "fail" => Err(BoxError::from("unavailable")),
+                        "ban" => Err(Disconnect::new(Unknown, "banned").into()),
+                        _ => Ok(vec![Action::set_var(Scope::Transaction, "score", 10)]),
+                    }
+                })),
+                (),
+            )
+        };
+        let notify = |frame_id, name| Frame::notify(1, frame_id, [Message::new(name, [("n", 1)])]);
+
+        let (mut client, mut conn) = testing::pair(runtime(Builder::new().continue_on_error()));
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client.send(notify(1, "fail")).await.unwrap();
+        match client.recv().await.unwrap() {
+            Frame::AgentAck(ack) => assert_eq!(
+                ack.actions,
+                vec![Action::set_var(
+                    Scope::Transaction,
+                    ERROR_VAR,
+                    u32::from(Unknown)
+                )]
+            ),
+            frame => panic!("unexpected frame: {frame:?}"),
+        }
+
+        // the connection goes on after the failed frame
+        client.send(notify(2, "check")).await.unwrap();
+        assert!(
+            matches!(client.recv().await.unwrap(), Frame::AgentAck(ack) if ack.actions.len() == 1)
+        );
+
+        // the services may still close the connection
+        client.send(notify(3, "ban")).await.unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(d) if d.message == "banned"
+        ));
+        drop(client);
+        serving.await.unwrap().unwrap();
+
+        // the connection is closed on error by default
+        let (mut client, mut conn) = testing::pair(runtime(Builder::new()));
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client.send(notify(1, "fail")).await.unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        use std::io;
+        use std::sync::atomic::{AtomicUsize, Ordering::SeqCst};
+
+        use crate::runtime::{BreakerPolicy, BreakerState, CircuitBreaker, DEGRADED_VAR};
+
+        // the `MakeService` fails its first call
+        let breaking = |policy| {
+            let calls = Arc::new(AtomicUsize::new(0));
+            let make = {
+                let calls = calls.clone();
+
+                service_fn(move |_: ()| {
+                    let failed = calls.fetch_add(1, SeqCst) == 0;
+
+                    async move {
+                        if failed {
+                            return Err(io::Error::other("unavailable"));
+                        }
+
+                        Ok(service_fn(|_: Vec<Message>| async {
+                            Ok::<_, Infallible>(vec![Action::set_var(
+                                Scope::Transaction,
+                                "score",
+                                1,
+                            )])
+                        }))
+                    }
+                })
+            };
+            let breaker = CircuitBreaker::new()
+                .threshold(1)
+                .cooldown(Duration::from_millis(50))
+                .policy(policy);
+
+            (
+                Builder::new()
+                    .circuit_breaker(breaker)
+                    .make_service(make, ()),
+                calls,
+            )
+        };
+        let notify = |frame_id| Frame::notify(1, frame_id, [Message::new("check", [("n", 1)])]);
+
+        let (runtime, calls) = breaking(BreakerPolicy::FailClosed);
+        for _ in 0..2 {
+            let (mut client, mut conn) = testing::pair(runtime.clone());
+            tokio::spawn(async move { conn.serve().await });
+
+            assert!(client.hello().await.is_err());
+        }
+        // the open breaker rejected the second handshake without calling the `MakeService`
+        assert_eq!(calls.load(SeqCst), 1);
+        assert_eq!(
+            runtime.circuit_breaker.as_ref().unwrap().state(),
+            BreakerState::Open
+        );
+
+        sleep(Duration::from_millis(60)).await;
+
+        let (mut client, mut conn) = testing::pair(runtime.clone());
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        assert_eq!(calls.load(SeqCst), 2);
+        assert_eq!(
+            runtime.circuit_breaker.as_ref().unwrap().state(),
+            BreakerState::Closed
+        );
+
+        // the connections are served without a service while the breaker fails open
+        let (runtime, _) = breaking(BreakerPolicy::FailOpen);
+        let (mut client, mut conn) = testing::pair(runtime);
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client.send(notify(1)).await.unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentAck(ack) if ack.actions == vec![Action::set_var(Scope::Transaction, DEGRADED_VAR, true)]
+        ));
+
+        sleep(Duration::from_millis(60)).await;
+
+        client.send(notify(2)).await.unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentAck(ack) if ack.actions == vec![Action::set_var(Scope::Transaction, "score", 1)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_half_close() {
+        let runtime = |builder: Builder| {
+            builder.make_service(
+                Shared::new(service_fn(|_: Vec<Message>| async {
+                    sleep(Duration::from_millis(20)).await;
+
+                    Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+                })),
+                (),
+            )
+        };
+
+        let (mut client, mut conn) = testing::pair(runtime(
+            Builder::new().half_close_linger(Duration::from_millis(100)),
+        ));
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+
+        // the ACK frame in flight is written before the connection is closed
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentAck(ack) if ack.actions.len() == 1
+        ));
+        assert!(client.recv().await.is_err());
+        serving.await.unwrap().unwrap();
+
+        // the end of the stream fails the connection by default
+        let (mut client, mut conn) = testing::pair(runtime(Builder::new()));
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client.shutdown().await.unwrap();
+        assert!(serving.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sampling() {
+        use crate::runtime::{RuntimeHandle, SAMPLED_VAR};
+
+        let runtime = Builder::new()
+            .sample_rate("analytics", 0.0)
+            .sampled_var()
+            .make_service(
+                Shared::new(service_fn(|msgs: Vec<Message>| async move {
+                    Ok::<_, Infallible>(vec![Action::set_var(
+                        Scope::Transaction,
+                        "processed",
+                        msgs.len() as u32,
+                    )])
+                })),
+                (),
+            );
+        let handle = RuntimeHandle::new(runtime.clone());
+        let (mut client, mut conn) = testing::pair(runtime);
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("analytics", [("n", 1)])]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentAck(ack) if ack.actions == vec![Action::set_var(Scope::Transaction, SAMPLED_VAR, false)]
+        ));
+
+        // only the sampled messages are processed
+        client
+            .send(Frame::notify(
+                1,
+                2,
+                [
+                    Message::new("check", [("n", 1)]),
+                    Message::new("analytics", [("n", 1)]),
+                ],
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentAck(ack) if ack.actions == vec![Action::set_var(Scope::Transaction, "processed", 1u32)]
+        ));
+
+        // the rates are adjusted while serving
+        handle.set_sample_rate("analytics", 1.0);
+        assert!(handle.sample_rates().is_empty());
+
+        client
+            .send(Frame::notify(1, 3, [Message::new("analytics", [("n", 1)])]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentAck(ack) if ack.actions == vec![Action::set_var(Scope::Transaction, "processed", 1u32)]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_manual_frames() {
+        let runtime = fixture::runtime(Builder::new());
+        let (mut client, mut conn) = testing::pair(runtime);
+
+        let serving = tokio::spawn(async move {
+            let mut frames = conn.frames();
+            let mut n = 0;
+
+            while let Some(notify) = frames.next().await {
+                let notify = notify?;
+                assert!(frames.negotiated().is_some());
+
+                n += notify.messages.len();
+                frames
+                    .reply(Frame::ack(
+                        notify.stream_id,
+                        notify.frame_id,
+                        [Action::set_var(Scope::Transaction, "n", n as i32)],
+                    ))
+                    .await?;
+            }
+
+            Ok::<_, crate::Error>(n)
+        });
+
+        client.hello().await.unwrap();
+        for id in 1..=2 {
+            let ack = client
+                .notify(id, 1, [Message::new("check", [("n", 1)])])
+                .await
+                .unwrap();
+            assert_eq!(
+                ack.actions,
+                [Action::set_var(Scope::Transaction, "n", id as i32)]
+            );
+        }
+        client
+            .send(Frame::haproxy_disconnect(Normal, "bye"))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(_)
+        ));
+        assert_eq!(serving.await.unwrap().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_crossing() {
+        let runtime = fixture::runtime(Builder::new());
+        let (mut client, mut conn) = testing::pair(runtime);
+        let handle = conn.handle();
+
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        handle.disconnect(Normal, "restarting").unwrap();
+        sleep(Duration::from_millis(10)).await;
+
+        // the NOTIFY frame crosses the AGENT-DISCONNECT frame
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+
+        match client.recv().await.unwrap() {
+            Frame::AgentDisconnect(disconnect) => assert_eq!(disconnect.message, "restarting"),
+            frame => panic!("unexpected {frame:?}"),
+        }
+
+        let started = Instant::now();
+        client
+            .send(Frame::haproxy_disconnect(Normal, "bye"))
+            .await
+            .unwrap();
+
+        serving.await.unwrap().unwrap();
+        assert!(started.elapsed() < DISCONNECT_LINGER);
+        assert!(client.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_time_slice() {
+        let runtime = Builder::new()
+            .time_slice(Duration::from_millis(10), Duration::from_secs(60))
+            .haproxy_timeout(Duration::from_millis(200))
+            .make_service(
+                Shared::new(service_fn(|msgs: Vec<Message>| async move {
+                    match msgs[0].name.as_str() {
+                        "busy" => std::thread::sleep(Duration::from_millis(20)),
+                        _ => sleep(Duration::from_millis(50)).await,
+                    }
+
+                    Ok::<_, Infallible>(vec![])
+                })),
+                (),
+            );
+        let (mut client, mut conn) = testing::pair(runtime.clone());
+        let handle = conn.handle();
+
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+
+        // the time waiting for the service is not charged
+        let ack = client
+            .notify(1, 1, [Message::new("idle", [("n", 1)])])
+            .await
+            .unwrap();
+        assert_eq!(ack.stream_id, 1);
+        assert_eq!(runtime.load().throttled, 0);
+
+        let ack = client
+            .notify(2, 1, [Message::new("busy", [("n", 1)])])
+            .await
+            .unwrap();
+        assert_eq!(ack.stream_id, 2);
+
+        // the next frame is delayed at most half the `timeout processing`
+        let started = Instant::now();
+        let ack = client
+            .notify(3, 1, [Message::new("idle", [("n", 1)])])
+            .await
+            .unwrap();
+        assert_eq!(ack.stream_id, 3);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        // the delayed connection still handles its commands
+        client
+            .send(Frame::notify(4, 1, [Message::new("idle", [("n", 1)])]))
+            .await
+            .unwrap();
+        sleep(Duration::from_millis(10)).await;
+        handle.disconnect(Status::Normal, "stop").unwrap();
+
+        match client.recv().await.unwrap() {
+            Frame::AgentDisconnect(disconnect) => assert_eq!(disconnect.message, "stop"),
+            frame => panic!("unexpected {frame:?}"),
+        }
+        serving.await.unwrap().unwrap();
+        assert_eq!(runtime.load().throttled, 2);
+        assert_eq!(runtime.load().throttled_time, Duration::from_millis(200));
+    }
+
+    #[tokio::test]
+    async fn test_fairness() {
+        use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+
+        const FRAMES: u64 = 50;
+
+        let runtime = Builder::new()
+            .time_slice(Duration::from_millis(10), Duration::from_millis(100))
+            .make_service(
+                Shared::new(service_fn(|_: Vec<Message>| async {
+                    std::thread::sleep(Duration::from_millis(2));
+
+                    Ok::<_, Infallible>(vec![])
+                })),
+                (),
+            );
+        let (mut chatty, mut conn) = testing::pair(runtime.clone());
+        tokio::spawn(async move { conn.serve().await });
+        let (mut quiet, mut conn) = testing::pair(runtime.clone());
+        tokio::spawn(async move { conn.serve().await });
+
+        chatty.hello().await.unwrap();
+        quiet.hello().await.unwrap();
+
+        // the chatty connection pipelines its frames, hogging the single-threaded runtime
+        for id in 1..=FRAMES {
+            chatty
+                .send(Frame::notify(id, 1, [Message::new("check", [("n", 1)])]))
+                .await
+                .unwrap();
+        }
+        let acked = Arc::new(AtomicUsize::new(0));
+        let acking = tokio::spawn({
+            let acked = acked.clone();
+
+            async move {
+                for _ in 1..=FRAMES {
+                    assert!(matches!(chatty.recv().await.unwrap(), Frame::AgentAck(_)));
+                    acked.fetch_add(1, Relaxed);
+                }
+            }
+        });
+
+        // the quiet connection is served once the chatty one used up its time slice
+        let ack = quiet
+            .notify(1, 1, [Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+        assert_eq!(ack.stream_id, 1);
+        assert!(acked.load(Relaxed) < FRAMES as usize);
+
+        acking.await.unwrap();
+        assert!(runtime.load().throttled > 0);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let runtime = fixture::runtime(Builder::new().handshake_timeout(Duration::from_millis(10)));
+        let (mut client, mut conn) = testing::pair(runtime.clone());
+
+        assert!(matches!(
+            conn.serve().await,
+            Err(crate::Error::Status(Timeout))
+        ));
+        match client.recv().await.unwrap() {
+            Frame::AgentDisconnect(disconnect) => {
+                assert_eq!(disconnect.status_code, Timeout as u32)
+            }
+            frame => panic!("unexpected {frame:?}"),
+        }
+        assert_eq!(runtime.load().handshake_rejections, 1);
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let runtime = Builder::new()
+            .max_frame_size(1024)
+            .write_timeout(Duration::from_millis(50))
+            .make_service(
+                Shared::new(service_fn(|_: Vec<Message>| async {
+                    Ok::<_, Infallible>(vec![Action::set_var(
+                        Scope::Transaction,
+                        "padding",
+                        "x".repeat(512),
+                    )])
+                })),
+                (),
+            );
+        let (mut client, mut conn) = testing::pair(runtime.clone());
+
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+
+        // the peer stops reading its socket, the ACK frames fill the buffer
+        for id in 1..=16 {
+            client
+                .send(Frame::notify(id, 1, [Message::new("check", [("n", 1)])]))
+                .await
+                .unwrap();
+        }
+
+        assert!(matches!(
+            timeout(Duration::from_secs(5), serving)
+                .await
+                .unwrap()
+                .unwrap(),
+            Err(crate::Error::Status(Timeout))
+        ));
+        assert_eq!(runtime.load().write_timeouts, 1);
+    }
+
+    #[tokio::test]
+    async fn test_oversized_hello() {
+        let runtime = fixture::runtime(Builder::new().max_hello_size(16));
+        let (mut client, mut conn) = testing::pair(runtime.clone());
+        let mut events = runtime.registry.subscribe();
+
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        assert!(client.hello().await.is_err());
+        assert!(matches!(
+            serving.await.unwrap(),
+            Err(crate::Error::Status(TooBig))
+        ));
+        assert_eq!(runtime.load().handshake_rejections, 1);
+
+        assert!(matches!(
+            events.recv().await,
+            Ok(ConnectionEvent::Connected(_))
+        ));
+        match events.recv().await {
+            Ok(ConnectionEvent::Rejected(failure)) => {
+                assert_eq!(failure.phase, HandshakePhase::Handshake);
+                assert_eq!(failure.kind, crate::ErrorKind::Protocol);
+            }
+            event => panic!("unexpected {event:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_half_open_per_ip() {
+        let runtime = fixture::runtime(Builder::new().max_half_open_per_ip(1));
+        let peer: SocketAddr = "10.0.0.1:4321".parse().unwrap();
+        let info = IoInfo {
+            peer_addr: Some(peer),
+            ..Default::default()
+        };
+
+        let (mut client, conn) = testing::pair(runtime.clone());
+        let mut conn = conn.with_io_info(info);
+        let serving = tokio::spawn(async move { conn.serve().await });
+        sleep(Duration::from_millis(10)).await;
+        assert_eq!(runtime.half_open.count(peer.ip()), 1);
+
+        let (_, conn) = testing::pair(runtime.clone());
+        assert!(matches!(
+            conn.with_io_info(info).serve().await,
+            Err(crate::Error::Status(ResourceAllocErr))
+        ));
+        assert_eq!(runtime.load().handshake_rejections, 1);
+
+        client.hello().await.unwrap();
+        assert_eq!(runtime.half_open.count(peer.ip()), 0);
+
+        drop(client);
+        let _ = serving.await.unwrap();
+    }
 }
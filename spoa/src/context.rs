@@ -0,0 +1,188 @@
+//! The context of the NOTIFY frame being processed.
+//!
+//! HAProxy doesn't tell the agent which `spoe-group` or event triggered the messages,
+//! the names can be passed through well-known arguments instead:
+//!
+//! ```text
+//! [spoe]
+//! spoe-message check-client-ip
+//!     args spoe_event=str(on-client-session) ip=src
+//!     event on-client-session
+//!
+//! spoe-message check-request
+//!     args spoe_group=str(request-checks) path=path
+//!
+//! spoe-group request-checks
+//!     messages check-request
+//! ```
+//!
+//! When enabled with `Builder::spoe_events`, those arguments are removed from the messages
+//! and exposed by [`NotifyContext::current`], so routing can be done by event type
+//! rather than message name alone.
+//!
+//! The context also carries the stable identifiers of the connection and the frame,
+//! logged in the tracing spans and recorded in the history, to correlate a request
+//! across the observability outputs.
+//!
+//! The context also carries the [`FrameTiming`] of the frame, so services can account
+//! the queueing before the processing and shed the work HAProxy already gave up on.
+//! Its deadline is split across the backends called by the services with a
+//! [`Budget`](crate::budget::Budget).
+//!
+//! The [`StreamLogger`] of the context tags the log records with the stream of the frame.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use ulid::Ulid;
+
+use crate::{
+    logger::StreamLogger,
+    spop::{FrameId, FrameTiming, Message, StreamId, Typed},
+};
+
+tokio::task_local! {
+    static CONTEXT: NotifyContext;
+}
+
+/// The context of the NOTIFY frame being processed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NotifyContext {
+    /// The stable identifier of the connection.
+    pub conn: Option<Ulid>,
+    /// The sequence number of the frame in the connection.
+    pub seq: u64,
+    /// The stream identifier.
+    pub stream_id: StreamId,
+    /// The frame identifier inside the stream.
+    pub frame_id: FrameId,
+    /// The uniq string that identify the SPOE engine.
+    pub engine_id: Option<String>,
+    /// The name of the `spoe-group` which triggered the messages.
+    pub group: Option<String>,
+    /// The name of the event which triggered the messages.
+    pub event: Option<String>,
+    /// When the frame was received and decoded.
+    pub timing: Option<FrameTiming>,
+    /// When the ACK frame must be sent, before HAProxy or the runtime give up on the frame.
+    pub deadline: Option<Instant>,
+    /// Log the records tagged with the stream, buffered with `Builder::tail_logging`.
+    pub logger: StreamLogger,
+}
+
+impl NotifyContext {
+    /// The argument carrying the name of the `spoe-group`.
+    pub const GROUP_ARG: &str = "spoe_group";
+    /// The argument carrying the name of the event.
+    pub const EVENT_ARG: &str = "spoe_event";
+
+    pub fn new(stream_id: StreamId, frame_id: FrameId, engine_id: Option<String>) -> Self {
+        NotifyContext {
+            stream_id,
+            frame_id,
+            engine_id,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the context of the NOTIFY frame being processed by the current task.
+    pub fn current() -> Option<NotifyContext> {
+        CONTEXT.try_with(Clone::clone).ok()
+    }
+
+    /// Returns the time elapsed since the first byte of the frame arrived.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.timing.map(|timing| timing.received_at.elapsed())
+    }
+
+    /// Returns the time left before the deadline, measured from the arrival of the frame.
+    pub fn remaining(&self, deadline: Duration) -> Option<Duration> {
+        self.elapsed()
+            .map(|elapsed| deadline.saturating_sub(elapsed))
+    }
+
+    /// Remove the well-known group and event arguments from the messages.
+    pub fn extract_events(&mut self, msgs: &mut [Message]) {
+        for msg in msgs {
+            msg.args.retain_mut(|(name, value)| {
+                let slot = match name.as_str() {
+                    Self::GROUP_ARG => &mut self.group,
+                    Self::EVENT_ARG => &mut self.event,
+                    _ => return true,
+                };
+
+                if let Typed::String(s) = value {
+                    slot.get_or_insert_with(|| std::mem::take(s));
+                }
+
+                false
+            });
+        }
+    }
+
+    /// Call the closure and poll the returned future with the context set.
+    pub(crate) async fn scope<F, Fut>(self, f: F) -> Fut::Output
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future,
+    {
+        let fut = CONTEXT.sync_scope(self.clone(), f);
+
+        CONTEXT.scope(self, fut).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{make::Shared, service_fn};
+
+    use crate::{
+        runtime::Builder,
+        spop::{Action, Scope},
+        testing,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_frame_stamps() {
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|_: Vec<Message>| async {
+                let cx = NotifyContext::current().unwrap();
+                let timing = cx.timing.unwrap();
+
+                assert!(cx.conn.is_some());
+                assert!(cx.deadline.unwrap() > timing.received_at);
+
+                Ok::<_, Infallible>(vec![
+                    Action::set_var(
+                        Scope::Transaction,
+                        "queued",
+                        cx.elapsed().unwrap() >= timing.decode_time(),
+                    ),
+                    Action::set_var(Scope::Transaction, "seq", cx.seq),
+                ])
+            })),
+            (),
+        );
+        let (mut client, mut conn) = testing::pair(runtime);
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        let ack = client
+            .notify(1, 1, [Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ack.actions,
+            vec![
+                Action::set_var(Scope::Transaction, "queued", true),
+                // the HAPROXY-HELLO frame is the first one
+                Action::set_var(Scope::Transaction, "seq", 2u64),
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Append the default actions to every ACK frame.
+//!
+//! The ambient actions, e.g. always setting `txn.spoa_version`, are registered once
+//! and appended to the actions returned by the services:
+//!
+//! - a default action is dropped when the service returned an action on the same variable;
+//! - the frames with a message opted out, e.g. the health checks, are left untouched.
+//!
+//! ```
+//! # use tower::{service_fn, ServiceBuilder};
+//! # use haproxy_spoa::{defaults::DefaultActions, spop::{Action, Message, Scope}};
+//! let defaults = DefaultActions::new()
+//!     .action(Action::set_var(Scope::Transaction, "spoa_version", env!("CARGO_PKG_VERSION")))
+//!     .opt_out("healthcheck");
+//!
+//! let svc = ServiceBuilder::new()
+//!     .layer(defaults)
+//!     .service(service_fn(|_: Vec<Message>| async { Ok::<_, ()>(Vec::<Action>::new()) }));
+//! ```
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use pin_project::pin_project;
+use tower::{Layer, Service};
+
+use crate::spop::{Action, Message};
+
+/// The actions appended to every ACK frame, unless overridden by the service.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DefaultActions {
+    actions: Vec<Action>,
+    opt_outs: HashSet<String>,
+}
+
+impl DefaultActions {
+    pub fn new() -> Self {
+        DefaultActions::default()
+    }
+
+    /// Append the action to every ACK frame.
+    pub fn action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    /// Don't append the default actions to the frames with the message.
+    pub fn opt_out<S: Into<String>>(mut self, name: S) -> Self {
+        self.opt_outs.insert(name.into());
+        self
+    }
+
+    /// Returns `true` if the default actions apply to the messages.
+    pub fn applies_to<'a, I>(&self, names: I) -> bool
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        !self.actions.is_empty() && names.into_iter().all(|name| !self.opt_outs.contains(name))
+    }
+
+    /// Append the default actions not overridden by the actions of the service.
+    pub fn apply(&self, mut actions: Vec<Action>) -> Vec<Action> {
+        let overridden = actions
+            .iter()
+            .map(|action| (action.scope(), action.name().to_string()))
+            .collect::<HashSet<_>>();
+
+        actions.extend(
+            self.actions
+                .iter()
+                .filter(|action| !overridden.contains(&(action.scope(), action.name().to_string())))
+                .cloned(),
+        );
+
+        actions
+    }
+}
+
+impl<S> Layer<S> for DefaultActions {
+    type Service = WithDefaults<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        WithDefaults {
+            inner,
+            defaults: Arc::new(self.clone()),
+        }
+    }
+}
+
+/// A service appending the default actions to the actions returned by the inner service.
+#[derive(Clone, Debug)]
+pub struct WithDefaults<S> {
+    inner: S,
+    defaults: Arc<DefaultActions>,
+}
+
+impl<S> Service<Vec<Message>> for WithDefaults<S>
+where
+    S: Service<Vec<Message>, Response = Vec<Action>>,
+{
+    type Response = Vec<Action>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, msgs: Vec<Message>) -> Self::Future {
+        let defaults = self
+            .defaults
+            .applies_to(msgs.iter().map(|msg| msg.name.as_str()))
+            .then(|| self.defaults.clone());
+
+        ResponseFuture {
+            inner: self.inner.call(msgs),
+            defaults,
+        }
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    defaults: Option<Arc<DefaultActions>>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Vec<Action>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        this.inner
+            .poll(cx)
+            .map_ok(|actions| match this.defaults.take() {
+                Some(defaults) => defaults.apply(actions),
+                None => actions,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::{service_fn, ServiceBuilder, ServiceExt as _};
+
+    use crate::spop::Scope;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_default_actions() {
+        let svc = ServiceBuilder::new()
+            .layer(
+                DefaultActions::new()
+                    .action(Action::set_var(Scope::Transaction, "version", "1.0"))
+                    .action(Action::set_var(Scope::Transaction, "score", 0))
+                    .opt_out("healthcheck"),
+            )
+            .service(service_fn(|msgs: Vec<Message>| async move {
+                Ok::<_, ()>(
+                    msgs.iter()
+                        .filter(|msg| msg.name == "check")
+                        .map(|_| Action::set_var(Scope::Transaction, "score", 10))
+                        .collect::<Vec<_>>(),
+                )
+            }));
+
+        assert_eq!(
+            svc.clone()
+                .oneshot(vec![Message::new("log", [("i", 1)])])
+                .await,
+            Ok(vec![
+                Action::set_var(Scope::Transaction, "version", "1.0"),
+                Action::set_var(Scope::Transaction, "score", 0),
+            ])
+        );
+
+        // the actions of the service override the defaults
+        assert_eq!(
+            svc.clone()
+                .oneshot(vec![Message::new("check", [("i", 1)])])
+                .await,
+            Ok(vec![
+                Action::set_var(Scope::Transaction, "score", 10),
+                Action::set_var(Scope::Transaction, "version", "1.0"),
+            ])
+        );
+
+        assert_eq!(
+            svc.oneshot(vec![
+                Message::new("check", [("i", 1)]),
+                Message::new("healthcheck", [("i", 1)]),
+            ])
+            .await,
+            Ok(vec![Action::set_var(Scope::Transaction, "score", 10)])
+        );
+    }
+}
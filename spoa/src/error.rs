@@ -6,6 +6,7 @@ use std::{
 };
 
 use thiserror::Error;
+use tower::BoxError;
 
 use crate::{
     runtime::Acker,
@@ -14,6 +15,16 @@ use crate::{
 
 pub type Result<T> = StdResult<T, Error>;
 
+/// The frame couldn't be sent to the processors.
+pub type ProcessingError =
+    tokio::sync::mpsc::error::SendError<(Acker, tokio::sync::mpsc::UnboundedReceiver<Message>)>;
+
+impl From<ProcessingError> for Error {
+    fn from(err: ProcessingError) -> Self {
+        Error::Send(Box::new(err))
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("closed")]
@@ -38,13 +49,13 @@ pub enum Error {
     InvalidHeaderValue(#[from] http::header::InvalidHeaderValue),
 
     #[error(transparent)]
-    Send(
-        #[from]
-        tokio::sync::mpsc::error::SendError<(
-            Acker,
-            tokio::sync::mpsc::UnboundedReceiver<Message>,
-        )>,
-    ),
+    Arg(#[from] crate::req::ArgError),
+
+    #[error(transparent)]
+    Send(#[from] Box<ProcessingError>),
+
+    #[error("service failed, {0}")]
+    Service(#[source] BoxError),
 
     #[error("{context}, {source}")]
     Context {
@@ -54,10 +65,96 @@ pub enum Error {
     },
 }
 
+/// The layer of the connection an error comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The underlying IO failed, e.g. the peer reset the connection; usually worth a retry.
+    Transport,
+    /// The peer violated the protocol, e.g. an unexpected frame or a malformed payload.
+    Protocol,
+    /// The user service failed or timed out processing the messages.
+    Service,
+    /// The connection is closed or the agent is shutting down; usually safe to ignore.
+    Shutdown,
+}
+
+impl ErrorKind {
+    /// Returns the name of the layer, used as a metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Transport => "transport",
+            ErrorKind::Protocol => "protocol",
+            ErrorKind::Service => "service",
+            ErrorKind::Shutdown => "shutdown",
+        }
+    }
+}
+
 impl Error {
+    /// Returns the layer of the connection the error comes from.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Closed | Error::Send(_) => ErrorKind::Shutdown,
+            Error::Status(status) => status_kind(*status),
+            Error::Utf8(_) => ErrorKind::Protocol,
+            Error::Io(_) => ErrorKind::Transport,
+            Error::Http(_)
+            | Error::InvalidHeaderName(_)
+            | Error::InvalidHeaderValue(_)
+            | Error::Arg(_)
+            | Error::Service(_) => ErrorKind::Service,
+            Error::Context { source, .. } => {
+                if let Some(err) = source.downcast_ref::<Error>() {
+                    err.kind()
+                } else if let Some(status) = source.downcast_ref::<Status>() {
+                    status_kind(*status)
+                } else if source.is::<std::io::Error>() {
+                    ErrorKind::Transport
+                } else {
+                    ErrorKind::Protocol
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the underlying IO failed.
+    pub fn is_transport(&self) -> bool {
+        self.kind() == ErrorKind::Transport
+    }
+
+    /// Returns `true` if the peer violated the protocol.
+    pub fn is_protocol(&self) -> bool {
+        self.kind() == ErrorKind::Protocol
+    }
+
+    /// Returns `true` if the user service failed.
+    pub fn is_service(&self) -> bool {
+        self.kind() == ErrorKind::Service
+    }
+
+    /// Returns `true` if the connection is closed or the agent is shutting down.
+    pub fn is_shutdown(&self) -> bool {
+        self.kind() == ErrorKind::Shutdown
+    }
+
+    /// Returns `true` if the error only failed the frame being processed,
+    /// which HAProxy may ignore with the `option continue-on-error` of the engine.
+    ///
+    /// The services fail the whole connection by returning a [`Disconnect`].
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            Error::Service(source) => !source.is::<Disconnect>(),
+            Error::Context { source, .. } => source
+                .downcast_ref::<Error>()
+                .is_some_and(Error::is_recoverable),
+            _ => false,
+        }
+    }
+
     pub fn status(&self) -> Option<Status> {
         match self {
             Error::Status(status) => Some(*status),
+            Error::Service(source) => source.downcast_ref::<Status>().cloned(),
             Error::Context { source, .. } => {
                 if let Some(err) = source.downcast_ref::<Error>() {
                     err.status()
@@ -70,10 +167,29 @@ impl Error {
     }
 }
 
+fn status_kind(status: Status) -> ErrorKind {
+    match status {
+        Status::Normal => ErrorKind::Shutdown,
+        Status::Io => ErrorKind::Transport,
+        Status::Timeout | Status::ResourceAllocErr | Status::Unknown => ErrorKind::Service,
+        _ => ErrorKind::Protocol,
+    }
+}
+
 impl From<Error> for Disconnect {
     fn from(err: Error) -> Self {
         match err {
             Error::Status(status) => Disconnect::new(status, status.to_string()),
+            Error::Service(ref source) => match source.downcast_ref::<Disconnect>() {
+                Some(disconnect) => disconnect.clone(),
+                None => Disconnect::new(
+                    source
+                        .downcast_ref::<Status>()
+                        .cloned()
+                        .unwrap_or(Status::Unknown),
+                    err.to_string(),
+                ),
+            },
             Error::Context {
                 ref source,
                 ref context,
@@ -82,6 +198,11 @@ impl From<Error> for Disconnect {
                     Disconnect::new(status, context.to_string())
                 } else if let Some(status) = source.downcast_ref::<Status>() {
                     Disconnect::new(*status, context.to_string())
+                } else if let Some(disconnect) = source.downcast_ref::<Disconnect>() {
+                    Disconnect {
+                        status_code: disconnect.status_code,
+                        message: context.to_string(),
+                    }
                 } else {
                     Disconnect::new(Status::Unknown, err.to_string())
                 }
@@ -138,3 +259,136 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc::{error::SendError, unbounded_channel};
+
+    use crate::req::ArgError;
+
+    use super::*;
+
+    #[test]
+    fn test_status_kind() {
+        use ErrorKind::*;
+
+        for (status, kind) in [
+            (Status::Normal, Shutdown),
+            (Status::Io, Transport),
+            (Status::Timeout, Service),
+            (Status::TooBig, Protocol),
+            (Status::Invalid, Protocol),
+            (Status::NoVersion, Protocol),
+            (Status::NoFrameSize, Protocol),
+            (Status::NoCapabilities, Protocol),
+            (Status::BadVersion, Protocol),
+            (Status::BadFrameSize, Protocol),
+            (Status::FragmentNotSupported, Protocol),
+            (Status::InterlacedFrames, Protocol),
+            (Status::FrameIdNotFound, Protocol),
+            (Status::ResourceAllocErr, Service),
+            (Status::Unknown, Service),
+        ] {
+            assert_eq!(Error::Status(status).kind(), kind, "{status:?}");
+            assert_eq!(Error::Status(status).status(), Some(status));
+        }
+    }
+
+    #[test]
+    fn test_kind() {
+        use ErrorKind::*;
+
+        let (_, receiver) = unbounded_channel();
+        let (acker, _) = Acker::new(1, 1);
+
+        for (err, kind) in [
+            (Error::Closed, Shutdown),
+            (Error::from(SendError((acker, receiver))), Shutdown),
+            (
+                Error::from(String::from_utf8(vec![0xff]).unwrap_err().utf8_error()),
+                Protocol,
+            ),
+            (
+                Error::from(std::io::Error::from(std::io::ErrorKind::BrokenPipe)),
+                Transport,
+            ),
+            (
+                Error::from(http::Error::from(http::Uri::try_from("\0").unwrap_err())),
+                Service,
+            ),
+            (
+                Error::from(http::HeaderName::from_bytes(b"\0").unwrap_err()),
+                Service,
+            ),
+            (
+                Error::from(http::HeaderValue::from_bytes(b"\n").unwrap_err()),
+                Service,
+            ),
+            (
+                Error::from(ArgError::Missing {
+                    message: "check".to_string(),
+                    arg: "ip".to_string(),
+                }),
+                Service,
+            ),
+            (Error::Service("failed".into()), Service),
+            (
+                Err::<(), _>(Error::Closed).context("closing").unwrap_err(),
+                Shutdown,
+            ),
+            (
+                Err::<(), _>(Status::Io).context("reading").unwrap_err(),
+                Transport,
+            ),
+            (
+                Err::<(), _>(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+                    .context("writing")
+                    .unwrap_err(),
+                Transport,
+            ),
+            (
+                Err::<(), _>(std::fmt::Error)
+                    .context("encoding")
+                    .unwrap_err(),
+                Protocol,
+            ),
+        ] {
+            assert_eq!(err.kind(), kind, "{err:?}");
+        }
+    }
+
+    #[test]
+    fn test_source_chain() {
+        let err = Err::<(), _>(Status::BadVersion)
+            .context("handshake")
+            .context("connection")
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "connection, handshake, unsupported version"
+        );
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert_eq!(err.status(), Some(Status::BadVersion));
+
+        let source = err.source().unwrap();
+        assert!(source.is::<Error>());
+        let source = source.source().unwrap();
+        assert_eq!(source.downcast_ref::<Status>(), Some(&Status::BadVersion));
+        assert!(source.source().is_none());
+
+        // the status of the failed services is kept
+        let err = Error::Service(Box::new(Status::Timeout));
+        assert!(err.is_recoverable());
+        assert_eq!(err.status(), Some(Status::Timeout));
+        assert_eq!(
+            err.source().unwrap().downcast_ref::<Status>(),
+            Some(&Status::Timeout)
+        );
+
+        let disconnect = Disconnect::new(Status::ResourceAllocErr, "overloaded");
+        let err = Error::Service(Box::new(disconnect.clone()));
+        assert!(!err.is_recoverable());
+        assert_eq!(Disconnect::from(err), disconnect);
+    }
+}
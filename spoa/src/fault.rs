@@ -0,0 +1,218 @@
+//! Inject faults in the processing of the NOTIFY frames, for chaos testing.
+//!
+//! The agent misbehaves on purpose, with the configured probabilities, to validate
+//! the `timeout processing` and the fallback of the HAProxy configuration:
+//!
+//! - the ACK frames are delayed, e.g. beyond the processing timeout of HAProxy;
+//! - the frames are dropped without any ACK frame, as a stuck agent would do;
+//! - the connection is closed with an AGENT-DISCONNECT frame of the status.
+//!
+//! ```
+//! # use std::time::Duration;
+//! # use haproxy_spoa::{fault::FaultInjection, runtime::Builder, spop::{Disconnect, Error}};
+//! let builder = Builder::new().fault_injection(
+//!     FaultInjection::new()
+//!         .delay(0.1, Duration::from_millis(500))
+//!         .drop_frames(0.01)
+//!         .disconnect(0.001, Disconnect::new(Error::Io, "chaos")),
+//! );
+//! ```
+//!
+//! The malformed frames are sent from the HAProxy side with
+//! [`Client::send_corrupted`](crate::testing::Client::send_corrupted).
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+use tracing::debug;
+
+use crate::{metrics, spop::Disconnect};
+
+/// A fault injected in the processing of a NOTIFY frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// Delay the ACK frame.
+    Delay(Duration),
+    /// Drop the frame without sending an ACK frame.
+    Drop,
+    /// Close the connection with the AGENT-DISCONNECT frame.
+    Disconnect(Disconnect),
+}
+
+impl Fault {
+    /// Returns the kind of the fault, used as a metric label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Fault::Delay(_) => "delay",
+            Fault::Drop => "drop",
+            Fault::Disconnect(_) => "disconnect",
+        }
+    }
+}
+
+/// The faults injected with their probabilities, see [`Builder::fault_injection`].
+///
+/// [`Builder::fault_injection`]: crate::runtime::Builder::fault_injection
+#[derive(Debug)]
+pub struct FaultInjection {
+    delay: Option<(f64, Duration)>,
+    drop: f64,
+    disconnect: Option<(f64, Disconnect)>,
+    rng: Mutex<StdRng>,
+}
+
+impl Default for FaultInjection {
+    fn default() -> Self {
+        FaultInjection {
+            delay: None,
+            drop: 0.0,
+            disconnect: None,
+            rng: Mutex::new(StdRng::from_entropy()),
+        }
+    }
+}
+
+impl FaultInjection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make the injected faults reproducible with a seed.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = Mutex::new(StdRng::seed_from_u64(seed));
+        self
+    }
+
+    /// Delay the ACK frames with the probability.
+    pub fn delay(mut self, probability: f64, delay: Duration) -> Self {
+        self.delay = Some((probability, delay));
+        self
+    }
+
+    /// Drop the frames with the probability, HAProxy times out waiting for their ACK frames.
+    pub fn drop_frames(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    /// Close the connection with the AGENT-DISCONNECT frame with the probability.
+    pub fn disconnect(mut self, probability: f64, disconnect: Disconnect) -> Self {
+        self.disconnect = Some((probability, disconnect));
+        self
+    }
+
+    /// Returns the fault to inject in the processing of a frame, if any.
+    ///
+    /// The disconnects take precedence over the drops, and the drops over the delays.
+    pub fn inject(&self) -> Option<Fault> {
+        let mut rng = self.rng.lock().unwrap();
+        let mut roll = |probability: f64| probability > 0.0 && rng.gen_bool(probability.min(1.0));
+
+        let fault = match self.disconnect {
+            Some((probability, ref disconnect)) if roll(probability) => {
+                Fault::Disconnect(disconnect.clone())
+            }
+            _ if roll(self.drop) => Fault::Drop,
+            _ => match self.delay {
+                Some((probability, delay)) if roll(probability) => Fault::Delay(delay),
+                _ => return None,
+            },
+        };
+
+        debug!(?fault, "fault injected");
+        metrics::labeled_counter(metrics::FAULTS_INJECTED, &[("kind", fault.kind())], 1);
+
+        Some(fault)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        fixture,
+        runtime::Builder,
+        spop::{Error, Frame, Message, UserStatus},
+        testing,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_inject() {
+        assert_eq!(FaultInjection::new().inject(), None);
+
+        let faults = FaultInjection::new()
+            .seed(42)
+            .delay(1.0, Duration::from_millis(10));
+        assert_eq!(
+            faults.inject(),
+            Some(Fault::Delay(Duration::from_millis(10)))
+        );
+
+        let faults = faults.drop_frames(1.0);
+        assert_eq!(faults.inject(), Some(Fault::Drop));
+
+        let disconnect = Disconnect::new(Error::Io, "chaos");
+        let faults = faults.disconnect(1.0, disconnect.clone());
+        assert_eq!(faults.inject(), Some(Fault::Disconnect(disconnect)));
+
+        let faults = FaultInjection::new().seed(42).drop_frames(0.5);
+        let dropped = (0..1000).filter(|_| faults.inject().is_some()).count();
+        assert!((400..600).contains(&dropped), "dropped {dropped}");
+    }
+
+    #[tokio::test]
+    async fn test_faults() {
+        let runtime = |faults| fixture::runtime(Builder::new().fault_injection(faults));
+
+        let (mut client, mut conn) = testing::pair(runtime(FaultInjection::new().drop_frames(1.0)));
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), client.recv())
+                .await
+                .is_err()
+        );
+
+        let disconnect = Disconnect::user(UserStatus::new(403).unwrap(), "chaos");
+        let (mut client, mut conn) =
+            testing::pair(runtime(FaultInjection::new().disconnect(1.0, disconnect)));
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .send(Frame::notify(1, 1, [Message::new("check", [("n", 1)])]))
+            .await
+            .unwrap();
+        match client.recv().await.unwrap() {
+            Frame::AgentDisconnect(d) => assert_eq!(d.status_code, 403),
+            frame => panic!("unexpected frame: {frame:?}"),
+        }
+
+        let (mut client, mut conn) = testing::pair(runtime(FaultInjection::new()));
+        let serving = tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .send_corrupted(
+                Frame::notify(1, 1, [Message::new("check", [("n", 1)])]),
+                u32::MAX >> 8,
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            serving.await.unwrap(),
+            Err(crate::Error::Status(Error::TooBig))
+        ));
+        assert!(matches!(
+            client.recv().await.unwrap(),
+            Frame::AgentDisconnect(disconnect) if disconnect.status_code == Error::TooBig as u32
+        ));
+    }
+}
@@ -0,0 +1,73 @@
+//! The fixtures shared by the tests.
+
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::future::{ready, Ready};
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tower::{make::Shared, BoxError, MakeService, Service};
+
+use crate::{
+    error::Result,
+    runtime::{Builder, Runtime},
+    spop::{Action, Message},
+    Agent,
+};
+
+/// A service answering every NOTIFY frame with an empty ACK frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Noop;
+
+impl Service<Vec<Message>> for Noop {
+    type Response = Vec<Action>;
+    type Error = Infallible;
+    type Future = Ready<std::result::Result<Vec<Action>, Infallible>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<std::result::Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _msgs: Vec<Message>) -> Self::Future {
+        ready(Ok(vec![]))
+    }
+}
+
+/// Build the runtime of the [`Noop`] service.
+pub fn runtime(builder: Builder) -> Arc<Runtime<Shared<Noop>, ()>> {
+    builder.make_service(Shared::new(Noop), ())
+}
+
+/// Bind a non-blocking listener on a local port.
+pub fn listener() -> (StdTcpListener, SocketAddr) {
+    let listener = StdTcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    (listener, addr)
+}
+
+/// Serve the runtime on a local port,
+/// returning its address, the token to shut it down and the task of the agent.
+pub fn serve<S, T>(
+    runtime: Arc<Runtime<S, T>>,
+) -> (SocketAddr, CancellationToken, JoinHandle<Result<()>>)
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync + 'static,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send + 'static,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync + 'static,
+{
+    let (listener, addr) = listener();
+    let agent = Agent::new(runtime, listener).unwrap();
+    let shutdown = agent.shutdown();
+    let serving = tokio::spawn(async move { agent.serve().await });
+
+    (addr, shutdown, serving)
+}
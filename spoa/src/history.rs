@@ -0,0 +1,95 @@
+//! The flight recorder of the NOTIFY frames processed by a connection.
+//!
+//! When enabled with `Builder::history`, every connection records the last frames it processed,
+//! available from its [`ConnectionHandle`](crate::runtime::ConnectionHandle)
+//! and logged when the connection is closed on an error.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::spop::{FrameId, StreamId};
+
+/// A NOTIFY frame processed by the connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    /// The sequence number of the frame in the connection.
+    pub seq: u64,
+    /// The stream identifier.
+    pub stream_id: StreamId,
+    /// The frame identifier inside the stream.
+    pub frame_id: FrameId,
+    /// The names of the messages.
+    pub messages: Vec<String>,
+    /// The number of actions replied.
+    pub actions: usize,
+    /// The processing time of the frame.
+    pub latency: Duration,
+    /// The error of the processing, if any.
+    pub error: Option<String>,
+}
+
+/// A ring buffer of the last NOTIFY frames processed by the connection.
+#[derive(Clone, Debug)]
+pub struct History {
+    records: Arc<Mutex<VecDeque<Record>>>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Record a frame, dropping the oldest record when full.
+    pub fn record(&self, record: Record) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+
+        records.push_back(record);
+    }
+
+    /// Returns the records, from the oldest to the newest.
+    pub fn records(&self) -> Vec<Record> {
+        self.records.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history() {
+        let history = History::new(2);
+        let record = |stream_id| Record {
+            seq: stream_id,
+            stream_id,
+            frame_id: 1,
+            messages: vec!["check".into()],
+            actions: 0,
+            latency: Duration::ZERO,
+            error: None,
+        };
+
+        for stream_id in 0..3 {
+            history.record(record(stream_id));
+        }
+
+        assert_eq!(history.records(), vec![record(1), record(2)]);
+
+        let history = History::new(0);
+        history.record(record(0));
+        assert!(history.records().is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//! Match the IP addresses against subnets, e.g. to implement the allow or deny lists.
+//!
+//! The IP arguments are normalized by [`Typed::to_ip_addr`](crate::spop::Typed::to_ip_addr),
+//! so the IPv4-mapped IPv6 addresses sent by the dual-stack listeners match the IPv4 subnets.
+//!
+//! ```
+//! # use haproxy_spoa::ip::{IpAddrExt, IpNet, IpSet, IpTrie};
+//! let private: Vec<IpNet> = vec!["10.0.0.0/8".parse().unwrap(), "fc00::/7".parse().unwrap()];
+//! assert!("10.1.2.3".parse::<std::net::IpAddr>().unwrap().in_any(&private));
+//!
+//! // the prefix trie scales to the large lists
+//! let denied: IpSet = private.iter().copied().collect();
+//! assert!(denied.contains("fd00::1".parse().unwrap()));
+//!
+//! let mut zones = IpTrie::new();
+//! zones.insert("10.0.0.0/8".parse().unwrap(), "internal");
+//! zones.insert("10.1.0.0/16".parse().unwrap(), "lab");
+//! assert_eq!(zones.get("10.1.2.3".parse().unwrap()), Some(&"lab"));
+//! ```
+
+use std::net::IpAddr;
+
+pub use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+
+/// Extend [`IpAddr`] with the subnet matching.
+pub trait IpAddrExt {
+    /// Returns whether the address belongs to any of the subnets.
+    fn in_any(&self, nets: &[IpNet]) -> bool;
+}
+
+impl IpAddrExt for IpAddr {
+    fn in_any(&self, nets: &[IpNet]) -> bool {
+        nets.iter().any(|net| net.contains(self))
+    }
+}
+
+/// A binary prefix trie mapping the subnets to the values, with the longest prefix match.
+///
+/// The lookups walk at most 32 nodes for IPv4 and 128 nodes for IPv6,
+/// whatever the number of subnets.
+#[derive(Clone, Debug)]
+pub struct IpTrie<T> {
+    nodes: Vec<Node<T>>,
+    len: usize,
+}
+
+/// The roots of the IPv4 and IPv6 subnets.
+const V4: usize = 0;
+const V6: usize = 1;
+
+#[derive(Clone, Debug)]
+struct Node<T> {
+    children: [Option<u32>; 2],
+    value: Option<(IpNet, T)>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Node {
+            children: [None, None],
+            value: None,
+        }
+    }
+}
+
+impl<T> Default for IpTrie<T> {
+    fn default() -> Self {
+        IpTrie {
+            nodes: vec![Node::default(), Node::default()],
+            len: 0,
+        }
+    }
+}
+
+/// Returns the root and the bits of the address, most significant first.
+fn bits(addr: IpAddr) -> (usize, u128) {
+    match addr {
+        IpAddr::V4(addr) => (V4, u128::from(u32::from(addr)) << 96),
+        IpAddr::V6(addr) => (V6, u128::from(addr)),
+    }
+}
+
+fn bit(bits: u128, n: u8) -> usize {
+    ((bits >> (127 - n)) & 1) as usize
+}
+
+impl<T> IpTrie<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of subnets.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the trie has no subnet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Insert the subnet, returning the previous value of the same subnet.
+    ///
+    /// The host bits of the subnet are ignored, e.g. `10.1.2.3/8` is `10.0.0.0/8`.
+    pub fn insert(&mut self, net: IpNet, value: T) -> Option<T> {
+        let net = net.trunc();
+        let (mut idx, bits) = bits(net.network());
+
+        for n in 0..net.prefix_len() {
+            let b = bit(bits, n);
+
+            idx = match self.nodes[idx].children[b] {
+                Some(child) => child as usize,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    self.nodes[idx].children[b] = Some(child as u32);
+                    child
+                }
+            };
+        }
+
+        let prev = self.nodes[idx].value.replace((net, value));
+        if prev.is_none() {
+            self.len += 1;
+        }
+        prev.map(|(_, value)| value)
+    }
+
+    /// Returns the most specific subnet containing the address, with its value.
+    pub fn longest_match(&self, addr: IpAddr) -> Option<(IpNet, &T)> {
+        let (root, bits) = bits(addr);
+        let max_len = if root == V4 { 32 } else { 128 };
+        let mut idx = root;
+        let mut found = self.nodes[idx].value.as_ref();
+
+        for n in 0..max_len {
+            match self.nodes[idx].children[bit(bits, n)] {
+                Some(child) => idx = child as usize,
+                None => break,
+            }
+            if let Some(value) = self.nodes[idx].value.as_ref() {
+                found = Some(value);
+            }
+        }
+
+        found.map(|(net, value)| (*net, value))
+    }
+
+    /// Returns the value of the most specific subnet containing the address.
+    pub fn get(&self, addr: IpAddr) -> Option<&T> {
+        self.longest_match(addr).map(|(_, value)| value)
+    }
+
+    /// Returns whether any subnet contains the address.
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.longest_match(addr).is_some()
+    }
+}
+
+impl<T> FromIterator<(IpNet, T)> for IpTrie<T> {
+    fn from_iter<I: IntoIterator<Item = (IpNet, T)>>(iter: I) -> Self {
+        let mut trie = IpTrie::new();
+        for (net, value) in iter {
+            trie.insert(net, value);
+        }
+        trie
+    }
+}
+
+/// A set of subnets, e.g. an allow or deny list.
+pub type IpSet = IpTrie<()>;
+
+impl FromIterator<IpNet> for IpSet {
+    fn from_iter<I: IntoIterator<Item = IpNet>>(iter: I) -> Self {
+        iter.into_iter().map(|net| (net, ())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn net(s: &str) -> IpNet {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_in_any() {
+        let nets = [net("10.0.0.0/8"), net("2001:db8::/32")];
+
+        assert!(ip("10.1.2.3").in_any(&nets));
+        assert!(ip("2001:db8::1").in_any(&nets));
+        assert!(!ip("192.168.0.1").in_any(&nets));
+        assert!(!ip("::ffff:10.1.2.3").in_any(&nets));
+        assert!(!ip("10.0.0.1").in_any(&[]));
+    }
+
+    #[test]
+    fn test_trie() {
+        let mut trie = IpTrie::new();
+
+        assert!(trie.is_empty());
+        assert_eq!(trie.insert(net("10.0.0.0/8"), 1), None);
+        assert_eq!(trie.insert(net("10.1.2.3/16"), 2), None);
+        assert_eq!(trie.insert(net("10.1.0.0/16"), 3), Some(2));
+        assert_eq!(trie.insert(net("0.0.0.0/0"), 0), None);
+        assert_eq!(trie.insert(net("2001:db8::/32"), 6), None);
+        assert_eq!(trie.insert(net("192.168.1.1/32"), 4), None);
+        assert_eq!(trie.len(), 5);
+
+        assert_eq!(
+            trie.longest_match(ip("10.1.2.3")),
+            Some((net("10.1.0.0/16"), &3))
+        );
+        assert_eq!(trie.get(ip("10.2.0.1")), Some(&1));
+        assert_eq!(trie.get(ip("172.16.0.1")), Some(&0));
+        assert_eq!(trie.get(ip("192.168.1.1")), Some(&4));
+        assert_eq!(trie.get(ip("192.168.1.2")), Some(&0));
+        assert_eq!(trie.get(ip("2001:db8:1::1")), Some(&6));
+        assert_eq!(trie.get(ip("2001:db9::1")), None);
+
+        let set: IpSet = [net("10.0.0.0/8"), net("::1/128")].into_iter().collect();
+        assert!(set.contains(ip("10.255.255.255")));
+        assert!(set.contains(ip("::1")));
+        assert!(!set.contains(ip("11.0.0.0")));
+        assert!(!set.contains(ip("::2")));
+    }
+}
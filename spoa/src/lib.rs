@@ -1,15 +1,51 @@
 pub use haproxy_spop as spop;
 
+pub mod accept;
 mod agent;
+pub mod budget;
 mod conn;
+mod context;
+pub mod defaults;
 mod error;
+pub mod fault;
+#[cfg(test)]
+mod fixture;
+pub mod history;
+#[cfg(feature = "ipnet")]
+pub mod ip;
+mod listener;
+pub mod logger;
+mod loom;
+pub mod metrics;
+pub mod normalize;
+#[cfg(feature = "proxy-protocol")]
+pub mod proxy;
+#[cfg(feature = "quic")]
+pub mod quic;
 pub mod req;
+pub mod router;
 pub mod runtime;
+mod server;
+mod sink;
 mod state;
+mod task;
 mod tcp;
+pub mod tenant;
+pub mod testing;
+#[cfg(feature = "tls-fingerprint")]
+pub mod tls;
+pub mod verdict;
 
 pub use self::agent::Agent;
-pub use self::conn::Connection;
-pub use self::error::Error;
-pub use self::runtime::Runtime;
-pub use self::state::State;
+pub use self::conn::{Connection, FrameHook, Frames, IoInfo, DISCONNECT_LINGER, SHUTDOWN_REASON};
+pub use self::context::NotifyContext;
+pub use self::error::{Context, Error, ErrorKind, Reason};
+pub use self::listener::{ListenerOptions, DEFAULT_LISTENER};
+pub use self::logger::StreamLogger;
+pub use self::server::SpopServer;
+pub use self::sink::{ActionSink, Streaming};
+pub use self::state::{HandshakeHook, Negotiated, State};
+pub use ulid::Ulid;
+
+#[deprecated(note = "use `runtime::Runtime` instead")]
+pub type Runtime<S, T> = self::runtime::Runtime<S, T>;
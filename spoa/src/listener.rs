@@ -0,0 +1,209 @@
+use std::future::poll_fn;
+use std::io;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener as StdUnixListener;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::{error::Result, spop::Capability};
+
+/// The name of the listener passed to [`Agent::new`](crate::Agent::new).
+pub const DEFAULT_LISTENER: &str = "default";
+
+/// The protocol options of a listener, overriding the ones of the runtime.
+///
+/// ```no_run
+/// # use std::net::TcpListener;
+/// # use haproxy_spoa::{ListenerOptions, spop::Capability};
+/// # fn agent<S, T>(agent: haproxy_spoa::Agent<S, T>) -> Result<(), haproxy_spoa::Error> {
+/// // the plaintext listener for the local HAProxy, and a restricted one for the remote instances
+/// let agent = agent.with_listener(
+///     TcpListener::bind("0.0.0.0:12346")?,
+///     ListenerOptions::new("public")
+///         .max_frame_size(4096)
+///         .capabilities([Capability::Pipelining]),
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenerOptions {
+    /// The name of the listener, used as the `listener` label of the metrics.
+    pub name: String,
+    /// The max frame size announced in the AGENT-HELLO frame, instead of the runtime one.
+    pub max_frame_size: Option<usize>,
+    /// The capabilities supported by the agent, instead of the runtime ones.
+    pub capabilities: Option<Vec<Capability>>,
+}
+
+impl Default for ListenerOptions {
+    fn default() -> Self {
+        ListenerOptions::new(DEFAULT_LISTENER)
+    }
+}
+
+impl ListenerOptions {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        ListenerOptions {
+            name: name.into(),
+            max_frame_size: None,
+            capabilities: None,
+        }
+    }
+
+    /// Override the max frame size of the runtime.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Override the capabilities of the runtime.
+    pub fn capabilities<I: IntoIterator<Item = Capability>>(mut self, capabilities: I) -> Self {
+        self.capabilities = Some(capabilities.into_iter().collect());
+        self
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Listener {
+    inner: Inner,
+    options: Arc<ListenerOptions>,
+}
+
+#[derive(Debug)]
+enum Inner {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub fn new(listener: StdTcpListener, options: ListenerOptions) -> Result<Self> {
+        Ok(Listener {
+            inner: Inner::Tcp(TcpListener::from_std(listener)?),
+            options: Arc::new(options),
+        })
+    }
+
+    /// Accept the connections from a unix domain socket.
+    #[cfg(unix)]
+    pub fn unix(listener: StdUnixListener, options: ListenerOptions) -> Result<Self> {
+        listener.set_nonblocking(true)?;
+
+        Ok(Listener {
+            inner: Inner::Unix(UnixListener::from_std(listener)?),
+            options: Arc::new(options),
+        })
+    }
+
+    /// Returns the local address of the TCP listener.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.inner {
+            Inner::Tcp(listener) => listener.local_addr(),
+            #[cfg(unix)]
+            Inner::Unix(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<(Stream, Option<SocketAddr>)>> {
+        match &self.inner {
+            Inner::Tcp(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, peer)| (Stream::Tcp(stream), Some(peer))),
+            #[cfg(unix)]
+            Inner::Unix(listener) => listener
+                .poll_accept(cx)
+                .map_ok(|(stream, _)| (Stream::Unix(stream), None)),
+        }
+    }
+}
+
+/// Accept a connection from any of the listeners, with the options of its listener.
+///
+/// The peers of the unix domain sockets have no address.
+pub(crate) async fn accept(
+    listeners: &[Listener],
+) -> io::Result<(Stream, Option<SocketAddr>, Arc<ListenerOptions>)> {
+    poll_fn(|cx| {
+        for listener in listeners {
+            if let Poll::Ready(res) = listener.poll_accept(cx) {
+                return Poll::Ready(
+                    res.map(|(stream, peer)| (stream, peer, listener.options.clone())),
+                );
+            }
+        }
+
+        Poll::Pending
+    })
+    .await
+}
+
+/// A connection accepted by a [`Listener`].
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Returns the local address of the TCP connection.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Stream::Tcp(stream) => stream.local_addr().ok(),
+            #[cfg(unix)]
+            Stream::Unix(_) => None,
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,224 @@
+//! Log records attributed to the HAProxy stream of the NOTIFY frame being processed.
+//!
+//! The records of the [`StreamLogger`] are tagged with the engine, stream and frame identifiers,
+//! so the logs of a request can be correlated with the HAProxy logs.
+//!
+//! With `Builder::tail_logging`, the records are buffered and only emitted when the frame
+//! failed or exceeded the latency threshold, keeping the forensic detail of the slow or
+//! failing frames without logging every frame.
+//!
+//! ```no_run
+//! # use haproxy_spoa::NotifyContext;
+//! let cx = NotifyContext::current().expect("processing NOTIFY frame");
+//!
+//! cx.logger.info("cache miss");
+//! ```
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, error, info, trace, warn, Level};
+
+use crate::spop::{FrameId, StreamId};
+
+/// A log record buffered by the [`StreamLogger`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogRecord {
+    /// The level of the record.
+    pub level: Level,
+    /// When the record was logged.
+    pub at: Instant,
+    /// The message of the record.
+    pub message: String,
+}
+
+/// Emit the log records tagged with the HAProxy stream of a NOTIFY frame.
+#[derive(Clone, Debug, Default)]
+pub struct StreamLogger(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    engine_id: Option<String>,
+    stream_id: StreamId,
+    frame_id: FrameId,
+    /// The records buffered until the frame completes, if tail-based logging is enabled.
+    buffer: Option<Mutex<Vec<LogRecord>>>,
+}
+
+impl PartialEq for StreamLogger {
+    fn eq(&self, other: &Self) -> bool {
+        (&self.0.engine_id, self.0.stream_id, self.0.frame_id)
+            == (&other.0.engine_id, other.0.stream_id, other.0.frame_id)
+    }
+}
+
+impl Eq for StreamLogger {}
+
+impl StreamLogger {
+    /// Create a logger for the frame, buffering the records if `buffered`.
+    pub fn new(
+        engine_id: Option<String>,
+        stream_id: StreamId,
+        frame_id: FrameId,
+        buffered: bool,
+    ) -> Self {
+        StreamLogger(Arc::new(Inner {
+            engine_id,
+            stream_id,
+            frame_id,
+            buffer: buffered.then(Mutex::default),
+        }))
+    }
+
+    /// Returns `true` if the records are buffered until the frame completes.
+    pub fn is_buffered(&self) -> bool {
+        self.0.buffer.is_some()
+    }
+
+    /// Log the message at the level.
+    pub fn log<S: Into<String>>(&self, level: Level, message: S) {
+        let record = LogRecord {
+            level,
+            at: Instant::now(),
+            message: message.into(),
+        };
+
+        match self.0.buffer {
+            Some(ref buffer) => buffer.lock().unwrap().push(record),
+            None => self.emit(&record, None),
+        }
+    }
+
+    pub fn trace<S: Into<String>>(&self, message: S) {
+        self.log(Level::TRACE, message)
+    }
+
+    pub fn debug<S: Into<String>>(&self, message: S) {
+        self.log(Level::DEBUG, message)
+    }
+
+    pub fn info<S: Into<String>>(&self, message: S) {
+        self.log(Level::INFO, message)
+    }
+
+    pub fn warn<S: Into<String>>(&self, message: S) {
+        self.log(Level::WARN, message)
+    }
+
+    pub fn error<S: Into<String>>(&self, message: S) {
+        self.log(Level::ERROR, message)
+    }
+
+    /// Returns the buffered records.
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.0
+            .buffer
+            .as_ref()
+            .map(|buffer| buffer.lock().unwrap().clone())
+            .unwrap_or_default()
+    }
+
+    /// Emit the buffered records if the frame failed or was slower than the threshold,
+    /// discard them otherwise.
+    pub(crate) fn finish(&self, failed: bool, latency: Duration, threshold: Duration) {
+        let Some(ref buffer) = self.0.buffer else {
+            return;
+        };
+        let records = std::mem::take(&mut *buffer.lock().unwrap());
+
+        if failed || latency > threshold {
+            for record in &records {
+                self.emit(record, Some(latency));
+            }
+        }
+    }
+
+    fn emit(&self, record: &LogRecord, latency: Option<Duration>) {
+        let Inner {
+            ref engine_id,
+            stream_id,
+            frame_id,
+            ..
+        } = *self.0;
+        let engine_id = engine_id.as_deref();
+        let message = &record.message;
+
+        macro_rules! emit {
+            ($log:ident) => {
+                $log!(target: "spoa::stream", engine_id, stream_id, frame_id, ?latency, "{message}")
+            };
+        }
+
+        match record.level {
+            Level::TRACE => emit!(trace),
+            Level::DEBUG => emit!(debug),
+            Level::INFO => emit!(info),
+            Level::WARN => emit!(warn),
+            _ => emit!(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{make::Shared, service_fn};
+
+    use crate::{runtime::Builder, spop::Message, testing, NotifyContext};
+
+    use super::*;
+
+    #[test]
+    fn test_finish() {
+        let logger = StreamLogger::new(Some("engine".into()), 1, 2, true);
+
+        logger.info("cache miss");
+        logger.warn("slow backend");
+        assert_eq!(
+            logger
+                .records()
+                .into_iter()
+                .map(|record| (record.level, record.message))
+                .collect::<Vec<_>>(),
+            vec![
+                (Level::INFO, "cache miss".to_string()),
+                (Level::WARN, "slow backend".to_string())
+            ]
+        );
+
+        logger.finish(false, Duration::from_millis(1), Duration::from_millis(10));
+        assert!(logger.records().is_empty());
+
+        let logger = StreamLogger::new(None, 1, 2, false);
+        logger.info("emitted");
+        assert!(!logger.is_buffered());
+        assert!(logger.records().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_context_logger() {
+        let runtime = Builder::new()
+            .tail_logging(Duration::from_secs(1))
+            .make_service(
+                Shared::new(service_fn(|_: Vec<Message>| async {
+                    let cx = NotifyContext::current().unwrap();
+
+                    cx.logger.info("processing");
+                    assert!(cx.logger.is_buffered());
+                    assert_eq!(cx.logger.records().len(), 1);
+
+                    Ok::<_, Infallible>(vec![])
+                })),
+                (),
+            );
+        let (mut client, mut conn) = testing::pair(runtime);
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+        client
+            .notify(1, 1, [Message::new("check", [("n", 1)])])
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,16 @@
+//! The synchronization primitives shared across the connections,
+//! replaced by the [loom](https://docs.rs/loom) models under `--cfg loom`.
+//!
+//! The flag must only be passed to this crate, since `tokio` drops its `net` module under it:
+//!
+//! ```text
+//! cargo rustc -p haproxy-spoa --lib --profile test -- --cfg loom
+//! ```
+//!
+//! then run the `model_` tests of the built test binary.
+
+#[cfg(not(loom))]
+pub(crate) use std::sync;
+
+#[cfg(loom)]
+pub(crate) use ::loom::sync;
@@ -0,0 +1,249 @@
+//! The instrumentation of the agent, emitted through the [`metrics`](https://docs.rs/metrics) facade.
+//!
+//! When the `metrics` feature is enabled, the runtime records the following metrics
+//! with the recorder installed by the application, e.g. a Prometheus or StatsD exporter:
+//!
+//! | Name                                | Type      | Description                                              |
+//! |-------------------------------------|-----------|----------------------------------------------------------|
+//! | `spoa_connections`                  | gauge     | The number of active connections.                        |
+//! | `spoa_listener_connections_total`   | counter   | The number of connections accepted by each listener.     |
+//! | `spoa_connection_panics_total`      | counter   | The number of connection tasks which panicked.           |
+//! | `spoa_handshake_rejections_total`   | counter   | The number of connections rejected before the handshake. |
+//! | `spoa_write_timeouts_total`         | counter   | The number of connections closed on a write timeout.     |
+//! | `spoa_connections_stalled_total`    | counter   | The number of wedged connections closed by the watchdog. |
+//! | `spoa_disconnects_total`            | counter   | The number of AGENT-DISCONNECT frames sent.              |
+//! | `spoa_dropped_total`                | counter   | The number of frames, messages and actions dropped.      |
+//! | `spoa_frames_total`                 | counter   | The number of frames received and sent, by type.         |
+//! | `spoa_frames_in_flight`             | gauge     | The number of frames being processed.                    |
+//! | `spoa_frames_processed_total`       | counter   | The number of frames processed.                          |
+//! | `spoa_frame_processing_seconds`     | histogram | The processing time of the frames.                       |
+//! | `spoa_frames_throttled_total`       | counter   | The number of frames delayed by the time slices.         |
+//! | `spoa_frame_throttle_seconds`       | histogram | The delay of the throttled frames.                       |
+//! | `spoa_frames_haproxy_timeout_total` | counter   | The number of frames HAProxy would have timed out.       |
+//! | `spoa_frames_shed_total`            | counter   | The number of frames belonging to the other shards.      |
+//! | `spoa_faults_injected_total`        | counter   | The number of faults injected for the chaos testing.     |
+//! | `spoa_adaptive_timeout_seconds`     | gauge     | The adaptive timeout of the frames of the messages.      |
+//! | `spoa_adaptive_shed_total`          | counter   | The number of frames shed by the adaptive timeout.       |
+//! | `spoa_breaker_state`                | gauge     | The state of the `MakeService` circuit breaker.          |
+//! | `spoa_breaker_rejected_total`       | counter   | The number of services not made by the open breaker.     |
+//! | `spoa_sample_rate`                  | gauge     | The ratio of the messages of the name processed.         |
+//! | `spoa_messages_unsampled_total`     | counter   | The number of messages not sampled.                      |
+//! | `spoa_vars_oversized_total`         | counter   | The number of variable values exceeding the limit.       |
+//! | `spoa_notify_frame_bytes`           | histogram | The size of the NOTIFY frames received.                  |
+//! | `spoa_ack_frame_bytes`              | histogram | The size of the ACK frames sent.                         |
+//! | `spoa_acks_rerouted_total`          | counter   | The number of ACK frames written on another connection.  |
+//! | `spoa_acks_suppressed_total`        | counter   | The number of duplicate ACK frames dropped.              |
+//! | `spoa_engine_connections_total`     | counter   | The number of handshakes of the engine.                  |
+//! | `spoa_engine_frames_total`          | counter   | The number of frames of the engine processed.            |
+//! | `spoa_engine_actions_total`         | counter   | The number of actions sent to the engine.                |
+//! | `spoa_engine_errors_total`          | counter   | The number of frames of the engine which failed.         |
+//! | `spoa_engine_processing_seconds`    | histogram | The processing time of the frames of the engine.         |
+//! | `spoa_draining`                     | gauge     | `1` when the agent stopped accepting connections.        |
+//! | `spoa_paused`                       | gauge     | `1` when the agent paused processing the frames.         |
+//!
+//! The handshake rejections are labeled with the `phase` and the error `kind`,
+//! e.g. `phase="handshake",kind="transport"` for the peers closing the connection without a HELLO frame.
+//!
+//! The frames are labeled with the [`frame_type_name`](crate::runtime::frame_type_name) of their type,
+//! e.g. `type="haproxy_notify"`.
+//!
+//! The drops are labeled with the [`DropReason`](crate::runtime::DropReason),
+//! e.g. `reason="processor_closed"` for the messages of a dead worker.
+//!
+//! The accepted connections are labeled with the `listener` name of their
+//! [`ListenerOptions`](crate::ListenerOptions), e.g. `listener="public"`.
+//!
+//! The adaptive timeouts and shed frames are labeled with the `messages` names of the frames,
+//! e.g. `messages="check-client-ip"`, and the sampled messages with their name.
+//!
+//! The engine metrics are recorded with `Builder::engine_stats`, labeled with the `engine` identifier.
+//!
+//! The disconnects are labeled with the numeric `status` code and its `class`,
+//! `spoe` for the predefined codes or `user` for the vendor-specific ones, e.g. `status="403",class="user"`.
+//!
+//! The names are stable, and the same values are available without a recorder
+//! from [`Agent::load`](crate::Agent::load).
+//! With the `prometheus` feature, [`render`] formats them in the Prometheus text exposition format,
+//! e.g. for the `/metrics` endpoint of an agent without a recorder.
+
+use std::time::Duration;
+
+#[cfg(feature = "prometheus")]
+mod prometheus;
+
+#[cfg(feature = "prometheus")]
+pub use self::prometheus::render;
+
+/// The number of active connections.
+pub const CONNECTIONS: &str = "spoa_connections";
+/// The number of connections accepted, labeled with the name of the listener.
+pub const LISTENER_CONNECTIONS: &str = "spoa_listener_connections_total";
+/// The number of connection tasks which panicked.
+pub const CONNECTION_PANICS: &str = "spoa_connection_panics_total";
+/// The number of connections rejected before completing the handshake.
+pub const HANDSHAKE_REJECTIONS: &str = "spoa_handshake_rejections_total";
+/// The number of connections closed because writing a frame timed out.
+pub const WRITE_TIMEOUTS: &str = "spoa_write_timeouts_total";
+/// The number of connections closed by the watchdog because they stalled with pending frames.
+pub const CONNECTIONS_STALLED: &str = "spoa_connections_stalled_total";
+/// The number of AGENT-DISCONNECT frames sent, labeled with the status code.
+pub const DISCONNECTS: &str = "spoa_disconnects_total";
+/// The number of frames, messages and actions dropped, labeled with the reason.
+pub const DROPPED: &str = "spoa_dropped_total";
+/// The number of frames received and sent, labeled with their `type`.
+pub const FRAMES: &str = "spoa_frames_total";
+/// The number of frames being processed.
+pub const FRAMES_IN_FLIGHT: &str = "spoa_frames_in_flight";
+/// The number of frames processed.
+pub const FRAMES_PROCESSED: &str = "spoa_frames_processed_total";
+/// The processing time of the frames, in seconds.
+pub const FRAME_PROCESSING_SECONDS: &str = "spoa_frame_processing_seconds";
+/// The number of frames delayed by the time slices of their connections.
+pub const FRAMES_THROTTLED: &str = "spoa_frames_throttled_total";
+/// The delay of the throttled frames, in seconds.
+pub const FRAME_THROTTLE_SECONDS: &str = "spoa_frame_throttle_seconds";
+/// The number of frames which would have timed out at HAProxy.
+pub const FRAMES_HAPROXY_TIMEOUT: &str = "spoa_frames_haproxy_timeout_total";
+/// The number of frames answered with an empty ACK frame, belonging to the other shards.
+pub const FRAMES_SHED: &str = "spoa_frames_shed_total";
+/// The number of faults injected by the `FaultInjection`, labeled by `kind`.
+pub const FAULTS_INJECTED: &str = "spoa_faults_injected_total";
+/// The adaptive timeout of the frames, in seconds, labeled with the names of their messages.
+pub const ADAPTIVE_TIMEOUT_SECONDS: &str = "spoa_adaptive_timeout_seconds";
+/// The number of frames shed by the adaptive timeout, labeled with the names of their messages.
+pub const ADAPTIVE_SHED: &str = "spoa_adaptive_shed_total";
+/// The state of the circuit breaker of the `MakeService`, `0` closed, `1` open and `2` half-open.
+pub const BREAKER_STATE: &str = "spoa_breaker_state";
+/// The number of services not made while the circuit breaker was open.
+pub const BREAKER_REJECTED: &str = "spoa_breaker_rejected_total";
+/// The ratio of the messages processed, labeled with their name.
+pub const SAMPLE_RATE: &str = "spoa_sample_rate";
+/// The number of messages not sampled, labeled with their name.
+pub const MESSAGES_UNSAMPLED: &str = "spoa_messages_unsampled_total";
+/// The number of variable values exceeding the limit of the `VarGuard`.
+pub const VARS_OVERSIZED: &str = "spoa_vars_oversized_total";
+/// The size of the NOTIFY frames received, in bytes.
+pub const NOTIFY_FRAME_BYTES: &str = "spoa_notify_frame_bytes";
+/// The size of the ACK frames sent, in bytes.
+pub const ACK_FRAME_BYTES: &str = "spoa_ack_frame_bytes";
+/// The number of ACK frames written on another connection of the engine than the NOTIFY frame.
+pub const ACKS_REROUTED: &str = "spoa_acks_rerouted_total";
+/// The number of duplicate ACK frames dropped by the write scheduler.
+pub const ACKS_SUPPRESSED: &str = "spoa_acks_suppressed_total";
+/// The number of connections of the engine which completed the handshake.
+pub const ENGINE_CONNECTIONS: &str = "spoa_engine_connections_total";
+/// The number of NOTIFY frames of the engine processed.
+pub const ENGINE_FRAMES: &str = "spoa_engine_frames_total";
+/// The number of actions sent to the engine.
+pub const ENGINE_ACTIONS: &str = "spoa_engine_actions_total";
+/// The number of NOTIFY frames of the engine which failed.
+pub const ENGINE_ERRORS: &str = "spoa_engine_errors_total";
+/// The processing time of the frames of the engine, in seconds.
+pub const ENGINE_PROCESSING_SECONDS: &str = "spoa_engine_processing_seconds";
+/// `1` when the agent stopped accepting new connections.
+pub const DRAINING: &str = "spoa_draining";
+/// `1` when the agent answers the NOTIFY frames without processing them.
+pub const PAUSED: &str = "spoa_paused";
+
+#[cfg(feature = "metrics")]
+pub(crate) fn counter(name: &'static str, n: u64) {
+    ::metrics::counter!(name).increment(n)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn labeled_counter(name: &'static str, labels: &[(&'static str, &'static str)], n: u64) {
+    ::metrics::counter!(name, labels).increment(n)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn status_counter(name: &'static str, code: u32, user: bool) {
+    let class = if user { "user" } else { "spoe" };
+
+    ::metrics::counter!(name, "status" => code.to_string(), "class" => class).increment(1)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn engine_counter(name: &'static str, engine: &str, n: u64) {
+    ::metrics::counter!(name, "engine" => engine.to_string()).increment(n)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn listener_counter(name: &'static str, listener: &str, n: u64) {
+    ::metrics::counter!(name, "listener" => listener.to_string()).increment(n)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn message_counter(name: &'static str, messages: &str, n: u64) {
+    ::metrics::counter!(name, "messages" => messages.to_string()).increment(n)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn message_gauge(name: &'static str, messages: &str, value: f64) {
+    ::metrics::gauge!(name, "messages" => messages.to_string()).set(value)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn engine_histogram(name: &'static str, engine: &str, d: Duration) {
+    ::metrics::histogram!(name, "engine" => engine.to_string()).record(d.as_secs_f64())
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn gauge(name: &'static str, delta: f64) {
+    ::metrics::gauge!(name).increment(delta)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn set_gauge(name: &'static str, value: f64) {
+    ::metrics::gauge!(name).set(value)
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn histogram(name: &'static str, d: Duration) {
+    ::metrics::histogram!(name).record(d.as_secs_f64())
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn bytes(name: &'static str, n: usize) {
+    ::metrics::histogram!(name).record(n as f64)
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn counter(_name: &'static str, _n: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn labeled_counter(
+    _name: &'static str,
+    _labels: &[(&'static str, &'static str)],
+    _n: u64,
+) {
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn status_counter(_name: &'static str, _code: u32, _user: bool) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn engine_counter(_name: &'static str, _engine: &str, _n: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn listener_counter(_name: &'static str, _listener: &str, _n: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn message_counter(_name: &'static str, _messages: &str, _n: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn message_gauge(_name: &'static str, _messages: &str, _value: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn engine_histogram(_name: &'static str, _engine: &str, _d: Duration) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn gauge(_name: &'static str, _delta: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn set_gauge(_name: &'static str, _value: f64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn histogram(_name: &'static str, _d: Duration) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn bytes(_name: &'static str, _n: usize) {}
@@ -0,0 +1,271 @@
+use std::fmt::Write as _;
+
+use crate::{
+    metrics::*,
+    runtime::{
+        frame_type_name, DropReason, LoadSnapshot, SizeHistogram, LATENCY_BUCKETS, SIZE_BUCKETS,
+    },
+};
+
+/// Render the snapshot in the Prometheus text exposition format.
+///
+/// ```
+/// # use haproxy_spoa::{metrics, runtime::LoadSnapshot};
+/// let text = metrics::render(&LoadSnapshot::default());
+///
+/// assert!(text.contains("# TYPE spoa_connections gauge\nspoa_connections 0\n"));
+/// ```
+pub fn render(load: &LoadSnapshot) -> String {
+    let mut w = Writer::default();
+
+    w.gauge(
+        CONNECTIONS,
+        "The number of active connections.",
+        load.connections,
+    );
+    w.counter(
+        CONNECTION_PANICS,
+        "The number of connection tasks which panicked.",
+        load.panics,
+    );
+    w.counter(
+        HANDSHAKE_REJECTIONS,
+        "The number of connections rejected before the handshake.",
+        load.handshake_rejections,
+    );
+    w.counter(
+        WRITE_TIMEOUTS,
+        "The number of connections closed on a write timeout.",
+        load.write_timeouts,
+    );
+    w.counter(
+        CONNECTIONS_STALLED,
+        "The number of wedged connections closed by the watchdog.",
+        load.stalled,
+    );
+    w.counter(
+        DISCONNECTS,
+        "The number of AGENT-DISCONNECT frames sent.",
+        load.disconnects,
+    );
+
+    w.header(
+        DROPPED,
+        "counter",
+        "The number of frames, messages and actions dropped.",
+    );
+    for reason in DropReason::ALL {
+        w.sample(
+            DROPPED,
+            &[("reason", reason.as_str())],
+            load.dropped.get(reason),
+        );
+    }
+
+    w.header(
+        FRAMES,
+        "counter",
+        "The number of frames received and sent, by type.",
+    );
+    for (ty, n) in load.frames.iter() {
+        w.sample(FRAMES, &[("type", frame_type_name(ty))], n);
+    }
+
+    w.gauge(
+        FRAMES_IN_FLIGHT,
+        "The number of frames being processed.",
+        load.in_flight,
+    );
+    w.counter(
+        FRAMES_PROCESSED,
+        "The number of frames processed.",
+        load.processed,
+    );
+
+    w.header(
+        FRAME_PROCESSING_SECONDS,
+        "histogram",
+        "The processing time of the frames.",
+    );
+    let buckets = LATENCY_BUCKETS
+        .iter()
+        .map(|bound| Some(bound.as_secs_f64()))
+        .chain([None]);
+    w.histogram(
+        FRAME_PROCESSING_SECONDS,
+        buckets.zip(load.latencies.buckets),
+        load.latencies.sum.as_secs_f64(),
+    );
+
+    w.counter(
+        FRAMES_THROTTLED,
+        "The number of frames delayed by the time slices.",
+        load.throttled,
+    );
+    w.counter(
+        FRAMES_HAPROXY_TIMEOUT,
+        "The number of frames HAProxy would have timed out.",
+        load.deadlines.haproxy_timeouts,
+    );
+    w.counter(
+        FRAMES_SHED,
+        "The number of frames belonging to the other shards.",
+        load.shed,
+    );
+    w.counter(
+        VARS_OVERSIZED,
+        "The number of variable values exceeding the limit.",
+        load.oversized,
+    );
+
+    for (name, help, sizes) in [
+        (
+            NOTIFY_FRAME_BYTES,
+            "The size of the NOTIFY frames received.",
+            &load.frame_sizes.notify,
+        ),
+        (
+            ACK_FRAME_BYTES,
+            "The size of the ACK frames sent.",
+            &load.frame_sizes.ack,
+        ),
+    ] {
+        w.header(name, "histogram", help);
+        let buckets =
+            (0..SIZE_BUCKETS).map(|bucket| SizeHistogram::bound(bucket).map(|n| n as f64));
+        // the sizes are only recorded by bucket
+        w.histogram(name, buckets.zip(sizes.buckets), f64::NAN);
+    }
+
+    w.counter(
+        ACKS_REROUTED,
+        "The number of ACK frames written on another connection.",
+        load.acks_rerouted,
+    );
+    w.counter(
+        ACKS_SUPPRESSED,
+        "The number of duplicate ACK frames dropped.",
+        load.acks_suppressed,
+    );
+    w.gauge(
+        DRAINING,
+        "1 when the agent stopped accepting connections.",
+        u8::from(load.draining),
+    );
+    w.gauge(
+        PAUSED,
+        "1 when the agent paused processing the frames.",
+        u8::from(load.paused),
+    );
+
+    w.0
+}
+
+#[derive(Default)]
+struct Writer(String);
+
+impl Writer {
+    fn header(&mut self, name: &str, ty: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} {ty}");
+    }
+
+    fn sample<V: std::fmt::Display>(&mut self, name: &str, labels: &[(&str, &str)], value: V) {
+        self.0.push_str(name);
+
+        if !labels.is_empty() {
+            self.0.push('{');
+            for (i, (key, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.0.push(',');
+                }
+                let _ = write!(self.0, "{key}=\"{value}\"");
+            }
+            self.0.push('}');
+        }
+
+        let _ = writeln!(self.0, " {value}");
+    }
+
+    fn counter<V: std::fmt::Display>(&mut self, name: &str, help: &str, value: V) {
+        self.header(name, "counter", help);
+        self.sample(name, &[], value);
+    }
+
+    fn gauge<V: std::fmt::Display>(&mut self, name: &str, help: &str, value: V) {
+        self.header(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+
+    /// Write the cumulative buckets, the last one without an upper bound.
+    fn histogram<I>(&mut self, name: &str, buckets: I, sum: f64)
+    where
+        I: IntoIterator<Item = (Option<f64>, u64)>,
+    {
+        let bucket = format!("{name}_bucket");
+        let mut count = 0;
+
+        for (bound, n) in buckets {
+            count += n;
+
+            let le = bound.map_or_else(|| "+Inf".to_string(), |bound| bound.to_string());
+            self.sample(&bucket, &[("le", &le)], count);
+        }
+
+        if !sum.is_nan() {
+            self.sample(&format!("{name}_sum"), &[], sum);
+        }
+        self.sample(&format!("{name}_count"), &[], count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{runtime::LatencyHistogram, spop::FrameType};
+
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let load = LoadSnapshot {
+            connections: 2,
+            latencies: LatencyHistogram {
+                buckets: [1, 2, 0, 0, 0, 0, 0, 0, 1],
+                sum: Duration::from_millis(1500),
+            },
+            ..Default::default()
+        };
+        let text = render(&load);
+
+        assert!(text.contains("# TYPE spoa_connections gauge\nspoa_connections 2\n"));
+        assert!(text.contains(
+            "spoa_frame_processing_seconds_bucket{le=\"0.001\"} 1\n\
+             spoa_frame_processing_seconds_bucket{le=\"0.005\"} 3\n"
+        ));
+        assert!(text.contains(
+            "spoa_frame_processing_seconds_bucket{le=\"+Inf\"} 4\n\
+             spoa_frame_processing_seconds_sum 1.5\n\
+             spoa_frame_processing_seconds_count 4\n"
+        ));
+        assert!(text.contains(&format!(
+            "spoa_frames_total{{type=\"{}\"}} 0\n",
+            frame_type_name(FrameType::HaproxyNotify)
+        )));
+        assert!(text.contains("spoa_dropped_total{reason=\"expired_ack\"} 0\n"));
+        assert!(text.contains("spoa_ack_frame_bytes_bucket{le=\"64\"} 0\n"));
+        assert!(!text.contains("spoa_ack_frame_bytes_sum"));
+
+        // every sample follows the headers of its metric
+        for line in text.lines().filter(|line| !line.starts_with('#')) {
+            let (name, _) = line.split_once([' ', '{']).unwrap();
+            let base = name
+                .trim_end_matches("_bucket")
+                .trim_end_matches("_sum")
+                .trim_end_matches("_count");
+
+            assert!(text.contains(&format!("# TYPE {base} ")), "{line}");
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Normalize the actions returned by the services before they are sent in the ACK frame.
+//!
+//! HAProxy applies the actions in order, so only the last action on a variable is effective:
+//!
+//! - the duplicate set-var actions of the same scope and name are removed, keeping the last one;
+//! - a set-var action followed by an unset-var action of the same variable is dropped,
+//!   and vice versa;
+//! - the actions are sorted by scope and name, for a deterministic output.
+//!
+//! ```
+//! # use tower::{service_fn, ServiceBuilder};
+//! # use haproxy_spoa::{normalize::ActionNormalizer, spop::{Action, Message}};
+//! let svc = ServiceBuilder::new()
+//!     .layer(ActionNormalizer)
+//!     .service(service_fn(|_: Vec<Message>| async { Ok::<_, ()>(Vec::<Action>::new()) }));
+//! ```
+
+use std::collections::BTreeMap;
+use std::task::{Context, Poll};
+
+use futures::future::{FutureExt as _, Map};
+use tower::{Layer, Service};
+
+use crate::spop::{Action, Scope};
+
+/// Normalize the actions, only keeping the effective action of every variable.
+pub fn normalize<I>(actions: I) -> Vec<Action>
+where
+    I: IntoIterator<Item = Action>,
+{
+    actions
+        .into_iter()
+        .map(|action| ((action.scope(), action.name().to_string()), action))
+        .collect::<BTreeMap<(Scope, String), Action>>()
+        .into_values()
+        .collect()
+}
+
+/// A layer normalizing the actions returned by the inner service.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ActionNormalizer;
+
+impl<S> Layer<S> for ActionNormalizer {
+    type Service = Normalize<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Normalize(inner)
+    }
+}
+
+/// A service normalizing the actions returned by the inner service.
+#[derive(Clone, Debug)]
+pub struct Normalize<S>(pub S);
+
+type NormalizeFn<E> = fn(Result<Vec<Action>, E>) -> Result<Vec<Action>, E>;
+
+impl<S, Req> Service<Req> for Normalize<S>
+where
+    S: Service<Req, Response = Vec<Action>>,
+{
+    type Response = Vec<Action>;
+    type Error = S::Error;
+    type Future = Map<S::Future, NormalizeFn<S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.0.call(req).map(|res| res.map(normalize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert!(normalize(vec![]).is_empty());
+
+        assert_eq!(
+            normalize(vec![
+                Action::set_var(Scope::Transaction, "b", 1),
+                Action::set_var(Scope::Request, "a", 1),
+                Action::set_var(Scope::Transaction, "b", 2),
+            ]),
+            vec![
+                Action::set_var(Scope::Transaction, "b", 2),
+                Action::set_var(Scope::Request, "a", 1),
+            ]
+        );
+
+        assert_eq!(
+            normalize(vec![
+                Action::set_var(Scope::Session, "a", 1),
+                Action::unset_var(Scope::Session, "a"),
+                Action::unset_var(Scope::Session, "b"),
+                Action::set_var(Scope::Session, "b", 2),
+            ]),
+            vec![
+                Action::unset_var(Scope::Session, "a"),
+                Action::set_var(Scope::Session, "b", 2),
+            ]
+        );
+
+        // the same name in different scopes are different variables
+        assert_eq!(
+            normalize(vec![
+                Action::set_var(Scope::Response, "a", 1),
+                Action::unset_var(Scope::Request, "a"),
+            ]),
+            vec![
+                Action::unset_var(Scope::Request, "a"),
+                Action::set_var(Scope::Response, "a", 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layer() {
+        use tower::{service_fn, ServiceBuilder, ServiceExt as _};
+
+        let svc = ServiceBuilder::new()
+            .layer(ActionNormalizer)
+            .service(service_fn(|n: i32| async move {
+                Ok::<_, ()>(vec![
+                    Action::set_var(Scope::Request, "a", 0),
+                    Action::set_var(Scope::Request, "a", n),
+                ])
+            }));
+
+        assert_eq!(
+            svc.oneshot(3).await,
+            Ok(vec![Action::set_var(Scope::Request, "a", 3)])
+        );
+    }
+}
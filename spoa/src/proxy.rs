@@ -0,0 +1,309 @@
+//! The PROXY protocol v1/v2 header sent before the HAProxy handshake.
+//!
+//! When the agent sits behind another L4 proxy, the address of the HAProxy peer is lost,
+//! the proxy is configured to send the PROXY protocol header to preserve it, for example:
+//!
+//! ```text
+//! backend spoe-agents
+//!     mode tcp
+//!     server agent1 127.0.0.1:12345 send-proxy-v2
+//! ```
+//!
+//! See <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::{
+    error::{Context as _, Result},
+    spop::Error::Invalid,
+};
+
+/// The signature of the PROXY protocol v2 header.
+pub const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// The maximum time to wait for the PROXY protocol header.
+pub const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum length of the PROXY protocol v1 header.
+pub const V1_MAX_LEN: usize = 107;
+
+/// The addresses of the original connection carried by the PROXY protocol header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// The address of the original peer, `None` if the proxy didn't know it (`UNKNOWN` or `LOCAL`).
+    pub source: Option<SocketAddr>,
+    /// The address the original peer connected to.
+    pub destination: Option<SocketAddr>,
+}
+
+/// Read the PROXY protocol v1 or v2 header, without reading past it.
+pub async fn read_header<R>(r: &mut R) -> Result<ProxyHeader>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut prefix = [0; 5];
+    r.read_exact(&mut prefix).await?;
+
+    if &prefix == b"PROXY" {
+        read_v1(r).await
+    } else if prefix == V2_SIGNATURE[..5] {
+        read_v2(r, prefix).await
+    } else {
+        Err(Invalid).context("missing PROXY protocol header")
+    }
+}
+
+async fn read_v1<R>(r: &mut R) -> Result<ProxyHeader>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut line = b"PROXY".to_vec();
+
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LEN {
+            return Err(Invalid).context("PROXY protocol v1 header too long");
+        }
+
+        line.push(r.read_u8().await?);
+    }
+
+    let line = str::from_utf8(&line[..line.len() - 2])?;
+    let mut parts = line.split(' ').skip(1);
+
+    match parts.next() {
+        Some("UNKNOWN") => Ok(ProxyHeader {
+            source: None,
+            destination: None,
+        }),
+        Some(proto @ ("TCP4" | "TCP6")) => {
+            let mut next = || {
+                parts
+                    .next()
+                    .ok_or(Invalid)
+                    .context("truncated PROXY protocol v1 header")
+            };
+
+            let src: IpAddr = next()?
+                .parse()
+                .context("PROXY protocol v1 source address")?;
+            let dst: IpAddr = next()?
+                .parse()
+                .context("PROXY protocol v1 destination address")?;
+            let sport: u16 = next()?.parse().context("PROXY protocol v1 source port")?;
+            let dport: u16 = next()?
+                .parse()
+                .context("PROXY protocol v1 destination port")?;
+
+            if parts.next().is_some()
+                || src.is_ipv4() != (proto == "TCP4")
+                || src.is_ipv4() != dst.is_ipv4()
+            {
+                return Err(Invalid).context("malformed PROXY protocol v1 header");
+            }
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(src, sport)),
+                destination: Some(SocketAddr::new(dst, dport)),
+            })
+        }
+        _ => Err(Invalid).context("unsupported PROXY protocol v1 protocol"),
+    }
+}
+
+async fn read_v2<R>(r: &mut R, prefix: [u8; 5]) -> Result<ProxyHeader>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut hdr = [0; 16];
+    hdr[..5].copy_from_slice(&prefix);
+    r.read_exact(&mut hdr[5..]).await?;
+
+    if &hdr[..12] != V2_SIGNATURE {
+        return Err(Invalid).context("bad PROXY protocol v2 signature");
+    }
+
+    let (version, command) = (hdr[12] >> 4, hdr[12] & 0x0F);
+    let (family, transport) = (hdr[13] >> 4, hdr[13] & 0x0F);
+    let len = u16::from_be_bytes([hdr[14], hdr[15]]) as usize;
+
+    if version != 2 || command > 1 {
+        return Err(Invalid).context("unsupported PROXY protocol v2 version or command");
+    }
+
+    let mut addrs = vec![0; len];
+    r.read_exact(&mut addrs).await?;
+
+    const LOCAL: u8 = 0;
+    const INET: u8 = 1;
+    const INET6: u8 = 2;
+    const STREAM: u8 = 1;
+
+    let unknown = ProxyHeader {
+        source: None,
+        destination: None,
+    };
+
+    if command == LOCAL || transport != STREAM {
+        return Ok(unknown);
+    }
+
+    match family {
+        INET if len >= 12 => {
+            let src = Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[0..4]).unwrap());
+            let dst = Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[4..8]).unwrap());
+            let sport = u16::from_be_bytes([addrs[8], addrs[9]]);
+            let dport = u16::from_be_bytes([addrs[10], addrs[11]]);
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(src.into(), sport)),
+                destination: Some(SocketAddr::new(dst.into(), dport)),
+            })
+        }
+        INET6 if len >= 36 => {
+            let src = Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[0..16]).unwrap());
+            let dst = Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[16..32]).unwrap());
+            let sport = u16::from_be_bytes([addrs[32], addrs[33]]);
+            let dport = u16::from_be_bytes([addrs[34], addrs[35]]);
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(src.into(), sport)),
+                destination: Some(SocketAddr::new(dst.into(), dport)),
+            })
+        }
+        INET | INET6 => Err(Invalid).context("truncated PROXY protocol v2 addresses"),
+        _ => Ok(unknown),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(mut buf: &[u8]) -> (Result<ProxyHeader>, &[u8]) {
+        let res = read_header(&mut buf).await;
+
+        (res, buf)
+    }
+
+    fn header(source: &str, destination: &str) -> ProxyHeader {
+        ProxyHeader {
+            source: Some(source.parse().unwrap()),
+            destination: Some(destination.parse().unwrap()),
+        }
+    }
+
+    fn v2(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.push(0x20 | command);
+        buf.push(family << 4 | 1);
+        buf.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        buf.extend_from_slice(addrs);
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_v1() {
+        let (res, rest) = parse(b"PROXY TCP4 192.168.0.1 10.0.0.1 56324 12345\r\nhello").await;
+        assert_eq!(res.unwrap(), header("192.168.0.1:56324", "10.0.0.1:12345"));
+        assert_eq!(rest, b"hello");
+
+        let (res, _) = parse(b"PROXY TCP6 2001:db8::1 ::1 56324 12345\r\n").await;
+        assert_eq!(res.unwrap(), header("[2001:db8::1]:56324", "[::1]:12345"));
+
+        let unknown = ProxyHeader {
+            source: None,
+            destination: None,
+        };
+        for line in [
+            &b"PROXY UNKNOWN\r\n"[..],
+            b"PROXY UNKNOWN ::1 ::1 56324 12345\r\n",
+        ] {
+            assert_eq!(parse(line).await.0.unwrap(), unknown);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_v1_malformed() {
+        let long = format!("PROXY TCP4 {} 10.0.0.1 1 2\r\n", "1".repeat(V1_MAX_LEN));
+        let (res, rest) = parse(long.as_bytes()).await;
+        assert_eq!(res.unwrap_err().status(), Some(Invalid));
+        // the header is not read past the max length
+        assert_eq!(rest.len(), long.len() - V1_MAX_LEN);
+
+        for line in [
+            &b"PROXY TCP4 ::1 ::1 1 2\r\n"[..],
+            b"PROXY TCP6 10.0.0.1 10.0.0.2 1 2\r\n",
+            b"PROXY TCP4 10.0.0.1 ::1 1 2\r\n",
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 1\r\n",
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 1 2 3\r\n",
+            b"PROXY TCP4 10.0.0.1 10.0.0.2 1 65536\r\n",
+            b"PROXY UDP4 10.0.0.1 10.0.0.2 1 2\r\n",
+        ] {
+            assert!(parse(line).await.0.is_err(), "{:?}", str::from_utf8(line));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_v2() {
+        let mut buf = v2(0, 0, &[]);
+        buf.extend_from_slice(b"hello");
+        let (res, rest) = parse(&buf).await;
+        assert_eq!(
+            res.unwrap(),
+            ProxyHeader {
+                source: None,
+                destination: None,
+            }
+        );
+        assert_eq!(rest, b"hello");
+
+        let inet = [192, 168, 0, 1, 10, 0, 0, 1, 0xDC, 0x04, 0x30, 0x39];
+        assert_eq!(
+            parse(&v2(1, 1, &inet)).await.0.unwrap(),
+            header("192.168.0.1:56324", "10.0.0.1:12345")
+        );
+
+        let mut inet6 = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        inet6.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        inet6.extend_from_slice(&[0xDC, 0x04, 0x30, 0x39]);
+        // the TLVs following the addresses are skipped
+        inet6.extend_from_slice(&[0x04, 0x00, 0x01, 0x00]);
+        assert_eq!(
+            parse(&v2(1, 2, &inet6)).await.0.unwrap(),
+            header("[2001:db8::1]:56324", "[::1]:12345")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_v2_malformed() {
+        // the addresses shorter than the family
+        let (res, _) = parse(&v2(1, 1, &[192, 168, 0, 1, 10, 0, 0, 1])).await;
+        assert_eq!(res.unwrap_err().status(), Some(Invalid));
+        let (res, _) = parse(&v2(1, 2, &[0; 32])).await;
+        assert_eq!(res.unwrap_err().status(), Some(Invalid));
+
+        // the header announcing more bytes than sent
+        let mut buf = v2(1, 1, &[0; 12]);
+        buf.truncate(buf.len() - 4);
+        assert!(parse(&buf).await.0.is_err());
+
+        // the bad signatures, versions and commands
+        let mut buf = v2(1, 1, &[0; 12]);
+        buf[6] = b'X';
+        assert_eq!(parse(&buf).await.0.unwrap_err().status(), Some(Invalid));
+
+        let mut buf = v2(1, 1, &[0; 12]);
+        buf[12] = 0x11;
+        assert_eq!(parse(&buf).await.0.unwrap_err().status(), Some(Invalid));
+
+        let (res, _) = parse(&v2(2, 1, &[0; 12])).await;
+        assert_eq!(res.unwrap_err().status(), Some(Invalid));
+
+        let (res, _) = parse(b"HELLO WORLD\r\n").await;
+        assert_eq!(res.unwrap_err().status(), Some(Invalid));
+    }
+}
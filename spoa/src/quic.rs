@@ -0,0 +1,220 @@
+//! Serve the agent over QUIC, experimental.
+//!
+//! Every bidirectional stream opened by the engine on a QUIC connection is served
+//! as a SPOP connection, with the same frame codec as over TCP.
+//! This is aimed at the lab setups where the agents run across lossy WAN links,
+//! HAProxy itself only connects to the agents over TCP.
+//!
+//! The engine side is `haproxy_spoe::quic::QuicConnector`.
+
+use std::error::Error as StdError;
+use std::sync::Arc;
+
+use tokio::{io::join, select};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::{BoxError, MakeService, Service};
+use tracing::{debug, debug_span, Instrument as _};
+
+use crate::{
+    error::Result,
+    runtime::Runtime,
+    spop::{Action, Message},
+    Connection, IoInfo,
+};
+
+/// An agent accepting the SPOP connections on a QUIC endpoint.
+#[derive(Debug)]
+pub struct QuicAgent<S, T> {
+    runtime: Arc<Runtime<S, T>>,
+    endpoint: quinn::Endpoint,
+    tracker: TaskTracker,
+    token: CancellationToken,
+}
+
+impl<S, T> QuicAgent<S, T> {
+    /// Serve the connections accepted by the server endpoint.
+    pub fn new(runtime: Arc<Runtime<S, T>>, endpoint: quinn::Endpoint) -> Self {
+        QuicAgent {
+            runtime,
+            endpoint,
+            tracker: TaskTracker::new(),
+            token: CancellationToken::new(),
+        }
+    }
+
+    pub fn shutdown(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+impl<S, T> QuicAgent<S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync + 'static,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send + 'static,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync + 'static,
+{
+    pub async fn serve(&self) -> Result<()> {
+        loop {
+            select! {
+                _ = self.token.cancelled() => {
+                    debug!("shutting down");
+                    break
+                }
+
+                incoming = self.endpoint.accept() => {
+                    let Some(incoming) = incoming else {
+                        debug!("endpoint closed");
+                        break;
+                    };
+
+                    self.tracker.spawn(serve_connection(
+                        self.runtime.clone(),
+                        incoming,
+                        self.token.child_token(),
+                        self.tracker.clone(),
+                    ));
+                }
+            }
+        }
+
+        self.tracker.close();
+        self.tracker.wait().await;
+
+        Ok(())
+    }
+}
+
+/// Serve every bidirectional stream of the QUIC connection as a SPOP connection.
+async fn serve_connection<S, T>(
+    runtime: Arc<Runtime<S, T>>,
+    incoming: quinn::Incoming,
+    token: CancellationToken,
+    tracker: TaskTracker,
+) where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync + 'static,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send + 'static,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync + 'static,
+{
+    let conn = match incoming.await {
+        Ok(conn) => conn,
+        Err(err) => {
+            debug!(?err, "QUIC handshake failed");
+            return;
+        }
+    };
+    let info = IoInfo {
+        peer_addr: Some(conn.remote_address()),
+        local_addr: None,
+        proxy_addr: None,
+    };
+
+    loop {
+        select! {
+            _ = token.cancelled() => break,
+
+            res = conn.accept_bi() => {
+                let (send, recv) = match res {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        debug!(peer = ?info.peer_addr, ?err, "QUIC connection closed");
+                        break;
+                    }
+                };
+
+                let stream = send.id();
+                let mut spop = Connection::new(runtime.clone(), join(recv, send), token.child_token())
+                    .with_io_info(info);
+                let span = debug_span!("quic", ulid = %spop.ulid(), peer = ?info.peer_addr, %stream);
+
+                tracker.spawn(
+                    async move {
+                        if let Err(err) = spop.serve().await {
+                            debug!(?err, "connection closed");
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use haproxy_spoe::Connector;
+    use quinn::rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+        RootCertStore,
+    };
+    use tower::{make::Shared, service_fn};
+
+    use crate::{runtime::Builder, spop::Scope};
+
+    use super::*;
+
+    const CERT: &[u8] = include_bytes!("../testdata/localhost.crt.der");
+    const KEY: &[u8] = include_bytes!("../testdata/localhost.key.der");
+
+    #[tokio::test]
+    async fn test_quic() {
+        let server = quinn::ServerConfig::with_single_cert(
+            vec![CertificateDer::from(CERT)],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(KEY)),
+        )
+        .unwrap();
+        let endpoint = quinn::Endpoint::server(server, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = endpoint.local_addr().unwrap();
+
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|msgs: Vec<Message>| async move {
+                Ok::<_, Infallible>(vec![Action::set_var(
+                    Scope::Transaction,
+                    "messages",
+                    msgs.len() as i32,
+                )])
+            })),
+            (),
+        );
+        let agent = Arc::new(QuicAgent::new(runtime, endpoint));
+        let serving = tokio::spawn({
+            let agent = agent.clone();
+
+            async move { agent.serve().await }
+        });
+
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(CERT)).unwrap();
+        let mut client = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        client.set_default_client_config(
+            quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap(),
+        );
+        let connector = Connector::new(addr.to_string()).quic(client, "localhost");
+
+        // every SPOP connection is a stream of the same QUIC connection
+        for n in 1..=2 {
+            let conn = connector.connect().await.unwrap();
+            let actions = conn
+                .notify((0..n).map(|i| Message::new("check", [("i", i)])))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                actions,
+                vec![Action::set_var(Scope::Transaction, "messages", n)]
+            );
+        }
+
+        agent.shutdown().cancel();
+        serving.await.unwrap().unwrap();
+    }
+}
@@ -1,9 +1,253 @@
+//! Extract the request samples from the message arguments.
+//!
+//! The well-known arguments are named after the HAProxy sample fetches, for example:
+//!
+//! ```text
+//! spoe-message check-request
+//!     args src=src dst=dst method=method path=path query=query ver=req.ver hdrs=req.hdrs_bin body_size=req.body_size
+//! ```
+//!
+//! ```
+//! # use haproxy_spoa::{req::Args, spop::Message};
+//! # fn check(msg: &Message) -> Result<(), haproxy_spoa::req::ArgError> {
+//! let args = Args::new(msg);
+//! let src = args.src()?;
+//! let method = args.method()?;
+//! let query = args.query()?;
+//! # Ok(())
+//! # }
+//! ```
+
 use std::iter;
+use std::net::IpAddr;
+
+use bytes::{Buf, Bytes};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Version};
+use thiserror::Error;
+
+use crate::{
+    error::Result,
+    spop::{Message, Typed},
+};
+
+/// The name of the `src` argument, the client IP address.
+pub const SRC: &str = "src";
+/// The name of the `dst` argument, the IP address the client connected to.
+pub const DST: &str = "dst";
+/// The name of the `method` argument, the HTTP method.
+pub const METHOD: &str = "method";
+/// The name of the `path` argument, the path of the request.
+pub const PATH: &str = "path";
+/// The name of the `query` argument, the query string without the question mark.
+pub const QUERY: &str = "query";
+/// The name of the `ver` argument, the HTTP version of the request, e.g. `1.1`.
+pub const VER: &str = "ver";
+/// The name of the `status` argument, the HTTP status code of the response.
+pub const STATUS: &str = "status";
+/// The name of the `body_size` argument, the size of the body.
+pub const BODY_SIZE: &str = "body_size";
+/// The name of the `hdrs` argument, the headers in the `req.hdrs_bin` format.
+pub const HDRS: &str = "hdrs";
+/// The name of the `body` argument, the body of the request.
+pub const BODY: &str = "body";
+
+/// An argument missing or not of the expected type.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum ArgError {
+    #[error("message `{message}` has no argument `{arg}`")]
+    Missing { message: String, arg: String },
+
+    #[error("argument `{arg}` of message `{message}` is {found}, expected {expected}")]
+    Type {
+        message: String,
+        arg: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+
+    #[error("argument `{arg}` of message `{message}` is invalid, {reason}")]
+    Invalid {
+        message: String,
+        arg: String,
+        reason: String,
+    },
+}
+
+/// The typed getters of the message arguments.
+#[derive(Clone, Copy, Debug)]
+pub struct Args<'a>(&'a Message);
+
+impl<'a> From<&'a Message> for Args<'a> {
+    fn from(msg: &'a Message) -> Self {
+        Args(msg)
+    }
+}
+
+impl<'a> Args<'a> {
+    pub fn new(msg: &'a Message) -> Self {
+        Args(msg)
+    }
+
+    /// Returns the value of the argument, `None` if missing or null.
+    pub fn get(&self, name: &str) -> Option<&'a Typed> {
+        self.0
+            .args
+            .iter()
+            .find(|(arg, _)| arg == name)
+            .map(|(_, value)| value)
+            .filter(|value| !matches!(value, Typed::Null))
+    }
+
+    /// Returns the IP address argument, the IPv4-mapped IPv6 addresses as IPv4.
+    pub fn ip(&self, name: &str) -> ArgResult<IpAddr> {
+        let value = self.required(name)?;
+
+        value
+            .to_ip_addr()
+            .ok_or_else(|| self.type_error(name, "an IP address", value))
+    }
+
+    /// Returns the string argument.
+    pub fn str(&self, name: &str) -> ArgResult<&'a str> {
+        match self.required(name)? {
+            Typed::String(s) => Ok(s),
+            value => Err(self.type_error(name, "a string", value)),
+        }
+    }
+
+    /// Returns the binary argument, or the bytes of the string argument.
+    pub fn bytes(&self, name: &str) -> ArgResult<Bytes> {
+        match self.required(name)? {
+            Typed::Binary(b) => Ok(b.clone()),
+            Typed::String(s) => Ok(Bytes::copy_from_slice(s.as_bytes())),
+            value => Err(self.type_error(name, "a binary", value)),
+        }
+    }
+
+    /// Returns the integer argument, of any integer type.
+    pub fn int(&self, name: &str) -> ArgResult<i64> {
+        match self.required(name)? {
+            value @ (Typed::Int32(_) | Typed::Uint32(_) | Typed::Int64(_) | Typed::Uint64(_)) => {
+                value
+                    .to_i64()
+                    .ok_or_else(|| self.invalid(name, "out of range of i64"))
+            }
+            value => Err(self.type_error(name, "an integer", value)),
+        }
+    }
+
+    /// Returns the client IP address.
+    pub fn src(&self) -> ArgResult<IpAddr> {
+        self.ip(SRC)
+    }
+
+    /// Returns the IP address the client connected to.
+    pub fn dst(&self) -> ArgResult<IpAddr> {
+        self.ip(DST)
+    }
+
+    /// Returns the HTTP method.
+    pub fn method(&self) -> ArgResult<Method> {
+        self.str(METHOD)?
+            .parse()
+            .map_err(|err| self.invalid(METHOD, err))
+    }
+
+    /// Returns the path of the request.
+    pub fn path(&self) -> ArgResult<&'a str> {
+        self.str(PATH)
+    }
 
-use bytes::Buf;
-use http::{HeaderMap, HeaderName, HeaderValue};
+    /// Returns the query string, `None` if the request has no query string.
+    pub fn query(&self) -> ArgResult<Option<&'a str>> {
+        if self.get(QUERY).is_none() {
+            return Ok(None);
+        }
+
+        self.str(QUERY)
+            .map(|query| Some(query).filter(|q| !q.is_empty()))
+    }
+
+    /// Returns the HTTP version of the request.
+    pub fn ver(&self) -> ArgResult<Version> {
+        match self.str(VER)? {
+            "0.9" => Ok(Version::HTTP_09),
+            "1.0" => Ok(Version::HTTP_10),
+            "1.1" => Ok(Version::HTTP_11),
+            "2" | "2.0" => Ok(Version::HTTP_2),
+            "3" | "3.0" => Ok(Version::HTTP_3),
+            ver => Err(self.invalid(VER, format!("unknown HTTP version {ver}"))),
+        }
+    }
+
+    /// Returns the HTTP status code of the response.
+    pub fn status(&self) -> ArgResult<StatusCode> {
+        let status = self.int(STATUS)?;
+
+        u16::try_from(status)
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .ok_or_else(|| self.invalid(STATUS, format!("unknown status code {status}")))
+    }
+
+    /// Returns the size of the body.
+    pub fn body_size(&self) -> ArgResult<u64> {
+        let size = self.int(BODY_SIZE)?;
 
-use crate::error::Result;
+        u64::try_from(size).map_err(|err| self.invalid(BODY_SIZE, err))
+    }
+
+    /// Returns the headers, sent in the `req.hdrs_bin` format.
+    pub fn hdrs(&self) -> ArgResult<HeaderMap> {
+        hdrs_bin(self.bytes(HDRS)?).map_err(|err| self.invalid(HDRS, err))
+    }
+
+    /// Returns the body, empty if the request has no body.
+    pub fn body(&self) -> ArgResult<Bytes> {
+        if self.get(BODY).is_none() {
+            return Ok(Bytes::new());
+        }
+
+        self.bytes(BODY)
+    }
+
+    fn required(&self, name: &str) -> ArgResult<&'a Typed> {
+        self.get(name).ok_or_else(|| ArgError::Missing {
+            message: self.0.name.clone(),
+            arg: name.to_string(),
+        })
+    }
+
+    fn type_error(&self, name: &str, expected: &'static str, value: &Typed) -> ArgError {
+        ArgError::Type {
+            message: self.0.name.clone(),
+            arg: name.to_string(),
+            expected,
+            found: type_name(value),
+        }
+    }
+
+    fn invalid<E: ToString>(&self, name: &str, reason: E) -> ArgError {
+        ArgError::Invalid {
+            message: self.0.name.clone(),
+            arg: name.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+type ArgResult<T> = std::result::Result<T, ArgError>;
+
+fn type_name(value: &Typed) -> &'static str {
+    match value {
+        Typed::Null => "null",
+        Typed::Boolean(_) => "a boolean",
+        Typed::Int32(_) | Typed::Uint32(_) | Typed::Int64(_) | Typed::Uint64(_) => "an integer",
+        Typed::Ipv4(_) | Typed::Ipv6(_) => "an IP address",
+        Typed::String(_) => "a string",
+        Typed::Binary(_) => "a binary",
+    }
+}
 
 pub fn hdrs_bin<T: Buf>(mut b: T) -> Result<HeaderMap> {
     let mut hdrs = HeaderMap::new();
@@ -29,3 +273,48 @@ pub fn hdrs_bin<T: Buf>(mut b: T) -> Result<HeaderMap> {
 
     Ok(hdrs)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        let msg = Message::builder("check-request")
+            .arg(SRC, IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .arg(METHOD, "POST")
+            .arg(PATH, "/login")
+            .arg(QUERY, ())
+            .arg(VER, "1.1")
+            .arg(STATUS, 404)
+            .arg(BODY_SIZE, 42i64)
+            .arg(HDRS, &b"\x04host\x07example\x00\x00"[..])
+            .build();
+        let args = Args::new(&msg);
+
+        assert_eq!(args.src(), Ok(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(args.method(), Ok(Method::POST));
+        assert_eq!(args.path(), Ok("/login"));
+        assert_eq!(args.query(), Ok(None));
+        assert_eq!(args.ver(), Ok(Version::HTTP_11));
+        assert_eq!(args.status(), Ok(StatusCode::NOT_FOUND));
+        assert_eq!(args.body_size(), Ok(42));
+        assert_eq!(args.hdrs().unwrap()["host"], "example");
+        assert_eq!(args.body(), Ok(Bytes::new()));
+
+        assert_eq!(
+            args.dst().unwrap_err().to_string(),
+            "message `check-request` has no argument `dst`"
+        );
+        assert_eq!(
+            args.ip(PATH).unwrap_err().to_string(),
+            "argument `path` of message `check-request` is a string, expected an IP address"
+        );
+        assert!(matches!(
+            Args::new(&Message::new("m", [(STATUS, 1000)])).status(),
+            Err(ArgError::Invalid { .. })
+        ));
+    }
+}
@@ -0,0 +1,224 @@
+//! Route the messages to the services by their names.
+//!
+//! HAProxy may send messages the agent doesn't know, for example after the SPOE configuration
+//! has been changed, how to handle them is configured with [`Unmatched`].
+//!
+//! The router is cloned for every connection, use `tower::make::Shared` to serve it.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use derive_more::Debug;
+use futures::future::{try_join_all, BoxFuture, FutureExt as _};
+use tower::{util::BoxCloneSyncService, BoxError, Service, ServiceExt as _};
+use tracing::warn;
+
+use crate::spop::{Action, Message};
+
+/// A service handling a single message.
+pub type MessageService = BoxCloneSyncService<Message, Vec<Action>, BoxError>;
+
+/// How to handle the messages without a route.
+#[derive(Clone, Debug)]
+pub enum Unmatched {
+    /// Ignore the messages, only counting them.
+    Ignore,
+    /// Ignore the messages, logging one of every `n` of them.
+    LogSample(u64),
+    /// Invoke the catch-all service.
+    #[debug("Fallback")]
+    Fallback(MessageService),
+}
+
+impl Default for Unmatched {
+    fn default() -> Self {
+        Unmatched::LogSample(100)
+    }
+}
+
+/// A service routing the messages to the services by their names.
+#[derive(Clone, Debug, Default)]
+pub struct Router {
+    #[debug("{:?}", routes.keys())]
+    routes: HashMap<String, MessageService>,
+    unmatched: Unmatched,
+    unmatched_count: Arc<AtomicU64>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    /// Route the messages with the name to the service.
+    pub fn route<N, S>(mut self, name: N, svc: S) -> Self
+    where
+        N: Into<String>,
+        S: Service<Message, Response = Vec<Action>> + Clone + Send + Sync + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        self.routes.insert(
+            name.into(),
+            BoxCloneSyncService::new(svc.map_err(Into::into)),
+        );
+        self
+    }
+
+    /// Set how to handle the messages without a route.
+    pub fn unmatched(mut self, unmatched: Unmatched) -> Self {
+        self.unmatched = unmatched;
+        self
+    }
+
+    /// Invoke the catch-all service for the messages without a route.
+    pub fn fallback<S>(self, svc: S) -> Self
+    where
+        S: Service<Message, Response = Vec<Action>> + Clone + Send + Sync + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send + 'static,
+    {
+        self.unmatched(Unmatched::Fallback(BoxCloneSyncService::new(
+            svc.map_err(Into::into),
+        )))
+    }
+
+    /// Returns the number of messages without a route, shared by all the clones of the router.
+    pub fn unmatched_count(&self) -> u64 {
+        self.unmatched_count.load(Relaxed)
+    }
+
+    fn dispatch(&self, msg: Message) -> Option<BoxFuture<'static, Result<Vec<Action>, BoxError>>> {
+        if let Some(svc) = self.routes.get(&msg.name) {
+            return Some(svc.clone().oneshot(msg).boxed());
+        }
+
+        let n = self.unmatched_count.fetch_add(1, Relaxed);
+
+        match self.unmatched {
+            Unmatched::Ignore => None,
+            Unmatched::LogSample(every) => {
+                if n.is_multiple_of(every.max(1)) {
+                    warn!(name = %msg.name, unmatched = n + 1, "unknown message");
+                }
+
+                None
+            }
+            Unmatched::Fallback(ref svc) => Some(svc.clone().oneshot(msg).boxed()),
+        }
+    }
+}
+
+impl Service<Vec<Message>> for Router {
+    type Response = Vec<Action>;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Vec<Action>, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, msgs: Vec<Message>) -> Self::Future {
+        let calls = msgs
+            .into_iter()
+            .flat_map(|msg| self.dispatch(msg))
+            .collect::<Vec<_>>();
+
+        async move {
+            let actions = try_join_all(calls).await?;
+
+            Ok(actions.into_iter().flatten().collect())
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tower::service_fn;
+
+    use crate::spop::Scope;
+
+    use super::*;
+
+    fn score(n: i32) -> MessageService {
+        BoxCloneSyncService::new(service_fn(move |msg: Message| async move {
+            Ok(vec![Action::set_var(
+                Scope::Transaction,
+                format!("{}_score", msg.name),
+                n,
+            )])
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_route() {
+        let router = Router::new()
+            .route("ip", score(10))
+            .route("ua", score(20))
+            .unmatched(Unmatched::Ignore);
+
+        let actions = router
+            .clone()
+            .oneshot(vec![
+                Message::new("ua", [("n", 1)]),
+                Message::new("unknown", [("n", 1)]),
+                Message::new("ip", [("n", 1)]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            actions,
+            [
+                Action::set_var(Scope::Transaction, "ua_score", 20),
+                Action::set_var(Scope::Transaction, "ip_score", 10),
+            ]
+        );
+
+        // the unmatched messages are counted across the clones
+        assert!(router
+            .clone()
+            .oneshot(vec![Message::new("unknown", [("n", 1)])])
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(router.unmatched_count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let router = Router::new().route("ip", score(10)).fallback(score(0));
+
+        let actions = router
+            .clone()
+            .oneshot(vec![
+                Message::new("ip", [("n", 1)]),
+                Message::new("unknown", [("n", 1)]),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            actions,
+            [
+                Action::set_var(Scope::Transaction, "ip_score", 10),
+                Action::set_var(Scope::Transaction, "unknown_score", 0),
+            ]
+        );
+        assert_eq!(router.unmatched_count(), 1);
+
+        // a failed route fails the whole frame
+        let router = router.route(
+            "ua",
+            service_fn(|_: Message| async { Err::<Vec<Action>, BoxError>("failed".into()) }),
+        );
+        assert!(router
+            .oneshot(vec![
+                Message::new("ip", [("n", 1)]),
+                Message::new("ua", [("n", 1)]),
+            ])
+            .await
+            .is_err());
+    }
+}
@@ -1,65 +1,289 @@
-use derive_more::Into;
-use tokio::sync::oneshot;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::sync::{
+    oneshot::{self, error::RecvError},
+    OwnedSemaphorePermit, Semaphore,
+};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     error::{Error::Closed, Result},
-    spop::{Action, AgentAck, FrameId, Scope, StreamId, Typed},
+    runtime::{DropReason, Drops, VarGuard},
+    spop::{Action, AgentAck, Error as Status, FrameId, Scope, StreamId, Typed},
 };
 
 #[derive(Debug)]
-pub struct Acker(Option<Inner>);
+pub struct Acker {
+    inner: Option<Inner>,
+    guard: Option<Arc<VarGuard>>,
+    aborted: CancellationToken,
+    done: CancellationToken,
+    deadline: Option<Instant>,
+    outgoing: Option<Arc<Semaphore>>,
+    drops: Option<Arc<Drops>>,
+}
 
 #[derive(Debug)]
-struct Inner(AgentAck, oneshot::Sender<AgentAck>);
+struct Inner(AgentAck, oneshot::Sender<Outgoing>);
+
+/// The ACK frame, holding its slot in the outgoing queue until it is received.
+type Outgoing = (AgentAck, Option<OwnedSemaphorePermit>);
+
+/// Resolved with the ACK frame once the frame is completed or aborted by its [`Acker`].
+#[derive(Debug)]
+pub struct Acked(oneshot::Receiver<Outgoing>);
+
+impl Future for Acked {
+    type Output = std::result::Result<AgentAck, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0)
+            .poll(cx)
+            .map(|res| res.map(|(ack, _permit)| ack))
+    }
+}
 
 impl Drop for Acker {
     fn drop(&mut self) {
-        let _ = self.complete();
+        if self.inner.is_some() && self.expire().is_ok() {
+            // the acker can't wait for the outgoing queue when dropped
+            let permit = self
+                .outgoing
+                .clone()
+                .and_then(|outgoing| outgoing.try_acquire_owned().ok());
+
+            let _ = self.send(permit);
+        }
+
+        self.done.cancel();
     }
 }
 
 impl Acker {
-    pub fn new(stream_id: StreamId, frame_id: FrameId) -> (Self, oneshot::Receiver<AgentAck>) {
+    pub fn new(stream_id: StreamId, frame_id: FrameId) -> (Self, Acked) {
         let (sender, receiver) = oneshot::channel();
         (
-            Acker(Some(Inner(AgentAck::new(stream_id, frame_id), sender))),
-            receiver,
+            Acker {
+                inner: Some(Inner(AgentAck::new(stream_id, frame_id), sender)),
+                guard: None,
+                aborted: CancellationToken::new(),
+                done: CancellationToken::new(),
+                deadline: None,
+                outgoing: None,
+                drops: None,
+            },
+            Acked(receiver),
         )
     }
 
-    pub fn complete(&mut self) -> Result<()> {
-        if let Some(Inner(ack, sender)) = self.0.take() {
-            sender.send(ack).map_err(|_| Closed)
-        } else {
-            Err(Closed)
+    /// Enforce the max length of the variable values.
+    pub fn with_guard(mut self, guard: Option<Arc<VarGuard>>) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Fail fast when the frame is completed after the deadline, since HAProxy gave up on it.
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Wait for a slot in the bounded outgoing queue when completing the frame.
+    pub fn with_outgoing(mut self, outgoing: Option<Arc<Semaphore>>) -> Self {
+        self.outgoing = outgoing;
+        self
+    }
+
+    /// Abort the frame when the token of its stream is cancelled.
+    pub fn with_cancellation(mut self, stream: &CancellationToken) -> Self {
+        self.aborted = stream.child_token();
+        self
+    }
+
+    /// Count the ACK frames dropped.
+    pub(crate) fn with_drops(mut self, drops: Arc<Drops>) -> Self {
+        self.drops = Some(drops);
+        self
+    }
+
+    /// Returns a token cancelled when the frame is aborted,
+    /// to stop forwarding the following fragments of the frame.
+    pub fn aborted(&self) -> CancellationToken {
+        self.aborted.clone()
+    }
+
+    /// Returns a token cancelled once the ACK frame is sent or dropped,
+    /// whether the frame was completed, aborted or its acker dropped.
+    pub fn done(&self) -> CancellationToken {
+        self.done.clone()
+    }
+
+    /// Send the ACK frame, waiting for a slot when the outgoing queue is full.
+    ///
+    /// Completing an aborted frame is a no-op, and the ACK frame is dropped
+    /// with a timeout error when the deadline expired.
+    pub async fn complete(&mut self) -> Result<()> {
+        if !self.pending()? {
+            return Ok(());
+        }
+
+        let permit = match self.outgoing {
+            Some(ref outgoing) => Some(outgoing.clone().acquire_owned().await.map_err(|_| Closed)?),
+            None => None,
+        };
+
+        // the frame may be aborted or expire while waiting for the outgoing queue
+        if !self.pending()? {
+            return Ok(());
         }
+
+        self.send(permit)
     }
 
+    /// Abort the processing of the frame, replying an ACK frame with the ABORT and FIN flags,
+    /// the actions set before are discarded.
     pub fn abort(&mut self) -> Result<()> {
-        if let Some(Inner(mut ack, sender)) = self.0.take() {
-            ack.aborted = true;
-            sender.send(ack).map_err(|_| Closed)
+        if let Some(Inner(ack, sender)) = self.inner.take() {
+            self.aborted.cancel();
+            self.done.cancel();
+
+            sender
+                .send((AgentAck::abort(ack.stream_id, ack.frame_id), None))
+                .map_err(|_| Closed)
         } else {
             Err(Closed)
         }
     }
 
     pub fn set_var<S: Into<String>, V: Into<Typed>>(&mut self, scope: Scope, name: S, value: V) {
-        if let Some(Inner(ref mut ack, _)) = self.0 {
-            ack.actions.push(Action::SetVar {
-                scope,
-                name: name.into(),
-                value: value.into(),
-            });
+        if let Some(Inner(ref mut ack, _)) = self.inner {
+            let action = Action::set_var(scope, name, value);
+
+            match self.guard {
+                Some(ref guard) => guard.push(&mut ack.actions, action),
+                None => ack.actions.push(action),
+            }
         }
     }
 
     pub fn unset_var<S: Into<String>>(&mut self, scope: Scope, name: S) {
-        if let Some(Inner(ref mut ack, _)) = self.0 {
+        if let Some(Inner(ref mut ack, _)) = self.inner {
             ack.actions.push(Action::UnsetVar {
                 scope,
                 name: name.into(),
             });
         }
     }
+
+    /// Drop the ACK frame of an aborted frame or after the deadline.
+    ///
+    /// Returns an error only when the deadline expired, the aborted frames are silently completed.
+    fn expire(&mut self) -> Result<()> {
+        let reason = if self.aborted.is_cancelled() {
+            DropReason::CancelledAck
+        } else if self
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now())
+        {
+            DropReason::ExpiredAck
+        } else {
+            return Ok(());
+        };
+
+        if self.inner.take().is_some() {
+            self.done.cancel();
+
+            if let Some(ref drops) = self.drops {
+                drops.record(reason, 1);
+            }
+        }
+
+        match reason {
+            DropReason::ExpiredAck => Err(Status::Timeout.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the ACK frame is still to be sent, `false` if the frame was aborted.
+    fn pending(&mut self) -> Result<bool> {
+        self.expire()?;
+
+        match self.inner {
+            Some(_) => Ok(true),
+            None if self.aborted.is_cancelled() => Ok(false),
+            None => Err(Closed),
+        }
+    }
+
+    fn send(&mut self, permit: Option<OwnedSemaphorePermit>) -> Result<()> {
+        if let Some(Inner(ack, sender)) = self.inner.take() {
+            self.done.cancel();
+
+            sender.send((ack, permit)).map_err(|_| Closed)
+        } else {
+            Err(Closed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_complete() {
+        let drops = Arc::new(Drops::default());
+        let outgoing = Arc::new(Semaphore::new(1));
+        let ack = |frame_id| {
+            let (acker, acked) = Acker::new(1, frame_id);
+
+            (
+                acker
+                    .with_outgoing(Some(outgoing.clone()))
+                    .with_drops(drops.clone()),
+                acked,
+            )
+        };
+
+        let (mut acker, first) = ack(1);
+        acker.set_var(Scope::Transaction, "foo", "bar");
+        acker.complete().await.unwrap();
+        assert!(acker.complete().await.is_err());
+
+        // the outgoing queue is full until the first ACK frame is received
+        let (mut acker, second) = ack(2);
+        let completing = tokio::spawn(async move { acker.complete().await });
+        tokio::task::yield_now().await;
+        assert!(!completing.is_finished());
+
+        assert_eq!(first.await.unwrap().actions.len(), 1);
+        completing.await.unwrap().unwrap();
+        assert_eq!(second.await.unwrap().frame_id, 2);
+
+        // completing an aborted frame is a no-op
+        let stream = CancellationToken::new();
+        let (acker, cancelled) = ack(3);
+        let mut acker = acker.with_cancellation(&stream);
+        stream.cancel();
+        assert!(acker.aborted().is_cancelled());
+        acker.complete().await.unwrap();
+        assert!(cancelled.await.is_err());
+
+        // the expired frames fail fast
+        let (acker, expired) = ack(4);
+        let mut acker = acker.with_deadline(Some(Instant::now() - Duration::from_millis(1)));
+        assert!(acker.complete().await.is_err());
+        drop(acker);
+        assert!(expired.await.is_err());
+
+        let drops = drops.snapshot();
+        assert_eq!(drops.get(DropReason::CancelledAck), 1);
+        assert_eq!(drops.get(DropReason::ExpiredAck), 1);
+    }
 }
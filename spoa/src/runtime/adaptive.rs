@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use dashmap::DashMap;
+use tracing::debug;
+
+use crate::metrics;
+
+/// The number of recent processing times kept for every kind of frame.
+pub const ADAPTIVE_WINDOW: usize = 100;
+
+/// The number of processing times observed before adapting the timeout of a kind of frame.
+pub const ADAPTIVE_MIN_SAMPLES: usize = 20;
+
+/// Adapt the processing timeout of the frames to their recent processing times,
+/// instead of the fixed `max_process_time`.
+///
+/// The frames are keyed by the names of their messages, e.g. `check-client-ip,check-headers`.
+/// The expected latency of every kind of frame is a percentile of its recent processing times:
+///
+/// - the frames are timed out after the expected latency with some headroom,
+///   within the deadline budget of `max_process_time` and the HAProxy `timeout processing`;
+/// - the frames are shed with an empty ACK frame once the expected latency is close to
+///   the deadline budget, since HAProxy would likely time them out anyway.
+///   One of every [`probe_interval`](AdaptiveTimeout::probe_interval) frames is still processed,
+///   to notice when the latency recovers.
+#[derive(Debug)]
+pub struct AdaptiveTimeout {
+    percentile: f64,
+    headroom: f64,
+    min_timeout: Duration,
+    shed_ratio: f64,
+    probe_interval: u64,
+    frames: DashMap<String, Arc<Mutex<Window>>>,
+}
+
+/// The decision for a frame, see [`AdaptiveTimeout::decide`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Process the frame within the timeout.
+    Process(Duration),
+    /// Answer the frame with an empty ACK frame without processing it.
+    Shed,
+}
+
+/// The adaptive state of a kind of frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdaptiveState {
+    /// The names of the messages of the frames.
+    pub messages: String,
+    /// The number of recent processing times.
+    pub samples: usize,
+    /// The percentile of the recent processing times, once enough were observed.
+    pub expected: Option<Duration>,
+    /// The last effective timeout.
+    pub timeout: Option<Duration>,
+    /// Whether the frames are being shed.
+    pub shedding: bool,
+    /// The number of frames shed.
+    pub shed: u64,
+}
+
+#[derive(Debug, Default)]
+struct Window {
+    latencies: VecDeque<Duration>,
+    expected: Option<Duration>,
+    timeout: Option<Duration>,
+    shedding: bool,
+    skipped: u64,
+    shed: u64,
+}
+
+impl Default for AdaptiveTimeout {
+    fn default() -> Self {
+        AdaptiveTimeout {
+            percentile: 99.0,
+            headroom: 2.0,
+            min_timeout: Duration::from_millis(10),
+            shed_ratio: 0.8,
+            probe_interval: 10,
+            frames: DashMap::new(),
+        }
+    }
+}
+
+impl AdaptiveTimeout {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The percentile of the recent processing times used as the expected latency, `99` by default.
+    pub fn percentile(mut self, percentile: f64) -> Self {
+        self.percentile = percentile.clamp(0.0, 100.0);
+        self
+    }
+
+    /// The multiplier of the expected latency giving the timeout, `2` by default.
+    pub fn headroom(mut self, headroom: f64) -> Self {
+        self.headroom = headroom.max(1.0);
+        self
+    }
+
+    /// The lower bound of the timeout, `10ms` by default.
+    pub fn min_timeout(mut self, timeout: Duration) -> Self {
+        self.min_timeout = timeout;
+        self
+    }
+
+    /// Shed the frames when the expected latency exceeds the ratio of the deadline budget,
+    /// `0.8` by default.
+    pub fn shed_ratio(mut self, ratio: f64) -> Self {
+        self.shed_ratio = ratio;
+        self
+    }
+
+    /// Process one of every `n` frames while shedding, `10` by default.
+    pub fn probe_interval(mut self, n: u64) -> Self {
+        self.probe_interval = n.max(1);
+        self
+    }
+
+    fn window(&self, messages: &str) -> Arc<Mutex<Window>> {
+        if let Some(window) = self.frames.get(messages) {
+            return window.clone();
+        }
+
+        self.frames.entry(messages.to_string()).or_default().clone()
+    }
+
+    /// Decide how to handle a frame of the messages, within the deadline budget.
+    pub fn decide(&self, messages: &str, budget: Duration) -> Decision {
+        let window = self.window(messages);
+        let mut window = window.lock().unwrap();
+
+        let Some(expected) = window.expected else {
+            return Decision::Process(budget);
+        };
+
+        let shedding = expected.as_secs_f64() >= budget.as_secs_f64() * self.shed_ratio;
+        if shedding != window.shedding {
+            debug!(messages, ?expected, ?budget, shedding, "adaptive shedding");
+
+            window.shedding = shedding;
+            window.skipped = 0;
+        }
+
+        if shedding {
+            window.skipped += 1;
+
+            if !window.skipped.is_multiple_of(self.probe_interval) {
+                window.shed += 1;
+                metrics::message_counter(metrics::ADAPTIVE_SHED, messages, 1);
+
+                return Decision::Shed;
+            }
+        }
+
+        let timeout = expected
+            .mul_f64(self.headroom)
+            .clamp(self.min_timeout.min(budget), budget);
+        if window.timeout != Some(timeout) {
+            window.timeout = Some(timeout);
+            metrics::message_gauge(
+                metrics::ADAPTIVE_TIMEOUT_SECONDS,
+                messages,
+                timeout.as_secs_f64(),
+            );
+        }
+
+        Decision::Process(timeout)
+    }
+
+    /// Record the processing time of a frame of the messages, the timed out ones included.
+    pub(crate) fn record(&self, messages: &str, latency: Duration) {
+        let window = self.window(messages);
+        let mut window = window.lock().unwrap();
+
+        if window.latencies.len() == ADAPTIVE_WINDOW {
+            window.latencies.pop_front();
+        }
+        window.latencies.push_back(latency);
+
+        window.expected = (window.latencies.len() >= ADAPTIVE_MIN_SAMPLES).then(|| {
+            let mut latencies = window.latencies.iter().copied().collect::<Vec<_>>();
+            latencies.sort_unstable();
+
+            let rank = (latencies.len() as f64 * self.percentile / 100.0).ceil() as usize;
+            latencies[rank.clamp(1, latencies.len()) - 1]
+        });
+    }
+
+    /// Returns the adaptive state of all the kinds of frames seen so far, sorted by messages.
+    pub fn snapshot(&self) -> Vec<AdaptiveState> {
+        let mut states = self
+            .frames
+            .iter()
+            .map(|entry| {
+                let window = entry.value().lock().unwrap();
+
+                AdaptiveState {
+                    messages: entry.key().clone(),
+                    samples: window.latencies.len(),
+                    expected: window.expected,
+                    timeout: window.timeout,
+                    shedding: window.shedding,
+                    shed: window.shed,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        states.sort_by(|a, b| a.messages.cmp(&b.messages));
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUDGET: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_adaptive_timeout() {
+        let adaptive = AdaptiveTimeout::new().probe_interval(4);
+        let ms = Duration::from_millis;
+
+        // the full budget until enough processing times were observed
+        assert_eq!(adaptive.decide("check", BUDGET), Decision::Process(BUDGET));
+        for _ in 0..ADAPTIVE_MIN_SAMPLES {
+            adaptive.record("check", ms(50));
+        }
+        assert_eq!(adaptive.decide("check", BUDGET), Decision::Process(ms(100)));
+        assert_eq!(adaptive.decide("other", BUDGET), Decision::Process(BUDGET));
+
+        // the timeout is bounded by the budget and the min timeout
+        assert_eq!(adaptive.decide("check", ms(80)), Decision::Process(ms(80)));
+        for _ in 0..ADAPTIVE_WINDOW {
+            adaptive.record("check", ms(1));
+        }
+        assert_eq!(adaptive.decide("check", BUDGET), Decision::Process(ms(10)));
+
+        // the frames trending toward the deadline are shed, but a few probes
+        for _ in 0..ADAPTIVE_WINDOW {
+            adaptive.record("check", ms(900));
+        }
+        let decisions = (0..8)
+            .map(|_| adaptive.decide("check", BUDGET))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decisions
+                .iter()
+                .filter(|decision| **decision == Decision::Shed)
+                .count(),
+            6
+        );
+        assert_eq!(decisions[3], Decision::Process(BUDGET));
+
+        let states = adaptive.snapshot();
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].messages, "check");
+        assert_eq!(states[0].samples, ADAPTIVE_WINDOW);
+        assert_eq!(states[0].expected, Some(ms(900)));
+        assert!(states[0].shedding);
+        assert_eq!(states[0].shed, 6);
+
+        // the latency recovers
+        for _ in 0..ADAPTIVE_WINDOW {
+            adaptive.record("check", ms(20));
+        }
+        assert_eq!(adaptive.decide("check", BUDGET), Decision::Process(ms(40)));
+        assert!(!adaptive.snapshot()[0].shedding);
+    }
+}
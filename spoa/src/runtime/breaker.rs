@@ -0,0 +1,251 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+use crate::metrics;
+
+/// The transaction variable set to `true` in the ACK frames while the circuit breaker
+/// of the service factory is open, with the [`BreakerPolicy::FailOpen`] policy.
+pub const DEGRADED_VAR: &str = "spoa_degraded";
+
+/// Stop calling the `MakeService` once it repeatedly failed, instead of failing
+/// every connection after waiting for it.
+///
+/// - the breaker opens after [`threshold`](CircuitBreaker::threshold) consecutive failures;
+/// - while open, the connections are handled by the [`BreakerPolicy`] without making a service;
+/// - once the [`cooldown`](CircuitBreaker::cooldown) elapsed, the breaker is half-open and
+///   a single connection probes the `MakeService`, closing the breaker on success
+///   or opening it again on failure.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    policy: BreakerPolicy,
+    inner: Mutex<Inner>,
+}
+
+/// How the connections are handled while the circuit breaker is open.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BreakerPolicy {
+    /// Reject the handshakes with the `ResourceAllocErr` status,
+    /// and close the connections failing to re-make their service.
+    #[default]
+    FailClosed,
+    /// Complete the handshakes, answering the NOTIFY frames with an ACK frame only setting
+    /// the [`DEGRADED_VAR`] variable until the service is made.
+    FailOpen,
+}
+
+/// The state of a [`CircuitBreaker`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BreakerState {
+    /// The services are made.
+    #[default]
+    Closed,
+    /// The services are not made until the cooldown elapsed.
+    Open,
+    /// A single service is made to probe the `MakeService`.
+    HalfOpen,
+}
+
+impl BreakerState {
+    fn gauge(self) -> f64 {
+        match self {
+            BreakerState::Closed => 0.0,
+            BreakerState::Open => 1.0,
+            BreakerState::HalfOpen => 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    state: BreakerState,
+    failures: u32,
+    opened_at: Option<Instant>,
+    probing: bool,
+    rejected: u64,
+}
+
+/// A snapshot of a [`CircuitBreaker`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BreakerSnapshot {
+    pub state: BreakerState,
+    /// The number of consecutive failures of the `MakeService`.
+    pub failures: u32,
+    /// The number of services not made while the breaker was open.
+    pub rejected: u64,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker {
+            threshold: 5,
+            cooldown: Duration::from_secs(10),
+            policy: BreakerPolicy::default(),
+            inner: Mutex::default(),
+        }
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open the breaker after the number of consecutive failures, `5` by default.
+    pub fn threshold(mut self, n: u32) -> Self {
+        self.threshold = n.max(1);
+        self
+    }
+
+    /// Probe the `MakeService` once the breaker was open for the duration, `10s` by default.
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Handle the connections with the policy while the breaker is open.
+    pub fn policy(mut self, policy: BreakerPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns whether the connections are served without a service while the breaker is open.
+    pub(crate) fn fail_open(&self) -> bool {
+        self.policy == BreakerPolicy::FailOpen
+    }
+
+    /// Returns the current state of the breaker.
+    pub fn state(&self) -> BreakerState {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Returns a snapshot of the breaker.
+    pub fn snapshot(&self) -> BreakerSnapshot {
+        let inner = self.inner.lock().unwrap();
+
+        BreakerSnapshot {
+            state: inner.state,
+            failures: inner.failures,
+            rejected: inner.rejected,
+        }
+    }
+
+    /// Returns whether a service may be made, moving to the half-open state once the cooldown elapsed.
+    ///
+    /// The caller must [`record`](CircuitBreaker::record) the outcome once allowed.
+    pub(crate) fn try_acquire(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+
+        let allowed = match inner.state {
+            BreakerState::Closed => true,
+            BreakerState::Open
+                if inner
+                    .opened_at
+                    .is_some_and(|opened_at| opened_at.elapsed() >= self.cooldown) =>
+            {
+                debug!("circuit breaker half-open");
+
+                inner.probing = true;
+                Self::transition(&mut inner, BreakerState::HalfOpen);
+                true
+            }
+            BreakerState::HalfOpen if !inner.probing => {
+                inner.probing = true;
+                true
+            }
+            BreakerState::Open | BreakerState::HalfOpen => false,
+        };
+
+        if !allowed {
+            inner.rejected += 1;
+            metrics::counter(metrics::BREAKER_REJECTED, 1);
+        }
+
+        allowed
+    }
+
+    /// Record the outcome of making a service.
+    pub(crate) fn record(&self, ok: bool) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.probing = false;
+
+        if ok {
+            inner.failures = 0;
+            if inner.state != BreakerState::Closed {
+                debug!("circuit breaker closed");
+
+                Self::transition(&mut inner, BreakerState::Closed);
+            }
+        } else {
+            inner.failures = inner.failures.saturating_add(1);
+
+            if inner.state == BreakerState::HalfOpen || inner.failures >= self.threshold {
+                if inner.state != BreakerState::Open {
+                    warn!(failures = inner.failures, "circuit breaker open");
+                }
+
+                inner.opened_at = Some(Instant::now());
+                Self::transition(&mut inner, BreakerState::Open);
+            }
+        }
+    }
+
+    fn transition(inner: &mut Inner, state: BreakerState) {
+        inner.state = state;
+        metrics::set_gauge(metrics::BREAKER_STATE, state.gauge());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new()
+            .threshold(2)
+            .cooldown(Duration::from_millis(20));
+
+        assert!(breaker.try_acquire());
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        // a success resets the consecutive failures
+        assert!(breaker.try_acquire());
+        breaker.record(true);
+        for _ in 0..2 {
+            assert!(breaker.try_acquire());
+            breaker.record(false);
+        }
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.try_acquire());
+
+        // a single probe once the cooldown elapsed
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.try_acquire());
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        assert!(!breaker.try_acquire());
+
+        // the failed probe opens the breaker again
+        breaker.record(false);
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!breaker.try_acquire());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(breaker.try_acquire());
+        breaker.record(true);
+
+        assert_eq!(
+            breaker.snapshot(),
+            BreakerSnapshot {
+                state: BreakerState::Closed,
+                failures: 0,
+                rejected: 3,
+            }
+        );
+    }
+}
@@ -1,11 +1,21 @@
 use std::{collections::HashSet, sync::Arc, time::Duration};
 
+use derive_more::Debug;
 use haproxy_spop::{Action, Message};
+use tokio::sync::Semaphore;
 use tower::MakeService;
 
 use crate::{
-    runtime::{Runtime, MAX_PROCESS_TIME},
-    spop::{Capability, Version, MAX_FRAME_SIZE},
+    accept::{Accept, AcceptFilter},
+    defaults::DefaultActions,
+    fault::FaultInjection,
+    runtime::{
+        AdaptiveTimeout, CircuitBreaker, EngineStats, HandshakeLimits, Oversized, Runtime, Shard,
+        Supervisor, VarGuard, Watchdog, WriteScheduler, MAX_BACKOFF, MAX_PROCESS_TIME, MIN_BACKOFF,
+    },
+    spop::{Capability, Error as Status, HaproxyHello, Limits, Version, MAX_FRAME_SIZE},
+    state::HandshakeHook,
+    IoInfo, Negotiated,
 };
 
 #[derive(Debug, Default)]
@@ -14,6 +24,39 @@ pub struct Builder {
     pub capabilities: HashSet<Capability>,
     pub max_frame_size: Option<usize>,
     pub max_process_time: Option<Duration>,
+    pub max_connections: Option<usize>,
+    pub haproxy_timeout: Option<Duration>,
+    pub adaptive_timeout: Option<AdaptiveTimeout>,
+    pub write_timeout: Option<Duration>,
+    pub half_close_linger: Option<Duration>,
+    pub drain_timeout: Option<Duration>,
+    pub watchdog: Option<Duration>,
+    pub shard: Option<Shard>,
+    pub sample_rates: Vec<(String, f64)>,
+    pub sampled_var: bool,
+    pub tail_logging: Option<Duration>,
+    pub fault_injection: Option<FaultInjection>,
+    pub engine_stats: bool,
+    pub time_slice: Option<(Duration, Duration)>,
+    pub history: Option<usize>,
+    pub health_interval: Option<Duration>,
+    pub spoe_events: bool,
+    pub continue_on_error: bool,
+    pub circuit_breaker: Option<CircuitBreaker>,
+    pub max_var_len: Option<(usize, Oversized)>,
+    pub default_actions: Option<DefaultActions>,
+    pub max_outgoing_acks: Option<usize>,
+    pub offload_size: Option<usize>,
+    pub decode_limits: Option<Limits>,
+    pub reorder_window: Option<usize>,
+    pub handshake: HandshakeLimits,
+    pub worker_backoff: Option<(Duration, Duration)>,
+    #[cfg(feature = "proxy-protocol")]
+    pub proxy_protocol: bool,
+    #[debug(skip)]
+    pub accept_filter: Option<AcceptFilter>,
+    #[debug(skip)]
+    pub handshake_hook: Option<HandshakeHook>,
 }
 impl Builder {
     pub fn new() -> Builder {
@@ -63,11 +106,256 @@ impl Builder {
         self
     }
 
+    /// Stop accepting new connections when the number of active connections reached the limit.
+    pub fn max_connections(mut self, n: usize) -> Self {
+        self.max_connections = Some(n);
+        self
+    }
+
+    /// Set the `timeout processing` configured in HAProxy,
+    /// to count the frames which would have timed out at HAProxy.
+    pub fn haproxy_timeout<D: Into<Duration>>(mut self, d: D) -> Self {
+        self.haproxy_timeout = Some(d.into());
+        self
+    }
+
+    /// Adapt the processing timeout of the frames to their recent processing times,
+    /// shedding the frames trending toward the HAProxy deadline with an empty ACK frame.
+    pub fn adaptive_timeout(mut self, adaptive: AdaptiveTimeout) -> Self {
+        self.adaptive_timeout = Some(adaptive);
+        self
+    }
+
+    /// Close the connection with the `Timeout` status when writing a frame takes longer,
+    /// e.g. the peer stopped reading from its socket.
+    pub fn write_timeout<D: Into<Duration>>(mut self, d: D) -> Self {
+        self.write_timeout = Some(d.into());
+        self
+    }
+
+    /// Keep writing the ACK frames still in flight for the duration after HAProxy shut down
+    /// its side of the connection, instead of failing the connection on the end of the stream.
+    pub fn half_close_linger<D: Into<Duration>>(mut self, d: D) -> Self {
+        self.half_close_linger = Some(d.into());
+        self
+    }
+
+    /// On shutdown, write the ACK frames in flight and send an AGENT-DISCONNECT frame
+    /// with the `Normal` status for up to the duration, instead of closing the connections,
+    /// so HAProxy doesn't report processing errors during the rolling restarts.
+    pub fn drain_timeout<D: Into<Duration>>(mut self, d: D) -> Self {
+        self.drain_timeout = Some(d.into());
+        self
+    }
+
+    /// Close the connections making no progress for the duration while having pending frames,
+    /// the duration should exceed the max processing time.
+    pub fn watchdog<D: Into<Duration>>(mut self, threshold: D) -> Self {
+        self.watchdog = Some(threshold.into());
+        self
+    }
+
+    /// Process only the streams of the shard, answering the others with an empty ACK frame,
+    /// when several agent processes serve the same HAProxy backend.
+    pub fn shard(mut self, shard: Shard) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Process only the ratio of the messages of the name, between `0` and `1`,
+    /// answering the frames left without messages with an empty ACK frame.
+    ///
+    /// The rates may be adjusted while serving with [`RuntimeHandle::set_sample_rate`](crate::runtime::RuntimeHandle::set_sample_rate).
+    pub fn sample_rate<S: Into<String>>(mut self, message: S, rate: f64) -> Self {
+        self.sample_rates.push((message.into(), rate));
+        self
+    }
+
+    /// Set the [`SAMPLED_VAR`](crate::runtime::SAMPLED_VAR) variable to `false`
+    /// in the ACK frames of the frames not sampled.
+    pub fn sampled_var(mut self) -> Self {
+        self.sampled_var = true;
+        self
+    }
+
+    /// Buffer the records of the [`StreamLogger`](crate::StreamLogger) of every frame,
+    /// emitted only when the frame failed or its processing exceeded the latency threshold.
+    pub fn tail_logging<D: Into<Duration>>(mut self, threshold: D) -> Self {
+        self.tail_logging = Some(threshold.into());
+        self
+    }
+
+    /// Inject faults in the processing of the frames, to validate the HAProxy configuration
+    /// against a misbehaving agent.
+    pub fn fault_injection(mut self, faults: FaultInjection) -> Self {
+        self.fault_injection = Some(faults);
+        self
+    }
+
+    /// Aggregate the statistics of every SPOE engine across its connections,
+    /// kept for the lifetime of the process and labeled with the `engine` in the metrics.
+    pub fn engine_stats(mut self) -> Self {
+        self.engine_stats = true;
+        self
+    }
+
+    /// Limit the processing time of every connection per scheduling interval,
+    /// the frames of a connection exceeding its budget are delayed in favor of the others.
+    pub fn time_slice<D: Into<Duration>>(mut self, budget: D, interval: D) -> Self {
+        self.time_slice = Some((budget.into(), interval.into()));
+        self
+    }
+
+    /// Set the interval between the checks of the dependencies registered with `Health::register`.
+    pub fn health_interval<D: Into<Duration>>(mut self, d: D) -> Self {
+        self.health_interval = Some(d.into());
+        self
+    }
+
+    /// Record the last NOTIFY frames processed by every connection,
+    /// available from its handle and logged when it is closed on an error.
+    pub fn history(mut self, n: usize) -> Self {
+        self.history = Some(n);
+        self
+    }
+
+    /// Extract the `spoe-group` and event names passed through the well-known message arguments
+    /// into the [`NotifyContext`](crate::NotifyContext).
+    pub fn spoe_events(mut self) -> Self {
+        self.spoe_events = true;
+        self
+    }
+
+    /// Match the `option continue-on-error` of the SPOE engine.
+    ///
+    /// HAProxy keeps processing the events of a stream after an error, so a frame failed
+    /// by the service is answered with an ACK frame setting the [`ERROR_VAR`] variable
+    /// to the status code, instead of closing the connection with all its frames in flight.
+    /// The protocol errors and the [`Disconnect`](crate::spop::Disconnect) returned by
+    /// the services still close the connection.
+    ///
+    /// [`ERROR_VAR`]: crate::runtime::ERROR_VAR
+    pub fn continue_on_error(mut self) -> Self {
+        self.continue_on_error = true;
+        self
+    }
+
+    /// Stop making the services once the `MakeService` repeatedly failed,
+    /// handling the connections with the policy of the breaker while it is open.
+    pub fn circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.circuit_breaker = Some(breaker);
+        self
+    }
+
+    /// Limit the length of the string and binary variable values set by the services,
+    /// the oversized values are truncated, rejected or split as the strategy.
+    pub fn max_var_len(mut self, max_len: usize, strategy: Oversized) -> Self {
+        self.max_var_len = Some((max_len, strategy));
+        self
+    }
+
+    /// Append the default actions to every ACK frame, unless overridden by the services.
+    pub fn default_actions(mut self, defaults: DefaultActions) -> Self {
+        self.default_actions = Some(defaults);
+        self
+    }
+
+    /// Bound the number of ACK frames completed and not written yet across the connections,
+    /// or completed by the dispatcher ackers and not received yet.
+    ///
+    /// The connections wait for a slot before writing an ACK frame when the outgoing queue is full,
+    /// without reading the next frames meanwhile, like `Acker::complete` does.
+    pub fn max_outgoing_acks(mut self, n: usize) -> Self {
+        self.max_outgoing_acks = Some(n);
+        self
+    }
+
+    /// Bound the number of ACK frames routed to the other connections of an engine
+    /// and not written yet, in the `async` mode.
+    pub fn reorder_window(mut self, n: usize) -> Self {
+        self.reorder_window = Some(n);
+        self
+    }
+
+    /// Decode and encode the frames of at least the size on the blocking thread pool,
+    /// e.g. [`OFFLOAD_FRAME_SIZE`](crate::spop::OFFLOAD_FRAME_SIZE) for the large headers or bodies.
+    pub fn offload_frames(mut self, min_size: usize) -> Self {
+        self.offload_size = Some(min_size);
+        self
+    }
+
+    /// Cap the number of messages per NOTIFY frame and arguments per message decoded,
+    /// the frames over the caps are rejected as too big.
+    pub fn decode_limits(mut self, limits: Limits) -> Self {
+        self.decode_limits = Some(limits);
+        self
+    }
+
+    /// Set the time to complete the handshake, the connections are closed when it elapsed.
+    pub fn handshake_timeout<D: Into<Duration>>(mut self, d: D) -> Self {
+        self.handshake.timeout = d.into();
+        self
+    }
+
+    /// Set the max size of the HAPROXY-HELLO frame.
+    pub fn max_hello_size(mut self, sz: usize) -> Self {
+        self.handshake.max_hello_size = sz;
+        self
+    }
+
+    /// Set the max number of bytes read before the handshake completed,
+    /// including the PROXY protocol header if any.
+    pub fn max_handshake_bytes(mut self, n: usize) -> Self {
+        self.handshake.max_bytes = n;
+        self
+    }
+
+    /// Limit the number of connections per source IP which have not completed the handshake.
+    pub fn max_half_open_per_ip(mut self, n: usize) -> Self {
+        self.handshake.max_half_open_per_ip = Some(n);
+        self
+    }
+
+    /// Expect the PROXY protocol v1/v2 header on the accepted connections,
+    /// rejecting the connections with a missing or malformed header.
+    #[cfg(feature = "proxy-protocol")]
+    pub fn proxy_protocol(mut self) -> Self {
+        self.proxy_protocol = true;
+        self
+    }
+
+    /// Decide whether to serve the accepted connections, and tag them with the HAProxy instance.
+    pub fn accept_filter<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&IoInfo) -> Accept + Send + Sync + 'static,
+    {
+        self.accept_filter = Some(Arc::new(f));
+        self
+    }
+
+    /// Inspect the HAPROXY-HELLO frame of the peer and adjust the negotiated parameters.
+    ///
+    /// The hook may only lower the max frame size or drop capabilities,
+    /// returning an error closes the connection with the status.
+    pub fn on_handshake<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HaproxyHello, &mut Negotiated) -> Result<(), Status> + Send + Sync + 'static,
+    {
+        self.handshake_hook = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the delays before restarting a dead worker, doubled after each restart.
+    pub fn worker_backoff<D: Into<Duration>>(mut self, min: D, max: D) -> Self {
+        self.worker_backoff = Some((min.into(), max.into()));
+        self
+    }
+
     pub fn make_service<S, T>(self, make_service: S, state: T) -> Arc<Runtime<S, T>>
     where
         S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
     {
-        Arc::new(Runtime::new(
+        let mut runtime = Runtime::new(
             if self.supported_versions.is_empty() {
                 vec![Version::V2_0]
             } else {
@@ -76,8 +364,61 @@ impl Builder {
             self.capabilities.into_iter().collect(),
             self.max_frame_size.unwrap_or(MAX_FRAME_SIZE),
             self.max_process_time.unwrap_or(MAX_PROCESS_TIME),
+            self.max_connections,
             make_service,
             state,
-        ))
+        );
+
+        runtime.haproxy_timeout = self.haproxy_timeout;
+        runtime.adaptive_timeout = self.adaptive_timeout;
+        runtime.write_timeout = self.write_timeout;
+        runtime.half_close_linger = self.half_close_linger;
+        runtime.drain_timeout = self.drain_timeout;
+        runtime.watchdog = self.watchdog.map(Watchdog::new);
+        runtime.shard = self.shard;
+        for (message, rate) in self.sample_rates {
+            runtime.sampling.set_rate(message, rate);
+        }
+        runtime.sampling.sampled_var = self.sampled_var;
+        runtime.tail_logging = self.tail_logging;
+        runtime.fault_injection = self.fault_injection.map(Arc::new);
+        if self.engine_stats {
+            runtime.engine_stats = Some(EngineStats::default());
+        }
+        runtime.time_slice = self.time_slice;
+        runtime.history = self.history;
+        if let Some(interval) = self.health_interval {
+            runtime.health.interval = interval;
+        }
+        runtime.spoe_events = self.spoe_events;
+        runtime.continue_on_error = self.continue_on_error;
+        runtime.circuit_breaker = self.circuit_breaker;
+        runtime.dispatcher.deadline = Some(runtime.haproxy_timeout());
+        runtime.dispatcher.outgoing = self.max_outgoing_acks.map(|n| Arc::new(Semaphore::new(n)));
+        runtime.offload_size = self.offload_size;
+        runtime.decode_limits = self.decode_limits.unwrap_or_default();
+        if let Some(window) = self.reorder_window {
+            runtime.scheduler = WriteScheduler::new(window);
+        }
+        runtime.handshake = self.handshake;
+        if let Some((max_len, strategy)) = self.max_var_len {
+            let guard =
+                Arc::new(VarGuard::new(max_len, strategy).with_drops(runtime.load.drops.clone()));
+
+            runtime.dispatcher.guard = Some(guard.clone());
+            runtime.var_guard = Some(guard);
+        }
+        runtime.default_actions = self.default_actions.map(Arc::new);
+        runtime.accept_filter = self.accept_filter;
+        runtime.handshake_hook = self.handshake_hook;
+        #[cfg(feature = "proxy-protocol")]
+        {
+            runtime.proxy_protocol = self.proxy_protocol;
+        }
+
+        let (min_backoff, max_backoff) = self.worker_backoff.unwrap_or((MIN_BACKOFF, MAX_BACKOFF));
+        runtime.supervisor = Supervisor::new(min_backoff, max_backoff);
+
+        Arc::new(runtime)
     }
 }
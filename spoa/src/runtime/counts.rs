@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+use crate::{metrics, spop::FrameType};
+
+/// The frame types counted, in the order of their counters.
+const TYPES: [FrameType; 6] = [
+    FrameType::HaproxyHello,
+    FrameType::HaproxyNotify,
+    FrameType::HaproxyDisconnect,
+    FrameType::AgentHello,
+    FrameType::AgentAck,
+    FrameType::AgentDisconnect,
+];
+
+/// Returns the name of the frame type, used as a metric label.
+pub fn frame_type_name(ty: FrameType) -> &'static str {
+    match ty {
+        FrameType::Unset => "unset",
+        FrameType::HaproxyHello => "haproxy_hello",
+        FrameType::HaproxyNotify => "haproxy_notify",
+        FrameType::HaproxyDisconnect => "haproxy_disconnect",
+        FrameType::AgentHello => "agent_hello",
+        FrameType::AgentAck => "agent_ack",
+        FrameType::AgentDisconnect => "agent_disconnect",
+    }
+}
+
+/// The number of frames received from HAProxy and sent by the agent, by type.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameCounts([u64; TYPES.len()]);
+
+impl FrameCounts {
+    /// Returns the number of frames of the type.
+    pub fn get(&self, ty: FrameType) -> u64 {
+        TYPES
+            .iter()
+            .position(|&t| t == ty)
+            .map_or(0, |idx| self.0[idx])
+    }
+
+    /// Returns the number of frames received from HAProxy.
+    pub fn received(&self) -> u64 {
+        self.0[..3].iter().sum()
+    }
+
+    /// Returns the number of frames sent by the agent.
+    pub fn sent(&self) -> u64 {
+        self.0[3..].iter().sum()
+    }
+
+    /// Returns the frame types with their number of frames.
+    pub fn iter(&self) -> impl Iterator<Item = (FrameType, u64)> + '_ {
+        TYPES.iter().copied().zip(self.0)
+    }
+}
+
+/// Count the frames by type, shared by all the connections.
+#[derive(Debug, Default)]
+pub(crate) struct FrameCounter([AtomicU64; TYPES.len()]);
+
+impl FrameCounter {
+    pub fn record(&self, ty: FrameType) {
+        if let Some(idx) = TYPES.iter().position(|&t| t == ty) {
+            self.0[idx].fetch_add(1, Relaxed);
+            metrics::labeled_counter(metrics::FRAMES, &[("type", frame_type_name(ty))], 1);
+        }
+    }
+
+    pub fn snapshot(&self) -> FrameCounts {
+        FrameCounts(self.0.each_ref().map(|n| n.load(Relaxed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_counts() {
+        let counter = FrameCounter::default();
+
+        for ty in [
+            FrameType::HaproxyHello,
+            FrameType::AgentHello,
+            FrameType::HaproxyNotify,
+            FrameType::HaproxyNotify,
+            FrameType::AgentAck,
+            FrameType::Unset,
+        ] {
+            counter.record(ty);
+        }
+
+        let counts = counter.snapshot();
+        assert_eq!(counts.get(FrameType::HaproxyNotify), 2);
+        assert_eq!(counts.get(FrameType::AgentDisconnect), 0);
+        assert_eq!(counts.get(FrameType::Unset), 0);
+        assert_eq!((counts.received(), counts.sent()), (3, 2));
+    }
+}
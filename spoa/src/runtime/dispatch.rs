@@ -1,67 +1,564 @@
+use std::collections::{hash_map, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
 use dashmap::{DashMap, Entry};
 use tokio::sync::{
     mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-    oneshot,
+    Semaphore,
 };
+use tokio_util::sync::CancellationToken;
 
 use crate::{
     error::Result,
-    runtime::Acker,
-    spop::{AgentAck, FrameId, HaproxyNotify, Message, StreamId},
+    runtime::{Acked, Acker, DropReason, Drops, VarGuard},
+    spop::{FrameId, HaproxyNotify, Message, StreamId},
 };
 
-#[derive(Debug, Clone)]
+/// How the fragments of the NOTIFY frames are mapped to the channels of their streams.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DispatchMode {
+    /// A map shared by all the connections.
+    #[default]
+    Shared,
+    /// A map confined to the task of every connection, without cross-thread contention.
+    ///
+    /// The fragments of a frame must be received on the same connection,
+    /// which is always the case with HAProxy.
+    PerConnection,
+}
+
+/// The min interval between the purges of the fragmented frames given up.
+pub const PURGE_INTERVAL: Duration = Duration::from_secs(1);
+
+type Processing = UnboundedSender<(Acker, UnboundedReceiver<Message>)>;
+
+/// Dispatch the messages of the NOTIFY frames to the processors, one channel per frame,
+/// replied through the [`Acker`] of the frame.
+///
+/// This is a standalone API for the agents driving their own [`Processor`](crate::runtime::Processor),
+/// e.g. with [`Connection::frames`](crate::Connection::frames):
+/// the connections served by the runtime call their service inline instead.
+#[derive(Debug)]
 pub struct Dispatcher {
-    processing: UnboundedSender<(Acker, UnboundedReceiver<Message>)>,
-    receiving: DashMap<(StreamId, FrameId), UnboundedSender<Message>>,
+    processing: Processing,
+    receiving: DashMap<(StreamId, FrameId), Receiving>,
+    created: Instant,
+    /// The time of the last purge, in microseconds since the dispatcher was created.
+    purged: AtomicU64,
+    /// The min interval between the purges of the fragmented frames given up.
+    pub purge_interval: Duration,
+    /// Enforce the max length of the variable values set by the ackers.
+    pub guard: Option<Arc<VarGuard>>,
+    /// The time given to the ackers to complete the frames.
+    pub deadline: Option<Duration>,
+    /// Bound the ACK frames completed but not received yet.
+    pub outgoing: Option<Arc<Semaphore>>,
+    /// Count the messages dropped.
+    pub(crate) drops: Arc<Drops>,
 }
 
 impl Dispatcher {
-    pub fn new(processing: UnboundedSender<(Acker, UnboundedReceiver<Message>)>) -> Self {
+    pub fn new(processing: Processing) -> Self {
         Self {
             processing,
             receiving: DashMap::new(),
+            created: Instant::now(),
+            purged: AtomicU64::new(0),
+            purge_interval: PURGE_INTERVAL,
+            guard: None,
+            deadline: None,
+            outgoing: None,
+            drops: Arc::default(),
         }
     }
 
-    pub fn recieve_messages(
-        &self,
-        notify: HaproxyNotify,
-    ) -> Result<Option<oneshot::Receiver<AgentAck>>> {
+    pub fn recieve_messages(&self, notify: HaproxyNotify) -> Result<Option<Acked>> {
         let key = (notify.stream_id, notify.frame_id);
-        let (sender, acked) = {
+
+        self.purge_elapsed();
+
+        let (stream, acked) = {
             match self.receiving.entry(key) {
                 Entry::Vacant(e) => {
-                    let (sender, receiver) = unbounded_channel();
+                    let acker = acker(
+                        &notify,
+                        &self.guard,
+                        self.deadline,
+                        &self.outgoing,
+                        &self.drops,
+                    );
+                    let (stream, acked) = start(&self.processing, acker)?;
 
                     if notify.fragmented {
-                        e.insert(sender.clone());
+                        e.insert(stream.clone());
                     }
 
-                    let (acker, acked) = Acker::new(notify.stream_id, notify.frame_id);
-
-                    self.processing.send((acker, receiver))?;
-
-                    (sender, Some(acked))
+                    (stream, Some(acked))
                 }
                 Entry::Occupied(e) => {
-                    let sender = if notify.fragmented {
+                    let stream = if notify.fragmented {
                         e.get().clone()
                     } else {
                         e.remove()
                     };
 
-                    (sender, None)
+                    (stream, None)
+                }
+            }
+        };
+
+        stream.forward(notify.messages, &self.drops);
+
+        Ok(acked)
+    }
+
+    /// Remove the fragmented frames already completed, aborted or whose acker was dropped,
+    /// their following fragments are not expected anymore, since HAProxy got the ACK frame.
+    ///
+    /// The frames are purged at most once per `purge_interval` when the fragments are received,
+    /// so the streams given up before their last fragment don't leak, and their late fragments
+    /// are still dropped meanwhile.
+    pub fn purge(&self) {
+        self.receiving.retain(|_, stream| !stream.is_done());
+    }
+
+    /// Purge the frames if the purge interval elapsed, only one of the concurrent callers does.
+    fn purge_elapsed(&self) {
+        let now = self.created.elapsed().as_micros() as u64;
+        let purged = self.purged.load(Relaxed);
+
+        if now.saturating_sub(purged) >= self.purge_interval.as_micros() as u64
+            && self
+                .purged
+                .compare_exchange(purged, now, Relaxed, Relaxed)
+                .is_ok()
+        {
+            self.purge();
+        }
+    }
+
+    /// Returns the dispatcher of a connection in the given mode,
+    /// see [`DispatchMode`].
+    pub fn dispatch(&self, mode: DispatchMode) -> Dispatch<'_> {
+        match mode {
+            DispatchMode::Shared => Dispatch::Shared(self),
+            DispatchMode::PerConnection => Dispatch::Local(self.local()),
+        }
+    }
+
+    /// Returns a dispatcher confined to a connection, sending to the same processors.
+    pub fn local(&self) -> LocalDispatcher {
+        LocalDispatcher {
+            processing: self.processing.clone(),
+            receiving: HashMap::new(),
+            purged: Instant::now(),
+            purge_interval: self.purge_interval,
+            guard: self.guard.clone(),
+            deadline: self.deadline,
+            outgoing: self.outgoing.clone(),
+            drops: self.drops.clone(),
+        }
+    }
+}
+
+/// A dispatcher owned by a connection, see [`DispatchMode::PerConnection`].
+#[derive(Debug)]
+pub struct LocalDispatcher {
+    processing: Processing,
+    receiving: HashMap<(StreamId, FrameId), Receiving>,
+    purged: Instant,
+    purge_interval: Duration,
+    guard: Option<Arc<VarGuard>>,
+    deadline: Option<Duration>,
+    outgoing: Option<Arc<Semaphore>>,
+    drops: Arc<Drops>,
+}
+
+impl LocalDispatcher {
+    pub fn recieve_messages(&mut self, notify: HaproxyNotify) -> Result<Option<Acked>> {
+        let key = (notify.stream_id, notify.frame_id);
+
+        if self.purged.elapsed() >= self.purge_interval {
+            self.purge();
+        }
+
+        let (stream, acked) = match self.receiving.entry(key) {
+            hash_map::Entry::Vacant(e) => {
+                let acker = acker(
+                    &notify,
+                    &self.guard,
+                    self.deadline,
+                    &self.outgoing,
+                    &self.drops,
+                );
+                let (stream, acked) = start(&self.processing, acker)?;
+
+                if notify.fragmented {
+                    e.insert(stream.clone());
                 }
+
+                (stream, Some(acked))
+            }
+            hash_map::Entry::Occupied(e) => {
+                let stream = if notify.fragmented {
+                    e.get().clone()
+                } else {
+                    e.remove()
+                };
+
+                (stream, None)
             }
         };
 
-        for msg in notify.messages {
-            if sender.send(msg).is_err() {
+        stream.forward(notify.messages, &self.drops);
+
+        Ok(acked)
+    }
+
+    /// Remove the fragmented frames already completed, see [`Dispatcher::purge`].
+    pub fn purge(&mut self) {
+        self.receiving.retain(|_, stream| !stream.is_done());
+        self.purged = Instant::now();
+    }
+
+    /// Returns `true` if no fragmented frame is being received.
+    pub fn is_empty(&self) -> bool {
+        self.receiving.is_empty()
+    }
+}
+
+/// The dispatcher used by a connection, depending on the [`DispatchMode`].
+#[derive(Debug)]
+pub enum Dispatch<'a> {
+    Shared(&'a Dispatcher),
+    Local(LocalDispatcher),
+}
+
+impl Dispatch<'_> {
+    pub fn recieve_messages(&mut self, notify: HaproxyNotify) -> Result<Option<Acked>> {
+        match self {
+            Dispatch::Shared(dispatcher) => dispatcher.recieve_messages(notify),
+            Dispatch::Local(dispatcher) => dispatcher.recieve_messages(notify),
+        }
+    }
+}
+
+/// The channel of a frame being received, and the lifecycle of its acker.
+#[derive(Clone, Debug)]
+struct Receiving {
+    sender: UnboundedSender<Message>,
+    /// Cancelled when the frame is aborted by its acker.
+    aborted: CancellationToken,
+    /// Cancelled when the ACK frame is sent or dropped.
+    done: CancellationToken,
+}
+
+impl Receiving {
+    fn is_done(&self) -> bool {
+        self.done.is_cancelled()
+    }
+
+    /// Forward the messages to the processor,
+    /// the fragments of an aborted or completed frame are dropped.
+    fn forward(&self, messages: Vec<Message>, drops: &Drops) {
+        if self.aborted.is_cancelled() {
+            drops.record(DropReason::Aborted, messages.len() as u64);
+            return;
+        }
+        if self.is_done() {
+            drops.record(DropReason::Completed, messages.len() as u64);
+            return;
+        }
+
+        let total = messages.len();
+        for (i, msg) in messages.into_iter().enumerate() {
+            if self.sender.send(msg).is_err() {
+                drops.record(DropReason::ProcessorClosed, (total - i) as u64);
                 break;
             }
         }
+    }
+}
 
-        Ok(acked)
+/// Create the acker of a new frame, its deadline starts when the first fragment is received.
+fn acker(
+    notify: &HaproxyNotify,
+    guard: &Option<Arc<VarGuard>>,
+    deadline: Option<Duration>,
+    outgoing: &Option<Arc<Semaphore>>,
+    drops: &Arc<Drops>,
+) -> (Acker, Acked) {
+    let (acker, acked) = Acker::new(notify.stream_id, notify.frame_id);
+    let acker = acker
+        .with_guard(guard.clone())
+        .with_deadline(deadline.map(|d| Instant::now() + d))
+        .with_outgoing(outgoing.clone())
+        .with_drops(drops.clone());
+
+    (acker, acked)
+}
+
+/// Send the channel of a new frame to the processors.
+fn start(processing: &Processing, (acker, acked): (Acker, Acked)) -> Result<(Receiving, Acked)> {
+    let (sender, receiver) = unbounded_channel();
+    let receiving = Receiving {
+        sender,
+        aborted: acker.aborted(),
+        done: acker.done(),
+    };
+
+    processing.send((acker, receiver))?;
+
+    Ok((receiving, acked))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::{future::join_all, StreamExt as _};
+
+    use crate::{
+        runtime::Processor,
+        spop::{Message, Scope},
+    };
+
+    use super::*;
+
+    const STREAMS: u64 = 64;
+    const FRAGMENTS: u64 = 8;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_fragments() {
+        let (sender, receiver) = unbounded_channel();
+        let dispatcher = Arc::new(Dispatcher::new(sender));
+        let mut processor = Processor(receiver);
+
+        let consumer = tokio::spawn(async move {
+            let mut streams = 0;
+
+            while let Some((mut acker, msgs)) = processor.next().await {
+                let n = msgs.count().await;
+
+                acker.set_var(Scope::Transaction, "fragments", n as i32);
+                acker.complete().await.unwrap();
+
+                streams += 1;
+                if streams == STREAMS {
+                    break;
+                }
+            }
+
+            streams
+        });
+
+        let producers = (0..STREAMS).map(|stream_id| {
+            let dispatcher = dispatcher.clone();
+
+            tokio::spawn(async move {
+                let mut acked = None;
+
+                for i in 0..FRAGMENTS {
+                    let notify = HaproxyNotify {
+                        fragmented: i + 1 < FRAGMENTS,
+                        stream_id,
+                        frame_id: 1,
+                        messages: vec![Message::new("fragment", [("i", i as i32)])],
+                    };
+
+                    acked = acked.or(dispatcher.recieve_messages(notify).unwrap());
+
+                    tokio::task::yield_now().await;
+                }
+
+                acked.unwrap().await.unwrap()
+            })
+        });
+
+        for ack in join_all(producers).await {
+            let ack = ack.unwrap();
+
+            assert_eq!(
+                ack.actions,
+                vec![crate::spop::Action::set_var(
+                    Scope::Transaction,
+                    "fragments",
+                    FRAGMENTS as i32
+                )]
+            );
+        }
+
+        assert_eq!(consumer.await.unwrap(), STREAMS);
+        assert!(dispatcher.receiving.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_acker() {
+        let (sender, receiver) = unbounded_channel();
+        let mut dispatcher = Dispatcher::new(sender);
+        let mut local = dispatcher.local();
+        let mut processor = Processor(receiver);
+
+        let notify = |stream_id, i: u64| HaproxyNotify {
+            fragmented: i + 1 < FRAGMENTS,
+            stream_id,
+            frame_id: 1,
+            messages: vec![Message::new("fragment", [("i", i as i32)])],
+        };
+
+        let acked = dispatcher.recieve_messages(notify(1, 0)).unwrap().unwrap();
+        let local_acked = local.recieve_messages(notify(1, 0)).unwrap().unwrap();
+
+        // the processing is cancelled before the last fragment
+        for _ in 0..2 {
+            let (mut acker, msgs) = processor.next().await.unwrap();
+            acker.set_var(Scope::Transaction, "foo", "bar");
+            drop((acker, msgs));
+        }
+
+        // the dropped ackers complete with the actions set so far
+        assert_eq!(acked.await.unwrap().actions.len(), 1);
+        assert_eq!(local_acked.await.unwrap().actions.len(), 1);
+
+        // the late fragments are dropped until the purge interval elapsed
+        assert!(dispatcher.recieve_messages(notify(1, 1)).unwrap().is_none());
+        assert!(local.recieve_messages(notify(1, 1)).unwrap().is_none());
+        assert_eq!(dispatcher.drops.snapshot().get(DropReason::Completed), 2);
+
+        // the next frame purges the stream given up before its last fragment
+        dispatcher.purge_interval = Duration::ZERO;
+        local.purge_interval = Duration::ZERO;
+
+        assert!(dispatcher
+            .recieve_messages(notify(2, FRAGMENTS - 1))
+            .unwrap()
+            .is_some());
+        assert!(local
+            .recieve_messages(notify(2, FRAGMENTS - 1))
+            .unwrap()
+            .is_some());
+        assert!(dispatcher.receiving.is_empty());
+        assert!(local.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_aborted_fragments() {
+        let (sender, receiver) = unbounded_channel();
+        let dispatcher = Dispatcher::new(sender);
+        let mut processor = Processor(receiver);
+
+        let notify = |i: u64| HaproxyNotify {
+            fragmented: i + 1 < FRAGMENTS,
+            stream_id: 1,
+            frame_id: 1,
+            messages: vec![Message::new("fragment", [("i", i as i32)])],
+        };
+
+        let acked = dispatcher.recieve_messages(notify(0)).unwrap().unwrap();
+        let (mut acker, mut msgs) = processor.next().await.unwrap();
+
+        assert!(msgs.next().await.is_some());
+        acker.set_var(Scope::Transaction, "foo", "bar");
+        acker.abort().unwrap();
+
+        let ack = acked.await.unwrap();
+        assert!(ack.aborted);
+        assert!(ack.actions.is_empty());
+
+        for i in 1..FRAGMENTS {
+            assert!(dispatcher.recieve_messages(notify(i)).unwrap().is_none());
+        }
+        assert!(msgs.next().await.is_none());
+        assert!(dispatcher.receiving.is_empty());
+        assert_eq!(
+            dispatcher.drops.snapshot().get(DropReason::Aborted),
+            FRAGMENTS - 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_mode() {
+        let (sender, receiver) = unbounded_channel();
+        let dispatcher = Dispatcher::new(sender);
+        let mut processor = Processor(receiver);
+        let notify = |fragmented| HaproxyNotify {
+            fragmented,
+            stream_id: 1,
+            frame_id: 1,
+            messages: vec![Message::new("fragment", [("n", 1)])],
+        };
+
+        // the fragments of a frame are mapped to the same channel across the shared dispatches
+        let mut first = dispatcher.dispatch(DispatchMode::Shared);
+        let mut second = dispatcher.dispatch(DispatchMode::Shared);
+        assert!(matches!(first, Dispatch::Shared(_)));
+        assert!(first.recieve_messages(notify(true)).unwrap().is_some());
+        assert!(second.recieve_messages(notify(false)).unwrap().is_none());
+
+        let (_acker, msgs) = processor.next().await.unwrap();
+        assert_eq!(msgs.take(2).count().await, 2);
+
+        // every owned dispatch maps the fragments of its connection only
+        let mut first = dispatcher.dispatch(DispatchMode::PerConnection);
+        let mut second = dispatcher.dispatch(DispatchMode::PerConnection);
+        assert!(matches!(first, Dispatch::Local(_)));
+        assert!(first.recieve_messages(notify(true)).unwrap().is_some());
+        assert!(second.recieve_messages(notify(false)).unwrap().is_some());
+
+        let (_acker, mut msgs) = processor.next().await.unwrap();
+        assert!(msgs.next().await.is_some());
+        let (_acker, msgs) = processor.next().await.unwrap();
+        assert_eq!(msgs.count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_interleaved_fragments_per_connection() {
+        let (sender, receiver) = unbounded_channel();
+        let mut dispatcher = Dispatcher::new(sender).local();
+        let mut processor = Processor(receiver);
+
+        let consumer = tokio::spawn(async move {
+            while let Some((mut acker, msgs)) = processor.next().await {
+                tokio::spawn(async move {
+                    let n = msgs.count().await;
+
+                    acker.set_var(Scope::Transaction, "fragments", n as i32);
+                    acker.complete().await.unwrap();
+                });
+            }
+        });
+
+        let mut acked = (0..STREAMS).map(|_| None).collect::<Vec<_>>();
+        for i in 0..FRAGMENTS {
+            for stream_id in 0..STREAMS {
+                let notify = HaproxyNotify {
+                    fragmented: i + 1 < FRAGMENTS,
+                    stream_id,
+                    frame_id: 1,
+                    messages: vec![Message::new("fragment", [("i", i as i32)])],
+                };
+
+                if let Some(ack) = dispatcher.recieve_messages(notify).unwrap() {
+                    acked[stream_id as usize] = Some(ack);
+                }
+            }
+        }
+        assert!(dispatcher.is_empty());
+
+        for ack in acked {
+            assert_eq!(
+                ack.unwrap().await.unwrap().actions,
+                vec![crate::spop::Action::set_var(
+                    Scope::Transaction,
+                    "fragments",
+                    FRAGMENTS as i32
+                )]
+            );
+        }
+
+        drop(dispatcher);
+        consumer.await.unwrap();
     }
 }
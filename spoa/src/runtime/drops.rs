@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+use tracing::debug;
+
+use crate::metrics;
+
+/// Why a frame, a message or an action was dropped instead of being processed or sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DropReason {
+    /// A message of a frame aborted by its acker.
+    Aborted,
+    /// A message whose processor is gone, e.g. the worker died.
+    ProcessorClosed,
+    /// A message of a frame already completed, or whose acker was dropped.
+    Completed,
+    /// A frame received after the AGENT-DISCONNECT frame was sent.
+    AfterDisconnect,
+    /// An ACK frame already sent for the same frame, suppressed by the write scheduler.
+    DuplicateAck,
+    /// A set-var action rejected by the `VarGuard` for its oversized value.
+    OversizedVar,
+    /// An ACK frame completed after its frame was aborted or its stream cancelled.
+    CancelledAck,
+    /// An ACK frame completed after its deadline, HAProxy already gave up on the frame.
+    ExpiredAck,
+}
+
+impl DropReason {
+    /// All the reasons, in the order of their counters.
+    pub const ALL: [DropReason; 8] = [
+        DropReason::Aborted,
+        DropReason::ProcessorClosed,
+        DropReason::Completed,
+        DropReason::AfterDisconnect,
+        DropReason::DuplicateAck,
+        DropReason::OversizedVar,
+        DropReason::CancelledAck,
+        DropReason::ExpiredAck,
+    ];
+
+    /// Returns the name of the reason, used as a metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::Aborted => "aborted",
+            DropReason::ProcessorClosed => "processor_closed",
+            DropReason::Completed => "completed",
+            DropReason::AfterDisconnect => "after_disconnect",
+            DropReason::DuplicateAck => "duplicate_ack",
+            DropReason::OversizedVar => "oversized_var",
+            DropReason::CancelledAck => "cancelled_ack",
+            DropReason::ExpiredAck => "expired_ack",
+        }
+    }
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// The counters of the dropped items, by reason.
+#[derive(Debug, Default)]
+pub struct Drops([AtomicU64; DropReason::ALL.len()]);
+
+impl Drops {
+    /// Count the items dropped for the reason.
+    pub fn record(&self, reason: DropReason, n: u64) {
+        if n == 0 {
+            return;
+        }
+
+        debug!(reason = reason.as_str(), n, "dropped");
+
+        self.0[reason.index()].fetch_add(n, Relaxed);
+        metrics::labeled_counter(metrics::DROPPED, &[("reason", reason.as_str())], n);
+    }
+
+    /// Returns the number of items dropped so far.
+    pub fn snapshot(&self) -> DropCounts {
+        DropCounts(self.0.each_ref().map(|n| n.load(Relaxed)))
+    }
+}
+
+/// The number of items dropped, by reason.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropCounts([u64; DropReason::ALL.len()]);
+
+impl DropCounts {
+    /// Returns the number of items dropped for the reason.
+    pub fn get(&self, reason: DropReason) -> u64 {
+        self.0[reason.index()]
+    }
+
+    /// Returns the number of items dropped for any reason.
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::DashMap;
+
+use crate::metrics;
+
+/// The statistics of the SPOE engines, aggregated across all their connections.
+///
+/// The statistics are kept for the lifetime of the process, so the reconnects
+/// of HAProxy, e.g. on a reload, don't reset the counters seen by the operators.
+#[derive(Debug, Default)]
+pub struct EngineStats(DashMap<String, Arc<Counters>>);
+
+#[derive(Debug, Default)]
+struct Counters {
+    connections: AtomicU64,
+    frames: AtomicU64,
+    actions: AtomicU64,
+    errors: AtomicU64,
+    latency: AtomicU64,
+}
+
+/// The statistics of a SPOE engine.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EngineSnapshot {
+    /// The identifier of the engine.
+    pub engine_id: String,
+    /// The number of connections which completed the handshake.
+    pub connections: u64,
+    /// The number of NOTIFY frames processed.
+    pub frames: u64,
+    /// The number of actions sent.
+    pub actions: u64,
+    /// The number of frames which failed.
+    pub errors: u64,
+    /// The average processing time of the frames.
+    pub avg_latency: Duration,
+}
+
+impl EngineStats {
+    fn counters(&self, engine_id: &str) -> Arc<Counters> {
+        if let Some(counters) = self.0.get(engine_id) {
+            return counters.clone();
+        }
+
+        self.0.entry(engine_id.to_string()).or_default().clone()
+    }
+
+    pub(crate) fn connect(&self, engine_id: &str) {
+        self.counters(engine_id).connections.fetch_add(1, Relaxed);
+        metrics::engine_counter(metrics::ENGINE_CONNECTIONS, engine_id, 1);
+    }
+
+    pub(crate) fn record(&self, engine_id: &str, actions: usize, failed: bool, latency: Duration) {
+        let counters = self.counters(engine_id);
+
+        counters.frames.fetch_add(1, Relaxed);
+        counters.actions.fetch_add(actions as u64, Relaxed);
+        counters
+            .latency
+            .fetch_add(latency.as_micros() as u64, Relaxed);
+        metrics::engine_counter(metrics::ENGINE_FRAMES, engine_id, 1);
+        metrics::engine_counter(metrics::ENGINE_ACTIONS, engine_id, actions as u64);
+        metrics::engine_histogram(metrics::ENGINE_PROCESSING_SECONDS, engine_id, latency);
+
+        if failed {
+            counters.errors.fetch_add(1, Relaxed);
+            metrics::engine_counter(metrics::ENGINE_ERRORS, engine_id, 1);
+        }
+    }
+
+    /// Returns the statistics of the engine.
+    pub fn get(&self, engine_id: &str) -> Option<EngineSnapshot> {
+        self.0
+            .get(engine_id)
+            .map(|entry| snapshot(entry.key(), entry.value()))
+    }
+
+    /// Returns the statistics of all the engines seen so far, sorted by identifier.
+    pub fn snapshot(&self) -> Vec<EngineSnapshot> {
+        let mut engines = self
+            .0
+            .iter()
+            .map(|entry| snapshot(entry.key(), entry.value()))
+            .collect::<Vec<_>>();
+
+        engines.sort_by(|a, b| a.engine_id.cmp(&b.engine_id));
+        engines
+    }
+}
+
+fn snapshot(engine_id: &str, counters: &Counters) -> EngineSnapshot {
+    let frames = counters.frames.load(Relaxed);
+
+    EngineSnapshot {
+        engine_id: engine_id.to_string(),
+        connections: counters.connections.load(Relaxed),
+        frames,
+        actions: counters.actions.load(Relaxed),
+        errors: counters.errors.load(Relaxed),
+        avg_latency: Duration::from_micros(
+            counters
+                .latency
+                .load(Relaxed)
+                .checked_div(frames)
+                .unwrap_or_default(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_stats() {
+        let stats = EngineStats::default();
+
+        stats.connect("engine-a");
+        stats.record("engine-a", 2, false, Duration::from_millis(10));
+        stats.record("engine-a", 0, true, Duration::from_millis(30));
+        // HAProxy reloaded and reconnected
+        stats.connect("engine-a");
+        stats.record("engine-a", 1, false, Duration::from_millis(20));
+        stats.connect("engine-b");
+
+        assert_eq!(
+            stats.get("engine-a"),
+            Some(EngineSnapshot {
+                engine_id: "engine-a".into(),
+                connections: 2,
+                frames: 3,
+                actions: 3,
+                errors: 1,
+                avg_latency: Duration::from_millis(20),
+            })
+        );
+        assert_eq!(
+            stats
+                .snapshot()
+                .into_iter()
+                .map(|engine| engine.engine_id)
+                .collect::<Vec<_>>(),
+            vec!["engine-a", "engine-b"]
+        );
+        assert_eq!(stats.get("unknown"), None);
+    }
+}
@@ -0,0 +1,212 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+
+use crate::{
+    metrics,
+    runtime::{DropReason, Drops},
+    spop::{Action, Typed},
+};
+
+/// What to do with a variable value exceeding the limit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Oversized {
+    /// Truncate the value to the limit, on a character boundary for strings.
+    #[default]
+    Truncate,
+    /// Drop the set-var action.
+    Reject,
+    /// Split the value into several variables,
+    /// the first one keeps the name and the following ones are suffixed with `_1`, `_2`, ...
+    Split,
+}
+
+/// Enforce the max length of the string and binary values of the set-var actions.
+///
+/// HAProxy truncates the oversized variables anyway, so sending them only wastes frame space.
+#[derive(Debug)]
+pub struct VarGuard {
+    /// The max length of a value, in bytes.
+    pub max_len: usize,
+    /// What to do with the oversized values.
+    pub strategy: Oversized,
+    occurrences: AtomicU64,
+    drops: Option<Arc<Drops>>,
+}
+
+impl VarGuard {
+    pub fn new(max_len: usize, strategy: Oversized) -> Self {
+        VarGuard {
+            max_len,
+            strategy,
+            occurrences: AtomicU64::new(0),
+            drops: None,
+        }
+    }
+
+    /// Count the rejected actions as dropped.
+    pub(crate) fn with_drops(mut self, drops: Arc<Drops>) -> Self {
+        self.drops = Some(drops);
+        self
+    }
+
+    /// Returns the number of oversized values.
+    pub fn occurrences(&self) -> u64 {
+        self.occurrences.load(Relaxed)
+    }
+
+    /// Enforce the limit on the actions.
+    pub fn apply<I>(&self, actions: I) -> Vec<Action>
+    where
+        I: IntoIterator<Item = Action>,
+    {
+        let mut guarded = vec![];
+
+        for action in actions {
+            self.push(&mut guarded, action);
+        }
+
+        guarded
+    }
+
+    /// Enforce the limit on the action, and push the resulting actions.
+    pub fn push(&self, actions: &mut Vec<Action>, action: Action) {
+        let Action::SetVar { scope, name, value } = action else {
+            actions.push(action);
+            return;
+        };
+
+        let len = match &value {
+            Typed::String(s) => s.len(),
+            Typed::Binary(b) => b.len(),
+            _ => 0,
+        };
+
+        if len <= self.max_len {
+            actions.push(Action::SetVar { scope, name, value });
+            return;
+        }
+
+        self.occurrences.fetch_add(1, Relaxed);
+        metrics::counter(metrics::VARS_OVERSIZED, 1);
+
+        match self.strategy {
+            Oversized::Truncate => {
+                let value = self.chunks(value).swap_remove(0);
+
+                actions.push(Action::SetVar { scope, name, value });
+            }
+            Oversized::Reject => {
+                if let Some(drops) = &self.drops {
+                    drops.record(DropReason::OversizedVar, 1);
+                }
+            }
+            Oversized::Split => actions.extend(self.chunks(value).into_iter().enumerate().map(
+                |(i, value)| {
+                    let name = if i == 0 {
+                        name.clone()
+                    } else {
+                        format!("{name}_{i}")
+                    };
+
+                    Action::SetVar { scope, name, value }
+                },
+            )),
+        }
+    }
+
+    /// Split the string or binary value into chunks of at most `max_len` bytes.
+    ///
+    /// The value is never empty, since only the oversized values are split.
+    fn chunks(&self, value: Typed) -> Vec<Typed> {
+        let max_len = self.max_len.max(1);
+        let mut chunks = vec![];
+
+        match value {
+            Typed::String(mut s) => {
+                while !s.is_empty() {
+                    let mut at = max_len.min(s.len());
+                    while !s.is_char_boundary(at) {
+                        at -= 1;
+                    }
+                    if at == 0 {
+                        // keep at least one character to make progress
+                        at = s.chars().next().map_or(s.len(), char::len_utf8);
+                    }
+
+                    let rest = s.split_off(at);
+                    chunks.push(Typed::String(s));
+                    s = rest;
+                }
+            }
+            Typed::Binary(mut b) => {
+                while !b.is_empty() {
+                    chunks.push(Typed::Binary(b.split_to(max_len.min(b.len()))));
+                }
+            }
+            value => chunks.push(value),
+        }
+
+        chunks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spop::Scope;
+
+    use super::*;
+
+    #[test]
+    fn test_var_guard() {
+        let actions = || {
+            vec![
+                Action::set_var(Scope::Transaction, "short", "abc"),
+                Action::set_var(Scope::Transaction, "long", "abcdé"),
+                Action::set_var(Scope::Transaction, "bin", &b"12345"[..]),
+                Action::set_var(Scope::Transaction, "n", 123456),
+                Action::unset_var(Scope::Transaction, "gone"),
+            ]
+        };
+
+        let guard = VarGuard::new(4, Oversized::Truncate);
+        assert_eq!(
+            guard.apply(actions()),
+            vec![
+                Action::set_var(Scope::Transaction, "short", "abc"),
+                Action::set_var(Scope::Transaction, "long", "abcd"),
+                Action::set_var(Scope::Transaction, "bin", &b"1234"[..]),
+                Action::set_var(Scope::Transaction, "n", 123456),
+                Action::unset_var(Scope::Transaction, "gone"),
+            ]
+        );
+        assert_eq!(guard.occurrences(), 2);
+
+        let drops = Arc::new(Drops::default());
+        let guard = VarGuard::new(4, Oversized::Reject).with_drops(drops.clone());
+        assert_eq!(
+            guard.apply(actions()),
+            vec![
+                Action::set_var(Scope::Transaction, "short", "abc"),
+                Action::set_var(Scope::Transaction, "n", 123456),
+                Action::unset_var(Scope::Transaction, "gone"),
+            ]
+        );
+        assert_eq!(guard.occurrences(), 2);
+        assert_eq!(drops.snapshot().get(DropReason::OversizedVar), 2);
+
+        let guard = VarGuard::new(4, Oversized::Split);
+        assert_eq!(
+            guard.apply(actions()),
+            vec![
+                Action::set_var(Scope::Transaction, "short", "abc"),
+                Action::set_var(Scope::Transaction, "long", "abcd"),
+                Action::set_var(Scope::Transaction, "long_1", "é"),
+                Action::set_var(Scope::Transaction, "bin", &b"1234"[..]),
+                Action::set_var(Scope::Transaction, "bin_1", &b"5"[..]),
+                Action::set_var(Scope::Transaction, "n", 123456),
+                Action::unset_var(Scope::Transaction, "gone"),
+            ]
+        );
+        assert_eq!(guard.occurrences(), 2);
+    }
+}
@@ -0,0 +1,112 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use dashmap::{DashMap, Entry};
+
+/// The default time to complete the handshake.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The default max size of the HAPROXY-HELLO frame.
+pub const MAX_HELLO_SIZE: usize = 4096;
+
+/// The default max number of bytes read before the handshake completed,
+/// including the PROXY protocol header if any.
+pub const MAX_HANDSHAKE_BYTES: usize = 16384;
+
+/// The limits of the connections before the handshake completed,
+/// protecting the agent exposed on a shared network from the slowloris attacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandshakeLimits {
+    /// The time to complete the handshake.
+    pub timeout: Duration,
+    /// The max size of the HAPROXY-HELLO frame.
+    pub max_hello_size: usize,
+    /// The max number of bytes read before the handshake completed.
+    pub max_bytes: usize,
+    /// The max number of half-open connections per source IP.
+    pub max_half_open_per_ip: Option<usize>,
+}
+
+impl Default for HandshakeLimits {
+    fn default() -> Self {
+        HandshakeLimits {
+            timeout: HANDSHAKE_TIMEOUT,
+            max_hello_size: MAX_HELLO_SIZE,
+            max_bytes: MAX_HANDSHAKE_BYTES,
+            max_half_open_per_ip: None,
+        }
+    }
+}
+
+/// The connections which have not completed the handshake yet, by source IP.
+#[derive(Debug, Default)]
+pub struct HalfOpen(Arc<DashMap<IpAddr, usize>>);
+
+impl HalfOpen {
+    /// Returns the number of half-open connections from the source IP.
+    pub fn count(&self, ip: IpAddr) -> usize {
+        self.0.get(&ip).map_or(0, |n| *n)
+    }
+
+    /// Track a half-open connection, unless the source IP reached the limit.
+    pub(crate) fn acquire(&self, ip: IpAddr, max: usize) -> Option<HalfOpenGuard> {
+        let mut n = self.0.entry(ip).or_default();
+
+        if *n < max {
+            *n += 1;
+
+            Some(HalfOpenGuard {
+                conns: self.0.clone(),
+                ip,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+/// A half-open connection, released when the handshake completed or the connection closed.
+#[derive(Debug)]
+pub(crate) struct HalfOpenGuard {
+    conns: Arc<DashMap<IpAddr, usize>>,
+    ip: IpAddr,
+}
+
+impl Drop for HalfOpenGuard {
+    fn drop(&mut self) {
+        if let Entry::Occupied(mut e) = self.conns.entry(self.ip) {
+            *e.get_mut() -= 1;
+
+            if *e.get() == 0 {
+                e.remove();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_half_open() {
+        let half_open = HalfOpen::default();
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        let first = half_open.acquire(ip, 2).unwrap();
+        let second = half_open.acquire(ip, 2).unwrap();
+        assert!(half_open.acquire(ip, 2).is_none());
+        assert_eq!(half_open.count(ip), 2);
+
+        drop(first);
+        assert_eq!(half_open.count(ip), 1);
+        assert!(half_open.acquire(ip, 2).is_some());
+
+        drop(second);
+        assert_eq!(half_open.count(ip), 0);
+        assert!(half_open.0.is_empty());
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::{task::JoinHandle, time::sleep};
+use tracing::{debug, info};
+
+use crate::{error::Result, runtime::Runtime, spop::Error as Status, task};
+
+/// The transaction variable set to `true` in the ACK frames while the processing is paused.
+pub const PAUSED_VAR: &str = "spoa_paused";
+
+/// The reason of the AGENT-DISCONNECT frames sent by [`RuntimeHandle::renegotiate`].
+pub const RENEGOTIATE_REASON: &str = "renegotiate";
+
+/// A handle to manage the runtime while serving.
+#[derive(Debug)]
+pub struct RuntimeHandle<S, T>(Arc<Runtime<S, T>>);
+
+impl<S, T> Clone for RuntimeHandle<S, T> {
+    fn clone(&self) -> Self {
+        RuntimeHandle(self.0.clone())
+    }
+}
+
+impl<S, T> RuntimeHandle<S, T> {
+    pub fn new(runtime: Arc<Runtime<S, T>>) -> Self {
+        RuntimeHandle(runtime)
+    }
+
+    /// Returns the runtime.
+    pub fn runtime(&self) -> &Arc<Runtime<S, T>> {
+        &self.0
+    }
+
+    /// Atomically replace the `MakeService` used for the new connections,
+    /// returning the previous one.
+    ///
+    /// The existing connections keep their service until [`RuntimeHandle::remake_services`] is called,
+    /// so handler logic can be deployed in-process without disconnecting HAProxy.
+    pub async fn swap_make_service(&self, maker: S) -> S {
+        let prev = self.0.service_maker.write().await.replace(maker);
+
+        debug!("make service swapped");
+
+        prev
+    }
+
+    /// Answer the new NOTIFY frames with an ACK frame only setting the [`PAUSED_VAR`] variable,
+    /// without calling the services nor disconnecting HAProxy,
+    /// e.g. while the dependencies of the services are in maintenance.
+    pub fn pause_processing(&self) {
+        self.0.load.pause(true);
+
+        info!("processing paused");
+    }
+
+    /// Resume processing the NOTIFY frames with the services.
+    pub fn resume_processing(&self) {
+        self.0.load.pause(false);
+
+        info!("processing resumed");
+    }
+
+    /// Returns `true` if the processing is paused.
+    pub fn is_paused(&self) -> bool {
+        self.0.load.is_paused()
+    }
+
+    /// Adjust the ratio of the messages of the name processed, between `0` and `1`.
+    pub fn set_sample_rate<M: Into<String>>(&self, message: M, rate: f64) {
+        self.0.sampling.set_rate(message, rate);
+    }
+
+    /// Returns the rates of the sampled messages, sorted by names.
+    pub fn sample_rates(&self) -> Vec<(String, f64)> {
+        self.0.sampling.rates()
+    }
+
+    /// Make the existing connections re-make their service at the next frame boundary.
+    pub fn remake_services(&self) {
+        let generation = self.0.generation.fetch_add(1, Relaxed) + 1;
+
+        debug!(generation, "remake services");
+    }
+
+    /// Make the handshaked connections negotiate again, e.g. once the handshake hook
+    /// announces other capabilities or max frame size.
+    ///
+    /// The connections are drained with a `Normal` AGENT-DISCONNECT frame once their frames
+    /// being processed are completed, relying on HAProxy to reconnect and renegotiate.
+    /// The disconnects are staggered over the window to avoid a thundering herd of reconnections.
+    ///
+    /// Returns the task resolved with the number of the drained connections.
+    pub fn renegotiate(&self, window: Duration) -> Result<JoinHandle<usize>> {
+        let conns = self
+            .0
+            .registry
+            .connections()
+            .into_iter()
+            .filter(|conn| conn.negotiated().is_some())
+            .collect::<Vec<_>>();
+        let step = window
+            .checked_div(u32::try_from(conns.len()).unwrap_or(u32::MAX))
+            .unwrap_or_default();
+
+        info!(conns = conns.len(), ?window, "renegotiating");
+
+        let task = task::spawn("renegotiate", async move {
+            let mut drained = 0;
+
+            for (i, conn) in conns.into_iter().enumerate() {
+                if i > 0 {
+                    sleep(step).await;
+                }
+
+                if conn.disconnect(Status::Normal, RENEGOTIATE_REASON).is_ok() {
+                    debug!(id = conn.id(), "draining for renegotiation");
+
+                    drained += 1;
+                }
+            }
+
+            drained
+        })?;
+
+        Ok(task)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{make::Shared, service_fn, Service};
+
+    use crate::{
+        runtime::Builder,
+        spop::{Action, Message, Scope},
+        testing,
+    };
+
+    fn scorer(
+        n: i32,
+    ) -> Shared<
+        impl Service<Vec<Message>, Response = Vec<Action>, Error = Infallible, Future: Send>
+            + Clone
+            + Send
+            + Sync,
+    > {
+        Shared::new(service_fn(move |_: Vec<Message>| async move {
+            Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "score", n)])
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_swap_make_service() {
+        let runtime = Builder::new().make_service(scorer(1), ());
+        let handle = runtime.handle();
+        let (mut client, mut conn) = testing::pair(runtime);
+
+        tokio::spawn(async move { conn.serve().await });
+
+        client.hello().await.unwrap();
+
+        let mut score = async |stream_id| {
+            client
+                .notify(stream_id, 1, [Message::new("check", [("n", 1)])])
+                .await
+                .unwrap()
+                .actions
+        };
+
+        assert_eq!(
+            score(1).await,
+            [Action::set_var(Scope::Transaction, "score", 1)]
+        );
+
+        // the connection keeps its service until the services are re-made
+        handle.swap_make_service(scorer(2)).await;
+        assert_eq!(
+            score(2).await,
+            [Action::set_var(Scope::Transaction, "score", 1)]
+        );
+
+        handle.remake_services();
+        assert_eq!(
+            score(3).await,
+            [Action::set_var(Scope::Transaction, "score", 2)]
+        );
+    }
+}
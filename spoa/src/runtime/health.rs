@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use derive_more::Debug;
+use futures::future::{join_all, BoxFuture, FutureExt as _};
+use tokio::time::timeout;
+use tower::BoxError;
+use tracing::warn;
+
+/// The default interval between the checks of the dependencies.
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The default timeout of a dependency probe.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An async probe checking a dependency of the services.
+pub type Probe = Arc<dyn Fn() -> BoxFuture<'static, Result<(), BoxError>> + Send + Sync>;
+
+/// The aggregated health of the dependencies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HealthState {
+    /// All the dependencies are healthy, or not checked yet.
+    #[default]
+    Healthy,
+    /// At least one dependency is unhealthy.
+    Unhealthy,
+}
+
+/// The result of the last check of a dependency.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbeStatus {
+    /// The name of the dependency.
+    pub name: String,
+    /// The error of the last check, if any.
+    pub error: Option<String>,
+    /// When the dependency was last checked.
+    pub checked_at: Instant,
+}
+
+impl ProbeStatus {
+    pub fn is_healthy(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// The health of the dependencies, as reported to the admin endpoint.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HealthReport {
+    pub state: HealthState,
+    pub probes: Vec<ProbeStatus>,
+}
+
+/// Check the dependencies of the services, to report the agent unhealthy to the SPOE healthchecks.
+#[derive(Debug)]
+pub struct Health {
+    #[debug("{:?}", probes.read().unwrap().iter().map(|(name, _)| name).collect::<Vec<_>>())]
+    probes: RwLock<Vec<(String, Probe)>>,
+    statuses: Mutex<Vec<ProbeStatus>>,
+    /// The interval between the checks.
+    pub interval: Duration,
+    /// The timeout of a probe.
+    pub timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health {
+            probes: RwLock::default(),
+            statuses: Mutex::default(),
+            interval: HEALTH_INTERVAL,
+            timeout: PROBE_TIMEOUT,
+        }
+    }
+}
+
+impl Health {
+    /// Register a probe checking a dependency.
+    pub fn register<N, F, Fut, E>(&self, name: N, probe: F)
+    where
+        N: Into<String>,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        let probe: Probe = Arc::new(move || probe().map(|res| res.map_err(Into::into)).boxed());
+
+        self.probes.write().unwrap().push((name.into(), probe));
+    }
+
+    /// Returns `true` if some probes have been registered.
+    pub fn has_probes(&self) -> bool {
+        !self.probes.read().unwrap().is_empty()
+    }
+
+    /// Run all the probes concurrently, and returns the aggregated health.
+    pub async fn check(&self) -> HealthState {
+        let probes = self.probes.read().unwrap().clone();
+
+        let statuses = join_all(probes.into_iter().map(|(name, probe)| async move {
+            let error = match timeout(self.timeout, probe()).await {
+                Ok(Ok(())) => None,
+                Ok(Err(err)) => Some(err.to_string()),
+                Err(_) => Some("probe timed out".to_string()),
+            };
+
+            if let Some(ref error) = error {
+                warn!(name, error, "dependency unhealthy");
+            }
+
+            ProbeStatus {
+                name,
+                error,
+                checked_at: Instant::now(),
+            }
+        }))
+        .await;
+
+        *self.statuses.lock().unwrap() = statuses;
+
+        self.state()
+    }
+
+    /// Returns the aggregated health of the last check.
+    pub fn state(&self) -> HealthState {
+        if self
+            .statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .all(ProbeStatus::is_healthy)
+        {
+            HealthState::Healthy
+        } else {
+            HealthState::Unhealthy
+        }
+    }
+
+    /// Returns the results of the last check.
+    pub fn report(&self) -> HealthReport {
+        let probes = self.statuses.lock().unwrap().clone();
+
+        HealthReport {
+            state: self.state(),
+            probes,
+        }
+    }
+
+    /// Returns the names of the unhealthy dependencies.
+    pub(crate) fn unhealthy(&self) -> Vec<String> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|status| !status.is_healthy())
+            .map(|status| status.name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_health() {
+        let health = Health {
+            timeout: Duration::from_millis(10),
+            ..Default::default()
+        };
+
+        assert_eq!(health.check().await, HealthState::Healthy);
+
+        health.register("db", || async { Ok::<_, BoxError>(()) });
+        assert_eq!(health.check().await, HealthState::Healthy);
+
+        health.register("api", || async { Err("connection refused") });
+        health.register("cache", || async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok::<_, BoxError>(())
+        });
+        assert_eq!(health.check().await, HealthState::Unhealthy);
+        assert_eq!(health.unhealthy(), ["api", "cache"]);
+
+        let report = health.report();
+        assert_eq!(report.state, HealthState::Unhealthy);
+        assert_eq!(
+            report
+                .probes
+                .iter()
+                .map(|status| status.error.as_deref())
+                .collect::<Vec<_>>(),
+            [None, Some("connection refused"), Some("probe timed out")]
+        );
+    }
+}
@@ -0,0 +1,437 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use crate::{
+    loom::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering::Relaxed},
+    metrics,
+    runtime::{
+        DropCounts, DropReason, Drops, FrameCounter, FrameCounts, FrameSizeRecorder, FrameSizes,
+        HandshakePhase, WorkerHealth,
+    },
+    spop::{Disconnect, FrameType},
+    ErrorKind,
+};
+
+/// The load of the runtime, shared by all the connections.
+#[derive(Debug, Default)]
+pub struct Load {
+    connections: AtomicUsize,
+    in_flight: AtomicUsize,
+    processed: AtomicU64,
+    processing_time: AtomicU64,
+    latencies: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    throttled: AtomicU64,
+    throttled_time: AtomicU64,
+    deadlines: [AtomicU64; DEADLINE_BUCKETS.len() + 1],
+    haproxy_timeouts: AtomicU64,
+    panics: AtomicU64,
+    handshake_rejections: AtomicU64,
+    write_timeouts: AtomicU64,
+    stalled: AtomicU64,
+    shed: AtomicU64,
+    disconnects: AtomicU64,
+    /// Shared with the dispatchers and the `VarGuard`.
+    pub(crate) drops: Arc<Drops>,
+    frame_sizes: FrameSizeRecorder,
+    frames: FrameCounter,
+    draining: AtomicBool,
+    paused: AtomicBool,
+    changed: Notify,
+}
+
+/// The upper bounds of the deadline histogram buckets, in percent of the processing deadline.
+pub const DEADLINE_BUCKETS: [u32; 5] = [25, 50, 75, 90, 100];
+
+/// The completion times of the NOTIFY frames, relative to the processing deadline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeadlineHistogram {
+    /// The number of frames completed within every bucket of [`DEADLINE_BUCKETS`],
+    /// the last one counting the frames which missed the deadline.
+    pub buckets: [u64; DEADLINE_BUCKETS.len() + 1],
+    /// The number of frames which would have timed out at HAProxy.
+    pub haproxy_timeouts: u64,
+}
+
+impl DeadlineHistogram {
+    /// Returns the number of frames completed within the percent of the deadline.
+    pub fn within(&self, percent: u32) -> u64 {
+        DEADLINE_BUCKETS
+            .iter()
+            .zip(self.buckets)
+            .take_while(|(&bound, _)| bound <= percent)
+            .map(|(_, n)| n)
+            .sum()
+    }
+}
+
+/// The upper bounds of the latency histogram buckets.
+pub const LATENCY_BUCKETS: [Duration; 8] = [
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(1000),
+];
+
+/// The processing time of the frames, until their ACK frame is completed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// The number of frames completed within every bucket of [`LATENCY_BUCKETS`],
+    /// the last one counting the slower frames.
+    pub buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    /// The total processing time of the frames.
+    pub sum: Duration,
+}
+
+impl LatencyHistogram {
+    /// Returns the number of frames.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the number of frames completed within the duration of a bucket bound.
+    pub fn within(&self, d: Duration) -> u64 {
+        LATENCY_BUCKETS
+            .iter()
+            .zip(self.buckets)
+            .take_while(|(&bound, _)| bound <= d)
+            .map(|(_, n)| n)
+            .sum()
+    }
+}
+
+/// A point-in-time view of the runtime load, polled by external autoscalers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LoadSnapshot {
+    /// The number of active connections.
+    pub connections: usize,
+    /// The number of messages being processed.
+    pub in_flight: usize,
+    /// The number of messages processed.
+    pub processed: u64,
+    /// The average processing time of the messages.
+    pub avg_processing_time: Duration,
+    /// The processing time of the frames.
+    pub latencies: LatencyHistogram,
+    /// The number of frames delayed by the time slices of their connections.
+    pub throttled: u64,
+    /// The total delay of the throttled frames.
+    pub throttled_time: Duration,
+    /// The completion times of the frames, relative to the processing deadline.
+    pub deadlines: DeadlineHistogram,
+    /// The number of connection tasks which panicked.
+    pub panics: u64,
+    /// The number of connections rejected before completing the handshake.
+    pub handshake_rejections: u64,
+    /// The number of connections closed because writing a frame timed out.
+    pub write_timeouts: u64,
+    /// The number of connections closed by the watchdog because they stalled.
+    pub stalled: u64,
+    /// The number of frames answered without processing, belonging to the other shards.
+    pub shed: u64,
+    /// The number of AGENT-DISCONNECT frames sent.
+    pub disconnects: u64,
+    /// The number of frames, messages and actions dropped, by reason.
+    pub dropped: DropCounts,
+    /// The number of variable values exceeding the limit of the `VarGuard`.
+    pub oversized: u64,
+    /// The number of ACK frames written on another connection of the engine than the NOTIFY frame.
+    pub acks_rerouted: u64,
+    /// The number of duplicate ACK frames dropped by the write scheduler.
+    pub acks_suppressed: u64,
+    /// The sizes of the NOTIFY frames received and the ACK frames sent.
+    pub frame_sizes: FrameSizes,
+    /// The number of frames received and sent, by type.
+    pub frames: FrameCounts,
+    /// The agent stopped accepting new connections.
+    pub draining: bool,
+    /// The agent answers the NOTIFY frames without processing them.
+    pub paused: bool,
+    /// The health of the supervised workers.
+    pub workers: WorkerHealth,
+}
+
+impl Load {
+    /// Returns a snapshot of the current load.
+    pub fn snapshot(&self) -> LoadSnapshot {
+        let processed = self.processed.load(Relaxed);
+        let processing_time = self.processing_time.load(Relaxed);
+
+        LoadSnapshot {
+            connections: self.connections.load(Relaxed),
+            in_flight: self.in_flight.load(Relaxed),
+            processed,
+            avg_processing_time: Duration::from_micros(
+                processing_time.checked_div(processed).unwrap_or_default(),
+            ),
+            latencies: LatencyHistogram {
+                buckets: self.latencies.each_ref().map(|n| n.load(Relaxed)),
+                sum: Duration::from_micros(processing_time),
+            },
+            throttled: self.throttled.load(Relaxed),
+            throttled_time: Duration::from_micros(self.throttled_time.load(Relaxed)),
+            deadlines: DeadlineHistogram {
+                buckets: self.deadlines.each_ref().map(|n| n.load(Relaxed)),
+                haproxy_timeouts: self.haproxy_timeouts.load(Relaxed),
+            },
+            panics: self.panics.load(Relaxed),
+            handshake_rejections: self.handshake_rejections.load(Relaxed),
+            write_timeouts: self.write_timeouts.load(Relaxed),
+            stalled: self.stalled.load(Relaxed),
+            shed: self.shed.load(Relaxed),
+            disconnects: self.disconnects.load(Relaxed),
+            dropped: self.drops.snapshot(),
+            oversized: 0,
+            acks_rerouted: 0,
+            acks_suppressed: 0,
+            frame_sizes: self.frame_sizes.snapshot(),
+            frames: self.frames.snapshot(),
+            draining: self.is_draining(),
+            paused: self.is_paused(),
+            workers: WorkerHealth::default(),
+        }
+    }
+
+    /// Returns the number of active connections.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Relaxed)
+    }
+
+    /// Stop or resume accepting new connections.
+    pub fn drain(&self, enable: bool) {
+        self.draining.store(enable, Relaxed);
+        metrics::set_gauge(metrics::DRAINING, if enable { 1.0 } else { 0.0 });
+        self.changed.notify_one();
+    }
+
+    /// Returns `true` if the agent stopped accepting new connections.
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Relaxed)
+    }
+
+    /// Pause or resume processing the NOTIFY frames.
+    pub fn pause(&self, enable: bool) {
+        self.paused.store(enable, Relaxed);
+        metrics::set_gauge(metrics::PAUSED, if enable { 1.0 } else { 0.0 });
+    }
+
+    /// Returns `true` if the NOTIFY frames are answered without being processed.
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Relaxed)
+    }
+
+    /// Wait until the load changed.
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+
+    pub(crate) fn connect(&self) {
+        self.connections.fetch_add(1, Relaxed);
+        metrics::gauge(metrics::CONNECTIONS, 1.0);
+    }
+
+    pub(crate) fn disconnect(&self) {
+        self.connections.fetch_sub(1, Relaxed);
+        metrics::gauge(metrics::CONNECTIONS, -1.0);
+        self.changed.notify_one();
+    }
+
+    pub(crate) fn panic(&self) {
+        self.panics.fetch_add(1, Relaxed);
+        metrics::counter(metrics::CONNECTION_PANICS, 1);
+    }
+
+    pub(crate) fn reject_handshake(&self, phase: HandshakePhase, kind: ErrorKind) {
+        self.handshake_rejections.fetch_add(1, Relaxed);
+        metrics::labeled_counter(
+            metrics::HANDSHAKE_REJECTIONS,
+            &[("phase", phase.as_str()), ("kind", kind.as_str())],
+            1,
+        );
+    }
+
+    pub(crate) fn write_timeout(&self) {
+        self.write_timeouts.fetch_add(1, Relaxed);
+        metrics::counter(metrics::WRITE_TIMEOUTS, 1);
+    }
+
+    pub(crate) fn stall(&self) {
+        self.stalled.fetch_add(1, Relaxed);
+        metrics::counter(metrics::CONNECTIONS_STALLED, 1);
+    }
+
+    pub(crate) fn shed(&self) {
+        self.shed.fetch_add(1, Relaxed);
+        metrics::counter(metrics::FRAMES_SHED, 1);
+    }
+
+    pub(crate) fn dropped(&self, reason: DropReason, n: u64) {
+        self.drops.record(reason, n);
+    }
+
+    pub(crate) fn send_disconnect(&self, disconnect: &Disconnect) {
+        self.disconnects.fetch_add(1, Relaxed);
+        metrics::status_counter(
+            metrics::DISCONNECTS,
+            disconnect.status_code,
+            disconnect.user_status().is_some(),
+        );
+    }
+
+    /// Count a frame received or sent.
+    pub(crate) fn frame(&self, ty: FrameType) {
+        self.frames.record(ty);
+    }
+
+    pub(crate) fn receive_notify(&self, size: usize) {
+        self.frame_sizes.notify(size);
+        metrics::bytes(metrics::NOTIFY_FRAME_BYTES, size);
+    }
+
+    pub(crate) fn send_ack(&self, size: usize) {
+        self.frame_sizes.ack(size);
+        metrics::bytes(metrics::ACK_FRAME_BYTES, size);
+    }
+
+    pub(crate) fn throttle(&self, delay: Duration) {
+        self.throttled.fetch_add(1, Relaxed);
+        self.throttled_time
+            .fetch_add(delay.as_micros() as u64, Relaxed);
+        metrics::counter(metrics::FRAMES_THROTTLED, 1);
+        metrics::histogram(metrics::FRAME_THROTTLE_SECONDS, delay);
+    }
+
+    /// Track a frame processed before the deadline,
+    /// and HAProxy giving up on it after the timeout.
+    pub(crate) fn process(&self, deadline: Duration, timeout: Duration) -> Processing<'_> {
+        self.in_flight.fetch_add(1, Relaxed);
+        metrics::gauge(metrics::FRAMES_IN_FLIGHT, 1.0);
+
+        Processing {
+            load: self,
+            started: Instant::now(),
+            deadline,
+            timeout,
+        }
+    }
+
+    fn record_deadline(&self, elapsed: Duration, deadline: Duration, timeout: Duration) {
+        let percent = elapsed.as_micros() * 100 / deadline.as_micros().max(1);
+        let bucket = DEADLINE_BUCKETS
+            .iter()
+            .position(|&bound| percent <= bound as u128)
+            .unwrap_or(DEADLINE_BUCKETS.len());
+
+        self.deadlines[bucket].fetch_add(1, Relaxed);
+
+        if elapsed >= timeout {
+            self.haproxy_timeouts.fetch_add(1, Relaxed);
+            metrics::counter(metrics::FRAMES_HAPROXY_TIMEOUT, 1);
+        }
+    }
+}
+
+/// Track the messages being processed.
+#[derive(Debug)]
+pub struct Processing<'a> {
+    load: &'a Load,
+    started: Instant,
+    deadline: Duration,
+    timeout: Duration,
+}
+
+impl Drop for Processing<'_> {
+    fn drop(&mut self) {
+        let elapsed = self.started.elapsed();
+
+        self.load
+            .record_deadline(elapsed, self.deadline, self.timeout);
+
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| elapsed <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.load.latencies[bucket].fetch_add(1, Relaxed);
+
+        metrics::gauge(metrics::FRAMES_IN_FLIGHT, -1.0);
+        metrics::counter(metrics::FRAMES_PROCESSED, 1);
+        metrics::histogram(metrics::FRAME_PROCESSING_SECONDS, elapsed);
+
+        let elapsed = elapsed.as_micros() as u64;
+
+        self.load.in_flight.fetch_sub(1, Relaxed);
+        self.load.processed.fetch_add(1, Relaxed);
+        self.load.processing_time.fetch_add(elapsed, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deadlines() {
+        const MS: Duration = Duration::from_millis(1);
+
+        let load = Load::default();
+
+        for elapsed in [0, 10, 25, 26, 80, 95, 100, 150] {
+            load.record_deadline(elapsed * MS, 100 * MS, 90 * MS);
+        }
+
+        let deadlines = load.snapshot().deadlines;
+
+        assert_eq!(deadlines.buckets, [3, 1, 0, 1, 2, 1]);
+        assert_eq!(deadlines.haproxy_timeouts, 3);
+        assert_eq!(deadlines.within(50), 4);
+        assert_eq!(deadlines.within(100), 7);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod model {
+    use ::loom::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn model_counters() {
+        ::loom::model(|| {
+            const TIMEOUT: Duration = Duration::from_secs(60);
+
+            let load = Arc::new(Load::default());
+            load.connect();
+
+            let handles = (0..2)
+                .map(|_| {
+                    let load = load.clone();
+
+                    thread::spawn(move || {
+                        load.connect();
+                        drop(load.process(TIMEOUT, TIMEOUT));
+                        load.reject_handshake(HandshakePhase::Handshake, ErrorKind::Protocol);
+                        load.disconnect();
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            let snapshot = load.snapshot();
+
+            assert_eq!(snapshot.connections, 1);
+            assert_eq!(snapshot.in_flight, 0);
+            assert_eq!(snapshot.processed, 2);
+            assert_eq!(snapshot.latencies.count(), 2);
+            assert_eq!(snapshot.deadlines.within(100), 2);
+            assert_eq!(snapshot.handshake_rejections, 2);
+        });
+    }
+}
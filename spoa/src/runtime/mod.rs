@@ -1,11 +1,67 @@
 mod acker;
+mod adaptive;
+mod breaker;
 mod builder;
+mod counts;
 mod dispatch;
+mod drops;
+mod engines;
+mod guard;
+mod halfopen;
+mod handle;
+mod health;
+mod load;
 mod processor;
+mod registry;
+#[allow(clippy::module_inception)]
 mod runtime;
+mod sampling;
+mod scheduler;
+mod shard;
+mod sizes;
+mod slice;
+mod supervisor;
+mod watchdog;
 
-pub use self::acker::Acker;
+pub use self::acker::{Acked, Acker};
+pub use self::adaptive::{
+    AdaptiveState, AdaptiveTimeout, Decision, ADAPTIVE_MIN_SAMPLES, ADAPTIVE_WINDOW,
+};
+pub use self::breaker::{
+    BreakerPolicy, BreakerSnapshot, BreakerState, CircuitBreaker, DEGRADED_VAR,
+};
 pub use self::builder::Builder;
-pub use self::dispatch::Dispatcher;
+pub(crate) use self::counts::FrameCounter;
+pub use self::counts::{frame_type_name, FrameCounts};
+pub use self::dispatch::{Dispatch, DispatchMode, Dispatcher, LocalDispatcher, PURGE_INTERVAL};
+pub use self::drops::{DropCounts, DropReason, Drops};
+pub use self::engines::{EngineSnapshot, EngineStats};
+pub use self::guard::{Oversized, VarGuard};
+pub(crate) use self::halfopen::HalfOpenGuard;
+pub use self::halfopen::{
+    HalfOpen, HandshakeLimits, HANDSHAKE_TIMEOUT, MAX_HANDSHAKE_BYTES, MAX_HELLO_SIZE,
+};
+pub use self::handle::{RuntimeHandle, PAUSED_VAR, RENEGOTIATE_REASON};
+pub use self::health::{
+    Health, HealthReport, HealthState, Probe, ProbeStatus, HEALTH_INTERVAL, PROBE_TIMEOUT,
+};
+pub use self::load::{
+    DeadlineHistogram, LatencyHistogram, Load, LoadSnapshot, DEADLINE_BUCKETS, LATENCY_BUCKETS,
+};
 pub use self::processor::Processor;
-pub use self::runtime::{Runtime, MAX_PROCESS_TIME};
+pub(crate) use self::registry::{Command, LiveState};
+pub use self::registry::{
+    ConnectionEvent, ConnectionHandle, ConnectionId, HandshakeFailure, HandshakePhase, Registry,
+    EVENTS_CAPACITY,
+};
+pub use self::runtime::{Runtime, ERROR_VAR, MAX_PROCESS_TIME};
+pub use self::sampling::{Sampling, SAMPLED_VAR};
+pub(crate) use self::scheduler::{AckLane, Route};
+pub use self::scheduler::{SchedulerStats, WriteScheduler, DEDUP_WINDOW, REORDER_WINDOW};
+pub use self::shard::{Shard, ShardKey};
+pub(crate) use self::sizes::FrameSizeRecorder;
+pub use self::sizes::{FrameSizes, SizeHistogram, MIN_SIZE_BUCKET, SIZE_BUCKETS};
+pub(crate) use self::slice::busy;
+pub use self::slice::TimeSlice;
+pub use self::supervisor::{Supervisor, WorkerHealth, MAX_BACKOFF, MIN_BACKOFF};
+pub use self::watchdog::Watchdog;
@@ -0,0 +1,362 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::{broadcast, mpsc::UnboundedSender};
+use tokio_util::sync::CancellationToken;
+use ulid::Ulid;
+
+use crate::{
+    error::{Error, Error::Closed, ErrorKind, Result},
+    history::{History, Record},
+    spop::{Disconnect, Error as Status},
+    IoInfo, Negotiated,
+};
+
+/// The identifier of a connection, unique in the runtime.
+pub type ConnectionId = u64;
+
+/// The number of connection events buffered for the slow subscribers, the oldest ones are dropped.
+pub const EVENTS_CAPACITY: usize = 256;
+
+/// The lifecycle events of the connections.
+#[derive(Clone, Debug)]
+pub enum ConnectionEvent {
+    /// The connection was accepted.
+    Connected(ConnectionHandle),
+    /// The connection completed the handshake.
+    Handshaked(ConnectionHandle),
+    /// The connection was closed.
+    Disconnected(ConnectionId),
+    /// The connection was rejected before completing the handshake.
+    Rejected(HandshakeFailure),
+}
+
+/// The phase of a connection before the SPOP handshake completed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum HandshakePhase {
+    /// The connection was accepted, e.g. over the limit of half-open connections.
+    Accept,
+    /// Reading the PROXY protocol header.
+    ProxyHeader,
+    /// Exchanging the HELLO frames.
+    Handshake,
+}
+
+impl HandshakePhase {
+    /// Returns the name of the phase, used as a metric label.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakePhase::Accept => "accept",
+            HandshakePhase::ProxyHeader => "proxy_header",
+            HandshakePhase::Handshake => "handshake",
+        }
+    }
+}
+
+/// Why a connection was rejected before completing the handshake,
+/// e.g. to tell the network scanners from the misconfigured HAProxy instances.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HandshakeFailure {
+    /// The identifier of the connection.
+    pub id: ConnectionId,
+    /// The address of the peer.
+    pub peer: Option<SocketAddr>,
+    /// The phase the connection failed in.
+    pub phase: HandshakePhase,
+    /// The layer the error comes from.
+    pub kind: ErrorKind,
+    /// The description of the error.
+    pub error: String,
+}
+
+impl HandshakeFailure {
+    pub(crate) fn new(
+        id: ConnectionId,
+        peer: Option<SocketAddr>,
+        phase: HandshakePhase,
+        err: &Error,
+    ) -> Self {
+        HandshakeFailure {
+            id,
+            peer,
+            phase,
+            kind: err.kind(),
+            error: err.to_string(),
+        }
+    }
+}
+
+/// The live state of a connection, updated while serving.
+#[derive(Debug)]
+pub(crate) struct LiveState {
+    connected_at: Instant,
+    negotiated: OnceLock<Negotiated>,
+    frames: AtomicU64,
+    /// The time of the last frame received, in microseconds since the connection was accepted.
+    last_activity: AtomicU64,
+    /// The time of the last frame read or written, in microseconds since the connection was accepted.
+    last_progress: AtomicU64,
+    /// The number of frames received and not handled yet.
+    pending: AtomicUsize,
+    /// Cancelled by the watchdog when the connection stalled.
+    stalled: CancellationToken,
+}
+
+impl Default for LiveState {
+    fn default() -> Self {
+        LiveState {
+            connected_at: Instant::now(),
+            negotiated: OnceLock::new(),
+            frames: AtomicU64::new(0),
+            last_activity: AtomicU64::new(0),
+            last_progress: AtomicU64::new(0),
+            pending: AtomicUsize::new(0),
+            stalled: CancellationToken::new(),
+        }
+    }
+}
+
+impl LiveState {
+    fn elapsed(&self) -> u64 {
+        self.connected_at.elapsed().as_micros() as u64
+    }
+
+    pub fn received(&self) {
+        let now = self.elapsed();
+
+        self.frames.fetch_add(1, Relaxed);
+        self.pending.fetch_add(1, Relaxed);
+        self.last_activity.store(now, Relaxed);
+        self.last_progress.store(now, Relaxed);
+    }
+
+    pub fn written(&self) {
+        self.last_progress.store(self.elapsed(), Relaxed);
+    }
+
+    pub fn handled(&self) {
+        self.pending.fetch_sub(1, Relaxed);
+        self.written();
+    }
+
+    pub fn stalled(&self) -> CancellationToken {
+        self.stalled.clone()
+    }
+
+    pub fn handshaked(&self, negotiated: &Negotiated) {
+        let _ = self.negotiated.set(negotiated.clone());
+    }
+}
+
+/// The commands sent to a connection by its handles.
+#[derive(Debug)]
+pub(crate) enum Command {
+    /// Send the AGENT-DISCONNECT frame and close the connection.
+    Disconnect(Disconnect),
+}
+
+/// A handle to an active connection, used by the operational tooling.
+#[derive(Clone, Debug)]
+pub struct ConnectionHandle {
+    id: ConnectionId,
+    ulid: Ulid,
+    info: IoInfo,
+    origin: Option<String>,
+    commands: UnboundedSender<Command>,
+    history: Option<History>,
+    live: Arc<LiveState>,
+}
+
+impl ConnectionHandle {
+    pub(crate) fn new(
+        id: ConnectionId,
+        ulid: Ulid,
+        info: IoInfo,
+        origin: Option<String>,
+        commands: UnboundedSender<Command>,
+        history: Option<History>,
+        live: Arc<LiveState>,
+    ) -> Self {
+        ConnectionHandle {
+            id,
+            ulid,
+            info,
+            origin,
+            commands,
+            history,
+            live,
+        }
+    }
+
+    /// Returns the identifier of the connection.
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /// Returns the stable identifier of the connection, logged in its tracing span.
+    pub fn ulid(&self) -> Ulid {
+        self.ulid
+    }
+
+    /// Returns the addresses of the connection.
+    pub fn io_info(&self) -> &IoInfo {
+        &self.info
+    }
+
+    /// Returns the identity of the HAProxy instance, as tagged by the accept filter.
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_deref()
+    }
+
+    /// Returns the parameters negotiated by the handshake, `None` before it completed.
+    pub fn negotiated(&self) -> Option<&Negotiated> {
+        self.live.negotiated.get()
+    }
+
+    /// Returns the identifier of the SPOE engine, once the handshake completed.
+    pub fn engine_id(&self) -> Option<&str> {
+        self.negotiated()?.engine_id.as_deref()
+    }
+
+    /// Returns the number of frames received on the connection.
+    pub fn frames(&self) -> u64 {
+        self.live.frames.load(Relaxed)
+    }
+
+    /// Returns when the connection was accepted.
+    pub fn connected_at(&self) -> Instant {
+        self.live.connected_at
+    }
+
+    /// Returns when the last frame was received, or the connection was accepted.
+    pub fn last_activity(&self) -> Instant {
+        self.live.connected_at + Duration::from_micros(self.live.last_activity.load(Relaxed))
+    }
+
+    /// Returns when the last frame was read or written.
+    pub fn last_progress(&self) -> Instant {
+        self.live.connected_at + Duration::from_micros(self.live.last_progress.load(Relaxed))
+    }
+
+    /// Returns the number of frames received and not handled yet.
+    pub fn pending(&self) -> usize {
+        self.live.pending.load(Relaxed)
+    }
+
+    /// Returns `true` if the connection has pending frames and made no progress for the duration.
+    pub fn is_stalled(&self, threshold: Duration) -> bool {
+        self.pending() > 0 && self.last_progress().elapsed() > threshold
+    }
+
+    /// Abort the frames being handled and close the connection with the `Timeout` status.
+    pub(crate) fn kill(&self) {
+        self.live.stalled.cancel()
+    }
+
+    /// Returns the last NOTIFY frames processed by the connection, if the history is enabled.
+    pub fn history(&self) -> Option<Vec<Record>> {
+        self.history.as_ref().map(History::records)
+    }
+
+    /// Returns `true` if the connection has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.commands.is_closed()
+    }
+
+    /// Send the AGENT-DISCONNECT frame with the status and reason, and close the connection.
+    ///
+    /// The frames being processed are completed first, the connection is closed
+    /// before reading the next frame.
+    pub fn disconnect<S: Into<String>>(&self, status: Status, reason: S) -> Result<()> {
+        self.disconnect_with(Disconnect::new(status, reason))
+    }
+
+    /// Send the AGENT-DISCONNECT frame, e.g. with a vendor-specific status code, and close the connection.
+    pub fn disconnect_with(&self, disconnect: Disconnect) -> Result<()> {
+        self.commands
+            .send(Command::Disconnect(disconnect))
+            .map_err(|_| Closed)
+    }
+
+    /// Close the connection gracefully, once the frames being processed are completed.
+    pub fn drain(&self) -> Result<()> {
+        self.disconnect(Status::Normal, "draining")
+    }
+}
+
+/// The active connections of the runtime.
+#[derive(Debug)]
+pub struct Registry {
+    next_id: AtomicU64,
+    conns: DashMap<ConnectionId, ConnectionHandle>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            next_id: AtomicU64::new(0),
+            conns: DashMap::new(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        }
+    }
+}
+
+impl Registry {
+    /// Subscribe to the lifecycle events of the connections.
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    /// Send the AGENT-DISCONNECT frame to the connection and close it.
+    pub fn disconnect<S: Into<String>>(
+        &self,
+        id: ConnectionId,
+        status: Status,
+        reason: S,
+    ) -> Result<()> {
+        self.get(id).ok_or(Closed)?.disconnect(status, reason)
+    }
+
+    /// Returns the handles of the active connections.
+    pub fn connections(&self) -> Vec<ConnectionHandle> {
+        self.conns
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect()
+    }
+
+    /// Returns the handle of the connection.
+    pub fn get(&self, id: ConnectionId) -> Option<ConnectionHandle> {
+        self.conns.get(&id).map(|entry| entry.value().clone())
+    }
+
+    pub(crate) fn next_id(&self) -> ConnectionId {
+        self.next_id.fetch_add(1, Relaxed)
+    }
+
+    pub(crate) fn register(&self, handle: ConnectionHandle) {
+        self.conns.insert(handle.id, handle.clone());
+        let _ = self.events.send(ConnectionEvent::Connected(handle));
+    }
+
+    pub(crate) fn handshaked(&self, id: ConnectionId) {
+        if let Some(handle) = self.get(id) {
+            let _ = self.events.send(ConnectionEvent::Handshaked(handle));
+        }
+    }
+
+    pub(crate) fn reject(&self, failure: HandshakeFailure) {
+        let _ = self.events.send(ConnectionEvent::Rejected(failure));
+    }
+
+    pub(crate) fn deregister(&self, id: ConnectionId) {
+        if self.conns.remove(&id).is_some() {
+            let _ = self.events.send(ConnectionEvent::Disconnected(id));
+        }
+    }
+}
@@ -1,13 +1,26 @@
 use std::error::Error as StdError;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
 use std::time::Duration;
 
+use derive_more::Debug;
 use tokio::sync::{mpsc::unbounded_channel, RwLock};
 use tower::MakeService;
+use tracing::debug;
 
 use crate::{
+    accept::AcceptFilter,
+    defaults::DefaultActions,
     error::{Context, Result},
-    runtime::{Dispatcher, Processor},
-    spop::{Capability, Version},
+    fault::FaultInjection,
+    runtime::{
+        AdaptiveTimeout, CircuitBreaker, Dispatcher, EngineStats, HalfOpen, HandshakeLimits,
+        Health, Load, LoadSnapshot, Processor, Registry, RuntimeHandle, Sampling, Shard,
+        Supervisor, VarGuard, Watchdog, WriteScheduler,
+    },
+    spop::{Capability, Error as Status, Limits, Version},
+    state::HandshakeHook,
 };
 
 #[derive(Debug)]
@@ -17,6 +30,11 @@ pub struct ServiceMaker<S, T> {
 }
 
 impl<S, T> ServiceMaker<S, T> {
+    /// Replace the `MakeService`, returning the previous one.
+    pub fn replace(&mut self, maker: S) -> S {
+        mem::replace(&mut self.maker, maker)
+    }
+
     pub async fn make<REQ>(&mut self) -> Result<S::Service>
     where
         S: MakeService<T, REQ>,
@@ -33,34 +51,140 @@ impl<S, T> ServiceMaker<S, T> {
 #[derive(Debug)]
 pub struct Runtime<S, T> {
     pub dispatcher: Dispatcher,
+    /// The min size of the frames decoded and encoded on the blocking thread pool.
+    pub offload_size: Option<usize>,
+    /// The caps on the number of items decoded from the frames.
+    pub decode_limits: Limits,
     pub processor: Processor,
     pub supported_versions: Vec<Version>,
     pub capabilities: Vec<Capability>,
     pub max_frame_size: usize,
     pub max_process_time: Duration,
+    pub max_connections: Option<usize>,
+    /// The `timeout processing` configured in HAProxy, defaults to `max_process_time`.
+    pub haproxy_timeout: Option<Duration>,
+    /// Adapt the processing timeout of the frames to their recent processing times.
+    pub adaptive_timeout: Option<AdaptiveTimeout>,
+    /// The max time to write a frame to the peer.
+    pub write_timeout: Option<Duration>,
+    /// How long to write the ACK frames in flight once HAProxy half-closed the connection.
+    pub half_close_linger: Option<Duration>,
+    /// How long to drain the connections on shutdown before closing them.
+    pub drain_timeout: Option<Duration>,
+    /// Close the connections stalled with pending frames.
+    pub watchdog: Option<Watchdog>,
+    /// Process only the streams of this shard, answering the others with an empty ACK frame.
+    pub shard: Option<Shard>,
+    /// Process only a ratio of the messages of some names.
+    pub sampling: Sampling,
+    /// Emit the records of the stream loggers only for the failed frames or slower than the threshold.
+    pub tail_logging: Option<Duration>,
+    /// The faults injected in the processing of the frames, for chaos testing.
+    pub fault_injection: Option<Arc<FaultInjection>>,
+    /// The statistics of the SPOE engines, aggregated across their connections.
+    pub engine_stats: Option<EngineStats>,
+    /// The number of NOTIFY frames recorded in the history of every connection.
+    pub history: Option<usize>,
+    /// The processing time budget of every connection per scheduling interval.
+    pub time_slice: Option<(Duration, Duration)>,
+    /// Enforce the max length of the variable values set by the services.
+    pub var_guard: Option<Arc<VarGuard>>,
+    /// The actions appended to every ACK frame, unless overridden by the services.
+    pub default_actions: Option<Arc<DefaultActions>>,
+    /// The limits of the connections before the handshake completed.
+    pub handshake: HandshakeLimits,
+    /// The connections which have not completed the handshake yet.
+    pub half_open: HalfOpen,
+    /// Route the ACK frames across the connections of the same engine in the `async` mode.
+    pub scheduler: WriteScheduler,
+    /// Extract the `spoe-group` and event names from the well-known message arguments.
+    pub spoe_events: bool,
+    /// Stop making the services once the `MakeService` repeatedly failed.
+    pub circuit_breaker: Option<CircuitBreaker>,
+    /// Answer the frames failed by the services with the [`ERROR_VAR`] variable,
+    /// as the engine is configured with `option continue-on-error`.
+    pub continue_on_error: bool,
+    /// Read the PROXY protocol header on the accepted connections.
+    #[cfg(feature = "proxy-protocol")]
+    pub proxy_protocol: bool,
+    /// Decide whether to serve the accepted connections.
+    #[debug(skip)]
+    pub accept_filter: Option<AcceptFilter>,
+    /// Inspect the HAPROXY-HELLO frames and adjust the negotiated parameters.
+    #[debug(skip)]
+    pub handshake_hook: Option<HandshakeHook>,
+    pub load: Load,
+    /// The active connections.
+    pub registry: Registry,
+    /// The health of the dependencies, reported to the SPOE healthchecks.
+    pub health: Health,
+    pub supervisor: Supervisor,
+    /// Bumped to make the existing connections re-make their service.
+    pub generation: AtomicU64,
     pub service_maker: RwLock<ServiceMaker<S, T>>,
 }
 
 pub const MAX_PROCESS_TIME: Duration = Duration::from_secs(15);
 
+/// The transaction variable set to the status code of the frames failed by the services,
+/// when the engine continues on error.
+pub const ERROR_VAR: &str = "spoa_error";
+
 impl<S, T> Runtime<S, T> {
     pub fn new(
         supported_versions: Vec<Version>,
         capabilities: Vec<Capability>,
         max_frame_size: usize,
         max_process_time: Duration,
+        max_connections: Option<usize>,
         make_service: S,
         make_state: T,
     ) -> Self {
         let (sender, receiver) = unbounded_channel();
+        let load = Load::default();
+        let mut dispatcher = Dispatcher::new(sender);
+        dispatcher.drops = load.drops.clone();
 
         Runtime {
-            dispatcher: Dispatcher::new(sender),
+            dispatcher,
+            offload_size: None,
+            decode_limits: Limits::default(),
             processor: Processor(receiver),
             supported_versions,
             capabilities,
             max_frame_size,
             max_process_time,
+            max_connections,
+            haproxy_timeout: None,
+            adaptive_timeout: None,
+            write_timeout: None,
+            half_close_linger: None,
+            drain_timeout: None,
+            watchdog: None,
+            shard: None,
+            sampling: Sampling::default(),
+            tail_logging: None,
+            fault_injection: None,
+            engine_stats: None,
+            history: None,
+            time_slice: None,
+            var_guard: None,
+            default_actions: None,
+            handshake: HandshakeLimits::default(),
+            half_open: HalfOpen::default(),
+            scheduler: WriteScheduler::default(),
+            spoe_events: false,
+            circuit_breaker: None,
+            continue_on_error: false,
+            #[cfg(feature = "proxy-protocol")]
+            proxy_protocol: false,
+            accept_filter: None,
+            handshake_hook: None,
+            load,
+            registry: Registry::default(),
+            health: Health::default(),
+            supervisor: Supervisor::default(),
+            generation: AtomicU64::new(0),
             service_maker: RwLock::new(ServiceMaker {
                 maker: make_service,
                 state: make_state,
@@ -68,3 +192,86 @@ impl<S, T> Runtime<S, T> {
         }
     }
 }
+
+impl<S, T> Runtime<S, T> {
+    /// Returns a snapshot of the current load.
+    pub fn load(&self) -> LoadSnapshot {
+        let routed = self.scheduler.stats();
+
+        LoadSnapshot {
+            workers: self.supervisor.health(),
+            oversized: self
+                .var_guard
+                .as_ref()
+                .map_or(0, |guard| guard.occurrences()),
+            acks_rerouted: routed.rerouted,
+            acks_suppressed: routed.suppressed,
+            ..self.load.snapshot()
+        }
+    }
+
+    /// Returns the `max-frame-size` fitting almost all the frames seen so far,
+    /// to be fed back into the SPOE configuration of HAProxy.
+    pub fn suggested_max_frame_size(&self) -> Option<usize> {
+        self.load.snapshot().frame_sizes.suggested_max_frame_size()
+    }
+
+    /// Returns the `timeout processing` configured in HAProxy.
+    pub fn haproxy_timeout(&self) -> Duration {
+        self.haproxy_timeout.unwrap_or(self.max_process_time)
+    }
+
+    /// Returns `true` if the runtime should stop accepting new connections.
+    pub fn is_overloaded(&self) -> bool {
+        self.load.is_draining()
+            || self
+                .max_connections
+                .is_some_and(|max| self.load.connections() >= max)
+    }
+}
+
+impl<S, T> Runtime<S, T> {
+    /// Make a service for a connection, through the circuit breaker if any.
+    ///
+    /// Returns `None` when no service was made while the circuit breaker
+    /// is open with the [`FailOpen`](crate::runtime::BreakerPolicy::FailOpen) policy.
+    pub(crate) async fn make_service<REQ>(&self) -> Result<Option<S::Service>>
+    where
+        S: MakeService<T, REQ>,
+        S::MakeError: StdError + Send + Sync + 'static,
+        T: Clone,
+    {
+        let Some(breaker) = &self.circuit_breaker else {
+            return self.service_maker.write().await.make().await.map(Some);
+        };
+
+        let res = if breaker.try_acquire() {
+            let res = self.service_maker.write().await.make().await;
+            breaker.record(res.is_ok());
+            res
+        } else {
+            Err(Status::ResourceAllocErr).context("circuit breaker open")
+        };
+
+        match res {
+            Err(err) if breaker.fail_open() => {
+                debug!(%err, "no service, fail open");
+
+                Ok(None)
+            }
+            res => res.map(Some),
+        }
+    }
+}
+
+impl<S, T> Runtime<S, T> {
+    /// Returns a handle to manage the runtime while serving.
+    pub fn handle(self: &Arc<Self>) -> RuntimeHandle<S, T> {
+        RuntimeHandle::new(self.clone())
+    }
+
+    /// Returns the generation of the services, the connections re-make their service when it changed.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Relaxed)
+    }
+}
@@ -0,0 +1,124 @@
+use dashmap::DashMap;
+use tracing::debug;
+
+use crate::{metrics, spop::Message};
+
+/// The transaction variable set to `false` in the ACK frames of the frames not sampled,
+/// with [`Builder::sampled_var`](crate::runtime::Builder::sampled_var).
+pub const SAMPLED_VAR: &str = "spoa_sampled";
+
+/// Process only a ratio of the messages of some names, e.g. the expensive analytics ones,
+/// without changing the HAProxy configuration.
+///
+/// The messages not sampled are removed from their frames before calling the services,
+/// and the frames left without messages are answered with an empty ACK frame.
+/// The rates may be adjusted while serving, e.g. with [`RuntimeHandle::set_sample_rate`].
+///
+/// [`RuntimeHandle::set_sample_rate`]: crate::runtime::RuntimeHandle::set_sample_rate
+#[derive(Debug, Default)]
+pub struct Sampling {
+    rates: DashMap<String, f64>,
+    /// Set the [`SAMPLED_VAR`] variable in the ACK frames of the frames not sampled.
+    pub sampled_var: bool,
+}
+
+impl Sampling {
+    /// Process the ratio of the messages of the name, between `0` and `1`.
+    ///
+    /// The rate of `1` processes all the messages, as the messages without a rate.
+    pub fn set_rate<S: Into<String>>(&self, message: S, rate: f64) {
+        let message = message.into();
+        let rate = rate.clamp(0.0, 1.0);
+
+        debug!(message, rate, "sample rate");
+        metrics::message_gauge(metrics::SAMPLE_RATE, &message, rate);
+
+        if rate < 1.0 {
+            self.rates.insert(message, rate);
+        } else {
+            self.rates.remove(&message);
+        }
+    }
+
+    /// Returns the ratio of the messages of the name processed.
+    pub fn rate(&self, message: &str) -> f64 {
+        self.rates.get(message).map_or(1.0, |rate| *rate)
+    }
+
+    /// Returns the rates of the sampled messages, sorted by names.
+    pub fn rates(&self) -> Vec<(String, f64)> {
+        let mut rates = self
+            .rates
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect::<Vec<_>>();
+
+        rates.sort_by(|a, b| a.0.cmp(&b.0));
+        rates
+    }
+
+    /// Returns `true` if all the messages are processed.
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// Remove the messages not sampled.
+    pub(crate) fn sample(&self, msgs: &mut Vec<Message>) {
+        msgs.retain(|msg| {
+            let Some(rate) = self.rates.get(&msg.name).map(|rate| *rate) else {
+                return true;
+            };
+
+            let sampled = rand::random::<f64>() < rate;
+            if !sampled {
+                metrics::message_counter(metrics::MESSAGES_UNSAMPLED, &msg.name, 1);
+            }
+            sampled
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sampling() {
+        let sampling = Sampling::default();
+        let msgs = || {
+            vec![
+                Message::new("check", [("n", 1)]),
+                Message::new("analytics", [("n", 1)]),
+                Message::new("never", [("n", 1)]),
+            ]
+        };
+
+        sampling.set_rate("analytics", 0.5);
+        sampling.set_rate("never", -1.0);
+        assert_eq!(
+            sampling.rates(),
+            vec![("analytics".to_string(), 0.5), ("never".to_string(), 0.0)]
+        );
+        assert_eq!(sampling.rate("check"), 1.0);
+
+        let mut sampled = 0;
+        for _ in 0..1000 {
+            let mut msgs = msgs();
+            sampling.sample(&mut msgs);
+
+            assert_eq!(msgs[0].name, "check");
+            assert!(msgs.iter().all(|msg| msg.name != "never"));
+            sampled += msgs.len() - 1;
+        }
+        assert!((350..650).contains(&sampled), "{sampled}");
+
+        // all the messages are processed at the full rate
+        sampling.set_rate("analytics", 1.0);
+        sampling.set_rate("never", 2.0);
+        assert!(sampling.is_empty());
+
+        let mut all = msgs();
+        sampling.sample(&mut all);
+        assert_eq!(all, msgs());
+    }
+}
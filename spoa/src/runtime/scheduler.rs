@@ -0,0 +1,414 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use tokio::sync::mpsc::{error::SendError, unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    loom::sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    metrics,
+    runtime::ConnectionId,
+    spop::{AgentAck, Frame, FrameId, StreamId},
+};
+
+/// The default max number of ACK frames routed to the other connections of an engine
+/// and not written yet, bounding how far the ACK frames may be reordered.
+pub const REORDER_WINDOW: usize = 64;
+
+/// The number of the last ACK frames of an engine remembered to suppress the duplicates.
+pub const DEDUP_WINDOW: usize = 1024;
+
+/// Where an ACK frame is written.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Route {
+    /// Written on the connection which received the NOTIFY frame.
+    Local(Frame),
+    /// Routed to another connection of the same engine.
+    Remote(ConnectionId),
+    /// Already sent for the same frame, dropped.
+    Duplicate,
+}
+
+/// The statistics of the ACK frames routed across the connections.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SchedulerStats {
+    /// The number of ACK frames written on another connection than the NOTIFY frame.
+    pub rerouted: u64,
+    /// The number of duplicate ACK frames dropped.
+    pub suppressed: u64,
+}
+
+/// Schedule the ACK frames on the connections of the same engine,
+/// when the `async` capability allows HAProxy to receive them on any connection.
+///
+/// Every ACK frame is written on the least-loaded connection of the engine,
+/// the one which received the NOTIFY frame unless another one has less work pending.
+///
+/// The load of a connection is the number of NOTIFY frames it is processing,
+/// including the one being acknowledged, and of the ACK frames routed to it and not written yet.
+#[derive(Debug)]
+pub struct WriteScheduler {
+    window: usize,
+    groups: Mutex<HashMap<String, Group>>,
+    rerouted: AtomicU64,
+    suppressed: AtomicU64,
+}
+
+impl Default for WriteScheduler {
+    fn default() -> Self {
+        WriteScheduler::new(REORDER_WINDOW)
+    }
+}
+
+/// The connections of an engine.
+#[derive(Debug, Default)]
+struct Group {
+    lanes: Vec<Lane>,
+    recent: VecDeque<(StreamId, FrameId)>,
+    seen: HashSet<(StreamId, FrameId)>,
+}
+
+#[derive(Debug)]
+struct Lane {
+    id: ConnectionId,
+    acks: UnboundedSender<Frame>,
+    load: Arc<Load>,
+}
+
+/// The work pending on a connection.
+#[derive(Debug, Default)]
+struct Load {
+    /// The NOTIFY frames received and not acknowledged yet.
+    in_flight: AtomicUsize,
+    /// The ACK frames routed to the connection and not written yet.
+    queued: AtomicUsize,
+}
+
+impl Load {
+    fn get(&self) -> usize {
+        self.in_flight.load(Relaxed) + self.queued.load(Relaxed)
+    }
+}
+
+/// The ACK frames routed to a connection by the other connections of its engine.
+#[derive(Debug)]
+pub(crate) struct AckLane {
+    pub engine_id: String,
+    pub acks: UnboundedReceiver<Frame>,
+    load: Arc<Load>,
+}
+
+impl AckLane {
+    /// Mark a NOTIFY frame as received by the connection.
+    pub fn received(&self) {
+        self.load.in_flight.fetch_add(1, Relaxed);
+    }
+
+    /// Mark a NOTIFY frame received by the connection as acknowledged, or dropped.
+    pub fn acked(&self) {
+        let _ = self
+            .load
+            .in_flight
+            .fetch_update(Relaxed, Relaxed, |n| n.checked_sub(1));
+    }
+
+    /// Mark a routed ACK frame as written.
+    pub fn written(&self) {
+        self.load.queued.fetch_sub(1, Relaxed);
+    }
+}
+
+impl WriteScheduler {
+    /// Reorder at most the number of ACK frames across the connections of an engine.
+    pub fn new(window: usize) -> Self {
+        WriteScheduler {
+            window,
+            groups: Mutex::default(),
+            rerouted: AtomicU64::new(0),
+            suppressed: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the max number of ACK frames routed and not written yet per engine.
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    /// Returns the number of connections of the engine.
+    pub fn connections(&self, engine_id: &str) -> usize {
+        self.groups
+            .lock()
+            .unwrap()
+            .get(engine_id)
+            .map_or(0, |group| group.lanes.len())
+    }
+
+    /// Returns the statistics of the routed ACK frames.
+    pub fn stats(&self) -> SchedulerStats {
+        SchedulerStats {
+            rerouted: self.rerouted.load(Relaxed),
+            suppressed: self.suppressed.load(Relaxed),
+        }
+    }
+
+    /// Add the connection to its engine, receiving the ACK frames routed to it.
+    pub(crate) fn join(&self, engine_id: &str, id: ConnectionId) -> AckLane {
+        let (sender, receiver) = unbounded_channel();
+        let load = Arc::new(Load::default());
+
+        self.groups
+            .lock()
+            .unwrap()
+            .entry(engine_id.to_string())
+            .or_default()
+            .lanes
+            .push(Lane {
+                id,
+                acks: sender,
+                load: load.clone(),
+            });
+
+        AckLane {
+            engine_id: engine_id.to_string(),
+            acks: receiver,
+            load,
+        }
+    }
+
+    /// Remove the connection from its engine.
+    pub(crate) fn leave(&self, engine_id: &str, id: ConnectionId) {
+        let mut groups = self.groups.lock().unwrap();
+
+        if let Some(group) = groups.get_mut(engine_id) {
+            group.lanes.retain(|lane| lane.id != id);
+
+            if group.lanes.is_empty() {
+                groups.remove(engine_id);
+            }
+        }
+    }
+
+    /// Route the ACK frame of a NOTIFY frame received by the connection.
+    pub(crate) fn route(&self, engine_id: &str, origin: ConnectionId, ack: AgentAck) -> Route {
+        let mut groups = self.groups.lock().unwrap();
+        let Some(group) = groups.get_mut(engine_id) else {
+            return Route::Local(Frame::AgentAck(ack));
+        };
+
+        let key = (ack.stream_id, ack.frame_id);
+        if !group.seen.insert(key) {
+            self.suppressed.fetch_add(1, Relaxed);
+            metrics::counter(metrics::ACKS_SUPPRESSED, 1);
+
+            return Route::Duplicate;
+        }
+        group.recent.push_back(key);
+        if group.recent.len() > DEDUP_WINDOW {
+            if let Some(key) = group.recent.pop_front() {
+                group.seen.remove(&key);
+            }
+        }
+
+        let load = |lane: &Lane| lane.load.get();
+        let queued = group
+            .lanes
+            .iter()
+            .map(|lane| lane.load.queued.load(Relaxed))
+            .sum::<usize>();
+        let local = group
+            .lanes
+            .iter()
+            .find(|lane| lane.id == origin)
+            .map_or(0, load);
+        let target = group
+            .lanes
+            .iter()
+            .filter(|lane| lane.id != origin && load(lane) < local)
+            .min_by_key(|lane| load(lane));
+
+        let frame = Frame::AgentAck(ack);
+
+        match target {
+            Some(lane) if queued < self.window => {
+                lane.load.queued.fetch_add(1, Relaxed);
+
+                match lane.acks.send(frame) {
+                    Ok(()) => {
+                        self.rerouted.fetch_add(1, Relaxed);
+                        metrics::counter(metrics::ACKS_REROUTED, 1);
+
+                        Route::Remote(lane.id)
+                    }
+                    Err(SendError(frame)) => {
+                        lane.load.queued.fetch_sub(1, Relaxed);
+
+                        Route::Local(frame)
+                    }
+                }
+            }
+            _ => Route::Local(frame),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(stream_id: StreamId) -> AgentAck {
+        AgentAck {
+            fragmented: false,
+            aborted: false,
+            stream_id,
+            frame_id: 1,
+            actions: vec![],
+        }
+    }
+
+    #[test]
+    fn test_route() {
+        let scheduler = WriteScheduler::new(2);
+
+        assert_eq!(
+            scheduler.route("engine", 1, ack(1)),
+            Route::Local(Frame::AgentAck(ack(1)))
+        );
+
+        let first = scheduler.join("engine", 1);
+        let mut second = scheduler.join("engine", 2);
+        assert_eq!(scheduler.connections("engine"), 2);
+
+        // both connections are processing a NOTIFY frame
+        first.received();
+        second.received();
+        assert_eq!(
+            scheduler.route("engine", 1, ack(2)),
+            Route::Local(Frame::AgentAck(ack(2)))
+        );
+        assert_eq!(scheduler.route("engine", 1, ack(2)), Route::Duplicate);
+        first.acked();
+
+        // the second connection is idle, the ACK frame is routed to it
+        second.acked();
+        first.received();
+        assert_eq!(scheduler.route("engine", 1, ack(3)), Route::Remote(2));
+        assert_eq!(second.acks.try_recv().unwrap(), Frame::AgentAck(ack(3)));
+
+        // the second connection is as loaded as the first one until it writes the routed ACK frame
+        assert_eq!(
+            scheduler.route("engine", 1, ack(4)),
+            Route::Local(Frame::AgentAck(ack(4)))
+        );
+        second.written();
+        first.acked();
+
+        // the reordering window is full
+        let scheduler = WriteScheduler::new(1);
+        let first = scheduler.join("engine", 1);
+        let _second = scheduler.join("engine", 2);
+        let _third = scheduler.join("engine", 3);
+
+        first.received();
+        assert_eq!(scheduler.route("engine", 1, ack(5)), Route::Remote(2));
+        assert_eq!(
+            scheduler.route("engine", 1, ack(6)),
+            Route::Local(Frame::AgentAck(ack(6)))
+        );
+        assert_eq!(
+            scheduler.stats(),
+            SchedulerStats {
+                rerouted: 1,
+                suppressed: 0
+            }
+        );
+
+        scheduler.leave("engine", 3);
+        scheduler.leave("engine", 2);
+        scheduler.leave("engine", 1);
+        assert_eq!(scheduler.connections("engine"), 0);
+        assert!(scheduler.groups.lock().unwrap().is_empty());
+    }
+}
+
+#[cfg(all(test, loom))]
+mod model {
+    use ::loom::thread;
+
+    use super::*;
+
+    fn ack(stream_id: StreamId) -> AgentAck {
+        AgentAck {
+            fragmented: false,
+            aborted: false,
+            stream_id,
+            frame_id: 1,
+            actions: vec![],
+        }
+    }
+
+    #[test]
+    fn model_join_leave_route() {
+        ::loom::model(|| {
+            let scheduler = Arc::new(WriteScheduler::new(1));
+            let first = scheduler.join("engine", 1);
+            first.received();
+
+            let handle = thread::spawn({
+                let scheduler = scheduler.clone();
+
+                move || {
+                    let second = scheduler.join("engine", 2);
+                    scheduler.leave("engine", 2);
+                    second
+                }
+            });
+
+            let route = scheduler.route("engine", 1, ack(1));
+            let mut second = handle.join().unwrap();
+
+            // the ACK frame is either written locally or received once by the second connection
+            match route {
+                Route::Local(frame) => {
+                    assert_eq!(frame, Frame::AgentAck(ack(1)));
+                    assert!(second.acks.try_recv().is_err());
+                }
+                Route::Remote(2) => {
+                    assert_eq!(second.acks.try_recv().unwrap(), Frame::AgentAck(ack(1)));
+                    second.written();
+                    assert_eq!(second.load.get(), 0);
+                }
+                route => panic!("unexpected route: {route:?}"),
+            }
+
+            assert_eq!(scheduler.connections("engine"), 1);
+            scheduler.leave("engine", 1);
+            assert!(scheduler.groups.lock().unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn model_lane_load() {
+        ::loom::model(|| {
+            let scheduler = WriteScheduler::default();
+            let lane = Arc::new(scheduler.join("engine", 1));
+            lane.received();
+
+            let handle = thread::spawn({
+                let lane = lane.clone();
+
+                move || {
+                    lane.acked();
+                    // a NOTIFY frame dropped after being acknowledged
+                    lane.acked();
+                }
+            });
+
+            lane.received();
+            handle.join().unwrap();
+
+            // the in-flight counter never wraps around
+            assert!(lane.load.in_flight.load(Relaxed) <= 1);
+        });
+    }
+}
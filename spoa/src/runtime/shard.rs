@@ -0,0 +1,146 @@
+use crate::spop::{Message, StreamId, Typed};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The key hashed to choose the shard of a NOTIFY frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ShardKey {
+    /// The identifier of the stream.
+    #[default]
+    StreamId,
+    /// The value of the message argument, e.g. the source IP to keep the per-client state on one agent.
+    ///
+    /// The frames without the argument are processed by every shard.
+    Arg(String),
+}
+
+/// Process only the streams belonging to this agent, when several agent processes
+/// serve the same HAProxy backend, without an external coordinator.
+///
+/// The other streams are answered with an empty ACK frame, so the hash must be stable
+/// across the processes: the key is hashed with FNV-1a, never with a random seed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shard {
+    /// The index of this agent, lower than the number of shards.
+    pub index: u64,
+    /// The number of shards.
+    pub total: u64,
+    /// The key hashed to choose the shard.
+    pub key: ShardKey,
+}
+
+impl Shard {
+    /// Shard the streams by their identifier.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index isn't lower than the number of shards.
+    pub fn new(index: u64, total: u64) -> Self {
+        assert!(index < total, "shard {index} out of {total}");
+
+        Shard {
+            index,
+            total,
+            key: ShardKey::StreamId,
+        }
+    }
+
+    /// Shard the streams by the value of the message argument.
+    pub fn by_arg<S: Into<String>>(mut self, name: S) -> Self {
+        self.key = ShardKey::Arg(name.into());
+        self
+    }
+
+    /// Returns `true` if the frame belongs to this shard.
+    pub fn owns(&self, stream_id: StreamId, msgs: &[Message]) -> bool {
+        let hash = match self.key {
+            ShardKey::StreamId => fnv1a(FNV_OFFSET_BASIS, &stream_id.to_be_bytes()),
+            ShardKey::Arg(ref name) => {
+                let value = msgs
+                    .iter()
+                    .flat_map(|msg| msg.args.iter())
+                    .find_map(|(k, v)| (k == name).then_some(v));
+
+                match value {
+                    Some(value) => hash_typed(value),
+                    None => return true,
+                }
+            }
+        };
+
+        hash % self.total == self.index
+    }
+}
+
+fn hash_typed(value: &Typed) -> u64 {
+    let h = FNV_OFFSET_BASIS;
+
+    match value {
+        Typed::Null => h,
+        Typed::Boolean(b) => fnv1a(h, &[*b as u8]),
+        Typed::Int32(n) => fnv1a(h, &i64::from(*n).to_be_bytes()),
+        Typed::Uint32(n) => fnv1a(h, &u64::from(*n).to_be_bytes()),
+        Typed::Int64(n) => fnv1a(h, &n.to_be_bytes()),
+        Typed::Uint64(n) => fnv1a(h, &n.to_be_bytes()),
+        Typed::Ipv4(ip) => fnv1a(h, &ip.octets()),
+        Typed::Ipv6(ip) => fnv1a(h, &ip.octets()),
+        Typed::String(s) => fnv1a(h, s.as_bytes()),
+        Typed::Binary(b) => fnv1a(h, b),
+    }
+}
+
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(hash, |h, &b| (h ^ u64::from(b)).wrapping_mul(FNV_PRIME))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn test_shard() {
+        const SHARDS: u64 = 4;
+
+        let shards = (0..SHARDS)
+            .map(|i| Shard::new(i, SHARDS))
+            .collect::<Vec<_>>();
+
+        for stream_id in 0..100 {
+            let owners = shards
+                .iter()
+                .filter(|shard| shard.owns(stream_id, &[]))
+                .count();
+            assert_eq!(owners, 1, "stream {stream_id}");
+        }
+
+        // the hash is stable across the processes
+        assert_eq!(fnv1a(FNV_OFFSET_BASIS, b"a"), 0xaf63_dc4c_8601_ec8c);
+
+        let by_src = shards
+            .into_iter()
+            .map(|shard| shard.by_arg("src"))
+            .collect::<Vec<_>>();
+        let msgs = [Message::new(
+            "client",
+            [("src", Ipv4Addr::new(10, 0, 0, 1))],
+        )];
+        let owner = by_src
+            .iter()
+            .position(|shard| shard.owns(1, &msgs))
+            .unwrap();
+        assert!((0..100).all(|stream_id| by_src[owner].owns(stream_id, &msgs)));
+        assert_eq!(
+            by_src.iter().filter(|shard| shard.owns(1, &msgs)).count(),
+            1
+        );
+
+        // the frames without the argument are processed by every shard
+        let msgs = [Message::new("client", [("port", 80)])];
+        assert!(by_src.iter().all(|shard| shard.owns(1, &msgs)));
+    }
+}
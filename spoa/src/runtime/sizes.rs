@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+/// The upper bound of the first bucket of the frame size histograms, doubled for each bucket.
+pub const MIN_SIZE_BUCKET: usize = 64;
+
+/// The number of buckets of the frame size histograms,
+/// the last one counting the frames larger than all the others.
+pub const SIZE_BUCKETS: usize = 12;
+
+/// The bounds of the `max-frame-size` accepted by HAProxy.
+const HAPROXY_FRAME_SIZE: (usize, usize) = (256, 16380);
+
+/// The percentile of the frames which should fit in the suggested `max-frame-size`.
+const SUGGESTED_PERCENTILE: f64 = 99.9;
+
+/// The exponential histogram of the frame sizes, without the length prefix as the `max-frame-size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// The number of frames whose size is within every bucket,
+    /// the upper bound of the bucket `i` is `MIN_SIZE_BUCKET << i`.
+    pub buckets: [u64; SIZE_BUCKETS],
+}
+
+impl SizeHistogram {
+    /// Returns the upper bound of the bucket, `None` for the last one.
+    pub fn bound(bucket: usize) -> Option<usize> {
+        (bucket + 1 < SIZE_BUCKETS).then(|| MIN_SIZE_BUCKET << bucket)
+    }
+
+    /// Returns the number of frames.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the upper bound of the bucket including the percentile of the frames,
+    /// `None` when no frame was recorded or the percentile is beyond the last bound.
+    pub fn percentile(&self, percentile: f64) -> Option<usize> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+
+        let target = (count as f64 * percentile / 100.0).ceil() as u64;
+        let mut seen = 0;
+
+        self.buckets.iter().enumerate().find_map(|(bucket, &n)| {
+            seen += n;
+
+            if seen >= target {
+                Some(Self::bound(bucket))
+            } else {
+                None
+            }
+        })?
+    }
+}
+
+/// The distribution of the NOTIFY frames received and the ACK frames sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameSizes {
+    pub notify: SizeHistogram,
+    pub ack: SizeHistogram,
+}
+
+impl FrameSizes {
+    /// Returns the `max-frame-size` fitting almost all the frames,
+    /// to be fed back into the SPOE configuration of HAProxy.
+    ///
+    /// `None` until some frames have been recorded.
+    pub fn suggested_max_frame_size(&self) -> Option<usize> {
+        if self.notify.count() + self.ack.count() == 0 {
+            return None;
+        }
+
+        let (min, max) = HAPROXY_FRAME_SIZE;
+        let size = [self.notify, self.ack]
+            .iter()
+            .map(|sizes| {
+                if sizes.count() == 0 {
+                    Some(min)
+                } else {
+                    sizes.percentile(SUGGESTED_PERCENTILE)
+                }
+            })
+            .try_fold(min, |size, bound| bound.map(|bound| size.max(bound)))
+            .unwrap_or(max);
+
+        Some(size.clamp(min, max))
+    }
+}
+
+/// Record the frame sizes, shared by all the connections.
+#[derive(Debug, Default)]
+pub(crate) struct FrameSizeRecorder {
+    notify: [AtomicU64; SIZE_BUCKETS],
+    ack: [AtomicU64; SIZE_BUCKETS],
+}
+
+impl FrameSizeRecorder {
+    pub fn notify(&self, size: usize) {
+        self.notify[bucket(size)].fetch_add(1, Relaxed);
+    }
+
+    pub fn ack(&self, size: usize) {
+        self.ack[bucket(size)].fetch_add(1, Relaxed);
+    }
+
+    pub fn snapshot(&self) -> FrameSizes {
+        FrameSizes {
+            notify: SizeHistogram {
+                buckets: self.notify.each_ref().map(|n| n.load(Relaxed)),
+            },
+            ack: SizeHistogram {
+                buckets: self.ack.each_ref().map(|n| n.load(Relaxed)),
+            },
+        }
+    }
+}
+
+fn bucket(size: usize) -> usize {
+    let bucket = size
+        .div_ceil(MIN_SIZE_BUCKET)
+        .next_power_of_two()
+        .trailing_zeros() as usize;
+
+    bucket.min(SIZE_BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(64), 0);
+        assert_eq!(bucket(65), 1);
+        assert_eq!(bucket(128), 1);
+        assert_eq!(bucket(4096), 6);
+        assert_eq!(bucket(4097), 7);
+        assert_eq!(bucket(65536), 10);
+        assert_eq!(bucket(65537), 11);
+        assert_eq!(bucket(usize::MAX / 2), 11);
+    }
+
+    #[test]
+    fn test_suggested_max_frame_size() {
+        let sizes = FrameSizeRecorder::default();
+        assert_eq!(sizes.snapshot().suggested_max_frame_size(), None);
+
+        for _ in 0..999 {
+            sizes.notify(3000);
+            sizes.ack(100);
+        }
+        assert_eq!(sizes.snapshot().notify.percentile(50.0), Some(4096));
+        assert_eq!(sizes.snapshot().suggested_max_frame_size(), Some(4096));
+
+        // the outliers beyond the percentile are ignored
+        sizes.notify(10000);
+        assert_eq!(sizes.snapshot().suggested_max_frame_size(), Some(4096));
+
+        for _ in 0..10 {
+            sizes.ack(100000);
+        }
+        assert_eq!(sizes.snapshot().suggested_max_frame_size(), Some(16380));
+    }
+}
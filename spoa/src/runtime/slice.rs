@@ -0,0 +1,93 @@
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::time::{Duration, Instant};
+
+/// The processing time budget of a connection per scheduling interval.
+///
+/// When a connection used up its budget, its next frame is read at the end of the interval,
+/// in favor of the other connections, but never delayed more than half the `timeout processing`.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeSlice {
+    budget: Duration,
+    interval: Duration,
+    started: Instant,
+    used: Duration,
+}
+
+impl TimeSlice {
+    pub fn new(budget: Duration, interval: Duration) -> Self {
+        TimeSlice {
+            budget,
+            interval,
+            started: Instant::now(),
+            used: Duration::ZERO,
+        }
+    }
+
+    /// Returns the processing time used in the current interval.
+    pub fn used(&self) -> Duration {
+        self.used
+    }
+
+    /// Returns the delay before processing the next frame, starting a new interval when elapsed.
+    pub fn delay(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.started);
+
+        if elapsed >= self.interval {
+            self.started = now;
+            self.used = Duration::ZERO;
+
+            None
+        } else if self.used >= self.budget {
+            Some(self.interval - elapsed)
+        } else {
+            None
+        }
+    }
+
+    /// Charge the processing time to the current interval.
+    pub fn consume(&mut self, d: Duration) {
+        self.used += d;
+    }
+}
+
+/// Run the future, returning its output and the time spent polling it,
+/// without the time it was waiting for the I/O or the timers.
+pub(crate) async fn busy<F: Future>(fut: F) -> (F::Output, Duration) {
+    let mut fut = pin!(fut);
+    let mut busy = Duration::ZERO;
+
+    let output = poll_fn(|cx| {
+        let started = Instant::now();
+        let poll = fut.as_mut().poll(cx);
+        busy += started.elapsed();
+        poll
+    })
+    .await;
+
+    (output, busy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_slice() {
+        const MS: Duration = Duration::from_millis(1);
+
+        let mut slice = TimeSlice::new(10 * MS, 100 * MS);
+        let started = slice.started;
+
+        assert_eq!(slice.delay(started), None);
+
+        slice.consume(6 * MS);
+        assert_eq!(slice.delay(started + 6 * MS), None);
+
+        slice.consume(6 * MS);
+        assert_eq!(slice.delay(started + 12 * MS), Some(88 * MS));
+
+        assert_eq!(slice.delay(started + 100 * MS), None);
+        assert_eq!(slice.used(), Duration::ZERO);
+    }
+}
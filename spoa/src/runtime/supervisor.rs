@@ -0,0 +1,256 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::{select, time::sleep};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{debug, warn};
+
+use crate::{error::Result, task};
+
+/// The initial delay before restarting a dead worker.
+pub const MIN_BACKOFF: Duration = Duration::from_millis(100);
+/// The maximum delay before restarting a dead worker.
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Supervise the worker tasks, restarting the dead workers with exponential backoff.
+#[derive(Debug)]
+pub struct Supervisor {
+    min_backoff: Duration,
+    max_backoff: Duration,
+    health: Arc<Health>,
+}
+
+#[derive(Debug, Default)]
+struct Health {
+    workers: AtomicUsize,
+    alive: AtomicUsize,
+    restarts: AtomicU64,
+    panics: AtomicU64,
+}
+
+/// The health of the supervised workers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WorkerHealth {
+    /// The number of supervised workers.
+    pub workers: usize,
+    /// The number of workers running.
+    pub alive: usize,
+    /// The number of workers restarted.
+    pub restarts: u64,
+    /// The number of workers died of a panic.
+    pub panics: u64,
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        Supervisor::new(MIN_BACKOFF, MAX_BACKOFF)
+    }
+}
+
+impl Supervisor {
+    pub fn new(min_backoff: Duration, max_backoff: Duration) -> Self {
+        Supervisor {
+            min_backoff,
+            max_backoff: max_backoff.max(min_backoff),
+            health: Arc::default(),
+        }
+    }
+
+    /// Returns the health of the supervised workers.
+    pub fn health(&self) -> WorkerHealth {
+        WorkerHealth {
+            workers: self.health.workers.load(Relaxed),
+            alive: self.health.alive.load(Relaxed),
+            restarts: self.health.restarts.load(Relaxed),
+            panics: self.health.panics.load(Relaxed),
+        }
+    }
+
+    /// Spawn the workers, restarting them when they died until the token is cancelled.
+    ///
+    /// A worker returning normally is not restarted.
+    pub fn spawn<F, Fut>(
+        &self,
+        workers: usize,
+        tracker: &TaskTracker,
+        token: CancellationToken,
+        f: F,
+    ) -> Result<()>
+    where
+        F: Fn(usize) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        for id in 0..workers {
+            let worker = Worker {
+                id,
+                min_backoff: self.min_backoff,
+                max_backoff: self.max_backoff,
+                health: self.health.clone(),
+                token: token.clone(),
+            };
+
+            task::spawn(
+                "supervisor",
+                tracker.track_future(worker.supervise(f.clone())),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+struct Worker {
+    id: usize,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    health: Arc<Health>,
+    token: CancellationToken,
+}
+
+impl Worker {
+    async fn supervise<F, Fut>(self, f: F)
+    where
+        F: Fn(usize) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.id;
+        let mut backoff = self.min_backoff;
+
+        self.health.workers.fetch_add(1, Relaxed);
+
+        loop {
+            let started = Instant::now();
+            let handle = match task::spawn("worker", f(id)) {
+                Ok(handle) => handle,
+                Err(err) => {
+                    warn!(id, %err, "failed to spawn worker");
+
+                    break;
+                }
+            };
+            let abort = handle.abort_handle();
+
+            self.health.alive.fetch_add(1, Relaxed);
+
+            let res = select! {
+                res = handle => Some(res),
+                _ = self.token.cancelled() => None,
+            };
+
+            self.health.alive.fetch_sub(1, Relaxed);
+
+            match res {
+                None => {
+                    abort.abort();
+                    break;
+                }
+                Some(Ok(())) => {
+                    debug!(id, "worker finished");
+                    break;
+                }
+                Some(Err(err)) => {
+                    if err.is_panic() {
+                        self.health.panics.fetch_add(1, Relaxed);
+                    }
+
+                    if started.elapsed() > self.max_backoff {
+                        backoff = self.min_backoff;
+                    }
+
+                    warn!(id, %err, ?backoff, "worker died, restarting");
+
+                    select! {
+                        _ = sleep(backoff) => {}
+                        _ = self.token.cancelled() => break,
+                    }
+
+                    backoff = (backoff * 2).min(self.max_backoff);
+
+                    self.health.restarts.fetch_add(1, Relaxed);
+                }
+            }
+        }
+
+        self.health.workers.fetch_sub(1, Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{sync::mpsc::unbounded_channel, time};
+
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restart() {
+        let supervisor = Supervisor::new(100 * MS, 400 * MS);
+        let tracker = TaskTracker::new();
+        let token = CancellationToken::new();
+        let (started, mut starts) = unbounded_channel();
+
+        supervisor
+            .spawn(1, &tracker, token.clone(), move |id| {
+                let started = started.clone();
+
+                async move {
+                    started.send(time::Instant::now()).unwrap();
+
+                    panic!("worker {id} died");
+                }
+            })
+            .unwrap();
+
+        // the backoff doubles on every restart, up to the max backoff
+        let mut last = starts.recv().await.unwrap();
+        for backoff in [100 * MS, 200 * MS, 400 * MS, 400 * MS] {
+            let now = starts.recv().await.unwrap();
+
+            assert_eq!(now - last, backoff);
+            last = now;
+        }
+
+        let health = supervisor.health();
+        assert_eq!(health.workers, 1);
+        assert_eq!(health.restarts, 4);
+        assert!(health.panics >= 4);
+
+        token.cancel();
+        tracker.close();
+        tracker.wait().await;
+
+        let health = supervisor.health();
+        assert_eq!(health.workers, 0);
+        assert_eq!(health.alive, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_finished() {
+        let supervisor = Supervisor::default();
+        let tracker = TaskTracker::new();
+        let (started, mut starts) = unbounded_channel();
+
+        supervisor
+            .spawn(2, &tracker, CancellationToken::new(), move |id| {
+                let started = started.clone();
+
+                async move {
+                    started.send(id).unwrap();
+                }
+            })
+            .unwrap();
+
+        // the workers returning normally are not restarted
+        tracker.close();
+        tracker.wait().await;
+
+        let mut ids = [starts.recv().await.unwrap(), starts.recv().await.unwrap()];
+        ids.sort();
+        assert_eq!(ids, [0, 1]);
+        assert!(starts.try_recv().is_err());
+        assert_eq!(supervisor.health(), WorkerHealth::default());
+    }
+}
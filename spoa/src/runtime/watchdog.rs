@@ -0,0 +1,56 @@
+use std::time::Duration;
+
+use tracing::warn;
+
+use crate::runtime::{ConnectionId, Load, Registry};
+
+/// The number of checks of the watchdog during the stall threshold.
+const CHECKS_PER_THRESHOLD: u32 = 4;
+
+/// Detect the connections wedged with pending frames, e.g. a service or a socket write
+/// which never completes, and close them before they silently accumulate.
+///
+/// The threshold should exceed the max processing time, so the slow frames are timed out first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchdog {
+    /// How long a connection may make no progress while having pending frames.
+    pub threshold: Duration,
+}
+
+impl Watchdog {
+    pub fn new(threshold: Duration) -> Self {
+        Watchdog { threshold }
+    }
+
+    /// Returns the interval between the checks.
+    pub fn interval(&self) -> Duration {
+        self.threshold / CHECKS_PER_THRESHOLD
+    }
+
+    /// Close the stalled connections, returning their identifiers.
+    pub fn check(&self, registry: &Registry, load: &Load) -> Vec<ConnectionId> {
+        registry
+            .connections()
+            .into_iter()
+            .filter(|conn| conn.is_stalled(self.threshold))
+            .map(|conn| {
+                warn!(
+                    id = conn.id(),
+                    ulid = %conn.ulid(),
+                    peer = ?conn.io_info().peer_addr,
+                    engine_id = conn.engine_id(),
+                    pending = conn.pending(),
+                    frames = conn.frames(),
+                    stalled_for = ?conn.last_progress().elapsed(),
+                    history = ?conn.history(),
+                    "connection stalled"
+                );
+
+                load.stall();
+                conn.kill();
+
+                conn.id()
+            })
+            .collect()
+    }
+}
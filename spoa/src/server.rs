@@ -0,0 +1,177 @@
+use std::error::Error as StdError;
+use std::mem;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::future::BoxFuture;
+use tokio::sync::Mutex;
+use tower::{BoxError, MakeService, Service};
+
+#[cfg(feature = "debug-protocol")]
+use crate::spop::debug::Monitor;
+use crate::{
+    error::{Error, Result},
+    runtime::Runtime,
+    spop::{Action, Frame, Message},
+    state::{AsyncHandler, Negotiated},
+    State,
+};
+
+/// A [`Service`] driving the whole connection state machine frame by frame.
+///
+/// Users with their own IO stack (custom TLS, proxies, in-process transports)
+/// read the frames sent by HAProxy, call the service with them,
+/// and write the returned frame, if any, back to HAProxy.
+///
+/// The frames are handled in the order of the calls, a protocol error is replied
+/// with an AGENT-DISCONNECT frame, and the service is closed after the disconnection.
+///
+/// The returned futures are `Send`, so the frames may be handled on any thread.
+#[derive(Debug)]
+pub struct SpopServer<S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+{
+    state: Arc<Mutex<State<S, T>>>,
+    #[cfg(feature = "debug-protocol")]
+    monitor: Arc<std::sync::Mutex<Monitor>>,
+}
+
+impl<S, T> Clone for SpopServer<S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+{
+    fn clone(&self) -> Self {
+        SpopServer {
+            state: self.state.clone(),
+            #[cfg(feature = "debug-protocol")]
+            monitor: self.monitor.clone(),
+        }
+    }
+}
+
+impl<S, T> SpopServer<S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+{
+    pub fn new(runtime: Arc<Runtime<S, T>>) -> Self {
+        SpopServer {
+            state: Arc::new(Mutex::new(State::new(runtime))),
+            #[cfg(feature = "debug-protocol")]
+            monitor: Arc::default(),
+        }
+    }
+
+    /// Returns the negotiated parameters once the handshake has completed.
+    pub async fn negotiated(&self) -> Option<Negotiated> {
+        self.state.lock().await.negotiated().cloned()
+    }
+
+    /// Returns `true` if the connection has been disconnected.
+    pub async fn is_closed(&self) -> bool {
+        matches!(*self.state.lock().await, State::Disconnecting)
+    }
+}
+
+impl<S, T> Service<Frame> for SpopServer<S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync + 'static,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync + 'static,
+{
+    type Response = Option<Frame>;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Option<Frame>>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, frame: Frame) -> Self::Future {
+        let state = self.state.clone();
+        #[cfg(feature = "debug-protocol")]
+        let monitor = self.monitor.clone();
+
+        Box::pin(async move {
+            let mut state = state.lock_owned().await;
+
+            #[cfg(feature = "debug-protocol")]
+            monitor.lock().unwrap().observe(&frame);
+
+            let current = mem::replace(&mut *state, State::Disconnecting);
+            if matches!(current, State::Disconnecting) {
+                return Err(Error::Closed);
+            }
+
+            let reply = match current.handle_frame(frame).await {
+                Ok((next, reply)) => {
+                    *state = next;
+
+                    reply
+                }
+                Err(err) => Some(Frame::AgentDisconnect(err.into())),
+            };
+
+            #[cfg(feature = "debug-protocol")]
+            if let Some(ref frame) = reply {
+                monitor.lock().unwrap().observe(frame);
+            }
+
+            Ok(reply)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use tower::{make::Shared, service_fn};
+
+    use crate::{
+        runtime::Builder,
+        spop::{HaproxyHello, Scope, Version},
+    };
+
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_spawn_calls() {
+        let runtime = Builder::new().make_service(
+            Shared::new(service_fn(|msgs: Vec<Message>| async move {
+                Ok::<_, Infallible>(vec![Action::set_var(
+                    Scope::Transaction,
+                    "n",
+                    msgs.len() as i32,
+                )])
+            })),
+            (),
+        );
+        let mut server = SpopServer::new(runtime);
+
+        let hello = Frame::HaproxyHello(HaproxyHello {
+            supported_versions: vec![Version::V2_0],
+            max_frame_size: 16380,
+            capabilities: vec![],
+            healthcheck: None,
+            engine_id: None,
+        });
+        let reply = tokio::spawn(server.call(hello)).await.unwrap().unwrap();
+        assert!(matches!(reply, Some(Frame::AgentHello(_))));
+
+        let notify = Frame::notify(1, 1, [Message::new("check", [("n", 1)])]);
+        let reply = tokio::spawn(server.call(notify)).await.unwrap().unwrap();
+        assert_eq!(
+            reply,
+            Some(Frame::ack(
+                1,
+                1,
+                [Action::set_var(Scope::Transaction, "n", 1)]
+            ))
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Write the actions incrementally while processing a NOTIFY frame.
+//!
+//! The actions pushed into the [`ActionSink`] are accumulated by the connection,
+//! and sent with the actions returned by the service when it completes.
+//! If the service misses the `max_process_time` deadline,
+//! the actions accumulated so far are sent instead of disconnecting.
+//!
+//! ```no_run
+//! # use haproxy_spoa::{spop::{Action, Scope}, ActionSink};
+//! # async fn enrich(backends: Vec<String>) -> Vec<Action> {
+//! let sink = ActionSink::current().expect("processing NOTIFY frame");
+//!
+//! for backend in backends {
+//!     sink.set_var(Scope::Transaction, backend, true);
+//! }
+//!
+//! vec![]
+//! # }
+//! ```
+
+use std::future::Future;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::{
+    future::{BoxFuture, FutureExt as _},
+    Stream, StreamExt as _,
+};
+use tower::Service;
+
+use crate::spop::{Action, Scope, Typed};
+
+tokio::task_local! {
+    static SINK: ActionSink;
+}
+
+/// Accumulate the actions of the NOTIFY frame being processed.
+#[derive(Clone, Debug, Default)]
+pub struct ActionSink(Arc<Mutex<Vec<Action>>>);
+
+impl ActionSink {
+    /// Returns the sink of the NOTIFY frame being processed by the current task.
+    pub fn current() -> Option<ActionSink> {
+        SINK.try_with(Clone::clone).ok()
+    }
+
+    /// Push an action.
+    pub fn push(&self, action: Action) {
+        self.0.lock().unwrap().push(action);
+    }
+
+    /// Push an action to set the variable.
+    pub fn set_var<S: Into<String>, V: Into<Typed>>(&self, scope: Scope, name: S, value: V) {
+        self.push(Action::set_var(scope, name, value))
+    }
+
+    /// Push an action to unset the variable.
+    pub fn unset_var<S: Into<String>>(&self, scope: Scope, name: S) {
+        self.push(Action::unset_var(scope, name))
+    }
+
+    /// Push the actions produced by the stream, until it ends.
+    pub async fn send_all<St>(&self, st: St)
+    where
+        St: Stream<Item = Action>,
+    {
+        let mut st = std::pin::pin!(st);
+
+        while let Some(action) = st.next().await {
+            self.push(action);
+        }
+    }
+
+    /// Returns the number of accumulated actions.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no action has been accumulated.
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+
+    /// Take the accumulated actions.
+    pub(crate) fn take(&self) -> Vec<Action> {
+        mem::take(&mut *self.0.lock().unwrap())
+    }
+
+    /// Call the closure and poll the returned future with the sink set.
+    pub(crate) async fn scope<F, Fut>(self, f: F) -> Fut::Output
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future,
+    {
+        let fut = SINK.sync_scope(self.clone(), f);
+
+        SINK.scope(self, fut).await
+    }
+}
+
+/// Adapt a service returning a stream of actions.
+///
+/// The actions are pushed into the current [`ActionSink`] as they are produced,
+/// or collected when called outside of a NOTIFY frame.
+#[derive(Clone, Debug)]
+pub struct Streaming<S>(pub S);
+
+impl<S, Req> Service<Req> for Streaming<S>
+where
+    S: Service<Req>,
+    S::Future: Send + 'static,
+    S::Response: Stream<Item = Action> + Send + 'static,
+{
+    type Response = Vec<Action>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let fut = self.0.call(req);
+        let sink = ActionSink::current();
+
+        async move {
+            let st = fut.await?;
+
+            Ok(match sink {
+                Some(sink) => {
+                    sink.send_all(st).await;
+
+                    vec![]
+                }
+                None => st.collect().await,
+            })
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::stream;
+    use tower::{service_fn, ServiceExt as _};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let svc = Streaming(service_fn(|n: usize| async move {
+            Ok::<_, ()>(stream::iter(
+                (0..n).map(|i| Action::unset_var(Scope::Request, format!("v{i}"))),
+            ))
+        }));
+
+        assert_eq!(svc.clone().oneshot(2).await.unwrap().len(), 2);
+
+        let sink = ActionSink::default();
+        let actions = sink.clone().scope(|| svc.oneshot(3)).await.unwrap();
+
+        assert!(actions.is_empty());
+        assert_eq!(sink.take().len(), 3);
+    }
+}
@@ -2,32 +2,45 @@ use std::error::Error as StdError;
 use std::sync::Arc;
 
 use derive_more::Debug;
-use tower::MakeService;
+use tower::{BoxError, MakeService, Service};
 use tracing::instrument;
 
 use crate::{
     error::{Context as _, Result},
+    listener::ListenerOptions,
     runtime::Runtime,
     spop::{Action, Error, Frame, HaproxyHello, Message, Reassembly},
-    state::{handshake::negotiate, AsyncHandler, Processing, State},
+    state::{
+        handshake::{negotiate, negotiate_with},
+        AsyncHandler, Processing, State,
+    },
 };
 
 #[derive(Debug)]
 pub struct Connecting<S, T> {
     pub runtime: Arc<Runtime<S, T>>,
+    /// The options of the listener which accepted the connection, if any.
+    pub listener: Option<Arc<ListenerOptions>>,
 }
 
 impl<S, T> Connecting<S, T> {
     pub fn new(runtime: Arc<Runtime<S, T>>) -> Self {
-        Connecting { runtime }
+        Connecting {
+            runtime,
+            listener: None,
+        }
     }
 }
 
 impl<S, T> AsyncHandler<S, T> for Connecting<S, T>
 where
-    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
     S::MakeError: StdError + Send + Sync + 'static,
-    T: Clone,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
 {
     #[instrument(skip(self), ret, err, level = "trace")]
     async fn handle_frame(self, frame: Frame) -> Result<(State<S, T>, Option<Frame>)> {
@@ -41,37 +54,56 @@ where
 
 impl<S, T> Connecting<S, T>
 where
-    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
     S::MakeError: StdError + Send + Sync + 'static,
-    T: Clone,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
 {
     async fn handshake(self, hello: HaproxyHello) -> Result<(State<S, T>, Option<Frame>)> {
-        let Self { runtime } = self;
+        let Self { runtime, listener } = self;
+
+        let max_frame_size = listener
+            .as_ref()
+            .and_then(|listener| listener.max_frame_size)
+            .unwrap_or(runtime.max_frame_size);
+        let capabilities = listener
+            .as_ref()
+            .and_then(|listener| listener.capabilities.clone())
+            .unwrap_or_else(|| runtime.capabilities.clone());
 
         let is_healthcheck = hello.healthcheck.unwrap_or_default();
-        let handshaked = {
-            negotiate(
-                runtime.supported_versions.clone(),
-                runtime.max_frame_size as u32,
-                runtime.capabilities.clone(),
-                hello,
-            )?
-        };
+        let mut handshaked = negotiate(
+            runtime.supported_versions.clone(),
+            u32::try_from(max_frame_size).unwrap_or(u32::MAX),
+            capabilities,
+            &hello,
+        )?;
+        if let Some(hook) = &runtime.handshake_hook {
+            handshaked =
+                negotiate_with(hook, &hello, handshaked).context("handshake vetoed by the hook")?;
+        }
         let frame = handshaked.agent_hello().into();
 
         let next = if is_healthcheck {
+            let unhealthy = runtime.health.unhealthy();
+            if !unhealthy.is_empty() {
+                return Err(Error::Unknown)
+                    .with_context(|| format!("unhealthy dependencies: {}", unhealthy.join(", ")));
+            }
+
             State::Disconnecting
         } else {
-            let service = runtime.service_maker.write().await.make().await?;
+            let generation = runtime.generation();
+            let service = runtime.make_service().await?;
+
+            let reassembly = handshaked
+                .supports_fragmentation()
+                .then(Reassembly::default);
 
-            Processing::new(
-                runtime,
-                service,
-                handshaked
-                    .supports_fragmentation()
-                    .then(Reassembly::default),
-            )
-            .into()
+            Processing::new(runtime, handshaked, service, generation, reassembly).into()
         };
 
         Ok((next, Some(frame)))
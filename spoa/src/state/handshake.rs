@@ -1,42 +1,67 @@
-use std::cmp;
 use std::collections::HashSet;
+use std::result::Result as StdResult;
+use std::sync::Arc;
 
 use tracing::instrument;
 
 use crate::{
     error::Result,
-    spop::{AgentHello, Capability, Error::NoVersion, HaproxyHello, Version},
+    spop::{self, AgentHello, Capability, Error as Status, HaproxyHello, Offer, Version},
 };
 
+/// A callback inspecting the HAPROXY-HELLO frame of the peer and the negotiated parameters.
+///
+/// It may lower the max frame size or drop capabilities before the AGENT-HELLO frame is sent,
+/// e.g. to disable an expensive feature for the older peers, or veto the connection with a status.
+pub type HandshakeHook =
+    Arc<dyn Fn(&HaproxyHello, &mut Negotiated) -> StdResult<(), Status> + Send + Sync>;
+
+/// Negotiate the parameters of the connection, see [`spop::negotiate`] for the rules.
 #[instrument(ret, err, level = "trace")]
 pub fn negotiate(
-    mut supported_versions: Vec<Version>,
+    supported_versions: Vec<Version>,
     max_frame_size: u32,
     capabilities: Vec<Capability>,
-    mut hello: HaproxyHello,
+    hello: &HaproxyHello,
 ) -> Result<Negotiated> {
-    hello.supported_versions.sort();
-    supported_versions.sort();
-
-    let version = hello
-        .supported_versions
-        .into_iter()
-        .rev()
-        .find(|version| supported_versions.iter().rev().any(|v| v == version))
-        .ok_or(NoVersion)?;
-    let max_frame_size = cmp::min(hello.max_frame_size, max_frame_size);
-    let capabilities = hello
-        .capabilities
-        .into_iter()
-        .collect::<HashSet<_>>()
-        .intersection(&capabilities.into_iter().collect::<HashSet<_>>())
-        .cloned()
-        .collect::<Vec<_>>();
+    let offer = Offer {
+        supported_versions,
+        max_frame_size,
+        capabilities,
+    };
+    let AgentHello {
+        version,
+        max_frame_size,
+        capabilities,
+    } = spop::negotiate(&offer, hello)?;
 
     Ok(Negotiated {
         version,
         max_frame_size,
         capabilities: capabilities.into_iter().collect(),
+        engine_id: hello.engine_id.clone(),
+    })
+}
+
+/// Call the hook, which may only restrict the negotiated parameters.
+pub fn negotiate_with(
+    hook: &HandshakeHook,
+    hello: &HaproxyHello,
+    negotiated: Negotiated,
+) -> StdResult<Negotiated, Status> {
+    let mut adjusted = negotiated.clone();
+
+    hook(hello, &mut adjusted)?;
+
+    Ok(Negotiated {
+        version: negotiated.version,
+        max_frame_size: adjusted.max_frame_size.min(negotiated.max_frame_size),
+        capabilities: adjusted
+            .capabilities
+            .intersection(&negotiated.capabilities)
+            .cloned()
+            .collect(),
+        engine_id: negotiated.engine_id,
     })
 }
 
@@ -45,6 +70,8 @@ pub struct Negotiated {
     pub version: Version,
     pub max_frame_size: u32,
     pub capabilities: HashSet<Capability>,
+    /// The uniq string that identify the SPOE engine.
+    pub engine_id: Option<String>,
 }
 
 impl Negotiated {
@@ -68,3 +95,76 @@ impl Negotiated {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(versions: &[Version]) -> HaproxyHello {
+        HaproxyHello {
+            supported_versions: versions.to_vec(),
+            max_frame_size: 16380,
+            capabilities: vec![],
+            healthcheck: None,
+            engine_id: None,
+        }
+    }
+
+    #[test]
+    fn test_negotiate_version() {
+        let negotiated = |agent: &[Version], haproxy: &[Version]| {
+            negotiate(agent.to_vec(), 16380, vec![], &hello(haproxy)).map(|n| n.version)
+        };
+
+        assert_eq!(
+            negotiated(&[Version::V2_0], &[Version::V2_0]).unwrap(),
+            Version::V2_0
+        );
+        assert_eq!(
+            negotiated(
+                &[Version::V2_0, Version::V2_1],
+                &[Version::V2_1, Version::V2_0]
+            )
+            .unwrap(),
+            Version::V2_1
+        );
+        assert_eq!(
+            negotiated(&[Version::V2_0], &[Version::V2_1, Version::V2_0]).unwrap(),
+            Version::V2_0
+        );
+        assert!(negotiated(&[Version::V2_1], &[Version::V2_0]).is_err());
+    }
+
+    #[test]
+    fn test_negotiate_with_hook() {
+        let mut peer = hello(&[Version::V2_0]);
+        peer.capabilities = vec![Capability::Pipelining, Capability::Async];
+        let caps = vec![Capability::Pipelining, Capability::Async];
+        let negotiated = negotiate(vec![Version::V2_0], 16380, caps, &peer).unwrap();
+
+        let hook: HandshakeHook = Arc::new(|hello, negotiated| {
+            if hello.engine_id.is_none() {
+                return Err(Status::Invalid);
+            }
+
+            // the hook may only restrict the negotiated parameters
+            negotiated.capabilities.remove(&Capability::Async);
+            negotiated.capabilities.insert(Capability::Fragmentation);
+            negotiated.max_frame_size = 65536;
+            Ok(())
+        });
+
+        assert_eq!(
+            negotiate_with(&hook, &peer, negotiated.clone()),
+            Err(Status::Invalid)
+        );
+
+        peer.engine_id = Some("engine".to_string());
+        let adjusted = negotiate_with(&hook, &peer, negotiated).unwrap();
+        assert_eq!(
+            adjusted.capabilities,
+            HashSet::from([Capability::Pipelining])
+        );
+        assert_eq!(adjusted.max_frame_size, 16380);
+    }
+}
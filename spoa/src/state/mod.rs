@@ -1,8 +1,10 @@
 mod connect;
 mod handshake;
 mod process;
+#[allow(clippy::module_inception)]
 mod state;
 
 pub use self::connect::Connecting;
+pub use self::handshake::{HandshakeHook, Negotiated};
 pub use self::process::Processing;
 pub use self::state::{AsyncHandler, State};
@@ -1,16 +1,25 @@
-use std::fmt;
+use std::error::Error as StdError;
 use std::sync::Arc;
+use std::time::Instant;
 
 use derive_more::Debug;
-use tokio::time::timeout;
-use tower::{MakeService, Service};
-use tracing::{instrument, trace};
+use tokio::time::{sleep, timeout};
+use tower::{BoxError, MakeService, Service};
+use tracing::{debug, instrument, trace};
+use ulid::Ulid;
 
 use crate::{
-    error::{Context, Result},
-    runtime::Runtime,
-    spop::{Action, Disconnect, Error::*, Frame, HaproxyNotify, Message, Reassembly},
-    state::{AsyncHandler, State},
+    context::NotifyContext,
+    error::{Context, Error, Result},
+    fault::Fault,
+    logger::StreamLogger,
+    runtime::{Decision, Runtime, DEGRADED_VAR, ERROR_VAR, PAUSED_VAR, SAMPLED_VAR},
+    sink::ActionSink,
+    spop::{
+        Action, AgentAck, Disconnect, Error::*, Frame, FrameId, FrameTiming, HaproxyNotify,
+        Message, Reassembly, Scope, StreamId,
+    },
+    state::{AsyncHandler, Negotiated, State},
 };
 
 #[derive(Debug)]
@@ -19,9 +28,19 @@ where
     S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
 {
     pub runtime: Arc<Runtime<S, T>>,
+    pub negotiated: Negotiated,
+    /// The service, `None` while the circuit breaker of the runtime fails open.
     #[debug(skip)]
-    pub service: S::Service,
+    pub service: Option<S::Service>,
+    /// The generation of the runtime when the service was made.
+    pub generation: u64,
     pub reassembly: Option<Reassembly<Message>>,
+    /// The stable identifier of the connection.
+    pub conn: Option<Ulid>,
+    /// The sequence number of the frame being handled.
+    pub seq: u64,
+    /// The timing of the frame being handled.
+    pub timing: Option<FrameTiming>,
 }
 
 impl<S, T> Processing<S, T>
@@ -30,21 +49,86 @@ where
 {
     pub fn new(
         runtime: Arc<Runtime<S, T>>,
-        service: S::Service,
+        negotiated: Negotiated,
+        service: Option<S::Service>,
+        generation: u64,
         reassembly: Option<Reassembly<Message>>,
     ) -> Self {
         Self {
             runtime,
+            negotiated,
             service,
+            generation,
             reassembly,
+            conn: None,
+            seq: 0,
+            timing: None,
         }
     }
 }
 
+impl<S, T> Processing<S, T>
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
+{
+    /// Fail the frame, or answer it with the [`ERROR_VAR`] variable
+    /// when the engine continues on error.
+    fn fail(
+        self,
+        stream_id: StreamId,
+        frame_id: FrameId,
+        err: Error,
+    ) -> Result<(State<S, T>, Option<Frame>)> {
+        if !self.runtime.continue_on_error || !err.is_recoverable() {
+            return Err(err);
+        }
+
+        let status = err.status().unwrap_or(Unknown);
+        debug!(stream_id, frame_id, %err, "frame failed, continue on error");
+
+        let error = Action::set_var(Scope::Transaction, ERROR_VAR, u32::from(status));
+
+        Ok((
+            self.into(),
+            Some(Frame::ack(stream_id, frame_id, vec![error])),
+        ))
+    }
+
+    /// Re-make the service at the frame boundary if the runtime asked for it,
+    /// or if the circuit breaker of the runtime failed open.
+    ///
+    /// The previous service is kept while the circuit breaker is open.
+    async fn remake_service(&mut self) -> Result<()> {
+        let generation = self.runtime.generation();
+
+        if generation != self.generation || self.service.is_none() {
+            if let Some(service) = self.runtime.make_service().await? {
+                self.service = Some(service);
+                self.generation = generation;
+
+                debug!(generation, "service remade");
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl<S, T> AsyncHandler<S, T> for Processing<S, T>
 where
-    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
-    S::Error: fmt::Display + Send + Sync + 'static,
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
+    S::MakeError: StdError + Send + Sync + 'static,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
 {
     #[instrument(skip(self), ret, err, level = "trace")]
     async fn handle_frame(mut self, frame: Frame) -> Result<(State<S, T>, Option<Frame>)> {
@@ -62,17 +146,194 @@ where
                     Some(messages)
                 };
 
-                if let Some(msgs) = msgs {
-                    match timeout(self.runtime.max_process_time, self.service.call(msgs)).await {
+                if msgs.is_some() && self.runtime.load.is_paused() {
+                    trace!(stream_id, frame_id, "processing paused");
+
+                    let paused = Action::set_var(Scope::Transaction, PAUSED_VAR, true);
+
+                    return Ok((
+                        self.into(),
+                        Some(Frame::ack(stream_id, frame_id, vec![paused])),
+                    ));
+                }
+
+                if let Some(shard) = &self.runtime.shard {
+                    if msgs
+                        .as_ref()
+                        .is_some_and(|msgs| !shard.owns(stream_id, msgs))
+                    {
+                        trace!(stream_id, frame_id, "stream of another shard");
+
+                        self.runtime.load.shed();
+
+                        return Ok((
+                            self.into(),
+                            Some(Frame::AgentAck(AgentAck::new(stream_id, frame_id))),
+                        ));
+                    }
+                }
+
+                let msgs = match msgs {
+                    Some(mut msgs) if !self.runtime.sampling.is_empty() => {
+                        self.runtime.sampling.sample(&mut msgs);
+
+                        if msgs.is_empty() {
+                            trace!(stream_id, frame_id, "frame not sampled");
+
+                            let frame = if self.runtime.sampling.sampled_var {
+                                let unsampled =
+                                    Action::set_var(Scope::Transaction, SAMPLED_VAR, false);
+
+                                Frame::ack(stream_id, frame_id, vec![unsampled])
+                            } else {
+                                Frame::AgentAck(AgentAck::new(stream_id, frame_id))
+                            };
+
+                            return Ok((self.into(), Some(frame)));
+                        }
+
+                        Some(msgs)
+                    }
+                    msgs => msgs,
+                };
+
+                if let Some(mut msgs) = msgs {
+                    let fault = self
+                        .runtime
+                        .fault_injection
+                        .as_ref()
+                        .and_then(|faults| faults.inject());
+
+                    match fault {
+                        Some(Fault::Drop) => return Ok((self.into(), None)),
+                        Some(Fault::Disconnect(disconnect)) => {
+                            return Err(Error::Service(disconnect.into()))
+                        }
+                        // delayed outside of the processing timeout, so the ACK frame is late
+                        Some(Fault::Delay(delay)) => sleep(delay).await,
+                        None => {}
+                    }
+
+                    self.remake_service().await?;
+
+                    if self.service.is_none() {
+                        trace!(stream_id, frame_id, "no service, fail open");
+
+                        let degraded = Action::set_var(Scope::Transaction, DEGRADED_VAR, true);
+
+                        return Ok((
+                            self.into(),
+                            Some(Frame::ack(stream_id, frame_id, vec![degraded])),
+                        ));
+                    }
+
+                    let runtime = self.runtime.clone();
+                    let budget = runtime.max_process_time.min(runtime.haproxy_timeout());
+                    let adaptive = runtime.adaptive_timeout.as_ref().map(|adaptive| {
+                        let names = msgs
+                            .iter()
+                            .map(|msg| msg.name.as_str())
+                            .collect::<Vec<_>>()
+                            .join(",");
+                        let decision = adaptive.decide(&names, budget);
+
+                        (adaptive, names, decision)
+                    });
+                    let max_process_time = match adaptive {
+                        Some((_, ref names, Decision::Shed)) => {
+                            trace!(
+                                stream_id,
+                                frame_id,
+                                names,
+                                "frame shed by the adaptive timeout"
+                            );
+
+                            return Ok((
+                                self.into(),
+                                Some(Frame::AgentAck(AgentAck::new(stream_id, frame_id))),
+                            ));
+                        }
+                        Some((_, _, Decision::Process(timeout))) => timeout,
+                        None => runtime.max_process_time,
+                    };
+
+                    let _processing = runtime
+                        .load
+                        .process(runtime.max_process_time, runtime.haproxy_timeout());
+
+                    let mut cx =
+                        NotifyContext::new(stream_id, frame_id, self.negotiated.engine_id.clone());
+                    cx.conn = self.conn;
+                    cx.seq = self.seq;
+                    cx.timing = self.timing.take();
+                    cx.deadline = Some(
+                        cx.timing
+                            .map_or_else(Instant::now, |timing| timing.received_at)
+                            + max_process_time.min(budget),
+                    );
+                    cx.logger = StreamLogger::new(
+                        cx.engine_id.clone(),
+                        stream_id,
+                        frame_id,
+                        runtime.tail_logging.is_some(),
+                    );
+                    if runtime.spoe_events {
+                        cx.extract_events(&mut msgs);
+                    }
+
+                    let defaults = runtime.default_actions.as_ref().filter(|defaults| {
+                        defaults.applies_to(msgs.iter().map(|msg| msg.name.as_str()))
+                    });
+                    let sink = ActionSink::default();
+                    let service = self.service.as_mut().expect("service");
+                    let logger = cx.logger.clone();
+                    let started = Instant::now();
+                    let processing = sink.clone().scope(|| cx.scope(|| service.call(msgs)));
+                    let res = timeout(max_process_time, processing).await;
+
+                    if let Some((adaptive, names, _)) = &adaptive {
+                        adaptive.record(names, started.elapsed());
+                    }
+
+                    if let Some(threshold) = runtime.tail_logging {
+                        logger.finish(!matches!(res, Ok(Ok(_))), started.elapsed(), threshold);
+                    }
+
+                    match res {
                         Ok(res) => match res {
                             Ok(actions) => {
-                                let ack = Frame::ack(stream_id, frame_id, actions);
+                                let mut pushed = sink.take();
+                                pushed.extend(actions);
+                                if let Some(defaults) = &defaults {
+                                    pushed = defaults.apply(pushed);
+                                }
+                                if let Some(guard) = &runtime.var_guard {
+                                    pushed = guard.apply(pushed);
+                                }
+                                let ack = Frame::ack(stream_id, frame_id, pushed);
 
                                 Ok((self.into(), Some(ack)))
                             }
-                            Err(err) => Err(Unknown).context(err.to_string()),
+                            Err(err) => self.fail(stream_id, frame_id, Error::Service(err.into())),
                         },
-                        Err(_) => Err(Timeout).context("process messages"),
+                        Err(_) if !sink.is_empty() => {
+                            debug!(
+                                actions = sink.len(),
+                                "deadline reached, sending partial actions"
+                            );
+
+                            let mut pushed = sink.take();
+                            if let Some(defaults) = &defaults {
+                                pushed = defaults.apply(pushed);
+                            }
+                            if let Some(guard) = &runtime.var_guard {
+                                pushed = guard.apply(pushed);
+                            }
+                            let ack = Frame::ack(stream_id, frame_id, pushed);
+
+                            Ok((self.into(), Some(ack)))
+                        }
+                        Err(_) => self.fail(stream_id, frame_id, Error::Service(Timeout.into())),
                     }
                 } else {
                     Ok((self.into(), None))
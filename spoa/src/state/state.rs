@@ -1,20 +1,23 @@
-use std::{error::Error as StdError, fmt, sync::Arc};
+use std::{error::Error as StdError, future::Future, sync::Arc};
 
 use derive_more::{Debug, From};
-use tower::MakeService;
+use tower::{BoxError, MakeService, Service};
 
 use crate::{
-    error::Result,
+    error::{Context as _, Error::Closed, Result},
     runtime::Runtime,
-    spop::{Action, Frame, Message},
-    state::{Connecting, Processing},
+    spop::{Action, Error, Frame, Message},
+    state::{Connecting, Negotiated, Processing},
 };
 
 pub trait AsyncHandler<S, T>
 where
     S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
 {
-    async fn handle_frame(self, frame: Frame) -> Result<(State<S, T>, Option<Frame>)>;
+    fn handle_frame(
+        self,
+        frame: Frame,
+    ) -> impl Future<Output = Result<(State<S, T>, Option<Frame>)>> + Send;
 }
 
 #[derive(Debug, From)]
@@ -37,14 +40,54 @@ where
     pub fn new(rt: Arc<Runtime<S, T>>) -> State<S, T> {
         State::Connecting(Connecting::new(rt))
     }
+
+    /// Returns the negotiated parameters once the handshake has completed.
+    pub fn negotiated(&self) -> Option<&Negotiated> {
+        match self {
+            State::Processing(processing) => Some(&processing.negotiated),
+            _ => None,
+        }
+    }
+
+    /// Checks whether the agent may send the frame in the current state.
+    pub fn validate(&self, frame: &Frame) -> Result<()> {
+        match (self, frame) {
+            (State::Disconnecting, _) => Err(Closed),
+            (_, Frame::AgentDisconnect(_)) => Ok(()),
+            (State::Processing(processing), Frame::AgentAck(ack)) => {
+                if ack.fragmented && !processing.negotiated.supports_fragmentation() {
+                    Err(Error::FragmentNotSupported).context("fragmentation not negotiated")
+                } else {
+                    Ok(())
+                }
+            }
+            (State::Processing(processing), Frame::Unset) => {
+                if processing.negotiated.supports_fragmentation() {
+                    Ok(())
+                } else {
+                    Err(Error::FragmentNotSupported).context("fragmentation not negotiated")
+                }
+            }
+            _ => Err(Error::Invalid).with_context(|| {
+                format!(
+                    "{:?} frame not allowed in {:?} state",
+                    frame.frame_type(),
+                    self
+                )
+            }),
+        }
+    }
 }
 
 impl<S, T> AsyncHandler<S, T> for State<S, T>
 where
-    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>> + Send + Sync,
+    S::Service: Send,
+    <S::Service as Service<Vec<Message>>>::Future: Send,
     S::MakeError: StdError + Send + Sync + 'static,
-    S::Error: fmt::Display + Send + Sync + 'static,
-    T: Clone,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    T: Clone + Send + Sync,
 {
     async fn handle_frame(self, frame: Frame) -> Result<(State<S, T>, Option<Frame>)> {
         match self {
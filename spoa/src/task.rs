@@ -0,0 +1,109 @@
+//! Spawn the named tasks of the agent.
+//!
+//! The names are given to the tokio task builder when the crate is built with both
+//! the `tokio-unstable` feature and `--cfg tokio_unstable`, so they show up in `tokio-console`.
+//! Otherwise the tasks are spawned with `tokio::spawn` and named by their tracing span.
+//!
+//! The tasks are bound to the tracing subscriber of the spawning task, instead of the global one,
+//! so an agent embedded in a larger service logs with the subscriber it was spawned with.
+
+use std::future::Future;
+use std::io;
+
+use tokio::{
+    runtime::Handle,
+    task::{JoinHandle, JoinSet},
+};
+use tracing::instrument::WithSubscriber as _;
+
+/// Spawn the named task.
+#[cfg(all(feature = "tokio-unstable", tokio_unstable))]
+pub(crate) fn spawn<F>(name: &'static str, task: F) -> io::Result<JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn(task.with_current_subscriber())
+}
+
+/// Spawn the named task on the runtime.
+#[cfg(all(feature = "tokio-unstable", tokio_unstable))]
+pub(crate) fn spawn_on<F>(
+    name: &'static str,
+    task: F,
+    handle: &Handle,
+) -> io::Result<JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn_on(task.with_current_subscriber(), handle)
+}
+
+/// Spawn the named task in the set.
+#[cfg(all(feature = "tokio-unstable", tokio_unstable))]
+pub(crate) fn spawn_in<F, T>(set: &mut JoinSet<T>, name: &'static str, task: F) -> io::Result<()>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    set.build_task()
+        .name(name)
+        .spawn(task.with_current_subscriber())
+        .map(|_| ())
+}
+
+/// Spawn the task in the span named after it.
+#[cfg(not(all(feature = "tokio-unstable", tokio_unstable)))]
+pub(crate) fn spawn<F>(name: &'static str, task: F) -> io::Result<JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    use tracing::Instrument as _;
+
+    Ok(tokio::spawn(
+        task.instrument(tracing::info_span!("task", name))
+            .with_current_subscriber(),
+    ))
+}
+
+/// Spawn the task on the runtime, in the span named after it.
+#[cfg(not(all(feature = "tokio-unstable", tokio_unstable)))]
+pub(crate) fn spawn_on<F>(
+    name: &'static str,
+    task: F,
+    handle: &Handle,
+) -> io::Result<JoinHandle<F::Output>>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    use tracing::Instrument as _;
+
+    Ok(handle.spawn(
+        task.instrument(tracing::info_span!("task", name))
+            .with_current_subscriber(),
+    ))
+}
+
+/// Spawn the task in the set, in the span named after it.
+#[cfg(not(all(feature = "tokio-unstable", tokio_unstable)))]
+pub(crate) fn spawn_in<F, T>(set: &mut JoinSet<T>, name: &'static str, task: F) -> io::Result<()>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    use tracing::Instrument as _;
+
+    set.spawn(
+        task.instrument(tracing::info_span!("task", name))
+            .with_current_subscriber(),
+    );
+
+    Ok(())
+}
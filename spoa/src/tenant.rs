@@ -0,0 +1,315 @@
+//! Multi-tenant variable namespace isolation.
+//!
+//! When one agent serves several logical tenants (e.g. different frontends),
+//! the tenant is resolved from a configured message argument, for example:
+//!
+//! ```text
+//! spoe-message check-client-ip
+//!     args tenant=str(shop) ip=src
+//! ```
+//!
+//! All the variables produced for that tenant are forced into a tenant-specific prefix,
+//! and the statistics and the in-flight limits are tracked per tenant.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use dashmap::DashMap;
+use pin_project::{pin_project, pinned_drop};
+use tower::{Layer, Service};
+
+use crate::spop::{Action, Message, Typed};
+
+/// The statistics of a tenant.
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// The number of messages processed.
+    pub messages: AtomicU64,
+    /// The number of actions produced.
+    pub actions: AtomicU64,
+    /// The number of messages acknowledged without processing due to the in-flight limit.
+    pub rejected: AtomicU64,
+    /// The number of messages being processed.
+    pub in_flight: AtomicUsize,
+}
+
+/// The statistics of all the tenants.
+pub type Tenants = Arc<DashMap<String, Arc<Stats>>>;
+
+/// A layer to isolate the variables of the tenants.
+#[derive(Clone, Debug)]
+pub struct TenantLayer {
+    arg: String,
+    separator: String,
+    max_in_flight: Option<usize>,
+    tenants: Tenants,
+}
+
+impl TenantLayer {
+    /// Resolve the tenant from the message argument with the name.
+    pub fn new<S: Into<String>>(arg: S) -> Self {
+        TenantLayer {
+            arg: arg.into(),
+            separator: "_".to_string(),
+            max_in_flight: None,
+            tenants: Tenants::default(),
+        }
+    }
+
+    /// Set the separator between the tenant prefix and the variable name.
+    pub fn separator<S: Into<String>>(mut self, sep: S) -> Self {
+        self.separator = sep.into();
+        self
+    }
+
+    /// Limit the number of messages processed concurrently per tenant.
+    pub fn max_in_flight(mut self, n: usize) -> Self {
+        self.max_in_flight = Some(n);
+        self
+    }
+
+    /// Returns the statistics of all the tenants.
+    pub fn tenants(&self) -> Tenants {
+        self.tenants.clone()
+    }
+}
+
+impl<S> Layer<S> for TenantLayer {
+    type Service = Tenant<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Tenant {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// A service forcing the variables into a tenant-specific prefix.
+#[derive(Clone, Debug)]
+pub struct Tenant<S> {
+    inner: S,
+    layer: TenantLayer,
+}
+
+impl<S> Tenant<S> {
+    fn resolve(&self, msgs: &[Message]) -> Option<String> {
+        msgs.iter()
+            .flat_map(|msg| msg.args.iter())
+            .find(|(name, _)| *name == self.layer.arg)
+            .and_then(|(_, value)| match value {
+                Typed::String(s) if !s.is_empty() => Some(s.clone()),
+                _ => None,
+            })
+    }
+}
+
+impl<S> Service<Vec<Message>> for Tenant<S>
+where
+    S: Service<Vec<Message>, Response = Vec<Action>>,
+{
+    type Response = Vec<Action>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, msgs: Vec<Message>) -> Self::Future {
+        let tenant = self.resolve(&msgs).map(|name| {
+            let stats = self.layer.tenants.entry(name.clone()).or_default().clone();
+            let prefix = format!("{}{}", name, self.layer.separator);
+
+            (prefix, stats)
+        });
+
+        if let Some((_, ref stats)) = tenant {
+            let in_flight = stats.in_flight.fetch_add(1, Relaxed);
+
+            if self.layer.max_in_flight.is_some_and(|max| in_flight >= max) {
+                stats.in_flight.fetch_sub(1, Relaxed);
+                stats.rejected.fetch_add(1, Relaxed);
+
+                return ResponseFuture {
+                    inner: None,
+                    tenant: None,
+                };
+            }
+
+            stats.messages.fetch_add(msgs.len() as u64, Relaxed);
+        }
+
+        ResponseFuture {
+            inner: Some(self.inner.call(msgs)),
+            tenant,
+        }
+    }
+}
+
+/// The response future of the [`Tenant`] service.
+#[pin_project(PinnedDrop)]
+#[derive(Debug)]
+pub struct ResponseFuture<F> {
+    #[pin]
+    inner: Option<F>,
+    tenant: Option<(String, Arc<Stats>)>,
+}
+
+#[pinned_drop]
+impl<F> PinnedDrop for ResponseFuture<F> {
+    fn drop(self: Pin<&mut Self>) {
+        if let Some((_, stats)) = self.project().tenant.take() {
+            stats.in_flight.fetch_sub(1, Relaxed);
+        }
+    }
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Vec<Action>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        let Some(inner) = this.inner.as_pin_mut() else {
+            return Poll::Ready(Ok(vec![]));
+        };
+
+        let mut actions = match inner.poll(cx) {
+            Poll::Ready(Ok(actions)) => actions,
+            Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+            Poll::Pending => return Poll::Pending,
+        };
+
+        if let Some((prefix, stats)) = this.tenant.as_ref() {
+            stats.actions.fetch_add(actions.len() as u64, Relaxed);
+
+            for action in actions.iter_mut() {
+                let (Action::SetVar { name, .. } | Action::UnsetVar { name, .. }) = action;
+
+                name.insert_str(0, prefix);
+            }
+        }
+
+        Poll::Ready(Ok(actions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::Semaphore;
+    use tower::{service_fn, ServiceBuilder, ServiceExt as _};
+
+    use crate::spop::Scope;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tenant_prefix() {
+        let layer = TenantLayer::new("tenant").separator(".");
+        let tenants = layer.tenants();
+        let svc = ServiceBuilder::new()
+            .layer(layer)
+            .service(service_fn(|_: Vec<Message>| async {
+                Ok::<_, ()>(vec![
+                    Action::set_var(Scope::Transaction, "score", 10),
+                    Action::unset_var(Scope::Session, "ip"),
+                ])
+            }));
+
+        assert_eq!(
+            svc.clone()
+                .oneshot(vec![Message::new(
+                    "check",
+                    [("tenant", "shop"), ("ip", "127.0.0.1")]
+                )])
+                .await,
+            Ok(vec![
+                Action::set_var(Scope::Transaction, "shop.score", 10),
+                Action::unset_var(Scope::Session, "shop.ip"),
+            ])
+        );
+
+        // the variables of the messages without a tenant are left as is
+        for msg in [
+            Message::new("check", [("ip", "127.0.0.1")]),
+            Message::new("check", [("tenant", "")]),
+        ] {
+            assert_eq!(
+                svc.clone().oneshot(vec![msg]).await,
+                Ok(vec![
+                    Action::set_var(Scope::Transaction, "score", 10),
+                    Action::unset_var(Scope::Session, "ip"),
+                ])
+            );
+        }
+
+        assert_eq!(tenants.len(), 1);
+        let stats = tenants.get("shop").unwrap();
+        assert_eq!(stats.messages.load(Relaxed), 1);
+        assert_eq!(stats.actions.load(Relaxed), 2);
+        assert_eq!(stats.in_flight.load(Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_max_in_flight() {
+        let gate = Arc::new(Semaphore::new(0));
+        let layer = TenantLayer::new("tenant").max_in_flight(1);
+        let tenants = layer.tenants();
+        let svc = ServiceBuilder::new().layer(layer).service(service_fn({
+            let gate = gate.clone();
+
+            move |_: Vec<Message>| {
+                let gate = gate.clone();
+
+                async move {
+                    gate.acquire().await.unwrap().forget();
+
+                    Ok::<_, ()>(vec![Action::set_var(Scope::Transaction, "score", 10)])
+                }
+            }
+        }));
+        let msg = |tenant| vec![Message::new("check", [("tenant", tenant)])];
+
+        let shop = tokio::spawn(svc.clone().oneshot(msg("shop")));
+        while tenants
+            .get("shop")
+            .is_none_or(|stats| stats.in_flight.load(Relaxed) == 0)
+        {
+            tokio::task::yield_now().await;
+        }
+
+        // the requests over the limit of the tenant are acknowledged without processing
+        assert_eq!(svc.clone().oneshot(msg("shop")).await, Ok(vec![]));
+        assert_eq!(tenants.get("shop").unwrap().rejected.load(Relaxed), 1);
+
+        // the other tenants have their own limit
+        let blog = tokio::spawn(svc.clone().oneshot(msg("blog")));
+
+        gate.add_permits(2);
+        assert_eq!(
+            shop.await.unwrap(),
+            Ok(vec![Action::set_var(Scope::Transaction, "shop_score", 10)])
+        );
+        assert_eq!(
+            blog.await.unwrap(),
+            Ok(vec![Action::set_var(Scope::Transaction, "blog_score", 10)])
+        );
+
+        for tenant in ["shop", "blog"] {
+            let stats = tenants.get(tenant).unwrap();
+
+            assert_eq!(stats.messages.load(Relaxed), 1);
+            assert_eq!(stats.in_flight.load(Relaxed), 0);
+        }
+        assert_eq!(tenants.get("blog").unwrap().rejected.load(Relaxed), 0);
+    }
+}
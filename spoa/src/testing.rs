@@ -0,0 +1,213 @@
+//! Serve the agent over an in-memory transport, without binding sockets.
+//!
+//! ```
+//! # use std::convert::Infallible;
+//! # use tower::{make::Shared, service_fn};
+//! # use haproxy_spoa::{runtime::Builder, spop::{Action, Message, Scope}, testing};
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), haproxy_spoa::Error> {
+//! let runtime = Builder::new().make_service(
+//!     Shared::new(service_fn(|msgs: Vec<Message>| async move {
+//!         Ok::<_, Infallible>(vec![Action::set_var(Scope::Transaction, "n", msgs.len() as i32)])
+//!     })),
+//!     (),
+//! );
+//! let (mut client, mut conn) = testing::pair(runtime);
+//! tokio::spawn(async move { conn.serve().await });
+//!
+//! client.hello().await?;
+//! let ack = client.notify(1, 1, [Message::new("check", [("ip", "127.0.0.1")])]).await?;
+//!
+//! assert_eq!(ack.actions, vec![Action::set_var(Scope::Transaction, "n", 1)]);
+//!
+//! // the actions are applied like HAProxy would do, to be read back
+//! assert_eq!(client.var(Scope::Transaction, "n"), Some(&1.into()));
+//! client.end_transaction();
+//! assert_eq!(client.var(Scope::Transaction, "n"), None);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::io::{duplex, AsyncWriteExt as _, DuplexStream};
+use tokio_util::sync::CancellationToken;
+use tower::MakeService;
+
+use crate::{
+    error::Result,
+    runtime::Runtime,
+    spop::{
+        Action, AgentAck, AgentHello, BufMutExt as _, Error::*, Frame, FrameId, Framer,
+        HaproxyHello, Message, Scope, StreamId, Typed, Version,
+    },
+    Connection,
+};
+
+/// Returns a client connected to an agent connection over an in-memory duplex stream.
+///
+/// The agent connection must be served for the client to get replies.
+pub fn pair<S, T>(runtime: Arc<Runtime<S, T>>) -> (Client, Connection<DuplexStream, S, T>)
+where
+    S: MakeService<T, Vec<Message>, Response = Vec<Action>>,
+{
+    let (client, agent) = duplex(runtime.max_frame_size * 4);
+    let max_frame_size = runtime.max_frame_size;
+    let client = Client {
+        framer: Framer::new(max_frame_size),
+        max_frame_size,
+        io: client,
+        vars: Vars::default(),
+    };
+    let conn = Connection::new(runtime, agent, CancellationToken::new());
+
+    (client, conn)
+}
+
+/// The HAProxy side of an in-memory connection, sending raw or typed frames.
+#[derive(Debug)]
+pub struct Client {
+    framer: Framer,
+    max_frame_size: usize,
+    io: DuplexStream,
+    vars: Vars,
+}
+
+/// The variables set by the ACK frames, emulating how HAProxy applies the actions.
+///
+/// The variables of the process and the session scopes live until the client is dropped,
+/// the other ones until the end of the transaction.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Vars(HashMap<(Scope, String), Typed>);
+
+impl Vars {
+    /// Apply the actions of an ACK frame.
+    pub fn apply<'a, I>(&mut self, actions: I)
+    where
+        I: IntoIterator<Item = &'a Action>,
+    {
+        for action in actions {
+            match action {
+                Action::SetVar { scope, name, value } => {
+                    self.0.insert((*scope, name.clone()), value.clone());
+                }
+                Action::UnsetVar { scope, name } => {
+                    self.0.remove(&(*scope, name.clone()));
+                }
+            }
+        }
+    }
+
+    /// Returns the value of the variable, if set.
+    pub fn get(&self, scope: Scope, name: &str) -> Option<&Typed> {
+        self.0.get(&(scope, name.to_string()))
+    }
+
+    /// Returns the number of variables set.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if no variable is set.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Drop the variables of the transaction, request and response scopes.
+    pub fn end_transaction(&mut self) {
+        self.0
+            .retain(|(scope, _), _| matches!(scope, Scope::Process | Scope::Session));
+    }
+}
+
+impl Client {
+    /// Send the frame.
+    pub async fn send(&mut self, frame: Frame) -> Result<usize> {
+        Ok(self.framer.write_frame(&mut self.io, frame).await?)
+    }
+
+    /// Send the raw bytes, including the length prefix of the frame if any.
+    pub async fn send_raw(&mut self, buf: &[u8]) -> Result<()> {
+        Ok(self.io.write_all(buf).await?)
+    }
+
+    /// Send the frame with a corrupted length prefix, as a misbehaving peer would do.
+    pub async fn send_corrupted(&mut self, frame: Frame, len: u32) -> Result<()> {
+        let mut buf = len.to_be_bytes().to_vec();
+        buf.put_frame(frame);
+
+        self.send_raw(&buf).await
+    }
+
+    /// Shut down the write side of the connection, as HAProxy half-closing it.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        Ok(self.io.shutdown().await?)
+    }
+
+    /// Receive a frame.
+    pub async fn recv(&mut self) -> Result<Frame> {
+        Ok(self.framer.read_frame(&mut self.io).await?)
+    }
+
+    /// Complete the handshake with the default HAPROXY-HELLO frame.
+    pub async fn hello(&mut self) -> Result<AgentHello> {
+        self.handshake(HaproxyHello {
+            supported_versions: vec![Version::V2_0],
+            max_frame_size: u32::try_from(self.max_frame_size).unwrap_or(u32::MAX),
+            capabilities: vec![],
+            healthcheck: None,
+            engine_id: None,
+        })
+        .await
+    }
+
+    /// Complete the handshake with the HAPROXY-HELLO frame.
+    pub async fn handshake(&mut self, hello: HaproxyHello) -> Result<AgentHello> {
+        self.send(Frame::HaproxyHello(hello)).await?;
+
+        match self.recv().await? {
+            Frame::AgentHello(hello) => Ok(hello),
+            _ => Err(Invalid.into()),
+        }
+    }
+
+    /// Returns the variables set by the ACK frames received by [`Client::notify`].
+    pub fn vars(&self) -> &Vars {
+        &self.vars
+    }
+
+    /// Returns the value of the variable set by the ACK frames, if any.
+    pub fn var(&self, scope: Scope, name: &str) -> Option<&Typed> {
+        self.vars.get(scope, name)
+    }
+
+    /// Drop the variables of the transaction, request and response scopes.
+    pub fn end_transaction(&mut self) {
+        self.vars.end_transaction()
+    }
+
+    /// Send the messages in a NOTIFY frame, and wait for the ACK frame,
+    /// applying its actions to the variables.
+    pub async fn notify<I>(
+        &mut self,
+        stream_id: StreamId,
+        frame_id: FrameId,
+        messages: I,
+    ) -> Result<AgentAck>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        self.send(Frame::notify(stream_id, frame_id, messages))
+            .await?;
+
+        match self.recv().await? {
+            Frame::AgentAck(ack) if ack.stream_id == stream_id && ack.frame_id == frame_id => {
+                self.vars.apply(&ack.actions);
+
+                Ok(ack)
+            }
+            _ => Err(Invalid.into()),
+        }
+    }
+}
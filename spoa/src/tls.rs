@@ -0,0 +1,283 @@
+//! Decode the TLS client hello captures and compute the JA3/JA4 fingerprints.
+//!
+//! HAProxy captures the client hello with `tune.ssl.capture-buffer-size`,
+//! and passes the binary lists to the agent with the `ssl_fc_*` samples:
+//!
+//! ```text
+//! global
+//!     tune.ssl.capture-buffer-size 128
+//!
+//! [spoe]
+//! spoe-message tls-fingerprint
+//!     args version=ssl_fc_protocol_hello_id ciphers=ssl_fc_cipherlist_bin(1) extensions=ssl_fc_extlist_bin(1) curves=ssl_fc_eclist_bin(1) ec_formats=ssl_fc_ecformats_bin sig_algs=ssl_fc_sigalgs_bin versions=ssl_fc_supported_versions_bin alpn=ssl_fc_alpn sni=ssl_fc_has_sni
+//!     event on-client-session
+//! ```
+//!
+//! The fingerprints are returned as `set-var` actions, e.g. `sess.spoe.ja3` and `sess.spoe.ja4`
+//! with `option var-prefix spoe`.
+
+use md5::{Digest as _, Md5};
+use sha2::Sha256;
+
+use crate::{
+    error::{Context as _, Result},
+    spop::{Action, Error::Invalid, Message, Scope, Typed},
+};
+
+/// The extension of the server name indication.
+pub const EXT_SERVER_NAME: u16 = 0x0000;
+/// The extension of the application layer protocol negotiation.
+pub const EXT_ALPN: u16 = 0x0010;
+
+/// Returns `true` if the value is a GREASE value (RFC 8701).
+pub fn is_grease(v: u16) -> bool {
+    v & 0x0f0f == 0x0a0a && v >> 8 == v & 0xff
+}
+
+/// Decode a list of big-endian 16 bits values, e.g. `ssl_fc_cipherlist_bin`.
+pub fn u16_list(b: &[u8]) -> Result<Vec<u16>> {
+    if !b.len().is_multiple_of(2) {
+        return Err(Invalid).context("odd length of 16 bits list");
+    }
+
+    Ok(b.chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect())
+}
+
+/// The fields of the TLS client hello used by the fingerprints.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ClientHello {
+    /// The TLS version of the client hello, e.g. `0x0303`.
+    pub version: u16,
+    /// The cipher suites.
+    pub ciphers: Vec<u16>,
+    /// The extensions, in the original order.
+    pub extensions: Vec<u16>,
+    /// The supported groups (elliptic curves).
+    pub curves: Vec<u16>,
+    /// The elliptic curve point formats.
+    pub point_formats: Vec<u8>,
+    /// The signature algorithms, in the original order.
+    pub signature_algorithms: Vec<u16>,
+    /// The supported versions.
+    pub supported_versions: Vec<u16>,
+    /// The application layer protocol.
+    pub alpn: Option<String>,
+    /// The client sent the server name indication.
+    pub sni: bool,
+    /// The client hello was sent over QUIC.
+    pub quic: bool,
+}
+
+impl ClientHello {
+    /// Decode the client hello from the arguments of the message.
+    ///
+    /// The missing arguments are left empty, the malformed ones are rejected.
+    pub fn from_message(msg: &Message) -> Result<Self> {
+        let mut hello = ClientHello::default();
+
+        for (name, value) in &msg.args {
+            let bin = || match value {
+                Typed::Binary(b) => Ok(&b[..]),
+                Typed::String(s) => Ok(s.as_bytes()),
+                Typed::Null => Ok(&[][..]),
+                _ => Err(Invalid).with_context(|| format!("expected binary argument `{name}`")),
+            };
+
+            match name.as_str() {
+                "version" => {
+                    hello.version = match value {
+                        Typed::Int32(n) => u16::try_from(*n).ok(),
+                        Typed::Uint32(n) => u16::try_from(*n).ok(),
+                        Typed::Int64(n) => u16::try_from(*n).ok(),
+                        Typed::Uint64(n) => u16::try_from(*n).ok(),
+                        _ => None,
+                    }
+                    .ok_or(Invalid)
+                    .context("expected TLS version argument")?
+                }
+                "ciphers" => hello.ciphers = u16_list(bin()?)?,
+                "extensions" => hello.extensions = u16_list(bin()?)?,
+                "curves" => hello.curves = u16_list(bin()?)?,
+                "ec_formats" => hello.point_formats = bin()?.to_vec(),
+                "sig_algs" => hello.signature_algorithms = u16_list(bin()?)?,
+                "versions" => hello.supported_versions = u16_list(bin()?)?,
+                "alpn" => {
+                    hello.alpn = match value {
+                        Typed::String(s) if !s.is_empty() => Some(s.clone()),
+                        _ => None,
+                    }
+                }
+                "sni" => {
+                    hello.sni = match value {
+                        Typed::Boolean(b) => *b,
+                        Typed::String(s) => !s.is_empty(),
+                        _ => false,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(hello)
+    }
+
+    /// Returns the JA3 string, before hashing.
+    pub fn ja3_string(&self) -> String {
+        fn join<T: ToString>(values: impl Iterator<Item = T>) -> String {
+            values.map(|v| v.to_string()).collect::<Vec<_>>().join("-")
+        }
+
+        let not_grease = |v: &&u16| !is_grease(**v);
+
+        format!(
+            "{},{},{},{},{}",
+            self.version,
+            join(self.ciphers.iter().filter(not_grease)),
+            join(self.extensions.iter().filter(not_grease)),
+            join(self.curves.iter().filter(not_grease)),
+            join(self.point_formats.iter()),
+        )
+    }
+
+    /// Returns the JA3 fingerprint, the MD5 hash of the JA3 string.
+    pub fn ja3(&self) -> String {
+        hex(&Md5::digest(self.ja3_string()))
+    }
+
+    /// Returns the JA4 fingerprint.
+    pub fn ja4(&self) -> String {
+        let version = self
+            .supported_versions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .max()
+            .unwrap_or(self.version);
+        let version = match version {
+            0x0304 => "13",
+            0x0303 => "12",
+            0x0302 => "11",
+            0x0301 => "10",
+            0x0300 => "s3",
+            0xfeff => "d1",
+            0xfefd => "d2",
+            0xfefc => "d3",
+            _ => "00",
+        };
+
+        let mut ciphers = self
+            .ciphers
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .collect::<Vec<_>>();
+        let exts = self
+            .extensions
+            .iter()
+            .copied()
+            .filter(|v| !is_grease(*v))
+            .collect::<Vec<_>>();
+
+        let alpn = match self.alpn.as_deref().map(str::as_bytes) {
+            Some([c]) => format!("{}{}", *c as char, *c as char),
+            Some([first, .., last]) => format!("{}{}", *first as char, *last as char),
+            _ => "00".to_string(),
+        };
+
+        let a = format!(
+            "{}{}{}{:02}{:02}{}",
+            if self.quic { 'q' } else { 't' },
+            version,
+            if self.sni { 'd' } else { 'i' },
+            ciphers.len().min(99),
+            exts.len().min(99),
+            alpn
+        );
+
+        ciphers.sort_unstable();
+
+        let mut sorted_exts = exts
+            .into_iter()
+            .filter(|&v| v != EXT_SERVER_NAME && v != EXT_ALPN)
+            .collect::<Vec<_>>();
+        sorted_exts.sort_unstable();
+
+        let b = truncated_sha256(&hex_list(&ciphers));
+        let c = {
+            let mut s = hex_list(&sorted_exts);
+
+            if !self.signature_algorithms.is_empty() {
+                s.push('_');
+                s.push_str(&hex_list(&self.signature_algorithms));
+            }
+
+            truncated_sha256(&s)
+        };
+
+        format!("{a}_{b}_{c}")
+    }
+
+    /// Returns the actions setting the `ja3` and `ja4` variables.
+    pub fn actions(&self, scope: Scope) -> Vec<Action> {
+        vec![
+            Action::set_var(scope, "ja3", self.ja3()),
+            Action::set_var(scope, "ja4", self.ja4()),
+        ]
+    }
+}
+
+fn hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn hex_list(values: &[u16]) -> String {
+    values
+        .iter()
+        .map(|v| format!("{v:04x}"))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn truncated_sha256(s: &str) -> String {
+    if s.is_empty() {
+        "000000000000".to_string()
+    } else {
+        hex(&Sha256::digest(s)[..6])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprints() {
+        let hello = ClientHello {
+            version: 0x0303,
+            ciphers: vec![
+                0x1301, 0x1302, 0x1303, 0xc02b, 0xc02f, 0xc02c, 0xc030, 0xcca9, 0xcca8, 0xc013,
+                0xc014, 0x009c, 0x009d, 0x002f, 0x0035,
+            ],
+            extensions: vec![
+                0x001b, 0x0000, 0x0033, 0x0010, 0x4469, 0x0017, 0x002d, 0x000d, 0x0005, 0x0023,
+                0x0012, 0x002b, 0xff01, 0x000b, 0x000a, 0x0015,
+            ],
+            curves: vec![0x001d, 0x0017, 0x0018],
+            point_formats: vec![0],
+            signature_algorithms: vec![
+                0x0403, 0x0804, 0x0401, 0x0503, 0x0805, 0x0501, 0x0806, 0x0601,
+            ],
+            supported_versions: vec![0x0a0a, 0x0304, 0x0303],
+            alpn: Some("h2".to_string()),
+            sni: true,
+            quic: false,
+        };
+
+        assert_eq!(hello.ja4(), "t13d1516h2_8daaf6152771_e5627efa2ab1");
+        assert!(hello.ja3_string().starts_with("771,4865-4866-4867-49195-"));
+        assert_eq!(hello.ja3().len(), 32);
+        assert!(is_grease(0x0a0a) && is_grease(0xfafa) && !is_grease(0x0a1a));
+    }
+}
@@ -0,0 +1,159 @@
+//! Reduce the processing of the messages to a verdict.
+//!
+//! The policy agents share the same variables, so the HAProxy snippets can be reused across them:
+//!
+//! ```text
+//! http-request send-spoe-group spoe check
+//! http-request deny deny_status 403 if { var(txn.spoa_verdict) -m str deny }
+//! http-request set-header X-Score %[var(txn.spoa_score)] if { var(txn.spoa_score) -m found }
+//! ```
+//!
+//! The services return a [`Verdict`], mapped to the actions by [`Verdict::into_actions`]:
+//!
+//! ```
+//! # use tower::{service_fn, ServiceBuilder};
+//! # use haproxy_spoa::{spop::Message, verdict::{Verdict, VerdictVars}};
+//! let vars = VerdictVars::default().prefix("waf");
+//!
+//! let svc = ServiceBuilder::new()
+//!     .map_response(move |verdict: Verdict| verdict.into_actions_with(&vars))
+//!     .service(service_fn(|msgs: Vec<Message>| async move {
+//!         Ok::<_, ()>(if msgs.is_empty() { Verdict::Allow } else { Verdict::deny("blocked") })
+//!     }));
+//! ```
+
+use crate::spop::{Action, Scope};
+
+/// The variable set to `allow` or `deny`.
+pub const VERDICT_VAR: &str = "spoa_verdict";
+
+/// The variable set to the score.
+pub const SCORE_VAR: &str = "spoa_score";
+
+/// The variable set to the status code of the denied requests.
+pub const STATUS_VAR: &str = "spoa_status";
+
+/// The variable set to the reason of the denied requests.
+pub const REASON_VAR: &str = "spoa_reason";
+
+/// The status code of the denied requests, by default.
+pub const DENY_STATUS: u16 = 403;
+
+/// The decision of a policy agent.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    /// Allow the request, setting the verdict variable to `allow`.
+    Allow,
+    /// Deny the request, setting the verdict variable to `deny`, with the status code and the reason.
+    Deny { code: u16, reason: String },
+    /// Score the request, setting the score variable, e.g. to deny above a threshold in HAProxy.
+    Score(u32),
+    /// Answer with the actions as is.
+    Custom(Vec<Action>),
+}
+
+impl Verdict {
+    /// Deny the request with the `403` status code.
+    pub fn deny<S: Into<String>>(reason: S) -> Self {
+        Verdict::Deny {
+            code: DENY_STATUS,
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns the actions setting the default variables.
+    pub fn into_actions(self) -> Vec<Action> {
+        self.into_actions_with(&VerdictVars::default())
+    }
+
+    /// Returns the actions setting the variables.
+    pub fn into_actions_with(self, vars: &VerdictVars) -> Vec<Action> {
+        match self {
+            Verdict::Allow => vec![Action::set_var(vars.scope, &vars.verdict, "allow")],
+            Verdict::Deny { code, reason } => vec![
+                Action::set_var(vars.scope, &vars.verdict, "deny"),
+                Action::set_var(vars.scope, &vars.status, u32::from(code)),
+                Action::set_var(vars.scope, &vars.reason, reason),
+            ],
+            Verdict::Score(score) => vec![Action::set_var(vars.scope, &vars.score, score)],
+            Verdict::Custom(actions) => actions,
+        }
+    }
+}
+
+impl From<Verdict> for Vec<Action> {
+    fn from(verdict: Verdict) -> Self {
+        verdict.into_actions()
+    }
+}
+
+/// The names and the scope of the variables set by the verdicts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerdictVars {
+    pub scope: Scope,
+    pub verdict: String,
+    pub score: String,
+    pub status: String,
+    pub reason: String,
+}
+
+impl Default for VerdictVars {
+    fn default() -> Self {
+        VerdictVars {
+            scope: Scope::Transaction,
+            verdict: VERDICT_VAR.to_string(),
+            score: SCORE_VAR.to_string(),
+            status: STATUS_VAR.to_string(),
+            reason: REASON_VAR.to_string(),
+        }
+    }
+}
+
+impl VerdictVars {
+    /// Set the variables in the scope.
+    pub fn scope(mut self, scope: Scope) -> Self {
+        self.scope = scope;
+        self
+    }
+
+    /// Replace the `spoa_` prefix of the variables, e.g. `waf_verdict` for the `waf` prefix.
+    pub fn prefix(self, prefix: &str) -> Self {
+        VerdictVars {
+            verdict: format!("{prefix}_verdict"),
+            score: format!("{prefix}_score"),
+            status: format!("{prefix}_status"),
+            reason: format!("{prefix}_reason"),
+            ..self
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_actions() {
+        assert_eq!(
+            Vec::<Action>::from(Verdict::Allow),
+            vec![Action::set_var(Scope::Transaction, VERDICT_VAR, "allow")]
+        );
+        assert_eq!(
+            Verdict::deny("blocked").into_actions(),
+            vec![
+                Action::set_var(Scope::Transaction, VERDICT_VAR, "deny"),
+                Action::set_var(Scope::Transaction, STATUS_VAR, 403u32),
+                Action::set_var(Scope::Transaction, REASON_VAR, "blocked"),
+            ]
+        );
+
+        let vars = VerdictVars::default().prefix("waf").scope(Scope::Session);
+        assert_eq!(
+            Verdict::Score(42).into_actions_with(&vars),
+            vec![Action::set_var(Scope::Session, "waf_score", 42u32)]
+        );
+
+        let custom = vec![Action::unset_var(Scope::Request, "foo")];
+        assert_eq!(Verdict::Custom(custom.clone()).into_actions(), custom);
+    }
+}
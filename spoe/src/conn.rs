@@ -0,0 +1,402 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+
+use derive_more::Debug;
+use tokio::{
+    io::{split, AsyncRead, AsyncWrite, BufReader},
+    sync::{oneshot, Mutex},
+    task::JoinHandle,
+};
+use tracing::{debug, instrument, trace};
+
+use crate::{
+    error::{Error, Result},
+    ids::{IdGenerator, MonotonicIds},
+    spop::{
+        Action, AgentAck, AgentHello, AsyncHandler, Capability, Error as Status, Frame, FrameId,
+        Framer, HaproxyHello, Message, Reassembly, StreamId, WireSize,
+    },
+};
+
+type Waiters = HashMap<(StreamId, FrameId), oneshot::Sender<Result<AgentAck>>>;
+
+/// The state shared with the task reading the frames from the agent.
+#[derive(Debug, Default)]
+struct Shared {
+    /// The NOTIFY frames waiting for the ACK frames, `None` once the connection is closed.
+    pending: StdMutex<Option<Waiters>>,
+}
+
+impl Shared {
+    fn complete(&self, ack: AgentAck) {
+        let waiter = self
+            .pending
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|pending| pending.remove(&(ack.stream_id, ack.frame_id)));
+
+        if let Some(waiter) = waiter {
+            let _ = waiter.send(Ok(ack));
+        } else {
+            debug!(
+                ack.stream_id,
+                ack.frame_id, "dropped ACK for unknown NOTIFY"
+            );
+        }
+    }
+
+    fn close<F>(&self, err: F)
+    where
+        F: Fn() -> Error,
+    {
+        if let Some(pending) = self.pending.lock().unwrap().take() {
+            for (_, waiter) in pending {
+                let _ = waiter.send(Err(err()));
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Writer {
+    framer: Framer,
+    #[debug(skip)]
+    w: Box<dyn AsyncWrite + Send + Unpin>,
+}
+
+/// A connection to an agent, with the NOTIFY frames pipelined.
+#[derive(Debug)]
+pub struct Connection {
+    hello: AgentHello,
+    max_frame_size: usize,
+    fragmentation: bool,
+    writer: Mutex<Writer>,
+    shared: Arc<Shared>,
+    reader: JoinHandle<()>,
+    ids: Arc<dyn IdGenerator>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+        self.shared.close(|| Error::Closed);
+    }
+}
+
+impl Connection {
+    /// Send the HAPROXY-HELLO frame and wait for the AGENT-HELLO frame.
+    #[instrument(skip(io), err, level = "trace")]
+    pub async fn handshake<IO>(io: IO, hello: HaproxyHello) -> Result<Self>
+    where
+        IO: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let mut framer = Framer::new(hello.max_frame_size as usize);
+        let (r, mut w) = split(io);
+        let mut r = BufReader::new(r);
+
+        let agent = exchange_hello(&mut framer, &mut r, &mut w, hello).await?;
+
+        let max_frame_size = agent.max_frame_size as usize;
+        let fragmentation = agent.capabilities.contains(&Capability::Fragmentation);
+        framer.set_max_write_size(Some(max_frame_size));
+        if fragmentation {
+            framer.set_fragmentation(Some(max_frame_size));
+        }
+        let shared = Arc::new(Shared {
+            pending: StdMutex::new(Some(Waiters::new())),
+        });
+        let reader = tokio::spawn(read_frames(Framer::new(max_frame_size), r, shared.clone()));
+
+        Ok(Connection {
+            hello: agent,
+            max_frame_size,
+            fragmentation,
+            writer: Mutex::new(Writer {
+                framer,
+                w: Box::new(w),
+            }),
+            shared,
+            reader,
+            ids: Arc::new(MonotonicIds::default()),
+        })
+    }
+
+    /// Send the HAPROXY-HELLO frame of a healthcheck and wait for the AGENT-HELLO frame,
+    /// the agent closing the connection once answered.
+    #[instrument(skip(io), err, level = "trace")]
+    pub async fn healthcheck<IO>(io: IO, hello: HaproxyHello) -> Result<AgentHello>
+    where
+        IO: AsyncRead + AsyncWrite + Send,
+    {
+        let mut framer = Framer::new(hello.max_frame_size as usize);
+        let (r, mut w) = split(io);
+        let mut r = BufReader::new(r);
+
+        exchange_hello(
+            &mut framer,
+            &mut r,
+            &mut w,
+            HaproxyHello {
+                healthcheck: Some(true),
+                ..hello
+            },
+        )
+        .await
+    }
+
+    /// Generate the stream ids of the NOTIFY frames with the generator.
+    pub fn with_id_generator(mut self, ids: Arc<dyn IdGenerator>) -> Self {
+        self.ids = ids;
+        self
+    }
+
+    /// Returns the AGENT-HELLO frame received during the handshake.
+    pub fn hello(&self) -> &AgentHello {
+        &self.hello
+    }
+
+    /// Returns the number of NOTIFY frames waiting for the ACK frames.
+    pub fn in_flight(&self) -> usize {
+        self.shared
+            .pending
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |pending| pending.len())
+    }
+
+    /// Returns `true` if the connection has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.shared.pending.lock().unwrap().is_none()
+    }
+
+    /// Send the messages in the first frame of a new stream, and wait for the actions.
+    pub async fn notify<I>(&self, messages: I) -> Result<Vec<Action>>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let stream_id = self.ids.next_stream_id();
+
+        self.notify_stream(stream_id, 1, messages).await
+    }
+
+    /// Send the messages in the stream, and wait for the actions.
+    ///
+    /// The frame is rejected if the same frame of the stream is still waiting for its ACK frame.
+    #[instrument(skip(self, messages), err, level = "trace")]
+    pub async fn notify_stream<I>(
+        &self,
+        stream_id: StreamId,
+        frame_id: FrameId,
+        messages: I,
+    ) -> Result<Vec<Action>>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let frame = Frame::notify(stream_id, frame_id, messages);
+        // the oversized frame is split in fragments once the fragmentation was agreed
+        if !self.fragmentation && frame.size() > self.max_frame_size {
+            return Err(Status::TooBig.into());
+        }
+
+        let key = (stream_id, frame_id);
+        let (sender, receiver) = oneshot::channel();
+
+        match self.shared.pending.lock().unwrap().as_mut() {
+            Some(pending) if pending.contains_key(&key) => {
+                return Err(Error::DuplicateId(stream_id, frame_id))
+            }
+            Some(pending) => {
+                pending.insert(key, sender);
+            }
+            None => return Err(Error::Closed),
+        }
+
+        if let Err(err) = self.send(frame).await {
+            if let Some(pending) = self.shared.pending.lock().unwrap().as_mut() {
+                pending.remove(&key);
+            }
+
+            return Err(err);
+        }
+
+        match receiver.await {
+            Ok(res) => res.map(|ack| ack.actions),
+            Err(_) => Err(Error::Closed),
+        }
+    }
+
+    /// Send the HAPROXY-DISCONNECT frame and close the connection.
+    pub async fn disconnect<S: Into<String>>(&self, status: Status, reason: S) -> Result<()> {
+        let res = self
+            .send(Frame::haproxy_disconnect(status, reason))
+            .await
+            .map(|_| ());
+
+        self.shared.close(|| Error::Closed);
+
+        res
+    }
+
+    async fn send(&self, frame: Frame) -> Result<usize> {
+        let mut writer = self.writer.lock().await;
+        let Writer { framer, w } = &mut *writer;
+
+        Ok(framer.write_frame(w, frame).await?)
+    }
+}
+
+async fn exchange_hello<R, W>(
+    framer: &mut Framer,
+    r: &mut R,
+    w: &mut W,
+    hello: HaproxyHello,
+) -> Result<AgentHello>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    framer
+        .write_frame(w, Frame::HaproxyHello(hello.clone()))
+        .await?;
+
+    let agent = match framer.read_frame(r).await? {
+        Frame::AgentHello(agent) => agent,
+        Frame::AgentDisconnect(disconnect) => return Err(Error::Disconnected(disconnect)),
+        _ => return Err(Error::Unexpected("handshake")),
+    };
+
+    if !hello.supported_versions.contains(&agent.version) {
+        return Err(Status::BadVersion.into());
+    }
+    if agent.max_frame_size > hello.max_frame_size {
+        return Err(Status::BadFrameSize.into());
+    }
+
+    Ok(agent)
+}
+
+async fn read_frames<R>(framer: Framer, mut r: R, shared: Arc<Shared>)
+where
+    R: AsyncRead + Unpin,
+{
+    // the fragmented ACK frames, when the fragmentation is negotiated
+    let mut reassembly = Reassembly::<Action>::default();
+
+    loop {
+        match framer.read_frame(&mut r).await {
+            Ok(Frame::AgentAck(ack)) => {
+                let (stream_id, frame_id) = (ack.stream_id, ack.frame_id);
+
+                match reassembly.handle_frame(Frame::AgentAck(ack)).await {
+                    Ok(Some(actions)) => {
+                        trace!(stream_id, frame_id, "received ACK");
+
+                        shared.complete(AgentAck {
+                            actions,
+                            ..AgentAck::new(stream_id, frame_id)
+                        });
+                    }
+                    Ok(None) => trace!(stream_id, frame_id, "received ACK fragment"),
+                    Err(status) => {
+                        shared.close(|| Error::Status(status));
+                        break;
+                    }
+                }
+            }
+            Ok(Frame::AgentDisconnect(disconnect)) => {
+                debug!(?disconnect, "disconnected by agent");
+
+                shared.close(|| Error::Disconnected(disconnect.clone()));
+                break;
+            }
+            Ok(frame) => {
+                debug!(frame = ?frame.frame_type(), "unexpected frame");
+
+                shared.close(|| Error::Unexpected("agent"));
+                break;
+            }
+            Err(status) => {
+                shared.close(|| Error::Status(status));
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use crate::{
+        spop::{Scope, Version},
+        Connector,
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fragmented_ack() {
+        let actions = (0..4)
+            .map(|i| Action::set_var(Scope::Transaction, format!("v{i}"), i))
+            .collect::<Vec<_>>();
+        let (client, agent) = duplex(4096);
+
+        // a scripted agent answering with the actions in fragments
+        let fragments = actions.clone();
+        tokio::spawn(async move {
+            let mut framer = Framer::new(1024);
+            let (r, mut w) = split(agent);
+            let mut r = BufReader::new(r);
+
+            assert!(matches!(
+                framer.read_frame(&mut r).await.unwrap(),
+                Frame::HaproxyHello(_)
+            ));
+            framer
+                .write_frame(
+                    &mut w,
+                    Frame::AgentHello(AgentHello {
+                        version: Version::V2_0,
+                        max_frame_size: 1024,
+                        capabilities: vec![Capability::Fragmentation],
+                    }),
+                )
+                .await
+                .unwrap();
+
+            let Frame::HaproxyNotify(notify) = framer.read_frame(&mut r).await.unwrap() else {
+                panic!("expected NOTIFY frame");
+            };
+            let n = fragments.len();
+            for (i, action) in fragments.into_iter().enumerate() {
+                let ack = AgentAck {
+                    fragmented: i + 1 < n,
+                    actions: vec![action],
+                    ..AgentAck::new(notify.stream_id, notify.frame_id)
+                };
+                framer
+                    .write_frame(&mut w, Frame::AgentAck(ack))
+                    .await
+                    .unwrap();
+            }
+
+            // hold the connection until the client is dropped
+            let _ = framer.read_frame(&mut r).await;
+        });
+
+        let conn = Connector::new("agent")
+            .capabilities([Capability::Fragmentation])
+            .connect_with(client)
+            .await
+            .unwrap();
+        assert_eq!(
+            conn.notify([Message::new("check", [("n", 1)])])
+                .await
+                .unwrap(),
+            actions
+        );
+        assert_eq!(conn.in_flight(), 0);
+    }
+}
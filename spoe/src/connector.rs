@@ -0,0 +1,138 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+use tracing::instrument;
+
+use crate::{
+    error::{Error, Result},
+    ids::IdGenerator,
+    spop::{AgentHello, Capability, HaproxyHello, Version, MAX_FRAME_SIZE},
+    Connection,
+};
+
+/// The default timeout to connect to the agent and complete the handshake.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connect to an agent and complete the handshake.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    addr: String,
+    pub(crate) hello: HaproxyHello,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) ids: Option<Arc<dyn IdGenerator>>,
+}
+
+impl Connector {
+    pub fn new<S: Into<String>>(addr: S) -> Self {
+        Connector {
+            addr: addr.into(),
+            hello: HaproxyHello {
+                supported_versions: vec![Version::V2_0],
+                max_frame_size: MAX_FRAME_SIZE as u32,
+                capabilities: vec![Capability::Pipelining],
+                healthcheck: None,
+                engine_id: None,
+            },
+            connect_timeout: CONNECT_TIMEOUT,
+            ids: None,
+        }
+    }
+
+    /// Returns the address of the agent.
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Announce the versions, `2.0` by default.
+    pub fn versions<I>(mut self, versions: I) -> Self
+    where
+        I: IntoIterator<Item = Version>,
+    {
+        self.hello.supported_versions = versions.into_iter().collect();
+        self
+    }
+
+    pub fn max_frame_size(mut self, sz: u32) -> Self {
+        self.hello.max_frame_size = sz;
+        self
+    }
+
+    pub fn capabilities<I>(mut self, caps: I) -> Self
+    where
+        I: IntoIterator<Item = Capability>,
+    {
+        self.hello.capabilities = caps.into_iter().collect();
+        self
+    }
+
+    pub fn engine_id<S: Into<String>>(mut self, id: S) -> Self {
+        self.hello.engine_id = Some(id.into());
+        self
+    }
+
+    pub fn connect_timeout(mut self, d: Duration) -> Self {
+        self.connect_timeout = d;
+        self
+    }
+
+    /// Generate the stream ids of the NOTIFY frames with the generator shared by the connections,
+    /// instead of the monotonic ids of every connection.
+    pub fn id_generator<G: IdGenerator + 'static>(mut self, ids: G) -> Self {
+        self.ids = Some(Arc::new(ids));
+        self
+    }
+
+    pub(crate) fn with_ids(&self, conn: Connection) -> Connection {
+        match self.ids {
+            Some(ref ids) => conn.with_id_generator(ids.clone()),
+            None => conn,
+        }
+    }
+
+    /// Connect to the agent and complete the handshake.
+    #[instrument(skip(self), fields(addr = %self.addr), err, level = "debug")]
+    pub async fn connect(&self) -> Result<Connection> {
+        timeout(self.connect_timeout, async {
+            let stream = TcpStream::connect(&self.addr).await?;
+            stream.set_nodelay(true)?;
+
+            Connection::handshake(stream, self.hello.clone())
+                .await
+                .map(|conn| self.with_ids(conn))
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+
+    /// Complete the handshake over the stream, e.g. an unix domain socket or an in-memory pipe,
+    /// ignoring the address of the connector.
+    pub async fn connect_with<IO>(&self, io: IO) -> Result<Connection>
+    where
+        IO: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        timeout(
+            self.connect_timeout,
+            Connection::handshake(io, self.hello.clone()),
+        )
+        .await
+        .map_err(|_| Error::Timeout)?
+        .map(|conn| self.with_ids(conn))
+    }
+
+    /// Connect to the agent and check its health, as the `option spop-check` of HAProxy.
+    #[instrument(skip(self), fields(addr = %self.addr), err, level = "debug")]
+    pub async fn healthcheck(&self) -> Result<AgentHello> {
+        timeout(self.connect_timeout, async {
+            let stream = TcpStream::connect(&self.addr).await?;
+
+            Connection::healthcheck(stream, self.hello.clone()).await
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+}
@@ -0,0 +1,47 @@
+use std::result::Result as StdResult;
+
+use thiserror::Error;
+
+use crate::spop::{Disconnect, Error as Status, FrameId, StreamId};
+
+pub type Result<T> = StdResult<T, Error>;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("closed")]
+    Closed,
+
+    #[error("timeout")]
+    Timeout,
+
+    #[error(transparent)]
+    Status(#[from] Status),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("disconnected by agent, {}", .0.message)]
+    Disconnected(Disconnect),
+
+    #[error("unexpected {0} frame")]
+    Unexpected(&'static str),
+
+    #[error("frame {1} of stream {0} already in flight")]
+    DuplicateId(StreamId, FrameId),
+}
+
+impl Error {
+    /// Returns the status of the error, to be reported to the agent.
+    pub fn status(&self) -> Status {
+        match self {
+            Error::Closed => Status::Normal,
+            Error::Timeout => Status::Timeout,
+            Error::Status(status) => *status,
+            Error::Io(_) => Status::Io,
+            Error::Disconnected(disconnect) => {
+                Status::try_from(disconnect.status_code).unwrap_or(Status::Unknown)
+            }
+            Error::Unexpected(_) | Error::DuplicateId(..) => Status::Invalid,
+        }
+    }
+}
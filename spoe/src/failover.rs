@@ -0,0 +1,307 @@
+//! Failover between ordered groups of agents, as the backup servers of HAProxy.
+//!
+//! The checkouts are spread over the primary agents which are up, and only fall back
+//! to the backup agents once all the primary agents are down.
+//!
+//! An agent is marked down after `fall` consecutive failed checkouts, and probed
+//! with a SPOP healthcheck every `check_interval` until `rise` consecutive checks
+//! succeed, when the checkouts return to it.
+
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    error::{Error, Result},
+    Pool, Pooled,
+};
+
+/// The default number of consecutive failures before marking an agent down.
+pub const FALL: usize = 3;
+
+/// The default number of consecutive successful checks before marking an agent up.
+pub const RISE: usize = 2;
+
+/// The default interval between the checks of an agent marked down.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Health {
+    up: bool,
+    failures: usize,
+    successes: usize,
+    checking: bool,
+    next_check: Instant,
+}
+
+#[derive(Debug)]
+struct Target {
+    pool: Pool,
+    backup: bool,
+    health: StdMutex<Health>,
+}
+
+impl Target {
+    fn is_up(&self) -> bool {
+        self.health.lock().unwrap().up
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    targets: Vec<Target>,
+    fall: usize,
+    rise: usize,
+    check_interval: Duration,
+    all_backups: bool,
+    next: AtomicUsize,
+}
+
+/// Checkout the connections from the primary agents, or the backup agents once they are all down.
+#[derive(Clone, Debug)]
+pub struct Failover(Arc<Inner>);
+
+/// Build a [`Failover`].
+#[derive(Debug, Default)]
+pub struct Builder {
+    primaries: Vec<Pool>,
+    backups: Vec<Pool>,
+    fall: Option<usize>,
+    rise: Option<usize>,
+    check_interval: Option<Duration>,
+    all_backups: bool,
+}
+
+impl Builder {
+    /// Add a primary agent.
+    pub fn primary(mut self, pool: Pool) -> Self {
+        self.primaries.push(pool);
+        self
+    }
+
+    /// Add a backup agent, only used once all the primary agents are down.
+    pub fn backup(mut self, pool: Pool) -> Self {
+        self.backups.push(pool);
+        self
+    }
+
+    /// Mark an agent down after the number of consecutive failures.
+    pub fn fall(mut self, n: usize) -> Self {
+        self.fall = Some(n.max(1));
+        self
+    }
+
+    /// Mark an agent up after the number of consecutive successful checks.
+    pub fn rise(mut self, n: usize) -> Self {
+        self.rise = Some(n.max(1));
+        self
+    }
+
+    /// Check the agents marked down at the interval.
+    pub fn check_interval(mut self, d: Duration) -> Self {
+        self.check_interval = Some(d);
+        self
+    }
+
+    /// Spread the checkouts over all the backup agents which are up,
+    /// instead of only the first one, as the `option allbackups` of HAProxy.
+    pub fn all_backups(mut self) -> Self {
+        self.all_backups = true;
+        self
+    }
+
+    pub fn build(self) -> Failover {
+        let now = Instant::now();
+        let targets = self
+            .primaries
+            .into_iter()
+            .map(|pool| (pool, false))
+            .chain(self.backups.into_iter().map(|pool| (pool, true)))
+            .map(|(pool, backup)| Target {
+                pool,
+                backup,
+                health: StdMutex::new(Health {
+                    up: true,
+                    failures: 0,
+                    successes: 0,
+                    checking: false,
+                    next_check: now,
+                }),
+            })
+            .collect();
+
+        Failover(Arc::new(Inner {
+            targets,
+            fall: self.fall.unwrap_or(FALL),
+            rise: self.rise.unwrap_or(RISE),
+            check_interval: self.check_interval.unwrap_or(CHECK_INTERVAL),
+            all_backups: self.all_backups,
+            next: AtomicUsize::default(),
+        }))
+    }
+}
+
+impl Failover {
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Returns whether any primary agent is up.
+    pub fn is_primary_up(&self) -> bool {
+        self.0
+            .targets
+            .iter()
+            .any(|target| !target.backup && target.is_up())
+    }
+
+    /// Returns the number of agents which are up.
+    pub fn up(&self) -> usize {
+        self.0
+            .targets
+            .iter()
+            .filter(|target| target.is_up())
+            .count()
+    }
+
+    /// Checkout a connection before the timeout.
+    pub async fn checkout_timeout(&self, d: Duration) -> Result<Pooled> {
+        self.checkout(Instant::now() + d).await
+    }
+
+    /// Checkout a connection before the deadline, from the primary agents which are up,
+    /// or the backup agents once all the primary agents are down.
+    ///
+    /// A failed checkout is redispatched to the next agent,
+    /// and all the agents are tried when none of them is up.
+    #[instrument(skip(self), err, level = "trace")]
+    pub async fn checkout(&self, deadline: Instant) -> Result<Pooled> {
+        self.check();
+
+        let mut last_err = None;
+
+        for idx in self.candidates() {
+            let target = &self.0.targets[idx];
+
+            match target.pool.checkout(deadline).await {
+                Ok(pooled) => {
+                    self.succeeded(target);
+
+                    return Ok(pooled);
+                }
+                Err(Error::Timeout) if Instant::now() >= deadline => return Err(Error::Timeout),
+                Err(err) => {
+                    debug!(
+                        addr = target.pool.connector().addr(),
+                        ?err,
+                        "checkout failed"
+                    );
+
+                    self.failed(target);
+
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or(Error::Closed))
+    }
+
+    /// The order to try the agents, starting with the selected ones.
+    fn candidates(&self) -> Vec<usize> {
+        let targets = &self.0.targets;
+        let up = |backup: bool| {
+            targets
+                .iter()
+                .enumerate()
+                .filter(move |(_, target)| target.backup == backup && target.is_up())
+                .map(|(idx, _)| idx)
+                .collect::<Vec<_>>()
+        };
+
+        let mut selected = up(false);
+        if selected.is_empty() {
+            selected = up(true);
+
+            if !self.0.all_backups {
+                selected.truncate(1);
+            }
+        }
+
+        if selected.len() > 1 {
+            let n = self.0.next.fetch_add(1, Relaxed) % selected.len();
+            selected.rotate_left(n);
+        }
+
+        let rest = (0..targets.len()).filter(|idx| !selected.contains(idx));
+
+        selected.extend(rest.collect::<Vec<_>>());
+        selected
+    }
+
+    /// Probe the agents marked down, when their check is due.
+    fn check(&self) {
+        let now = Instant::now();
+
+        for idx in 0..self.0.targets.len() {
+            let target = &self.0.targets[idx];
+
+            {
+                let mut health = target.health.lock().unwrap();
+
+                if health.up || health.checking || health.next_check > now {
+                    continue;
+                }
+
+                health.checking = true;
+            }
+
+            let inner = self.0.clone();
+
+            tokio::spawn(async move {
+                let target = &inner.targets[idx];
+                let res = target.pool.connector().healthcheck().await;
+                let mut health = target.health.lock().unwrap();
+
+                health.checking = false;
+                health.next_check = Instant::now() + inner.check_interval;
+
+                if res.is_ok() {
+                    health.successes += 1;
+
+                    if health.successes >= inner.rise {
+                        debug!(addr = target.pool.connector().addr(), "agent is up");
+
+                        health.up = true;
+                        health.failures = 0;
+                    }
+                } else {
+                    health.successes = 0;
+                }
+            });
+        }
+    }
+
+    fn succeeded(&self, target: &Target) {
+        let mut health = target.health.lock().unwrap();
+
+        health.up = true;
+        health.failures = 0;
+    }
+
+    fn failed(&self, target: &Target) {
+        let mut health = target.health.lock().unwrap();
+
+        health.failures += 1;
+
+        if health.up && health.failures >= self.0.fall {
+            warn!(addr = target.pool.connector().addr(), "agent is down");
+
+            health.up = false;
+            health.successes = 0;
+            health.next_check = Instant::now() + self.0.check_interval;
+        }
+    }
+}
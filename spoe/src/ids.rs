@@ -0,0 +1,93 @@
+//! Generate the stream ids of the NOTIFY frames sent by the connections.
+//!
+//! The ids are monotonic by default, the random ones with a fixed seed
+//! make the tests and the replay runs produce the same frames every time:
+//!
+//! ```
+//! # use haproxy_spoe::{ids::RandomIds, Connector};
+//! let connector = Connector::new("127.0.0.1:12345").id_generator(RandomIds::seeded(42));
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Mutex;
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+use crate::spop::StreamId;
+
+/// Generate the stream ids of the NOTIFY frames.
+///
+/// The generator is shared by all the connections of a connector,
+/// an id already waiting for its ACK frame on the connection is rejected when sent.
+pub trait IdGenerator: fmt::Debug + Send + Sync {
+    /// Returns the id of a new stream.
+    fn next_stream_id(&self) -> StreamId;
+}
+
+/// The stream ids in increasing order.
+#[derive(Debug, Default)]
+pub struct MonotonicIds(AtomicU64);
+
+impl MonotonicIds {
+    /// Generate the stream ids from the first one.
+    pub fn starting_at(first: StreamId) -> Self {
+        MonotonicIds(AtomicU64::new(first))
+    }
+}
+
+impl IdGenerator for MonotonicIds {
+    fn next_stream_id(&self) -> StreamId {
+        self.0.fetch_add(1, Relaxed)
+    }
+}
+
+/// The random stream ids, reproducible when seeded.
+#[derive(Debug)]
+pub struct RandomIds(Mutex<StdRng>);
+
+impl RandomIds {
+    /// Generate the stream ids from the entropy of the OS.
+    pub fn new() -> Self {
+        RandomIds(Mutex::new(StdRng::from_entropy()))
+    }
+
+    /// Generate the same stream ids for the same seed.
+    pub fn seeded(seed: u64) -> Self {
+        RandomIds(Mutex::new(StdRng::seed_from_u64(seed)))
+    }
+}
+
+impl Default for RandomIds {
+    fn default() -> Self {
+        RandomIds::new()
+    }
+}
+
+impl IdGenerator for RandomIds {
+    fn next_stream_id(&self) -> StreamId {
+        self.0.lock().unwrap().gen()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids() {
+        let ids = MonotonicIds::starting_at(10);
+        assert_eq!(
+            (0..3).map(|_| ids.next_stream_id()).collect::<Vec<_>>(),
+            [10, 11, 12]
+        );
+
+        let ids = |seed| {
+            let ids = RandomIds::seeded(seed);
+
+            (0..8).map(|_| ids.next_stream_id()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(42), ids(42));
+        assert_ne!(ids(42), ids(43));
+    }
+}
@@ -1 +1,21 @@
+//! Stream Processing Offload Engine.
+//!
+//! The engine is the client side of the protocol,
+//! it connects to the agents and sends the messages to be processed.
 
+pub use haproxy_spop as spop;
+
+mod conn;
+mod connector;
+mod error;
+pub mod failover;
+pub mod ids;
+pub mod pool;
+#[cfg(feature = "quic")]
+pub mod quic;
+
+pub use self::conn::Connection;
+pub use self::connector::Connector;
+pub use self::error::{Error, Result};
+pub use self::failover::Failover;
+pub use self::pool::{Pool, PoolStats, Pooled};
@@ -0,0 +1,719 @@
+//! A pool of handshaked connections to an agent.
+//!
+//! The connections are shared by the checkouts up to the pipelining depth,
+//! scored by the outcome of the NOTIFY frames, and evicted once unhealthy, closed,
+//! or idle for too long.
+//!
+//! Like a TCP congestion window, the number of checkouts sharing a connection starts small,
+//! grows while the ACK turnaround stays close to the fastest one seen on the connection,
+//! and is halved once it inflates or a NOTIFY frame fails.
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicI32, AtomicU64, AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use tokio::{
+    sync::Notify,
+    time::{timeout_at, Instant},
+};
+use tracing::{debug, instrument};
+
+use crate::{
+    error::{Error, Result},
+    spop::{Action, Capability, Message},
+    Connection, Connector,
+};
+
+/// The health score of a new connection.
+pub const MAX_HEALTH: i32 = 100;
+
+/// The default minimum health score of a connection before being evicted.
+pub const MIN_HEALTH: i32 = 50;
+
+/// The health score gained by a successful NOTIFY frame.
+const SUCCESS_SCORE: i32 = 1;
+
+/// The health score lost by a failed NOTIFY frame.
+const FAILURE_PENALTY: i32 = 25;
+
+/// The initial number of checkouts sharing a connection, grown up to the pipelining depth.
+pub const INITIAL_WINDOW: usize = 4;
+
+/// How many times slower than the fastest one an ACK frame is before shrinking the window.
+const CONGESTION_FACTOR: u64 = 2;
+
+/// The number of checkouts sharing a connection, adapted to the ACK turnaround.
+#[derive(Debug)]
+struct Window {
+    max: usize,
+    adaptive: bool,
+    current: AtomicUsize,
+    /// The fastest ACK turnaround, in microseconds.
+    min_rtt: AtomicU64,
+}
+
+impl Window {
+    fn new(max: usize, adaptive: bool) -> Self {
+        Window {
+            max,
+            adaptive,
+            current: AtomicUsize::new(if adaptive {
+                INITIAL_WINDOW.min(max)
+            } else {
+                max
+            }),
+            min_rtt: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    fn get(&self) -> usize {
+        self.current.load(Relaxed)
+    }
+
+    /// Grow the window by one while the ACK frames are fast, halve it once they slow down.
+    fn acked(&self, rtt: Duration) {
+        if !self.adaptive {
+            return;
+        }
+
+        let rtt = rtt.as_micros() as u64;
+        let min_rtt = self.min_rtt.fetch_min(rtt, Relaxed).min(rtt);
+
+        if rtt <= min_rtt.saturating_mul(CONGESTION_FACTOR) {
+            let _ = self
+                .current
+                .fetch_update(Relaxed, Relaxed, |n| Some((n + 1).min(self.max)));
+        } else {
+            self.shrink();
+        }
+    }
+
+    fn shrink(&self) {
+        if self.adaptive {
+            let _ = self
+                .current
+                .fetch_update(Relaxed, Relaxed, |n| Some((n / 2).max(1)));
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    conn: Connection,
+    window: Window,
+    health: AtomicI32,
+    checked_out: AtomicUsize,
+    last_used: StdMutex<Instant>,
+}
+
+impl Entry {
+    fn is_idle_for(&self, d: Duration) -> bool {
+        self.checked_out.load(Relaxed) == 0 && self.last_used.lock().unwrap().elapsed() > d
+    }
+}
+
+#[derive(Debug, Default)]
+struct Stats {
+    created: AtomicU64,
+    evicted: AtomicU64,
+    checkouts: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+/// The metrics of the pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// The number of open connections.
+    pub connections: usize,
+    /// The number of connections without checkout.
+    pub idle: usize,
+    /// The number of checkouts in use.
+    pub in_flight: usize,
+    /// The number of checkouts the open connections currently accept.
+    pub window: usize,
+    /// The number of connections created.
+    pub created: u64,
+    /// The number of connections evicted.
+    pub evicted: u64,
+    /// The number of successful checkouts.
+    pub checkouts: u64,
+    /// The number of checkouts which missed their deadline.
+    pub timeouts: u64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    connector: Connector,
+    max_connections: usize,
+    max_idle_time: Duration,
+    pipelining_depth: usize,
+    adaptive: bool,
+    min_health: i32,
+    entries: StdMutex<Vec<Arc<Entry>>>,
+    connecting: AtomicUsize,
+    released: Notify,
+    stats: Stats,
+}
+
+/// A pool of handshaked connections to an agent.
+#[derive(Clone, Debug)]
+pub struct Pool(Arc<Inner>);
+
+/// Build a [`Pool`].
+#[derive(Debug)]
+pub struct Builder {
+    connector: Connector,
+    max_connections: usize,
+    max_idle_time: Duration,
+    pipelining_depth: usize,
+    adaptive: bool,
+    min_health: i32,
+}
+
+impl Builder {
+    /// Limit the number of open connections.
+    pub fn max_connections(mut self, n: usize) -> Self {
+        self.max_connections = n.max(1);
+        self
+    }
+
+    /// Close the connections idle for longer than the duration.
+    pub fn max_idle_time(mut self, d: Duration) -> Self {
+        self.max_idle_time = d;
+        self
+    }
+
+    /// Limit the number of checkouts sharing a connection, when the agent supports pipelining.
+    pub fn pipelining_depth(mut self, n: usize) -> Self {
+        self.pipelining_depth = n.max(1);
+        self
+    }
+
+    /// Pin the number of checkouts sharing a connection to the pipelining depth,
+    /// instead of adapting it to the ACK turnaround.
+    pub fn fixed_pipelining_depth(mut self, n: usize) -> Self {
+        self.adaptive = false;
+        self.pipelining_depth(n)
+    }
+
+    /// Evict the connections with a health score below the minimum.
+    pub fn min_health(mut self, score: i32) -> Self {
+        self.min_health = score;
+        self
+    }
+
+    pub fn build(self) -> Pool {
+        Pool(Arc::new(Inner {
+            connector: self.connector,
+            max_connections: self.max_connections,
+            max_idle_time: self.max_idle_time,
+            pipelining_depth: self.pipelining_depth,
+            adaptive: self.adaptive,
+            min_health: self.min_health,
+            entries: StdMutex::default(),
+            connecting: AtomicUsize::default(),
+            released: Notify::new(),
+            stats: Stats::default(),
+        }))
+    }
+}
+
+impl Pool {
+    pub fn builder(connector: Connector) -> Builder {
+        Builder {
+            connector,
+            max_connections: 8,
+            max_idle_time: Duration::from_secs(60),
+            pipelining_depth: 16,
+            adaptive: true,
+            min_health: MIN_HEALTH,
+        }
+    }
+
+    /// Returns the connector of the agent.
+    pub fn connector(&self) -> &Connector {
+        &self.0.connector
+    }
+
+    /// Returns the metrics of the pool.
+    pub fn stats(&self) -> PoolStats {
+        let entries = self.0.entries.lock().unwrap();
+        let in_flight = entries
+            .iter()
+            .map(|entry| entry.checked_out.load(Relaxed))
+            .sum();
+        let idle = entries
+            .iter()
+            .filter(|entry| entry.checked_out.load(Relaxed) == 0)
+            .count();
+        let window = entries.iter().map(|entry| entry.window.get()).sum();
+
+        PoolStats {
+            connections: entries.len(),
+            idle,
+            in_flight,
+            window,
+            created: self.0.stats.created.load(Relaxed),
+            evicted: self.0.stats.evicted.load(Relaxed),
+            checkouts: self.0.stats.checkouts.load(Relaxed),
+            timeouts: self.0.stats.timeouts.load(Relaxed),
+        }
+    }
+
+    /// Checkout a connection before the timeout.
+    pub async fn checkout_timeout(&self, d: Duration) -> Result<Pooled> {
+        self.checkout(Instant::now() + d).await
+    }
+
+    /// Checkout a connection before the deadline,
+    /// reusing a healthy connection or connecting a new one if the pool is not full.
+    #[instrument(skip(self), err, level = "trace")]
+    pub async fn checkout(&self, deadline: Instant) -> Result<Pooled> {
+        loop {
+            let released = self.0.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            if let Some(pooled) = self.try_checkout() {
+                return Ok(pooled);
+            }
+
+            // the waiters retry once the reservation is dropped, with the connection inserted or failed
+            if let Some(_reservation) = self.reserve() {
+                let res = timeout_at(deadline, self.0.connector.connect()).await;
+
+                return match res {
+                    Ok(Ok(conn)) => Ok(self.insert(conn)),
+                    Ok(Err(err)) => Err(err),
+                    Err(_) => {
+                        self.0.stats.timeouts.fetch_add(1, Relaxed);
+
+                        Err(Error::Timeout)
+                    }
+                };
+            }
+
+            if timeout_at(deadline, released).await.is_err() {
+                self.0.stats.timeouts.fetch_add(1, Relaxed);
+
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    fn try_checkout(&self) -> Option<Pooled> {
+        let mut entries = self.0.entries.lock().unwrap();
+
+        let before = entries.len();
+        entries.retain(|entry| {
+            !entry.conn.is_closed()
+                && entry.health.load(Relaxed) >= self.0.min_health
+                && !entry.is_idle_for(self.0.max_idle_time)
+        });
+        let evicted = before - entries.len();
+
+        if evicted > 0 {
+            debug!(evicted, "evicted connections");
+
+            self.0.stats.evicted.fetch_add(evicted as u64, Relaxed);
+        }
+
+        let entry = entries
+            .iter()
+            .filter(|entry| entry.checked_out.load(Relaxed) < entry.window.get())
+            .min_by_key(|entry| entry.checked_out.load(Relaxed))?
+            .clone();
+
+        Some(self.pooled(entry))
+    }
+
+    fn reserve(&self) -> Option<Reservation<'_>> {
+        let entries = self.0.entries.lock().unwrap();
+
+        if entries.len() + self.0.connecting.load(Relaxed) < self.0.max_connections {
+            self.0.connecting.fetch_add(1, Relaxed);
+
+            Some(Reservation(&self.0))
+        } else {
+            None
+        }
+    }
+
+    fn insert(&self, conn: Connection) -> Pooled {
+        let window = if conn.hello().capabilities.contains(&Capability::Pipelining) {
+            Window::new(self.0.pipelining_depth, self.0.adaptive)
+        } else {
+            Window::new(1, false)
+        };
+        let entry = Arc::new(Entry {
+            conn,
+            window,
+            health: AtomicI32::new(MAX_HEALTH),
+            checked_out: AtomicUsize::default(),
+            last_used: StdMutex::new(Instant::now()),
+        });
+
+        self.0.entries.lock().unwrap().push(entry.clone());
+        self.0.stats.created.fetch_add(1, Relaxed);
+
+        self.pooled(entry)
+    }
+
+    fn pooled(&self, entry: Arc<Entry>) -> Pooled {
+        entry.checked_out.fetch_add(1, Relaxed);
+        self.0.stats.checkouts.fetch_add(1, Relaxed);
+
+        Pooled {
+            entry,
+            pool: self.0.clone(),
+        }
+    }
+}
+
+/// A connection being opened, released even if the checkout is cancelled while connecting.
+struct Reservation<'a>(&'a Inner);
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        self.0.connecting.fetch_sub(1, Relaxed);
+        self.0.released.notify_waiters();
+    }
+}
+
+/// A connection checked out of the [`Pool`], returned to the pool when dropped.
+#[derive(Debug)]
+pub struct Pooled {
+    entry: Arc<Entry>,
+    pool: Arc<Inner>,
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        *self.entry.last_used.lock().unwrap() = Instant::now();
+        self.entry.checked_out.fetch_sub(1, Relaxed);
+        self.pool.released.notify_waiters();
+    }
+}
+
+impl Deref for Pooled {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.entry.conn
+    }
+}
+
+impl Pooled {
+    /// Returns the health score of the connection.
+    pub fn health(&self) -> i32 {
+        self.entry.health.load(Relaxed)
+    }
+
+    /// Returns the number of checkouts the connection currently accepts.
+    pub fn window(&self) -> usize {
+        self.entry.window.get()
+    }
+
+    /// Send the messages in a new stream, and wait for the actions,
+    /// scoring the health of the connection by the outcome.
+    pub async fn notify<I>(&self, messages: I) -> Result<Vec<Action>>
+    where
+        I: IntoIterator<Item = Message>,
+    {
+        let started = Instant::now();
+        let res = self.entry.conn.notify(messages).await;
+
+        match res {
+            Ok(_) => self.entry.window.acked(started.elapsed()),
+            Err(_) => self.entry.window.shrink(),
+        }
+
+        let _ = self.entry.health.fetch_update(Relaxed, Relaxed, |score| {
+            Some(if res.is_ok() {
+                (score + SUCCESS_SCORE).min(MAX_HEALTH)
+            } else {
+                score - FAILURE_PENALTY
+            })
+        });
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use tokio::{net::TcpListener, time::sleep};
+
+    use crate::spop::{AgentHello, Frame, Framer, Version};
+
+    use super::*;
+
+    /// How the scripted agent handles a connection.
+    #[derive(Clone, Copy, Debug)]
+    enum Script {
+        /// Complete the handshake and answer the NOTIFY frames.
+        Serve,
+        /// Accept the connection and never answer.
+        Hang,
+        /// Close the connection after the delay, before the handshake.
+        Close(Duration),
+    }
+
+    /// Start an agent handling its n-th connection with the script, returns its address.
+    async fn agent<F>(pipelining: bool, script: F) -> String
+    where
+        F: Fn(usize) -> Script + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            for n in 0.. {
+                let (stream, _) = listener.accept().await.unwrap();
+                let script = script(n);
+
+                tokio::spawn(async move {
+                    let (mut r, mut w) = stream.into_split();
+                    let mut framer = Framer::new(1024);
+
+                    match script {
+                        Script::Serve => {}
+                        Script::Hang => {
+                            let _ = framer.read_frame(&mut r).await;
+                            sleep(Duration::from_secs(60)).await;
+                            return;
+                        }
+                        Script::Close(delay) => {
+                            sleep(delay).await;
+                            return;
+                        }
+                    }
+
+                    let Ok(Frame::HaproxyHello(_)) = framer.read_frame(&mut r).await else {
+                        return;
+                    };
+                    let hello = AgentHello {
+                        version: Version::V2_0,
+                        max_frame_size: 1024,
+                        capabilities: if pipelining {
+                            vec![Capability::Pipelining]
+                        } else {
+                            vec![]
+                        },
+                    };
+                    framer
+                        .write_frame(&mut w, Frame::AgentHello(hello))
+                        .await
+                        .unwrap();
+
+                    while let Ok(Frame::HaproxyNotify(notify)) = framer.read_frame(&mut r).await {
+                        let ack =
+                            Frame::ack(notify.stream_id, notify.frame_id, Vec::<Action>::new());
+
+                        if framer.write_frame(&mut w, ack).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    fn check() -> Message {
+        Message::new("check", [("n", 1)])
+    }
+
+    #[tokio::test]
+    async fn test_reuse() {
+        let pool = Pool::builder(Connector::new(agent(false, |_| Script::Serve).await)).build();
+
+        let pooled = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        pooled.notify([check()]).await.unwrap();
+        assert_eq!(pooled.health(), MAX_HEALTH);
+        drop(pooled);
+
+        let pooled = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        pooled.notify([check()]).await.unwrap();
+        drop(pooled);
+
+        let stats = pool.stats();
+        assert_eq!(stats.connections, 1);
+        assert_eq!(stats.idle, 1);
+        assert_eq!(stats.created, 1);
+        assert_eq!(stats.checkouts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_depth_limits() {
+        // without pipelining, a connection is checked out once
+        let pool = Pool::builder(Connector::new(agent(false, |_| Script::Serve).await))
+            .max_connections(1)
+            .build();
+
+        let pooled = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(pooled.window(), 1);
+        assert!(matches!(
+            pool.checkout_timeout(Duration::from_millis(50)).await,
+            Err(Error::Timeout)
+        ));
+        assert_eq!(pool.stats().timeouts, 1);
+        drop(pooled);
+
+        // with pipelining, up to the depth
+        let pool = Pool::builder(Connector::new(agent(true, |_| Script::Serve).await))
+            .max_connections(1)
+            .fixed_pipelining_depth(2)
+            .build();
+
+        let first = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        let second = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(pool.stats().in_flight, 2);
+        assert!(matches!(
+            pool.checkout_timeout(Duration::from_millis(50)).await,
+            Err(Error::Timeout)
+        ));
+
+        // a waiter is woken up once a checkout is returned
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+
+            async move {
+                pool.checkout_timeout(Duration::from_secs(5))
+                    .await
+                    .map(|_| ())
+            }
+        });
+        sleep(Duration::from_millis(20)).await;
+        drop(first);
+        waiting.await.unwrap().unwrap();
+        drop(second);
+
+        assert_eq!(pool.stats().created, 1);
+    }
+
+    #[tokio::test]
+    async fn test_eviction() {
+        let pool = Pool::builder(Connector::new(agent(false, |_| Script::Serve).await))
+            .max_idle_time(Duration::from_millis(20))
+            .build();
+
+        // the failed NOTIFY frames lower the health score until evicted
+        let pooled = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        let oversized = Message::new("check", [("data", "x".repeat(2048))]);
+        for score in [75, 50, 25] {
+            assert!(pooled.notify([oversized.clone()]).await.is_err());
+            assert_eq!(pooled.health(), score);
+        }
+        drop(pooled);
+
+        let pooled = pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(pooled.health(), MAX_HEALTH);
+        drop(pooled);
+        assert_eq!(pool.stats().evicted, 1);
+        assert_eq!(pool.stats().created, 2);
+
+        // the idle connections are evicted
+        sleep(Duration::from_millis(50)).await;
+        drop(pool.checkout_timeout(Duration::from_secs(5)).await.unwrap());
+        assert_eq!(pool.stats().evicted, 2);
+        assert_eq!(pool.stats().connections, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_checkout() {
+        let hung = Arc::new(AtomicBool::new(false));
+        let addr = agent(false, {
+            let hung = hung.clone();
+
+            move |n| {
+                hung.store(true, Relaxed);
+
+                if n == 0 {
+                    Script::Hang
+                } else {
+                    Script::Serve
+                }
+            }
+        })
+        .await;
+        let pool = Pool::builder(Connector::new(addr))
+            .max_connections(1)
+            .build();
+
+        // the checkout is dropped while connecting, its reservation is released
+        let far = Instant::now() + Duration::from_secs(60);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), pool.checkout(far))
+                .await
+                .is_err()
+        );
+        assert!(hung.load(Relaxed));
+
+        pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_connect_wakes_waiters() {
+        let addr = agent(false, |n| {
+            if n == 0 {
+                Script::Close(Duration::from_millis(50))
+            } else {
+                Script::Serve
+            }
+        })
+        .await;
+        let pool = Pool::builder(Connector::new(addr))
+            .max_connections(1)
+            .build();
+
+        let failing = tokio::spawn({
+            let pool = pool.clone();
+
+            async move {
+                pool.checkout_timeout(Duration::from_secs(5))
+                    .await
+                    .map(|_| ())
+            }
+        });
+        sleep(Duration::from_millis(10)).await;
+
+        // the waiter connects once the first connection failed, long before its deadline
+        let started = Instant::now();
+        pool.checkout_timeout(Duration::from_secs(5)).await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(failing.await.unwrap().is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        let window = Window::new(16, true);
+        assert_eq!(window.get(), INITIAL_WINDOW);
+
+        // grown while the ACK frames are fast
+        for _ in 0..20 {
+            window.acked(Duration::from_millis(10));
+        }
+        assert_eq!(window.get(), 16);
+        window.acked(Duration::from_millis(20));
+        assert_eq!(window.get(), 16);
+
+        // halved once the turnaround inflates
+        window.acked(Duration::from_millis(50));
+        assert_eq!(window.get(), 8);
+        window.shrink();
+        window.shrink();
+        window.shrink();
+        window.shrink();
+        assert_eq!(window.get(), 1);
+
+        let pinned = Window::new(16, false);
+        pinned.acked(Duration::from_millis(50));
+        pinned.shrink();
+        assert_eq!(pinned.get(), 16);
+    }
+}
@@ -0,0 +1,78 @@
+//! Connect to the agents over QUIC, experimental.
+//!
+//! Every SPOP connection is mapped to a bidirectional stream of a QUIC connection
+//! shared by the connector, keeping the same frame codec as over TCP.
+
+use std::io;
+use std::sync::Arc;
+
+use tokio::{io::join, net::lookup_host, sync::Mutex, time::timeout};
+use tracing::{debug, instrument};
+
+use crate::{
+    error::{Error, Result},
+    Connection, Connector,
+};
+
+/// Connect to an agent over QUIC and complete the handshake.
+#[derive(Clone, Debug)]
+pub struct QuicConnector {
+    connector: Connector,
+    endpoint: quinn::Endpoint,
+    server_name: String,
+    conn: Arc<Mutex<Option<quinn::Connection>>>,
+}
+
+impl Connector {
+    /// Connect over QUIC with the client endpoint, verifying the certificate of the server name.
+    pub fn quic<S: Into<String>>(self, endpoint: quinn::Endpoint, server_name: S) -> QuicConnector {
+        QuicConnector {
+            connector: self,
+            endpoint,
+            server_name: server_name.into(),
+            conn: Arc::default(),
+        }
+    }
+}
+
+impl QuicConnector {
+    /// Open a bidirectional stream to the agent and complete the handshake.
+    ///
+    /// The QUIC connection is established on the first call, and re-established once closed.
+    #[instrument(skip(self), fields(addr = %self.connector.addr()), err, level = "debug")]
+    pub async fn connect(&self) -> Result<Connection> {
+        timeout(self.connector.connect_timeout, async {
+            let conn = self.quic_connection().await?;
+            let (send, recv) = conn.open_bi().await.map_err(io::Error::other)?;
+
+            Connection::handshake(join(recv, send), self.connector.hello.clone())
+                .await
+                .map(|conn| self.connector.with_ids(conn))
+        })
+        .await
+        .map_err(|_| Error::Timeout)?
+    }
+
+    async fn quic_connection(&self) -> Result<quinn::Connection> {
+        let mut conn = self.conn.lock().await;
+
+        if let Some(conn) = conn.as_ref().filter(|conn| conn.close_reason().is_none()) {
+            return Ok(conn.clone());
+        }
+
+        let addr = lookup_host(self.connector.addr())
+            .await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "agent address not resolved"))?;
+        let connected = self
+            .endpoint
+            .connect(addr, &self.server_name)
+            .map_err(io::Error::other)?
+            .await
+            .map_err(io::Error::other)?;
+
+        debug!(%addr, "QUIC connection established");
+
+        Ok(conn.insert(connected).clone())
+    }
+}
@@ -0,0 +1,32 @@
+//! Decode a captured SPOP frame, without any async runtime.
+//!
+//! The codec builds without the default `tokio` feature, so this example also runs on `wasm32-wasip1`:
+//!
+//! ```text
+//! cargo build -p haproxy-spop --no-default-features --example decode --target wasm32-wasip1
+//! wasmtime target/wasm32-wasip1/debug/examples/decode.wasm 00000020030000000101010f...
+//! ```
+
+use std::env;
+
+use bytes::Buf;
+use haproxy_spop::BufExt as _;
+
+/// A NOTIFY frame captured from HAProxy, with its length prefix.
+const CAPTURED: &str = "00000020030000000101010f636865636b2d636c69656e742d69700102697006c0a80001";
+
+fn main() {
+    let hex = env::args().nth(1).unwrap_or_else(|| CAPTURED.to_string());
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).expect("hex string"))
+        .collect::<Vec<_>>();
+
+    let mut buf = &bytes[..];
+    let len = buf.get_u32() as usize;
+    assert_eq!(len, buf.remaining(), "truncated frame");
+
+    let frame = buf.get_frame().expect("SPOP frame");
+
+    println!("{frame:#?}");
+}
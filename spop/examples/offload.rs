@@ -0,0 +1,61 @@
+//! Measure the crossover frame size where decoding on the blocking thread pool pays off.
+//!
+//! The handoff to and from the pool has a fixed cost, the frames are worth offloading
+//! once decoding them inline takes longer than that, e.g. with large headers or bodies.
+//!
+//! ```sh
+//! cargo run --release -p haproxy-spop --example offload
+//! ```
+
+use std::time::{Duration, Instant};
+
+use haproxy_spop::{Frame, Framer, Message, WireSize as _, MAX_FRAME_SIZE};
+
+const ROUNDS: u32 = 200;
+const MAX_SIZE: usize = 2 << 20;
+
+#[tokio::main]
+async fn main() {
+    let mut crossover = None;
+
+    for shift in 8..=20 {
+        let size = 1usize << shift;
+        let frame = Frame::notify(1, 1, [Message::new("body", [("data", "x".repeat(size))])]);
+        let mut buf = vec![];
+        Framer::new(MAX_SIZE)
+            .write_frame(&mut buf, frame.clone())
+            .await
+            .unwrap();
+
+        let inline = measure(&buf, None).await;
+        let offloaded = measure(&buf, Some(0)).await;
+        let handoff = offloaded.saturating_sub(inline);
+
+        println!(
+            "{:>8} bytes: inline {inline:>10?}, offloaded {offloaded:>10?}, handoff {handoff:>10?}",
+            frame.size()
+        );
+
+        // the worker is blocked longer by decoding inline than by handing the frame off
+        if crossover.is_none() && inline >= handoff {
+            crossover = Some(frame.size());
+        }
+    }
+
+    match crossover {
+        Some(size) => println!("offloading pays off from about {size} bytes"),
+        None => println!("offloading never pays off, up to {MAX_FRAME_SIZE} bytes"),
+    }
+}
+
+async fn measure(buf: &[u8], offload_size: Option<usize>) -> Duration {
+    let mut framer = Framer::new(MAX_SIZE);
+    framer.set_offload_size(offload_size);
+
+    let started = Instant::now();
+    for _ in 0..ROUNDS {
+        framer.read_frame(buf).await.unwrap();
+    }
+
+    started.elapsed() / ROUNDS
+}
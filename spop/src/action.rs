@@ -13,7 +13,9 @@ pub enum Type {
 
 /// The variable scope
 #[repr(u8)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, TryFromPrimitive, IntoPrimitive,
+)]
 pub enum Scope {
     Process,
     Session,
@@ -67,4 +69,18 @@ impl Action {
             name: name.into(),
         }
     }
+
+    /// Returns the scope of the variable.
+    pub fn scope(&self) -> Scope {
+        match self {
+            Self::SetVar { scope, .. } | Self::UnsetVar { scope, .. } => *scope,
+        }
+    }
+
+    /// Returns the name of the variable.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::SetVar { name, .. } | Self::UnsetVar { name, .. } => name,
+        }
+    }
 }
@@ -1,5 +1,4 @@
 use std::convert::TryFrom;
-use std::iter;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 use bytes::{Buf, BufMut, Bytes};
@@ -17,9 +16,6 @@ pub trait BufExt {
 
     /// Get a string.
     fn string(&mut self) -> Option<String>;
-
-    /// Get key-value list.
-    fn kv_list(&mut self) -> impl Iterator<Item = (String, Typed)>;
 }
 
 impl<T> BufExt for T
@@ -39,19 +35,6 @@ where
         let b = get_bytes(self, sz as usize)?;
         String::from_utf8(b.to_vec()).ok()
     }
-
-    fn kv_list(&mut self) -> impl Iterator<Item = (String, Typed)> {
-        iter::from_fn(move || {
-            if self.has_remaining() {
-                let name = self.string()?;
-                let value = self.typed()?;
-
-                Some((name, value))
-            } else {
-                None
-            }
-        })
-    }
 }
 
 fn get_bytes<T: Buf>(mut buf: T, n: usize) -> Option<Bytes> {
@@ -64,8 +47,14 @@ fn typed_data<B: Buf>(mut buf: B) -> Option<Typed> {
     match ty {
         Type::Null => Some(Typed::Null),
         Type::Boolean => Some(Typed::Boolean(flags.contains(Flags::TRUE))),
-        Type::Int32 => buf.varint().map(|n| n as i32).map(Typed::Int32),
-        Type::Uint32 => buf.varint().map(|n| n as u32).map(Typed::Uint32),
+        Type::Int32 => buf
+            .varint()
+            .and_then(|n| i32::try_from(n as i64).ok())
+            .map(Typed::Int32),
+        Type::Uint32 => buf
+            .varint()
+            .and_then(|n| u32::try_from(n).ok())
+            .map(Typed::Uint32),
         Type::Int64 => buf.varint().map(|n| n as i64).map(Typed::Int64),
         Type::Uint64 => buf.varint().map(Typed::Uint64),
         Type::Ipv4 => get_bytes(buf, Typed::IPV4_ADDR_LEN)
@@ -8,5 +8,5 @@ pub mod varint;
 
 pub use self::buf::{BufExt, BufMutExt};
 pub use self::ty::{Flags, Type};
-pub use self::typed::Typed;
+pub use self::typed::{OutOfRange, Typed};
 pub use self::value::KeyValue;
@@ -1,7 +1,10 @@
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+use std::time::Duration;
 
 use bytes::{Bytes, BytesMut};
 use derive_more::{From, TryInto};
+use thiserror::Error;
 
 /// Typed data
 ///
@@ -93,9 +96,166 @@ impl From<IpAddr> for Typed {
     }
 }
 
+macro_rules! from_lossless {
+    ($($ty:ty => $variant:ident),* $(,)?) => {
+        $(
+            impl From<$ty> for Typed {
+                fn from(n: $ty) -> Self {
+                    Typed::$variant(n.into())
+                }
+            }
+        )*
+    };
+}
+
+from_lossless! {
+    i8 => Int32,
+    i16 => Int32,
+    u8 => Uint32,
+    u16 => Uint32,
+}
+
+/// The `usize` values saturate at `u64::MAX`.
+impl From<usize> for Typed {
+    fn from(n: usize) -> Self {
+        Typed::Uint64(u64::try_from(n).unwrap_or(u64::MAX))
+    }
+}
+
+/// The number is out of the range of the typed data.
+#[derive(Clone, Debug, PartialEq, Error)]
+#[error("{value} is out of range {}..={}", range.start(), range.end())]
+pub struct OutOfRange {
+    /// The number converted.
+    pub value: f64,
+    /// The accepted range.
+    pub range: RangeInclusive<f64>,
+}
+
 impl Typed {
     pub(crate) const IPV4_ADDR_LEN: usize = 4;
     pub(crate) const IPV6_ADDR_LEN: usize = 16;
 
     pub const TYPE_SIZE: usize = 1;
+
+    /// The range of the percentages.
+    pub const PERCENT: RangeInclusive<f64> = 0.0..=100.0;
+
+    /// Returns the percentage rounded to an integer, since HAProxy has no floating-point type.
+    pub fn percent(value: f64) -> Result<Self, OutOfRange> {
+        if Self::PERCENT.contains(&value) {
+            Ok(Typed::Uint32(value.round() as u32))
+        } else {
+            Err(OutOfRange {
+                value,
+                range: Self::PERCENT,
+            })
+        }
+    }
+
+    /// Returns the decimal as a fixed-point integer with the number of decimal places,
+    /// e.g. `0.125` with 3 places is `125`, to be divided back with the `div` converter.
+    pub fn decimal(value: f64, places: u8) -> Result<Self, OutOfRange> {
+        let scale = 10f64.powi(places.into());
+        let range = i64::MIN as f64 / scale..=i64::MAX as f64 / scale;
+
+        if range.contains(&value) {
+            Ok(Typed::Int64((value * scale).round() as i64))
+        } else {
+            Err(OutOfRange { value, range })
+        }
+    }
+
+    /// Returns the duration in milliseconds, saturating at `u64::MAX`.
+    pub fn millis(d: Duration) -> Self {
+        Typed::Uint64(u64::try_from(d.as_millis()).unwrap_or(u64::MAX))
+    }
+
+    /// Returns the integer as `i64`, `None` if not an integer or out of range.
+    pub fn to_i64(&self) -> Option<i64> {
+        match *self {
+            Typed::Int32(n) => Some(n.into()),
+            Typed::Uint32(n) => Some(n.into()),
+            Typed::Int64(n) => Some(n),
+            Typed::Uint64(n) => n.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer as `u64`, `None` if not an integer or negative.
+    pub fn to_u64(&self) -> Option<u64> {
+        match *self {
+            Typed::Int32(n) => n.try_into().ok(),
+            Typed::Uint32(n) => Some(n.into()),
+            Typed::Int64(n) => n.try_into().ok(),
+            Typed::Uint64(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the integer as `u32`, `None` if not an integer or out of range.
+    pub fn to_u32(&self) -> Option<u32> {
+        self.to_u64().and_then(|n| n.try_into().ok())
+    }
+
+    /// Returns the IP address, `None` if not an IPv4 or IPv6 address.
+    ///
+    /// The IPv4-mapped IPv6 addresses, e.g. `::ffff:10.0.0.1` of the dual-stack listeners,
+    /// are returned as IPv4 addresses.
+    pub fn to_ip_addr(&self) -> Option<IpAddr> {
+        match *self {
+            Typed::Ipv4(addr) => Some(IpAddr::V4(addr)),
+            Typed::Ipv6(addr) => Some(addr.to_ipv4_mapped().map_or(IpAddr::V6(addr), IpAddr::V4)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numeric() {
+        assert_eq!(Typed::percent(42.4), Ok(Typed::Uint32(42)));
+        assert_eq!(Typed::percent(100.0), Ok(Typed::Uint32(100)));
+        assert_eq!(
+            Typed::percent(-1.0).unwrap_err().to_string(),
+            "-1 is out of range 0..=100"
+        );
+        assert!(Typed::percent(f64::NAN).is_err());
+
+        assert_eq!(Typed::decimal(0.125, 3), Ok(Typed::Int64(125)));
+        assert_eq!(Typed::decimal(-1.5, 1), Ok(Typed::Int64(-15)));
+        assert!(Typed::decimal(1e300, 2).is_err());
+
+        assert_eq!(Typed::millis(Duration::from_micros(1500)), Typed::Uint64(1));
+        assert_eq!(Typed::millis(Duration::MAX), Typed::Uint64(u64::MAX));
+
+        assert_eq!(Typed::from(8u8), Typed::Uint32(8));
+        assert_eq!(Typed::from(-8i16), Typed::Int32(-8));
+        assert_eq!(Typed::from(8usize), Typed::Uint64(8));
+
+        assert_eq!(Typed::Int32(-1).to_u64(), None);
+        assert_eq!(Typed::Uint64(u64::MAX).to_i64(), None);
+        assert_eq!(Typed::Uint64(1 << 32).to_u32(), None);
+        assert_eq!(Typed::Int64(42).to_u32(), Some(42));
+        assert_eq!(Typed::from("42").to_i64(), None);
+    }
+
+    #[test]
+    fn test_ip_addr() {
+        let v4 = Ipv4Addr::new(10, 0, 0, 1);
+
+        assert_eq!(Typed::Ipv4(v4).to_ip_addr(), Some(IpAddr::V4(v4)));
+        assert_eq!(
+            Typed::Ipv6(v4.to_ipv6_mapped()).to_ip_addr(),
+            Some(IpAddr::V4(v4))
+        );
+        assert_eq!(
+            Typed::Ipv6(Ipv6Addr::LOCALHOST).to_ip_addr(),
+            Some(IpAddr::V6(Ipv6Addr::LOCALHOST))
+        );
+        assert_eq!(Typed::from("10.0.0.1").to_ip_addr(), None);
+    }
 }
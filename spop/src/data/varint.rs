@@ -99,7 +99,7 @@ mod tests {
             &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
         ),
         (
-            0x01020_4081_0204_08ef,
+            0x1020_4081_0204_08ef,
             &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f],
         ),
         (
@@ -0,0 +1,360 @@
+//! Deserialize the message arguments into user structs with serde.
+//!
+//! The arguments are mapped to the struct fields by name,
+//! and the values are coerced from the [`Typed`] data:
+//!
+//! - the integers are converted to any integer type in range,
+//! - the strings are parsed for the booleans, the integers and the floats,
+//! - the IP addresses are deserialized as `IpAddr`, `Ipv4Addr` or `Ipv6Addr`,
+//! - the null values are deserialized as `None` or `()`,
+//! - the binary values are deserialized as bytes or a sequence of `u8`, e.g. `Vec<u8>`.
+//!
+//! The missing and mistyped arguments are reported with their names in [`ArgsError::field`].
+//!
+//! ```
+//! use std::net::IpAddr;
+//!
+//! use haproxy_spop::Message;
+//! use serde::Deserialize;
+//!
+//! #[derive(Deserialize)]
+//! struct CheckClientIp {
+//!     ip: IpAddr,
+//!     port: u16,
+//!     tenant: Option<String>,
+//! }
+//!
+//! let msg = Message::builder("check-client-ip")
+//!     .arg("ip", "127.0.0.1".parse::<IpAddr>().unwrap())
+//!     .arg("port", 8080)
+//!     .build();
+//! let args = msg.parse_args::<CheckClientIp>().unwrap();
+//!
+//! assert_eq!(args.port, 8080);
+//! assert_eq!(args.tenant, None);
+//! ```
+
+use std::fmt;
+
+use serde::de::{
+    self,
+    value::{SeqDeserializer, StrDeserializer},
+    DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor,
+};
+
+use crate::{Message, Typed};
+
+/// The error of deserializing the message arguments.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArgsError {
+    /// The name of the argument failed to deserialize.
+    pub field: Option<String>,
+    /// The reason of the failure.
+    pub message: String,
+}
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(ref field) = self.field {
+            write!(f, "invalid argument `{}`: {}", field, self.message)
+        } else {
+            f.write_str(&self.message)
+        }
+    }
+}
+
+impl std::error::Error for ArgsError {}
+
+impl de::Error for ArgsError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ArgsError {
+            field: None,
+            message: msg.to_string(),
+        }
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        ArgsError {
+            field: Some(field.to_string()),
+            message: "missing".to_string(),
+        }
+    }
+}
+
+impl Message {
+    /// Deserialize the arguments into the struct, mapping the argument names to its fields.
+    pub fn parse_args<T: DeserializeOwned>(&self) -> Result<T, ArgsError> {
+        T::deserialize(ArgsDeserializer::new(&self.args))
+    }
+}
+
+/// A deserializer of the message arguments as a map.
+#[derive(Clone, Copy, Debug)]
+pub struct ArgsDeserializer<'a> {
+    args: &'a [(String, Typed)],
+}
+
+impl<'a> ArgsDeserializer<'a> {
+    pub fn new(args: &'a [(String, Typed)]) -> Self {
+        ArgsDeserializer { args }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ArgsDeserializer<'de> {
+    type Error = ArgsError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(Args {
+            iter: self.args.iter(),
+            value: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct Args<'a> {
+    iter: std::slice::Iter<'a, (String, Typed)>,
+    value: Option<(&'a str, &'a Typed)>,
+}
+
+impl<'de> MapAccess<'de> for Args<'de> {
+    type Error = ArgsError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((name, value)) = self.iter.next() else {
+            return Ok(None);
+        };
+
+        self.value = Some((name, value));
+
+        let key: StrDeserializer<'_, ArgsError> = name.as_str().into_deserializer();
+
+        seed.deserialize(key).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let (name, value) = self
+            .value
+            .take()
+            .ok_or_else(|| de::Error::custom("value is missing"))?;
+
+        seed.deserialize(TypedDeserializer(value))
+            .map_err(|err| ArgsError {
+                field: Some(name.to_string()),
+                ..err
+            })
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+/// A deserializer of the [`Typed`] data with type coercion.
+#[derive(Clone, Copy, Debug)]
+pub struct TypedDeserializer<'a>(pub &'a Typed);
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident),* $(,)?) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match self.0 {
+                    Typed::String(s) => match s.trim().parse() {
+                        Ok(v) => visitor.$visit(v),
+                        Err(err) => Err(de::Error::custom(format_args!("{err}, {s:?}"))),
+                    },
+                    _ => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for TypedDeserializer<'de> {
+    type Error = ArgsError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Typed::Null => visitor.visit_unit(),
+            Typed::Boolean(b) => visitor.visit_bool(*b),
+            Typed::Int32(n) => visitor.visit_i32(*n),
+            Typed::Uint32(n) => visitor.visit_u32(*n),
+            Typed::Int64(n) => visitor.visit_i64(*n),
+            Typed::Uint64(n) => visitor.visit_u64(*n),
+            Typed::Ipv4(addr) => visitor.visit_string(addr.to_string()),
+            Typed::Ipv6(addr) => visitor.visit_string(addr.to_string()),
+            Typed::String(s) => visitor.visit_borrowed_str(s),
+            Typed::Binary(b) => visitor.visit_borrowed_bytes(b),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Typed::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Typed::Binary(b) => visitor.visit_seq(SeqDeserializer::new(b.iter().copied())),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Typed::String(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct CheckClientIp {
+        ip: IpAddr,
+        port: u16,
+        secure: bool,
+        tenant: Option<String>,
+        score: Option<u8>,
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let msg = Message::builder("check-client-ip")
+            .arg("ip", Ipv4Addr::LOCALHOST)
+            .arg("port", 8080u32)
+            .arg("secure", "true")
+            .arg("score", ())
+            .arg("unknown", 1)
+            .build();
+
+        assert_eq!(
+            msg.parse_args::<CheckClientIp>(),
+            Ok(CheckClientIp {
+                ip: Ipv4Addr::LOCALHOST.into(),
+                port: 8080,
+                secure: true,
+                tenant: None,
+                score: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        let msg = Message::builder("check-client-ip")
+            .arg("ip", Ipv4Addr::LOCALHOST)
+            .arg("port", 65536u32)
+            .arg("secure", false)
+            .build();
+
+        let err = msg.parse_args::<CheckClientIp>().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("port"));
+
+        let msg = Message::builder("check-client-ip")
+            .arg("ip", "localhost")
+            .arg("port", "80")
+            .arg("secure", false)
+            .build();
+
+        let err = msg.parse_args::<CheckClientIp>().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("ip"));
+
+        let msg = Message::builder("check-client-ip")
+            .arg("ip", Ipv4Addr::LOCALHOST)
+            .arg("secure", false)
+            .build();
+
+        let err = msg.parse_args::<CheckClientIp>().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("port"));
+        assert_eq!(err.to_string(), "invalid argument `port`: missing");
+    }
+
+    #[test]
+    fn test_parse_binary_args() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Mirror {
+            #[serde(rename = "arg_method")]
+            method: String,
+            #[serde(rename = "arg_hdrs")]
+            hdrs: Vec<u8>,
+            #[serde(rename = "arg_body")]
+            body: Option<Vec<u8>>,
+        }
+
+        let msg = Message::builder("mirror")
+            .arg("arg_method", "GET")
+            .arg("arg_hdrs", &b"\x04host"[..])
+            .arg("arg_body", ())
+            .build();
+
+        assert_eq!(
+            msg.parse_args::<Mirror>(),
+            Ok(Mirror {
+                method: "GET".to_string(),
+                hdrs: b"\x04host".to_vec(),
+                body: None,
+            })
+        );
+
+        let msg = Message::builder("mirror")
+            .arg("arg_method", "GET")
+            .arg("arg_hdrs", 1)
+            .build();
+
+        let err = msg.parse_args::<Mirror>().unwrap_err();
+        assert_eq!(err.field.as_deref(), Some("arg_hdrs"));
+    }
+}
@@ -0,0 +1,207 @@
+//! Runtime assertions of the protocol state diagram, enabled by the `debug-protocol` feature.
+//!
+//! Every frame sent or received on a connection is validated against the state of the
+//! connection, in both the agent and the client roles, a violation panics in debug builds
+//! (e.g. in the tests) and is logged loudly in release builds.
+
+use std::collections::HashSet;
+
+use thiserror::Error;
+use tracing::error;
+
+use crate::{
+    frame::{FrameId, StreamId, Type},
+    AgentAck, Frame, HaproxyNotify,
+};
+
+/// The violations of the protocol state diagram.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+pub enum Violation {
+    /// A frame other than HAPROXY-HELLO before the handshake.
+    #[error("{0:?} frame before HAPROXY-HELLO")]
+    BeforeHello(Type),
+    /// A HELLO frame after the handshake.
+    #[error("duplicate {0:?} frame")]
+    DuplicateHello(Type),
+    /// A NOTIFY frame before the AGENT-HELLO frame.
+    #[error("NOTIFY frame before AGENT-HELLO")]
+    NotifyBeforeHello,
+    /// An ACK frame not following a known NOTIFY frame.
+    #[error("ACK frame for unknown NOTIFY frame, stream_id={stream_id}, frame_id={frame_id}")]
+    UnknownAck {
+        stream_id: StreamId,
+        frame_id: FrameId,
+    },
+    /// A frame other than DISCONNECT after the DISCONNECT frame.
+    #[error("{0:?} frame after DISCONNECT")]
+    AfterDisconnect(Type),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Phase {
+    #[default]
+    Connecting,
+    Handshaking,
+    Processing,
+    Disconnecting,
+}
+
+/// Validate the frames of a connection against the protocol state diagram.
+///
+/// The frames are observed in the order they are sent or received,
+/// whatever the side of the connection, so the same monitor serves both roles.
+#[derive(Clone, Debug, Default)]
+pub struct Monitor {
+    phase: Phase,
+    notified: HashSet<(StreamId, FrameId)>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Monitor::default()
+    }
+
+    /// Observe a frame, panics in debug builds or logs in release builds on a violation.
+    pub fn observe(&mut self, frame: &Frame) {
+        if let Err(violation) = self.check(frame) {
+            if cfg!(debug_assertions) {
+                panic!("SPOP violation: {violation}");
+            } else {
+                error!(%violation, "SPOP violation");
+            }
+        }
+    }
+
+    /// Check a frame against the state of the connection, and advance the state.
+    pub fn check(&mut self, frame: &Frame) -> Result<(), Violation> {
+        let ty = frame.frame_type();
+
+        match (self.phase, frame) {
+            (_, Frame::HaproxyDisconnect(_) | Frame::AgentDisconnect(_)) => {
+                self.phase = Phase::Disconnecting;
+                self.notified.clear();
+
+                Ok(())
+            }
+            (Phase::Disconnecting, _) => Err(Violation::AfterDisconnect(ty)),
+            (Phase::Connecting, Frame::HaproxyHello(_)) => {
+                self.phase = Phase::Handshaking;
+
+                Ok(())
+            }
+            (Phase::Connecting, _) => Err(Violation::BeforeHello(ty)),
+            (Phase::Handshaking, Frame::AgentHello(_)) => {
+                self.phase = Phase::Processing;
+
+                Ok(())
+            }
+            (Phase::Handshaking, Frame::HaproxyNotify(_)) => Err(Violation::NotifyBeforeHello),
+            (_, Frame::HaproxyHello(_) | Frame::AgentHello(_)) => {
+                Err(Violation::DuplicateHello(ty))
+            }
+            (
+                Phase::Processing,
+                Frame::HaproxyNotify(HaproxyNotify {
+                    stream_id,
+                    frame_id,
+                    ..
+                }),
+            ) => {
+                self.notified.insert((*stream_id, *frame_id));
+
+                Ok(())
+            }
+            (
+                Phase::Processing,
+                Frame::AgentAck(AgentAck {
+                    fragmented,
+                    stream_id,
+                    frame_id,
+                    ..
+                }),
+            ) => {
+                let key = (*stream_id, *frame_id);
+
+                if !self.notified.contains(&key) {
+                    Err(Violation::UnknownAck {
+                        stream_id: *stream_id,
+                        frame_id: *frame_id,
+                    })
+                } else {
+                    if !fragmented {
+                        self.notified.remove(&key);
+                    }
+
+                    Ok(())
+                }
+            }
+            (_, _) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Action, AgentHello, Error, HaproxyHello, Message, Version};
+
+    use super::*;
+
+    fn hello() -> [Frame; 2] {
+        [
+            Frame::HaproxyHello(HaproxyHello {
+                supported_versions: vec![Version::V2_0],
+                max_frame_size: 16380,
+                capabilities: vec![],
+                healthcheck: None,
+                engine_id: None,
+            }),
+            Frame::AgentHello(AgentHello {
+                version: Version::V2_0,
+                max_frame_size: 16380,
+                capabilities: vec![],
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_monitor() {
+        let mut m = Monitor::new();
+
+        assert_eq!(
+            m.check(&Frame::notify(1, 1, Vec::<Message>::new())),
+            Err(Violation::BeforeHello(Type::HaproxyNotify))
+        );
+
+        let [haproxy_hello, agent_hello] = hello();
+        assert_eq!(m.check(&haproxy_hello), Ok(()));
+        assert_eq!(
+            m.check(&Frame::notify(1, 1, Vec::<Message>::new())),
+            Err(Violation::NotifyBeforeHello)
+        );
+        assert_eq!(m.check(&agent_hello), Ok(()));
+        assert_eq!(
+            m.check(&agent_hello),
+            Err(Violation::DuplicateHello(Type::AgentHello))
+        );
+
+        assert_eq!(m.check(&Frame::notify(1, 1, Vec::<Message>::new())), Ok(()));
+        assert_eq!(m.check(&Frame::ack(1, 1, Vec::<Action>::new())), Ok(()));
+        assert_eq!(
+            m.check(&Frame::ack(1, 1, Vec::<Action>::new())),
+            Err(Violation::UnknownAck {
+                stream_id: 1,
+                frame_id: 1
+            })
+        );
+
+        assert_eq!(m.check(&Frame::agent_disconnect(Error::Normal, "")), Ok(()));
+        assert_eq!(
+            m.check(&Frame::notify(1, 2, Vec::<Message>::new())),
+            Err(Violation::AfterDisconnect(Type::HaproxyNotify))
+        );
+        assert_eq!(
+            m.check(&Frame::haproxy_disconnect(Error::Normal, "")),
+            Ok(())
+        );
+    }
+}
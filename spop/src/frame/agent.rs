@@ -40,9 +40,18 @@ impl Ack {
         }
     }
 
+    /// Abort the processing of the frame, the ACK frame has no action and terminates the stream.
+    pub fn abort(stream_id: StreamId, frame_id: FrameId) -> Self {
+        Ack {
+            aborted: true,
+            ..Ack::new(stream_id, frame_id)
+        }
+    }
+
+    /// Returns the metadata of the frame, an aborted frame is always the last fragment.
     pub fn metadata(&self) -> Metadata {
         Metadata {
-            flags: if self.fragmented {
+            flags: if self.fragmented && !self.aborted {
                 Flags::empty()
             } else {
                 Flags::FIN
@@ -1,9 +1,12 @@
-use tokio::io::{AsyncRead, AsyncWrite, BufReader};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncBufReadExt as _, AsyncRead, AsyncWrite, BufReader};
 use tracing::instrument;
 
 use crate::{
-    error::Result,
-    frame::{Frame, Framer},
+    error::{Error::Io, Result},
+    frame::{Frame, FrameTiming, Framer},
 };
 
 pub type BufCodec<T> = Codec<BufReader<T>>;
@@ -18,6 +21,18 @@ where
             framer,
         }
     }
+
+    /// Read a frame, with the time it was received and decoded,
+    /// or `None` when the peer shut down its side of the stream between two frames.
+    ///
+    /// The end of the stream in the middle of a frame is still an I/O error.
+    pub async fn next_timed_frame(&mut self) -> Result<Option<(Frame, FrameTiming)>> {
+        if self.stream.fill_buf().await.map_err(|_| Io)?.is_empty() {
+            return Ok(None);
+        }
+
+        self.read_timed_frame().await.map(Some)
+    }
 }
 
 #[derive(Debug)]
@@ -39,8 +54,75 @@ where
         self.framer.read_frame(&mut self.stream).await
     }
 
+    /// Read a frame, with the time it was received and decoded.
+    pub async fn read_timed_frame(&mut self) -> Result<(Frame, FrameTiming)> {
+        self.framer.read_timed_frame(&mut self.stream).await
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Reading from the stream directly is only safe before the first frame, e.g. for a preamble.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Returns the framer handling the length prefix of the frames.
+    pub fn framer(&self) -> &Framer {
+        &self.framer
+    }
+
+    /// Returns a mutable reference to the framer handling the length prefix of the frames.
+    pub fn framer_mut(&mut self) -> &mut Framer {
+        &mut self.framer
+    }
+
+    /// Write the frame started by [`Framer::start_write`] on the underlying stream.
+    pub fn poll_write_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<usize>> {
+        self.framer.poll_write_frame(cx, Pin::new(&mut self.stream))
+    }
+
+    /// Encode the frame to be written by [`Codec::poll_write_frame`].
+    pub fn start_write(&mut self, frame: Frame) -> Result<()> {
+        self.framer.start_write(frame)
+    }
+
     #[instrument(skip(self), err, level = "trace")]
     pub async fn write_frame(&mut self, frame: Frame) -> Result<usize> {
         self.framer.write_frame(&mut self.stream, frame).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncWriteExt as _};
+
+    use crate::{Message, MAX_FRAME_SIZE};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_half_close() {
+        let f = Frame::notify(1, 2, [Message::new("foo", [("bar", 123)])]);
+
+        let (mut w, r) = duplex(MAX_FRAME_SIZE);
+        let mut codec = Codec::buffered(r, Framer::new(MAX_FRAME_SIZE));
+        let mut framer = Framer::new(MAX_FRAME_SIZE);
+
+        framer.write_frame(&mut w, f.clone()).await.unwrap();
+        w.shutdown().await.unwrap();
+
+        // the frame written before the shutdown is still read
+        let (frame, _) = codec.next_timed_frame().await.unwrap().unwrap();
+        assert_eq!(frame, f);
+        assert_eq!(codec.next_timed_frame().await, Ok(None));
+
+        // the shutdown in the middle of a frame is an error
+        let (mut w, r) = duplex(MAX_FRAME_SIZE);
+        let mut codec = Codec::buffered(r, Framer::new(MAX_FRAME_SIZE));
+
+        w.write_all(&[0, 0, 0, 8, 3]).await.unwrap();
+        w.shutdown().await.unwrap();
+
+        assert_eq!(codec.next_timed_frame().await, Err(Io));
+    }
+}
@@ -14,8 +14,42 @@ use crate::{
     Action, Capability, Typed, Version,
 };
 
+/// The max number of messages decoded from a NOTIFY frame, by default.
+pub const MAX_MESSAGES: usize = 1024;
+
+/// The max number of actions decoded from an ACK frame, by default.
+pub const MAX_ACTIONS: usize = 1024;
+
+/// The caps on the number of items decoded from a frame.
+///
+/// The counts are announced or implied by the peer, so a crafted frame could otherwise
+/// make the decoder allocate far more than the frame size. The frames over the caps fail
+/// with [`TooBig`](crate::Error::TooBig).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// The max number of messages of a NOTIFY frame.
+    pub max_messages: usize,
+    /// The max number of arguments of a message.
+    pub max_args: usize,
+    /// The max number of actions of an ACK frame.
+    pub max_actions: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_messages: MAX_MESSAGES,
+            max_args: u8::MAX as usize,
+            max_actions: MAX_ACTIONS,
+        }
+    }
+}
+
 pub trait BufExt {
     fn get_frame(&mut self) -> Result<Frame>;
+
+    /// Parse a frame, enforcing the caps on the number of decoded items.
+    fn get_frame_with(&mut self, limits: &Limits) -> Result<Frame>;
 }
 
 impl<T> BufExt for T
@@ -25,10 +59,19 @@ where
     fn get_frame(&mut self) -> Result<Frame> {
         frame(self)
     }
+
+    fn get_frame_with(&mut self, limits: &Limits) -> Result<Frame> {
+        frame_with(self, limits)
+    }
 }
 
-/// Parse a frame from the buffer.
-pub fn frame<B: Buf>(mut buf: B) -> Result<Frame> {
+/// Parse a frame from the buffer, with the default [`Limits`].
+pub fn frame<B: Buf>(buf: B) -> Result<Frame> {
+    frame_with(buf, &Limits::default())
+}
+
+/// Parse a frame from the buffer, enforcing the caps on the number of decoded items.
+pub fn frame_with<B: Buf>(mut buf: B, limits: &Limits) -> Result<Frame> {
     let (ty, md) = frame_type(&mut buf)
         .zip(metadata(&mut buf))
         .ok_or(Invalid)?;
@@ -41,9 +84,11 @@ pub fn frame<B: Buf>(mut buf: B) -> Result<Frame> {
             agent_hello(&mut buf).map(Frame::AgentHello)
         }
         frame::Type::HaproxyNotify if md.frame_id != 0 => {
-            haproxy_notify(&mut buf, md).map(Frame::HaproxyNotify)
+            haproxy_notify(&mut buf, md, limits).map(Frame::HaproxyNotify)
+        }
+        frame::Type::AgentAck if md.frame_id != 0 => {
+            agent_ack(&mut buf, md, limits).map(Frame::AgentAck)
         }
-        frame::Type::AgentAck if md.frame_id != 0 => agent_ack(&mut buf, md).map(Frame::AgentAck),
         frame::Type::HaproxyDisconnect if md.stream_id == 0 && md.frame_id == 0 => {
             disconnect(&mut buf).map(Frame::HaproxyDisconnect)
         }
@@ -72,8 +117,8 @@ fn metadata<B: Buf>(mut buf: B) -> Option<frame::Metadata> {
     })
 }
 
-fn haproxy_hello<B: Buf>(mut buf: B) -> Result<haproxy::Hello> {
-    let mut kv = buf.kv_list().collect::<KVList>();
+fn haproxy_hello<B: Buf>(buf: B) -> Result<haproxy::Hello> {
+    let mut kv = kv_list(buf)?;
 
     Ok(haproxy::Hello {
         supported_versions: kv.supported_versions()?,
@@ -84,8 +129,8 @@ fn haproxy_hello<B: Buf>(mut buf: B) -> Result<haproxy::Hello> {
     })
 }
 
-fn agent_hello<B: Buf>(mut buf: B) -> Result<agent::Hello> {
-    let mut kv = buf.kv_list().collect::<KVList>();
+fn agent_hello<B: Buf>(buf: B) -> Result<agent::Hello> {
+    let mut kv = kv_list(buf)?;
 
     Ok(agent::Hello {
         version: kv.version()?,
@@ -94,27 +139,27 @@ fn agent_hello<B: Buf>(mut buf: B) -> Result<agent::Hello> {
     })
 }
 
-fn haproxy_notify<B: Buf>(buf: B, md: Metadata) -> Result<haproxy::Notify> {
+fn haproxy_notify<B: Buf>(buf: B, md: Metadata, limits: &Limits) -> Result<haproxy::Notify> {
     Ok(haproxy::Notify {
         fragmented: md.fragmented(),
         stream_id: md.stream_id,
         frame_id: md.frame_id,
-        messages: list_of_messages(buf).collect::<Vec<_>>(),
+        messages: list_of_messages(buf, limits)?,
     })
 }
 
-fn agent_ack<B: Buf>(buf: B, md: Metadata) -> Result<agent::Ack> {
+fn agent_ack<B: Buf>(buf: B, md: Metadata, limits: &Limits) -> Result<agent::Ack> {
     Ok(agent::Ack {
         fragmented: md.fragmented(),
         aborted: md.aborted(),
         stream_id: md.stream_id,
         frame_id: md.frame_id,
-        actions: list_of_actions(buf).collect::<Vec<_>>(),
+        actions: list_of_actions(buf, limits.max_actions)?,
     })
 }
 
-fn disconnect<B: Buf>(mut buf: B) -> Result<frame::Disconnect> {
-    let mut kv = buf.kv_list().collect::<KVList>();
+fn disconnect<B: Buf>(buf: B) -> Result<frame::Disconnect> {
+    let mut kv = kv_list(buf)?;
 
     Ok(haproxy::Disconnect {
         status_code: kv.status_code(),
@@ -122,32 +167,61 @@ fn disconnect<B: Buf>(mut buf: B) -> Result<frame::Disconnect> {
     })
 }
 
-fn list_of_messages<B: Buf>(mut buf: B) -> impl Iterator<Item = Message> {
-    iter::from_fn(move || {
-        if buf.has_remaining() {
-            message(&mut buf)
-        } else {
-            None
+/// Parse the messages of a NOTIFY frame.
+///
+/// The list may be empty, e.g. the last fragment of a fragmented frame,
+/// but a truncated or malformed message fails the whole frame instead of being dropped.
+fn list_of_messages<B: Buf>(mut buf: B, limits: &Limits) -> Result<Vec<Message>> {
+    let mut msgs = Vec::new();
+
+    while buf.has_remaining() {
+        if msgs.len() == limits.max_messages {
+            return Err(TooBig);
         }
-    })
+
+        msgs.push(message(&mut buf, limits.max_args)?);
+    }
+
+    Ok(msgs)
 }
 
-fn message<B: Buf>(mut buf: B) -> Option<Message> {
-    let name = buf.string()?;
-    let nb = get_u8(&mut buf)?;
-    let args = buf.kv_list().take(nb as usize).collect();
+/// Parse a message, its name must not be empty, the names of its arguments may be.
+fn message<B: Buf>(mut buf: B, max_args: usize) -> Result<Message> {
+    let name = buf
+        .string()
+        .filter(|name| !name.is_empty())
+        .ok_or(Invalid)?;
+    let nb = get_u8(&mut buf).ok_or(Invalid)? as usize;
+
+    if nb > max_args {
+        return Err(TooBig);
+    }
+    // every argument takes at least the length of its name and the type of its value
+    if nb > buf.remaining() / 2 {
+        return Err(Invalid);
+    }
 
-    Some(Message { name, args })
+    let mut args = Vec::with_capacity(nb);
+    for _ in 0..nb {
+        args.push(kv(&mut buf)?);
+    }
+
+    Ok(Message { name, args })
 }
 
-fn list_of_actions<B: Buf>(mut buf: B) -> impl Iterator<Item = Action> {
-    iter::from_fn(move || {
-        if buf.has_remaining() {
-            action(&mut buf)
-        } else {
-            None
+/// Parse the actions of an ACK frame, a malformed action fails the whole frame.
+fn list_of_actions<B: Buf>(mut buf: B, max_actions: usize) -> Result<Vec<Action>> {
+    let mut actions = Vec::new();
+
+    while buf.has_remaining() {
+        if actions.len() == max_actions {
+            return Err(TooBig);
         }
-    })
+
+        actions.push(action(&mut buf).ok_or(Invalid)?);
+    }
+
+    Ok(actions)
 }
 
 pub fn action<B: Buf>(mut buf: B) -> Option<Action> {
@@ -180,22 +254,45 @@ fn scope<B: Buf>(buf: B) -> Option<action::Scope> {
     try_from_u8(buf)
 }
 
+/// Parse a key-value pair, the value may be null.
+fn kv<B: Buf>(mut buf: B) -> Result<(String, Typed)> {
+    buf.string().zip(buf.typed()).ok_or(Invalid)
+}
+
+/// Parse the key-value list of a HELLO or DISCONNECT frame.
+fn kv_list<B: Buf>(mut buf: B) -> Result<KVList> {
+    iter::from_fn(|| buf.has_remaining().then(|| kv(&mut buf))).collect()
+}
+
+/// The key-value list of a HELLO or DISCONNECT frame, the null values are treated as missing.
 struct KVList(HashMap<String, Typed>);
 
 impl FromIterator<(String, Typed)> for KVList {
     fn from_iter<T: IntoIterator<Item = (String, Typed)>>(iter: T) -> Self {
-        Self(iter.into_iter().collect())
+        Self(
+            iter.into_iter()
+                .filter(|(_, value)| !matches!(value, Typed::Null))
+                .collect(),
+        )
     }
 }
 
 impl KVList {
     pub fn supported_versions(&mut self) -> Result<Vec<Version>> {
         let s = self.string(kv::SUPPORTED_VERSIONS_KEY).ok_or(NoVersion)?;
-
-        s.split(',')
-            .map(|s| s.trim().parse())
+        let versions = s
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
             .collect::<StdResult<Vec<_>, _>>()
-            .map_err(|_| Invalid)
+            .map_err(|_| Invalid)?;
+
+        if versions.is_empty() {
+            Err(NoVersion)
+        } else {
+            Ok(versions)
+        }
     }
 
     pub fn version(&mut self) -> Result<Version> {
@@ -206,24 +303,28 @@ impl KVList {
     }
 
     pub fn max_frame_size(&mut self) -> Result<u32> {
-        self.uint(kv::MAX_FRAME_SIZE_KEY)
-            .map(|n| n as u32)
-            .ok_or(NoFrameSize)
+        self.0
+            .remove(kv::MAX_FRAME_SIZE_KEY)
+            .ok_or(NoFrameSize)?
+            .to_u32()
+            .ok_or(BadFrameSize)
     }
 
     pub fn capabilities(&mut self) -> Result<Vec<Capability>> {
         let s = self.string(kv::CAPABILITIES_KEY).ok_or(NoCapabilities)?;
 
         s.split(',')
-            .map(|s| s.trim().parse())
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::parse)
             .collect::<StdResult<Vec<_>, _>>()
             .map_err(|_| Invalid)
     }
 
     pub fn status_code(&mut self) -> u32 {
-        self.uint(kv::STATUS_CODE_KEY)
-            .map(|n| n as u32)
-            .unwrap_or_default()
+        self.0
+            .remove(kv::STATUS_CODE_KEY)
+            .map_or(Normal.into(), |val| val.to_u32().unwrap_or(Unknown.into()))
     }
 
     pub fn message(&mut self) -> String {
@@ -234,16 +335,6 @@ impl KVList {
         self.0.remove(key).and_then(|val| bool::try_from(val).ok())
     }
 
-    pub fn uint(&mut self, key: &str) -> Option<u64> {
-        self.0.remove(key).and_then(|val| match val {
-            Typed::Int32(n) => Some(n as u64),
-            Typed::Uint32(n) => Some(n as u64),
-            Typed::Int64(n) => Some(n as u64),
-            Typed::Uint64(n) => Some(n),
-            _ => None,
-        })
-    }
-
     pub fn string(&mut self, key: &str) -> Option<String> {
         self.0
             .remove(key)
@@ -1,8 +1,50 @@
+use std::fmt;
+
+use thiserror::Error;
+
 use crate::Error;
 
+/// The min status code of the vendor-specific errors, the lower codes are reserved for the SPOE.
+pub const MIN_USER_STATUS: u32 = 100;
+
+/// A vendor-specific status code, sent in the DISCONNECT frames.
+///
+/// HAProxy logs the code as is, and handles it as an unknown error.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct UserStatus(u32);
+
+impl UserStatus {
+    /// Returns the status code, `None` if it is reserved for the SPOE.
+    pub const fn new(code: u32) -> Option<Self> {
+        if code >= MIN_USER_STATUS {
+            Some(UserStatus(code))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the numeric code.
+    pub const fn code(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for UserStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user status {}", self.0)
+    }
+}
+
+impl From<UserStatus> for u32 {
+    fn from(status: UserStatus) -> u32 {
+        status.0
+    }
+}
+
 /// If an error occurs, at anytime, from the HAProxy/agent side,
 /// a HAPROXY-DISCONNECT/AGENT-DISCONNECT frame is sent with information describing the error.
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("{message} (status {status_code})")]
 pub struct Disconnect {
     /// This is the code corresponding to the error.
     pub status_code: u32,
@@ -17,4 +59,52 @@ impl Disconnect {
             message: reason.into(),
         }
     }
+
+    /// Disconnect with a vendor-specific status code.
+    pub fn user<S: Into<String>>(status: UserStatus, reason: S) -> Self {
+        Self {
+            status_code: status.code(),
+            message: reason.into(),
+        }
+    }
+
+    /// Returns the predefined status the code maps to,
+    /// the vendor-specific and unknown codes are mapped to [`Error::Unknown`].
+    pub fn status(&self) -> Error {
+        Error::try_from(self.status_code).unwrap_or(Error::Unknown)
+    }
+
+    /// Returns the vendor-specific status code, if any.
+    pub fn user_status(&self) -> Option<UserStatus> {
+        UserStatus::new(self.status_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let d = Disconnect::new(Error::Timeout, "timeout");
+        assert_eq!(d.status(), Error::Timeout);
+        assert_eq!(d.user_status(), None);
+
+        let status = UserStatus::new(403).unwrap();
+        let d = Disconnect::user(status, "forbidden");
+        assert_eq!(d.status_code, 403);
+        assert_eq!(d.status(), Error::Unknown);
+        assert_eq!(d.user_status(), Some(status));
+        assert_eq!(d.to_string(), "forbidden (status 403)");
+
+        assert_eq!(UserStatus::new(Error::Unknown as u32), None);
+        assert_eq!(
+            Disconnect {
+                status_code: 42,
+                message: "reserved".into()
+            }
+            .status(),
+            Error::Unknown
+        );
+    }
 }
@@ -1,11 +1,46 @@
+use std::mem;
+
 use dashmap::{DashMap, Entry};
 
 use crate::{
     error::{Error, Result},
     frame::{agent::Ack, haproxy::Notify, Frame, FrameId, Message, StreamId},
-    Action, AsyncHandler,
+    Action, AsyncHandler, WireSize,
 };
 
+/// Split the messages or actions of a frame in the fragments fitting the max frame size,
+/// each one with the `base` size of the frame header and as many whole items as possible.
+///
+/// Fails with [`TooBig`](Error::TooBig) when an item doesn't fit a fragment alone.
+pub fn split_fragments<T: WireSize>(
+    items: Vec<T>,
+    base: usize,
+    max_frame_size: usize,
+) -> Result<Vec<Vec<T>>> {
+    let mut chunks = vec![];
+    let mut chunk = vec![];
+    let mut size = base;
+
+    for item in items {
+        let n = item.size();
+
+        if base + n > max_frame_size {
+            return Err(Error::TooBig);
+        }
+        if size + n > max_frame_size {
+            chunks.push(mem::take(&mut chunk));
+            size = base;
+        }
+
+        size += n;
+        chunk.push(item);
+    }
+
+    chunks.push(chunk);
+
+    Ok(chunks)
+}
+
 #[derive(Clone, Debug)]
 pub struct Reassembly<T>(Table<T>);
 
@@ -51,6 +86,16 @@ impl AsyncHandler<Option<Vec<Action>>> for Reassembly<Action> {
 
     async fn handle_frame(&mut self, frame: Frame) -> Result<Option<Vec<Action>>> {
         match frame {
+            Frame::AgentAck(Ack {
+                aborted: true,
+                stream_id,
+                frame_id,
+                ..
+            }) => {
+                self.0.abort((stream_id, frame_id));
+
+                Ok(Some(vec![]))
+            }
             Frame::AgentAck(Ack {
                 fragmented,
                 stream_id,
@@ -76,6 +121,11 @@ impl<T> Default for Table<T> {
 }
 
 impl<T> Table<T> {
+    /// Discard the fragments received for the aborted frame.
+    pub fn abort(&self, key: (StreamId, FrameId)) {
+        self.0.remove(&key);
+    }
+
     pub fn reassemble(
         &self,
         fragmented: bool,
@@ -1,72 +1,372 @@
-use std::{mem, pin::Pin};
+use std::{
+    future::poll_fn,
+    mem,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
 
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
 use futures::pin_mut;
 use hexplay::HexView;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    task::spawn_blocking,
+};
 use tracing::trace;
 
 use crate::{
     error::{Error::*, Result},
-    frame::{BufExt, BufMutExt, Frame},
+    frame::{agent, haproxy, split_fragments, BufExt, BufMutExt, Frame, Limits},
+    Action, Message, WireSize,
 };
 
+/// When a frame was received and decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameTiming {
+    /// When the first byte of the frame arrived.
+    pub received_at: Instant,
+    /// When the frame was decoded.
+    pub decoded_at: Instant,
+}
+
+impl FrameTiming {
+    /// Returns the time spent reading and decoding the frame.
+    pub fn decode_time(&self) -> Duration {
+        self.decoded_at.duration_since(self.received_at)
+    }
+}
+
+/// The min size of the frames worth decoding and encoding on the blocking thread pool.
+///
+/// Below it, the handoff to and from the pool costs more than the decoding itself,
+/// as measured by `cargo run --release -p haproxy-spop --example offload`.
+pub const OFFLOAD_FRAME_SIZE: usize = 64 * 1024;
+
+/// Handle the length prefix of the frames.
+///
+/// The write path is resumable, a frame interrupted by a short or pending write
+/// keeps the remaining bytes and continues from there on the next poll.
 #[derive(Clone, Debug)]
 pub struct Framer {
     max_frame_size: usize,
+    write_size: Option<usize>,
+    fragment_size: Option<usize>,
+    offload_size: Option<usize>,
+    limits: Limits,
+    wbuf: Bytes,
+    wlen: usize,
 }
 
 impl Framer {
     pub fn new(max_frame_size: usize) -> Framer {
-        Framer { max_frame_size }
+        Framer {
+            max_frame_size,
+            write_size: None,
+            fragment_size: None,
+            offload_size: None,
+            limits: Limits::default(),
+            wbuf: Bytes::new(),
+            wlen: 0,
+        }
+    }
+
+    /// Returns the max size of the frames read.
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Set the max size of the frames read, e.g. once the handshake has completed.
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Returns the max size of the frames written, if any.
+    pub fn max_write_size(&self) -> Option<usize> {
+        self.write_size.or(self.fragment_size)
+    }
+
+    /// Reject the frames written over the max frame size negotiated with the peer
+    /// with [`TooBig`](crate::Error::TooBig), before writing anything.
+    ///
+    /// With the fragmentation, the frames are split in fragments of the max frame size instead,
+    /// and only a message or an action which doesn't fit a fragment alone is rejected.
+    pub fn set_max_write_size(&mut self, max_frame_size: Option<usize>) {
+        self.write_size = max_frame_size;
+    }
+
+    /// Returns the size over which the NOTIFY and ACK frames written are fragmented, if any.
+    pub fn fragmentation(&self) -> Option<usize> {
+        self.fragment_size
+    }
+
+    /// Split the NOTIFY and ACK frames written over the negotiated max frame size in fragments,
+    /// once the `fragmentation` capability was agreed during the handshake.
+    ///
+    /// The fragments are frames of the same type, stream and frame, each one with whole messages
+    /// or actions, and only the last one has the `FIN` flag, as merged back by the [`Reassembly`].
+    ///
+    /// [`Reassembly`]: crate::Reassembly
+    pub fn set_fragmentation(&mut self, max_frame_size: Option<usize>) {
+        self.fragment_size = max_frame_size;
+    }
+
+    /// Returns the min size of the frames decoded and encoded on the blocking thread pool.
+    pub fn offload_size(&self) -> Option<usize> {
+        self.offload_size
+    }
+
+    /// Decode and encode the frames of at least the size on the blocking thread pool,
+    /// so the large frames don't delay the IO of the other connections on the worker.
+    ///
+    /// The frames are still handed back in order, as every offloaded frame is awaited
+    /// before the next one is read or written.
+    pub fn set_offload_size(&mut self, size: Option<usize>) {
+        self.offload_size = size;
+    }
+
+    /// Returns the caps on the number of items decoded from the frames read.
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Set the caps on the number of items decoded from the frames read.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
     pub async fn read_frame<R>(&self, r: R) -> Result<Frame>
+    where
+        R: AsyncRead + Sized,
+    {
+        self.read_timed_frame(r).await.map(|(frame, _)| frame)
+    }
+
+    /// Read a frame, with the time it was received and decoded.
+    ///
+    /// The length prefix is validated before reading or allocating anything for the frame:
+    /// it fails with [`Invalid`](crate::Error::Invalid) for an empty frame,
+    /// or [`TooBig`](crate::Error::TooBig) over the max frame size.
+    pub async fn read_timed_frame<R>(&self, r: R) -> Result<(Frame, FrameTiming)>
     where
         R: AsyncRead + Sized,
     {
         pin_mut!(r);
 
-        let len = r.read_u32().await.map_err(|_| Io)? as usize;
-        if len <= self.max_frame_size {
-            let mut buf = read_frame(r, self.max_frame_size, len).await?;
+        let first = r.read_u8().await.map_err(|_| Io)?;
+        let received_at = Instant::now();
+        let mut rest = [0; 3];
+        r.read_exact(&mut rest).await.map_err(|_| Io)?;
+
+        let len = u32::from_be_bytes([first, rest[0], rest[1], rest[2]]) as usize;
+        if len == 0 {
+            Err(Invalid)
+        } else if len <= self.max_frame_size {
+            let mut buf = read_frame(r, len).await?;
 
             trace!(buf=%HexView::new(&buf));
 
-            buf.get_frame().map_err(|_| Invalid)
+            let limits = self.limits;
+            let decoded = match self.offload_size {
+                Some(size) if len >= size => spawn_blocking(move || buf.get_frame_with(&limits))
+                    .await
+                    .map_err(|_| Invalid)?,
+                _ => buf.get_frame_with(&limits),
+            };
+            let frame = decoded.map_err(|err| if err == TooBig { TooBig } else { Invalid })?;
+            let timing = FrameTiming {
+                received_at,
+                decoded_at: Instant::now(),
+            };
+
+            Ok((frame, timing))
         } else {
-            Err(BadFrameSize)
+            trace!(len, max_frame_size = self.max_frame_size, "frame too big");
+
+            Err(TooBig)
         }
     }
 
-    pub async fn write_frame<W>(&self, w: W, frame: Frame) -> Result<usize>
+    pub async fn write_frame<W>(&mut self, w: W, frame: Frame) -> Result<usize>
     where
         W: AsyncWrite + Sized,
     {
-        let buf = write_frame(BytesMut::with_capacity(self.max_frame_size), frame);
+        pin_mut!(w);
+
+        if self.is_pending() {
+            poll_fn(|cx| self.poll_write_frame(cx, w.as_mut())).await?;
+        }
+
+        match self.offload_size {
+            Some(size) if frame.size() >= size => {
+                let (write_size, fragment_size) = (self.max_write_size(), self.fragment_size);
+                let buf =
+                    spawn_blocking(move || encode_fragments(frame, write_size, fragment_size))
+                        .await
+                        .map_err(|_| Invalid)??;
+
+                self.start_encoded(buf);
+            }
+            _ => self.start_write(frame)?,
+        }
+
+        poll_fn(|cx| self.poll_write_frame(cx, w.as_mut())).await
+    }
+
+    /// Encode the frame with its length prefix, to be written by [`Framer::poll_write_frame`],
+    /// or its fragments when the fragmentation is enabled.
+    ///
+    /// Fails with [`TooBig`](crate::Error::TooBig) over the max write size,
+    /// keeping any frame partially written before, which is discarded otherwise.
+    pub fn start_write(&mut self, frame: Frame) -> Result<()> {
+        let buf = encode_fragments(frame, self.max_write_size(), self.fragment_size)?;
+
+        self.start_encoded(buf);
+
+        Ok(())
+    }
 
+    fn start_encoded(&mut self, buf: Bytes) {
         trace!(buf=%HexView::new(&buf[4..]));
 
-        pin_mut!(w);
+        self.wlen = buf.len();
+        self.wbuf = buf;
+    }
+
+    /// Returns `true` if a frame has not been completely written.
+    pub fn is_pending(&self) -> bool {
+        self.wbuf.has_remaining()
+    }
+
+    /// Returns the number of bytes of the frame remaining to write.
+    pub fn remaining(&self) -> usize {
+        self.wbuf.remaining()
+    }
 
-        w.write_all(&buf).await.map_err(|_| Io)?;
+    /// Write the frame started by [`Framer::start_write`],
+    /// resuming from where the previous short or pending write stopped.
+    ///
+    /// Returns the size of the whole frame once it has been completely written,
+    /// with the length prefixes and the headers of all its fragments.
+    pub fn poll_write_frame<W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut w: Pin<&mut W>,
+    ) -> Poll<Result<usize>>
+    where
+        W: AsyncWrite,
+    {
+        while self.wbuf.has_remaining() {
+            match ready!(w.as_mut().poll_write(cx, &self.wbuf)) {
+                Ok(0) | Err(_) => return Poll::Ready(Err(Io)),
+                Ok(n) => self.wbuf.advance(n),
+            }
+        }
 
-        Ok(buf.len())
+        Poll::Ready(Ok(mem::take(&mut self.wlen)))
     }
 }
 
-async fn read_frame<R>(mut r: Pin<&mut R>, max_frame_size: usize, len: usize) -> Result<Bytes>
+async fn read_frame<R>(mut r: Pin<&mut R>, len: usize) -> Result<Bytes>
 where
     R: AsyncRead + Sized,
 {
-    let mut buf = BytesMut::with_capacity(max_frame_size);
-    buf.resize(len, 0);
+    let mut buf = BytesMut::zeroed(len);
 
     r.read_exact(&mut buf).await.map_err(|_| Io)?;
 
     Ok(buf.freeze())
 }
 
+/// Encode the frame, in fragments of the max frame size if any.
+///
+/// The frame over the max write size, or the message or the action which doesn't fit a fragment alone,
+/// is rejected.
+fn encode_fragments(
+    frame: Frame,
+    write_size: Option<usize>,
+    fragment_size: Option<usize>,
+) -> Result<Bytes> {
+    let Some(max_frame_size) = write_size.filter(|&max| frame.size() > max) else {
+        return Ok(encode(frame));
+    };
+    let Some(max_frame_size) = fragment_size.map(|size| size.min(max_frame_size)) else {
+        trace!(size = frame.size(), max_frame_size, "frame too big");
+
+        return Err(TooBig);
+    };
+    let fragments = match frame {
+        Frame::HaproxyNotify(notify) => {
+            let (stream_id, frame_id) = (notify.stream_id, notify.frame_id);
+            let base = Frame::notify(stream_id, frame_id, Vec::<Message>::new()).size();
+
+            split_fragments(notify.messages, base, max_frame_size)?
+                .into_iter()
+                .map(|messages| {
+                    Frame::HaproxyNotify(haproxy::Notify {
+                        fragmented: true,
+                        stream_id,
+                        frame_id,
+                        messages,
+                    })
+                })
+                .collect::<Vec<_>>()
+        }
+        Frame::AgentAck(ack) if !ack.aborted => {
+            let (stream_id, frame_id) = (ack.stream_id, ack.frame_id);
+            let base = Frame::ack(stream_id, frame_id, Vec::<Action>::new()).size();
+
+            split_fragments(ack.actions, base, max_frame_size)?
+                .into_iter()
+                .map(|actions| {
+                    Frame::AgentAck(agent::Ack {
+                        fragmented: true,
+                        actions,
+                        ..agent::Ack::new(stream_id, frame_id)
+                    })
+                })
+                .collect()
+        }
+        frame => {
+            trace!(ty = ?frame.frame_type(), size = frame.size(), max_frame_size, "frame too big");
+
+            return Err(TooBig);
+        }
+    };
+
+    let n = fragments.len();
+    let mut buf = BytesMut::with_capacity(
+        fragments
+            .iter()
+            .map(|f| mem::size_of::<u32>() + f.size())
+            .sum(),
+    );
+
+    for (i, mut fragment) in fragments.into_iter().enumerate() {
+        if i + 1 == n {
+            match fragment {
+                Frame::HaproxyNotify(ref mut notify) => notify.fragmented = false,
+                Frame::AgentAck(ref mut ack) => ack.fragmented = false,
+                _ => unreachable!(),
+            }
+        }
+
+        buf.put_u32(fragment.size() as u32);
+        buf.put_frame(fragment);
+    }
+
+    trace!(fragments = n, "frame fragmented");
+
+    Ok(buf.freeze())
+}
+
+fn encode(frame: Frame) -> Bytes {
+    write_frame(
+        BytesMut::with_capacity(mem::size_of::<u32>() + frame.size()),
+        frame,
+    )
+}
+
 fn write_frame(mut buf: BytesMut, frame: Frame) -> Bytes {
     buf.put_u32(0);
     buf.put_frame(frame);
@@ -77,3 +377,316 @@ fn write_frame(mut buf: BytesMut, frame: Frame) -> Bytes {
 
     buf.freeze()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures::task::noop_waker_ref;
+
+    use crate::{frame::Type, Action, Message, Scope, MAX_FRAME_SIZE};
+
+    use super::*;
+
+    /// A writer accepting a few bytes per call and returning `Pending` every other call.
+    #[derive(Default)]
+    struct ShortWriter {
+        buf: Vec<u8>,
+        chunk: usize,
+        pending: bool,
+    }
+
+    impl AsyncWrite for ShortWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.pending = !self.pending;
+
+            if self.pending {
+                cx.waker().wake_by_ref();
+
+                Poll::Pending
+            } else {
+                let n = buf.len().min(self.chunk);
+                self.buf.extend_from_slice(&buf[..n]);
+
+                Poll::Ready(Ok(n))
+            }
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn frames() -> Vec<Frame> {
+        vec![
+            Frame::notify(1, 2, [Message::new("foo", [("bar", 123)])]),
+            Frame::ack(1, 2, [Action::set_var(Scope::Session, "foo", "bar")]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_short_writes() {
+        let mut framer = Framer::new(MAX_FRAME_SIZE);
+        let mut w = ShortWriter {
+            chunk: 3,
+            ..Default::default()
+        };
+
+        for f in frames() {
+            let len = mem::size_of::<u32>() + f.size();
+
+            assert_eq!(framer.write_frame(&mut w, f).await, Ok(len));
+        }
+
+        let mut r = w.buf.as_slice();
+        for f in frames() {
+            assert_eq!(framer.read_frame(&mut r).await, Ok(f));
+        }
+        assert!(r.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_offload() {
+        let mut framer = Framer::new(MAX_FRAME_SIZE);
+        let mut w = ShortWriter {
+            chunk: 7,
+            ..Default::default()
+        };
+        let big = Frame::notify(1, 3, [Message::new("body", [("data", "x".repeat(256))])]);
+
+        framer.set_offload_size(Some(big.size()));
+
+        // only the frames of at least the offload size leave the connection task, in order
+        for f in frames().into_iter().chain([big.clone()]).chain(frames()) {
+            framer.write_frame(&mut w, f).await.unwrap();
+        }
+
+        let mut r = w.buf.as_slice();
+        for f in frames().into_iter().chain([big]).chain(frames()) {
+            assert_eq!(framer.read_frame(&mut r).await, Ok(f));
+        }
+        assert!(r.is_empty());
+    }
+
+    #[test]
+    fn test_resume_write() {
+        let mut framer = Framer::new(MAX_FRAME_SIZE);
+        let mut w = ShortWriter {
+            chunk: 5,
+            ..Default::default()
+        };
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let f = frames().remove(0);
+        let len = mem::size_of::<u32>() + f.size();
+
+        framer.start_write(f).unwrap();
+
+        assert!(framer
+            .poll_write_frame(&mut cx, Pin::new(&mut w))
+            .is_pending());
+        assert_eq!(framer.remaining(), len);
+
+        let mut polls = 1;
+        let res = loop {
+            polls += 1;
+
+            if let Poll::Ready(res) = framer.poll_write_frame(&mut cx, Pin::new(&mut w)) {
+                break res;
+            }
+
+            assert_eq!(framer.remaining(), len - w.buf.len());
+        };
+
+        assert_eq!(res, Ok(len));
+        assert_eq!(polls, 1 + len.div_ceil(5));
+        assert!(!framer.is_pending());
+        assert_eq!(w.buf.len(), len);
+    }
+
+    #[tokio::test]
+    async fn test_fragmentation() {
+        use crate::{AsyncHandler, Reassembly};
+
+        let mut framer = Framer::new(64);
+        let messages = (0..4)
+            .map(|i| Message::new("body", [("data", "x".repeat(20 + i))]))
+            .collect::<Vec<_>>();
+        let actions = (0..4)
+            .map(|i| Action::set_var(Scope::Transaction, "data", "x".repeat(20 + i)))
+            .collect::<Vec<_>>();
+        let notify = Frame::notify(1, 2, messages.clone());
+        let ack = Frame::ack(1, 2, actions.clone());
+
+        // the frames are not fragmented until the capability was agreed
+        let mut buf = vec![];
+        framer.write_frame(&mut buf, notify.clone()).await.unwrap();
+        assert_eq!(buf, encode(notify.clone()));
+        framer.set_fragmentation(Some(64));
+
+        let mut w = ShortWriter {
+            chunk: 7,
+            ..Default::default()
+        };
+        for f in frames() {
+            framer.write_frame(&mut w, f).await.unwrap();
+        }
+        let len = framer.write_frame(&mut w, notify).await.unwrap();
+        framer.write_frame(&mut w, ack).await.unwrap();
+        framer
+            .write_frame(&mut w, Frame::abort(1, 2))
+            .await
+            .unwrap();
+        assert!(len < w.buf.len());
+
+        let reader = Framer::new(64);
+        let mut r = &w.buf[..];
+        for f in frames() {
+            assert_eq!(reader.read_frame(&mut r).await, Ok(f));
+        }
+
+        // every fragment fits the max frame size, and is merged back by the reassembly
+        let mut notifies = Reassembly::<Message>::default();
+        let mut fragments = 0;
+        let reassembled = loop {
+            let f = reader.read_frame(&mut r).await.unwrap();
+            assert!(f.size() <= 64);
+
+            fragments += 1;
+            if let Some(messages) = notifies.handle_frame(f).await.unwrap() {
+                break messages;
+            }
+        };
+        assert!(fragments > 1);
+        assert_eq!(reassembled, messages);
+
+        let mut acks = Reassembly::<Action>::default();
+        let reassembled = loop {
+            let f = reader.read_frame(&mut r).await.unwrap();
+            assert!(f.size() <= 64);
+
+            if let Some(actions) = acks.handle_frame(f).await.unwrap() {
+                break actions;
+            }
+        };
+        assert_eq!(reassembled, actions);
+
+        // the aborted ACK frames are never fragmented
+        assert_eq!(reader.read_frame(&mut r).await, Ok(Frame::abort(1, 2)));
+        assert!(r.is_empty());
+
+        // a message over the max frame size is rejected before anything is written
+        let big = Message::new("body", [("data", "x".repeat(100))]);
+        let mut buf = vec![];
+        assert_eq!(
+            framer
+                .write_frame(&mut buf, Frame::notify(1, 3, [messages[0].clone(), big]))
+                .await,
+            Err(TooBig)
+        );
+        assert!(buf.is_empty());
+        assert!(!framer.is_pending());
+    }
+
+    #[tokio::test]
+    async fn test_max_write_size() {
+        let mut framer = Framer::new(MAX_FRAME_SIZE);
+        let big = Frame::ack(
+            1,
+            2,
+            [Action::set_var(Scope::Transaction, "data", "x".repeat(100))],
+        );
+
+        framer.set_max_write_size(Some(64));
+        assert_eq!(framer.max_write_size(), Some(64));
+
+        // the frames over the max write size are rejected without the fragmentation
+        let mut buf = vec![];
+        for f in frames() {
+            framer.write_frame(&mut buf, f).await.unwrap();
+        }
+        let written = buf.len();
+        assert_eq!(framer.write_frame(&mut buf, big.clone()).await, Err(TooBig));
+        assert_eq!(buf.len(), written);
+
+        // the offloaded frames are checked the same way
+        framer.set_offload_size(Some(1));
+        assert_eq!(framer.write_frame(&mut buf, big).await, Err(TooBig));
+        assert_eq!(buf.len(), written);
+
+        let reader = Framer::new(MAX_FRAME_SIZE);
+        let mut r = buf.as_slice();
+        for f in frames() {
+            assert_eq!(reader.read_frame(&mut r).await, Ok(f));
+        }
+        assert!(r.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_read_timed_frame() {
+        use tokio::io::{duplex, AsyncWriteExt as _};
+
+        let framer = Framer::new(MAX_FRAME_SIZE);
+        let f = frames().remove(0);
+        let buf = write_frame(BytesMut::new(), f.clone());
+
+        let (mut w, r) = duplex(MAX_FRAME_SIZE);
+        let reading = tokio::spawn(async move { framer.read_timed_frame(r).await });
+
+        w.write_all(&buf[..2]).await.unwrap();
+        tokio::task::yield_now().await;
+
+        // the first byte has been received, the frame is still incomplete
+        std::thread::sleep(Duration::from_millis(20));
+        w.write_all(&buf[2..]).await.unwrap();
+
+        let (frame, timing) = reading.await.unwrap().unwrap();
+        assert_eq!(frame, f);
+        assert!(timing.decode_time() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn test_adversarial_prefix() {
+        let framer = Framer::new(1024);
+        let body = [Type::AGENT_ACK, 0, 0, 0, 1, 1, 2];
+
+        for (len, err) in [
+            (0, Invalid),
+            (1025, TooBig),
+            (0x8000_0000, TooBig),
+            (u32::MAX, TooBig),
+        ] {
+            let mut buf = len.to_be_bytes().to_vec();
+            buf.extend_from_slice(&body);
+
+            // the frame is rejected on its length prefix, without reading its body
+            let mut r = buf.as_slice();
+            assert_eq!(framer.read_frame(&mut r).await, Err(err), "{len}");
+            assert_eq!(r.len(), body.len(), "{len}");
+        }
+
+        // the length prefix larger than the frame
+        let mut buf = 1024u32.to_be_bytes().to_vec();
+        buf.extend_from_slice(&body);
+        assert_eq!(framer.read_frame(buf.as_slice()).await, Err(Io));
+
+        // the length prefix smaller than the frame
+        let mut buf = 3u32.to_be_bytes().to_vec();
+        buf.extend_from_slice(&body);
+        assert_eq!(framer.read_frame(buf.as_slice()).await, Err(Invalid));
+
+        let mut buf = (body.len() as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(&body);
+        assert_eq!(
+            framer.read_frame(buf.as_slice()).await,
+            Ok(Frame::ack(1, 2, Vec::<Action>::new()))
+        );
+    }
+}
@@ -68,6 +68,11 @@ impl Frame {
         })
     }
 
+    /// Returns an ACK frame aborting the processing of the frame, with the ABORT and FIN flags.
+    pub fn abort(stream_id: u64, frame_id: u64) -> Self {
+        Frame::AgentAck(AgentAck::abort(stream_id, frame_id))
+    }
+
     pub fn haproxy_disconnect<S: Into<String>>(status: Error, reason: S) -> Self {
         Frame::HaproxyDisconnect(frame::Disconnect::new(status, reason))
     }
@@ -94,12 +99,14 @@ mod tests {
     use bytes::BufMut;
 
     use crate::{
+        action,
         data::BufMutExt,
+        error::Result,
         frame::{agent, decode, encode, haproxy, kv},
         Action, Capability,
         Error::*,
         Scope::{self, *},
-        Version,
+        Typed, Version, WireSize as _,
     };
 
     use super::*;
@@ -275,13 +282,10 @@ mod tests {
                 },
             ),
             (
-                Frame::AgentDisconnect(
-                    frame::Disconnect {
-                        status_code: BadFrameSize as u32,
-                        message: "bad frame size".into(),
-                    }
-                    .into(),
-                ),
+                Frame::AgentDisconnect(frame::Disconnect {
+                    status_code: BadFrameSize as u32,
+                    message: "bad frame size".into(),
+                }),
                 {
                     let mut v = vec![frame::Type::AGENT_DISCON];
                     encode::metadata(&mut v, Metadata::default());
@@ -303,4 +307,277 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_abort() {
+        let ack = agent::Ack {
+            fragmented: true,
+            ..agent::Ack::abort(123, 456)
+        };
+        let mut expected = vec![frame::Type::AGENT_ACK];
+        encode::metadata(
+            &mut expected,
+            Metadata {
+                flags: frame::Flags::FIN | frame::Flags::ABORT,
+                stream_id: 123,
+                frame_id: 456,
+            },
+        );
+
+        let mut v = Vec::new();
+        encode::frame(&mut v, Frame::AgentAck(ack.clone()));
+        assert_eq!(v, expected);
+        assert_eq!(Frame::AgentAck(ack).size(), v.len());
+        assert_eq!(decode::frame(v.as_slice()), Ok(Frame::abort(123, 456)));
+    }
+
+    #[test]
+    fn test_conformance() {
+        let notify = |messages: &dyn Fn(&mut Vec<u8>)| {
+            let mut v = vec![frame::Type::HAPROXY_NOTIFY];
+            encode::metadata(
+                &mut v,
+                Metadata {
+                    flags: frame::Flags::FIN,
+                    stream_id: 1,
+                    frame_id: 1,
+                },
+            );
+            messages(&mut v);
+            v
+        };
+        let notified = |messages: Vec<Message>| {
+            Ok(Frame::HaproxyNotify(haproxy::Notify {
+                fragmented: false,
+                stream_id: 1,
+                frame_id: 1,
+                messages,
+            }))
+        };
+        let hello = |kvs: &dyn Fn(&mut Vec<u8>)| {
+            let mut v = vec![frame::Type::HAPROXY_HELLO];
+            encode::metadata(&mut v, Metadata::default());
+            kvs(&mut v);
+            v
+        };
+
+        let cases: [(&str, Vec<u8>, Result<Frame>); 11] = [
+            ("empty list of messages", notify(&|_| {}), notified(vec![])),
+            (
+                "message without arguments",
+                notify(&|v| {
+                    v.put_string("check");
+                    v.put_u8(0);
+                }),
+                notified(vec![Message::new("check", Vec::<(String, Typed)>::new())]),
+            ),
+            (
+                "null, empty string and unnamed arguments",
+                notify(&|v| {
+                    v.put_string("check");
+                    v.put_u8(3);
+                    v.put_kv(("tenant", ()));
+                    v.put_kv(("path", ""));
+                    v.put_kv(("", 1));
+                }),
+                notified(vec![Message::new(
+                    "check",
+                    [
+                        ("tenant", Typed::Null),
+                        ("path", Typed::from("")),
+                        ("", Typed::from(1)),
+                    ],
+                )]),
+            ),
+            (
+                "message without name",
+                notify(&|v| {
+                    v.put_string("");
+                    v.put_u8(0);
+                }),
+                Err(Invalid),
+            ),
+            (
+                "truncated arguments",
+                notify(&|v| {
+                    v.put_string("check");
+                    v.put_u8(2);
+                    v.put_kv(("ip", Ipv4Addr::LOCALHOST));
+                }),
+                Err(Invalid),
+            ),
+            (
+                "truncated message",
+                notify(&|v| {
+                    v.put_u8(0);
+                    v.put_string("check");
+                }),
+                Err(Invalid),
+            ),
+            (
+                "empty capabilities",
+                hello(&|v| {
+                    v.put_kv(kv::supported_versions(&[Version::V2_0]));
+                    v.put_kv(kv::max_frame_size(1024));
+                    v.put_kv(kv::capabilities(&[]));
+                }),
+                Ok(Frame::HaproxyHello(haproxy::Hello {
+                    supported_versions: vec![Version::V2_0],
+                    max_frame_size: 1024,
+                    capabilities: vec![],
+                    healthcheck: None,
+                    engine_id: None,
+                })),
+            ),
+            (
+                "null capabilities",
+                hello(&|v| {
+                    v.put_kv(kv::supported_versions(&[Version::V2_0]));
+                    v.put_kv(kv::max_frame_size(1024));
+                    v.put_kv((kv::CAPABILITIES_KEY, ()));
+                }),
+                Err(NoCapabilities),
+            ),
+            (
+                "empty supported versions",
+                hello(&|v| {
+                    v.put_kv(kv::supported_versions(&[]));
+                    v.put_kv(kv::max_frame_size(1024));
+                    v.put_kv(kv::capabilities(&[]));
+                }),
+                Err(NoVersion),
+            ),
+            (
+                "truncated key-value",
+                hello(&|v| {
+                    v.put_kv(kv::supported_versions(&[Version::V2_0]));
+                    v.put_string(kv::MAX_FRAME_SIZE_KEY);
+                }),
+                Err(Invalid),
+            ),
+            (
+                "malformed action",
+                {
+                    let mut v = vec![frame::Type::AGENT_ACK];
+                    encode::metadata(
+                        &mut v,
+                        Metadata {
+                            flags: frame::Flags::FIN,
+                            stream_id: 1,
+                            frame_id: 1,
+                        },
+                    );
+                    encode::action(&mut v, Action::set_var(Scope::Request, "foo", "bar"));
+                    v.put_u8(action::Type::SetVar as u8);
+                    v.put_u8(2);
+                    v
+                },
+                Err(Invalid),
+            ),
+        ];
+
+        for (name, b, res) in cases {
+            assert_eq!(decode::frame(b.as_slice()), res, "{name}: {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = decode::Limits {
+            max_messages: 2,
+            max_args: 2,
+            max_actions: 2,
+        };
+        let frame = |ty, f: &dyn Fn(&mut Vec<u8>)| {
+            let mut v = vec![ty];
+            encode::metadata(
+                &mut v,
+                Metadata {
+                    flags: frame::Flags::FIN,
+                    stream_id: 1,
+                    frame_id: 1,
+                },
+            );
+            f(&mut v);
+            v
+        };
+
+        let cases: [(&str, decode::Limits, Vec<u8>, Result<()>); 6] = [
+            (
+                "messages within the cap",
+                limits,
+                frame(frame::Type::HAPROXY_NOTIFY, &|v| {
+                    for _ in 0..2 {
+                        v.put_string("check");
+                        v.put_u8(2);
+                        v.put_kv(("a", 1));
+                        v.put_kv(("b", 2));
+                    }
+                }),
+                Ok(()),
+            ),
+            (
+                "too many messages",
+                limits,
+                frame(frame::Type::HAPROXY_NOTIFY, &|v| {
+                    for _ in 0..3 {
+                        v.put_string("check");
+                        v.put_u8(0);
+                    }
+                }),
+                Err(TooBig),
+            ),
+            (
+                "too many arguments",
+                limits,
+                frame(frame::Type::HAPROXY_NOTIFY, &|v| {
+                    v.put_string("check");
+                    v.put_u8(3);
+                    for name in ["a", "b", "c"] {
+                        v.put_kv((name, 1));
+                    }
+                }),
+                Err(TooBig),
+            ),
+            (
+                "more arguments announced than the frame holds",
+                decode::Limits::default(),
+                frame(frame::Type::HAPROXY_NOTIFY, &|v| {
+                    v.put_string("check");
+                    v.put_u8(u8::MAX);
+                    v.put_kv(("a", 1));
+                }),
+                Err(Invalid),
+            ),
+            (
+                "too many actions",
+                limits,
+                frame(frame::Type::AGENT_ACK, &|v| {
+                    for _ in 0..3 {
+                        encode::action(&mut *v, Action::unset_var(Scope::Request, "foo"));
+                    }
+                }),
+                Err(TooBig),
+            ),
+            (
+                "thousands of empty messages",
+                decode::Limits::default(),
+                frame(frame::Type::HAPROXY_NOTIFY, &|v| {
+                    for _ in 0..=decode::MAX_MESSAGES {
+                        v.put_string("m");
+                        v.put_u8(0);
+                    }
+                }),
+                Err(TooBig),
+            ),
+        ];
+
+        for (name, limits, b, res) in cases {
+            assert_eq!(
+                decode::frame_with(b.as_slice(), &limits).map(|_| ()),
+                res,
+                "{name}: {b:?}"
+            );
+        }
+    }
 }
@@ -13,6 +13,7 @@ pub const HEALTHCHECK_KEY: &str = "healthcheck";
 pub const STATUS_CODE_KEY: &str = "status-code";
 pub const MSG_KEY: &str = "message";
 
+#[derive(Clone, Debug)]
 pub struct Punctuated<I>(I, &'static str);
 
 fn punctuated<I, T>(i: I) -> Punctuated<I::IntoIter>
@@ -38,7 +39,7 @@ where
     }
 }
 
-pub fn supported_versions(versions: &[Version]) -> KeyValue<Punctuated<Iter<Version>>> {
+pub fn supported_versions(versions: &[Version]) -> KeyValue<'_, Punctuated<Iter<'_, Version>>> {
     KeyValue(Cow::Borrowed(SUPPORTED_VERSIONS_KEY), punctuated(versions))
 }
 
@@ -50,7 +51,7 @@ pub const fn max_frame_size(sz: u32) -> KeyValue<'static, u32> {
     KeyValue(Cow::Borrowed(MAX_FRAME_SIZE_KEY), sz)
 }
 
-pub fn capabilities(caps: &[Capability]) -> KeyValue<Punctuated<Iter<Capability>>> {
+pub fn capabilities(caps: &[Capability]) -> KeyValue<'_, Punctuated<Iter<'_, Capability>>> {
     KeyValue(Cow::Borrowed(CAPABILITIES_KEY), punctuated(caps))
 }
 
@@ -58,7 +59,7 @@ pub const fn healthcheck(enable: bool) -> KeyValue<'static, bool> {
     KeyValue(Cow::Borrowed(HEALTHCHECK_KEY), enable)
 }
 
-pub const fn engine_id(id: &str) -> KeyValue<&str> {
+pub const fn engine_id(id: &str) -> KeyValue<'_, &str> {
     KeyValue(Cow::Borrowed(ENGINE_ID_KEY), id)
 }
 
@@ -66,6 +67,6 @@ pub const fn status_code(code: u32) -> KeyValue<'static, u32> {
     KeyValue(Cow::Borrowed(STATUS_CODE_KEY), code)
 }
 
-pub const fn message(msg: &str) -> KeyValue<&str> {
+pub const fn message(msg: &str) -> KeyValue<'_, &str> {
     KeyValue(Cow::Borrowed(MSG_KEY), msg)
 }
@@ -1,9 +1,5 @@
-use std::mem;
-
 use bitflags::bitflags;
 
-use crate::data::varint;
-
 /// The stream identifier
 pub type StreamId = u64;
 /// The frame identifier inside the stream
@@ -52,8 +48,4 @@ impl Metadata {
     pub const fn aborted(&self) -> bool {
         self.flags.contains(Flags::ABORT)
     }
-
-    pub const fn size(&self) -> usize {
-        mem::size_of::<Flags>() + varint::size_of(self.stream_id) + varint::size_of(self.frame_id)
-    }
 }
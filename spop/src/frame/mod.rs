@@ -1,23 +1,27 @@
 pub mod agent;
+#[cfg(feature = "tokio")]
 mod codec;
 mod decode;
 mod disconnect;
 mod encode;
 mod fragment;
+#[cfg(feature = "tokio")]
 mod framer;
 mod frames;
 pub mod haproxy;
-mod kv;
+pub(crate) mod kv;
 mod metadata;
 mod msg;
 mod ty;
 
+#[cfg(feature = "tokio")]
 pub use self::codec::{BufCodec, Codec};
-pub use self::decode::BufExt;
-pub use self::disconnect::Disconnect;
+pub use self::decode::{BufExt, Limits, MAX_ACTIONS, MAX_MESSAGES};
+pub use self::disconnect::{Disconnect, UserStatus, MIN_USER_STATUS};
 pub use self::encode::BufMutExt;
-pub use self::fragment::Reassembly;
-pub use self::framer::Framer;
+pub use self::fragment::{split_fragments, Reassembly};
+#[cfg(feature = "tokio")]
+pub use self::framer::{FrameTiming, Framer, OFFLOAD_FRAME_SIZE};
 pub use self::frames::Frame;
 pub use self::metadata::{Flags, FrameId, Metadata, StreamId};
 pub use self::msg::Message;
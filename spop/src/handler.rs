@@ -1,16 +1,26 @@
+#[cfg(feature = "tokio")]
 use std::convert::{Infallible, TryFrom};
 
+#[cfg(feature = "tokio")]
 use tower::{service_fn, MakeService};
 
-use crate::{frame::Frame, Action, AgentAck, Disconnect, Error, HaproxyNotify, Message};
+use std::future::Future;
 
-#[allow(async_fn_in_trait)]
+use crate::frame::Frame;
+#[cfg(feature = "tokio")]
+use crate::{Action, AgentAck, Disconnect, Error, HaproxyNotify, Message};
+
+/// Handle the frames of a connection.
+///
+/// The returned future is `Send`, so the handlers may be driven by the tasks of a multi-threaded runtime.
 pub trait AsyncHandler<T> {
     type Error;
 
-    async fn handle_frame(&mut self, frame: Frame) -> Result<T, Self::Error>;
+    fn handle_frame(&mut self, frame: Frame)
+        -> impl Future<Output = Result<T, Self::Error>> + Send;
 }
 
+#[cfg(feature = "tokio")]
 pub fn notify_handler(
 ) -> impl MakeService<(), Frame, Response = Option<Vec<Message>>, Error = Error, MakeError = Infallible>
 {
@@ -27,6 +37,7 @@ pub fn notify_handler(
     })
 }
 
+#[cfg(feature = "tokio")]
 pub fn ack_handler(
 ) -> impl MakeService<(), Frame, Response = Option<Vec<Action>>, Error = Error, MakeError = Infallible>
 {
@@ -43,7 +54,7 @@ pub fn ack_handler(
     })
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "tokio"))]
 mod tests {
     use tower::Service;
 
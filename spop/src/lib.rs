@@ -1,20 +1,34 @@
 mod action;
 mod caps;
 mod data;
+#[cfg(feature = "serde")]
+pub mod de;
+#[cfg(feature = "debug-protocol")]
+pub mod debug;
 mod error;
 mod frame;
 mod handler;
+mod negotiate;
+mod size;
 mod version;
+pub mod wire;
 
 pub use self::action::{Action, Scope};
 pub use self::caps::Capability;
-pub use self::data::Typed;
+pub use self::data::{OutOfRange, Typed};
 pub use self::error::Error;
 pub use self::frame::{
     agent::{Ack as AgentAck, Disconnect as AgentDisconnect, Hello as AgentHello},
     haproxy::{Disconnect as HaproxyDisconnect, Hello as HaproxyHello, Notify as HaproxyNotify},
-    BufCodec, Codec, Disconnect, Frame, FrameId, Framer, Message, Reassembly, StreamId,
-    MAX_FRAME_SIZE,
+    split_fragments, BufExt, BufMutExt, Disconnect, Frame, FrameId, Limits, Message, Reassembly,
+    StreamId, Type as FrameType, UserStatus, MAX_ACTIONS, MAX_FRAME_SIZE, MAX_MESSAGES,
+    MIN_USER_STATUS,
 };
+#[cfg(feature = "tokio")]
+pub use self::frame::{BufCodec, Codec, FrameTiming, Framer, OFFLOAD_FRAME_SIZE};
 pub use self::handler::AsyncHandler;
-pub use self::version::Version;
+#[cfg(feature = "tokio")]
+pub use self::handler::{ack_handler, notify_handler};
+pub use self::negotiate::{negotiate, Offer};
+pub use self::size::WireSize;
+pub use self::version::{ParseVersionError, Version};
@@ -0,0 +1,271 @@
+//! The negotiation of the HELLO handshake.
+//!
+//! The agent replies to the HAPROXY-HELLO frame with the AGENT-HELLO frame of
+//! the negotiated parameters:
+//!
+//! - the highest version supported by both sides;
+//! - the lowest max frame size of both sides;
+//! - the capabilities supported by both sides, in the order of the agent.
+//!
+//! The missing `supported-versions`, `max-frame-size` or `capabilities` items fail
+//! the decoding of the HAPROXY-HELLO frame, while the missing `healthcheck`
+//! and `engine-id` items are treated as unset.
+
+use crate::{
+    error::{Error::NoVersion, Result},
+    AgentHello, Capability, HaproxyHello, Version,
+};
+
+/// The parameters supported by the agent, negotiated with the ones of HAProxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Offer {
+    /// The SPOP versions supported by the agent.
+    pub supported_versions: Vec<Version>,
+    /// The max frame size accepted by the agent.
+    pub max_frame_size: u32,
+    /// The capabilities supported by the agent.
+    pub capabilities: Vec<Capability>,
+}
+
+/// Negotiate the parameters of the connection, returning the AGENT-HELLO frame to reply.
+///
+/// Fails with [`NoVersion`](crate::Error::NoVersion) when no version is supported by both sides.
+pub fn negotiate(offer: &Offer, hello: &HaproxyHello) -> Result<AgentHello> {
+    let version = hello
+        .supported_versions
+        .iter()
+        .copied()
+        .filter(|version| offer.supported_versions.contains(version))
+        .max()
+        .ok_or(NoVersion)?;
+    let max_frame_size = offer.max_frame_size.min(hello.max_frame_size);
+    let capabilities = offer
+        .capabilities
+        .iter()
+        .copied()
+        .filter(|capability| hello.capabilities.contains(capability))
+        .collect();
+
+    Ok(AgentHello {
+        version,
+        max_frame_size,
+        capabilities,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+
+    use crate::{
+        data::BufMutExt as _,
+        frame::{kv, Flags, Type},
+        BufExt as _, Error, Frame, Typed,
+    };
+
+    use super::*;
+
+    use Capability::*;
+
+    const AGENT: &[Capability] = &[Fragmentation, Pipelining, Async];
+
+    /// The outcome of the handshake: the AGENT-HELLO frame and whether it is a healthcheck.
+    type Outcome = std::result::Result<(Version, u32, Vec<Capability>, bool), Error>;
+
+    /// The raw items of the HAPROXY-HELLO frame.
+    type Items = Vec<(&'static str, Typed)>;
+
+    /// Encode the HAPROXY-HELLO frame with the raw items, which may be missing or invalid.
+    fn hello(items: &[(&str, Typed)]) -> BytesMut {
+        let mut buf = BytesMut::new();
+
+        buf.put_u8(Type::HaproxyHello as u8);
+        buf.put_u32(Flags::FIN.bits());
+        buf.put_varint(0);
+        buf.put_varint(0);
+        for (key, value) in items {
+            buf.put_string(key);
+            buf.put_typed(value.clone());
+        }
+
+        buf
+    }
+
+    fn handshake(offer: &Offer, items: &[(&str, Typed)]) -> Outcome {
+        match hello(items).get_frame()? {
+            Frame::HaproxyHello(hello) => {
+                let agent = negotiate(offer, &hello)?;
+
+                Ok((
+                    agent.version,
+                    agent.max_frame_size,
+                    agent.capabilities,
+                    hello.healthcheck.unwrap_or_default(),
+                ))
+            }
+            frame => panic!("unexpected frame: {frame:?}"),
+        }
+    }
+
+    #[test]
+    fn test_negotiation_matrix() {
+        let offer = |versions: &[Version], max_frame_size, capabilities: &[Capability]| Offer {
+            supported_versions: versions.to_vec(),
+            max_frame_size,
+            capabilities: capabilities.to_vec(),
+        };
+        let items = |versions: &str, max_frame_size: Typed, capabilities: &str| {
+            vec![
+                (kv::SUPPORTED_VERSIONS_KEY, Typed::from(versions)),
+                (kv::MAX_FRAME_SIZE_KEY, max_frame_size),
+                (kv::CAPABILITIES_KEY, Typed::from(capabilities)),
+            ]
+        };
+        let with = |mut items: Items, key, value: Typed| {
+            items.retain(|(k, _)| *k != key);
+            items.push((key, value));
+            items
+        };
+        let without = |mut items: Items, key| {
+            items.retain(|(k, _)| *k != key);
+            items
+        };
+
+        let v2 = offer(&[Version::V2_0], 16380, AGENT);
+        let both = offer(&[Version::V2_0, Version::V2_1], 16380, AGENT);
+        let base = items("2.0", Typed::Uint32(16380), "pipelining,async");
+
+        let cases: Vec<(&str, Offer, Items, Outcome)> = vec![
+            (
+                "same parameters",
+                v2.clone(),
+                base.clone(),
+                Ok((Version::V2_0, 16380, vec![Pipelining, Async], false)),
+            ),
+            (
+                "highest common version",
+                both.clone(),
+                items("2.1, 2.0", Typed::Uint32(16380), "pipelining"),
+                Ok((Version::V2_1, 16380, vec![Pipelining], false)),
+            ),
+            (
+                "older agent",
+                v2.clone(),
+                items("2.1,2.0", Typed::Uint32(16380), "pipelining"),
+                Ok((Version::V2_0, 16380, vec![Pipelining], false)),
+            ),
+            (
+                "no common version",
+                offer(&[Version::V2_1], 16380, AGENT),
+                base.clone(),
+                Err(Error::NoVersion),
+            ),
+            (
+                "invalid version",
+                v2.clone(),
+                with(base.clone(), kv::SUPPORTED_VERSIONS_KEY, "two".into()),
+                Err(Error::Invalid),
+            ),
+            (
+                "empty versions",
+                v2.clone(),
+                with(base.clone(), kv::SUPPORTED_VERSIONS_KEY, " , ".into()),
+                Err(Error::NoVersion),
+            ),
+            (
+                "smaller HAProxy frames",
+                v2.clone(),
+                items("2.0", Typed::Uint32(4096), "pipelining"),
+                Ok((Version::V2_0, 4096, vec![Pipelining], false)),
+            ),
+            (
+                "smaller agent frames",
+                offer(&[Version::V2_0], 1024, AGENT),
+                items("2.0", Typed::Uint32(16380), "pipelining"),
+                Ok((Version::V2_0, 1024, vec![Pipelining], false)),
+            ),
+            (
+                "frame size of another integer type",
+                v2.clone(),
+                items("2.0", Typed::Uint64(8192), "pipelining"),
+                Ok((Version::V2_0, 8192, vec![Pipelining], false)),
+            ),
+            (
+                "frame size out of range",
+                v2.clone(),
+                items("2.0", Typed::Uint64(u64::MAX), "pipelining"),
+                Err(Error::BadFrameSize),
+            ),
+            (
+                "capabilities in the agent order",
+                v2.clone(),
+                items("2.0", Typed::Uint32(16380), "async, fragmentation"),
+                Ok((Version::V2_0, 16380, vec![Fragmentation, Async], false)),
+            ),
+            (
+                "capabilities not supported by the agent",
+                offer(&[Version::V2_0], 16380, &[Pipelining]),
+                base.clone(),
+                Ok((Version::V2_0, 16380, vec![Pipelining], false)),
+            ),
+            (
+                "no capabilities",
+                v2.clone(),
+                items("2.0", Typed::Uint32(16380), ""),
+                Ok((Version::V2_0, 16380, vec![], false)),
+            ),
+            (
+                "unknown capability",
+                v2.clone(),
+                items("2.0", Typed::Uint32(16380), "pipelining,telepathy"),
+                Err(Error::Invalid),
+            ),
+            (
+                "healthcheck",
+                v2.clone(),
+                with(base.clone(), kv::HEALTHCHECK_KEY, true.into()),
+                Ok((Version::V2_0, 16380, vec![Pipelining, Async], true)),
+            ),
+            (
+                "healthcheck disabled",
+                v2.clone(),
+                with(base.clone(), kv::HEALTHCHECK_KEY, false.into()),
+                Ok((Version::V2_0, 16380, vec![Pipelining, Async], false)),
+            ),
+            (
+                "unknown items are ignored",
+                v2.clone(),
+                with(base.clone(), "x-vendor", "ignored".into()),
+                Ok((Version::V2_0, 16380, vec![Pipelining, Async], false)),
+            ),
+            (
+                "missing versions",
+                v2.clone(),
+                without(base.clone(), kv::SUPPORTED_VERSIONS_KEY),
+                Err(Error::NoVersion),
+            ),
+            (
+                "missing frame size",
+                v2.clone(),
+                without(base.clone(), kv::MAX_FRAME_SIZE_KEY),
+                Err(Error::NoFrameSize),
+            ),
+            (
+                "null frame size",
+                v2.clone(),
+                with(base.clone(), kv::MAX_FRAME_SIZE_KEY, Typed::Null),
+                Err(Error::NoFrameSize),
+            ),
+            (
+                "missing capabilities",
+                v2.clone(),
+                without(base.clone(), kv::CAPABILITIES_KEY),
+                Err(Error::NoCapabilities),
+            ),
+        ];
+
+        for (name, offer, items, outcome) in cases {
+            assert_eq!(handshake(&offer, &items), outcome, "{name}");
+        }
+    }
+}
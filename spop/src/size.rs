@@ -0,0 +1,245 @@
+use std::mem;
+
+use crate::{
+    data::{varint, KeyValue},
+    frame::{agent, haproxy, kv, Disconnect, Frame, Message, Metadata},
+    Action, Typed,
+};
+
+/// The size of a value once encoded on the wire.
+pub trait WireSize {
+    /// Returns the number of bytes written by the encoder.
+    fn size(&self) -> usize;
+}
+
+impl WireSize for str {
+    fn size(&self) -> usize {
+        varint::size_of(self.len() as u64) + self.len()
+    }
+}
+
+impl WireSize for String {
+    fn size(&self) -> usize {
+        self.as_str().size()
+    }
+}
+
+impl WireSize for Typed {
+    fn size(&self) -> usize {
+        Typed::TYPE_SIZE
+            + match self {
+                Typed::Null | Typed::Boolean(_) => 0,
+                Typed::Int32(n) => varint::size_of(*n as u64),
+                Typed::Uint32(n) => varint::size_of(*n as u64),
+                Typed::Int64(n) => varint::size_of(*n as u64),
+                Typed::Uint64(n) => varint::size_of(*n),
+                Typed::Ipv4(_) => Typed::IPV4_ADDR_LEN,
+                Typed::Ipv6(_) => Typed::IPV6_ADDR_LEN,
+                Typed::String(s) => s.size(),
+                Typed::Binary(b) => varint::size_of(b.len() as u64) + b.len(),
+            }
+    }
+}
+
+impl<T> WireSize for KeyValue<'_, T>
+where
+    T: Clone + Into<Typed>,
+{
+    fn size(&self) -> usize {
+        let KeyValue(key, value) = self;
+
+        key.size() + value.clone().into().size()
+    }
+}
+
+impl WireSize for Metadata {
+    fn size(&self) -> usize {
+        mem::size_of::<u32>() + varint::size_of(self.stream_id) + varint::size_of(self.frame_id)
+    }
+}
+
+impl WireSize for Message {
+    fn size(&self) -> usize {
+        self.name.size()
+            + mem::size_of::<u8>()
+            + self
+                .args
+                .iter()
+                .map(|(name, value)| name.size() + value.size())
+                .sum::<usize>()
+    }
+}
+
+impl WireSize for Action {
+    fn size(&self) -> usize {
+        const HEADER_SIZE: usize = 3;
+
+        HEADER_SIZE
+            + match self {
+                Action::SetVar { name, value, .. } => name.size() + value.size(),
+                Action::UnsetVar { name, .. } => name.size(),
+            }
+    }
+}
+
+impl WireSize for haproxy::Hello {
+    fn size(&self) -> usize {
+        kv::supported_versions(&self.supported_versions).size()
+            + kv::max_frame_size(self.max_frame_size).size()
+            + kv::capabilities(&self.capabilities).size()
+            + self.healthcheck.map_or(0, |b| kv::healthcheck(b).size())
+            + self
+                .engine_id
+                .as_deref()
+                .map_or(0, |id| kv::engine_id(id).size())
+    }
+}
+
+impl WireSize for agent::Hello {
+    fn size(&self) -> usize {
+        kv::version(self.version).size()
+            + kv::max_frame_size(self.max_frame_size).size()
+            + kv::capabilities(&self.capabilities).size()
+    }
+}
+
+impl WireSize for Disconnect {
+    fn size(&self) -> usize {
+        kv::status_code(self.status_code).size() + kv::message(&self.message).size()
+    }
+}
+
+impl WireSize for haproxy::Notify {
+    fn size(&self) -> usize {
+        self.metadata().size() + self.messages.iter().map(WireSize::size).sum::<usize>()
+    }
+}
+
+impl WireSize for agent::Ack {
+    fn size(&self) -> usize {
+        self.metadata().size() + self.actions.iter().map(WireSize::size).sum::<usize>()
+    }
+}
+
+impl WireSize for Frame {
+    fn size(&self) -> usize {
+        const TYPE_SIZE: usize = 1;
+
+        let default = Metadata::default().size();
+
+        TYPE_SIZE
+            + match self {
+                Frame::Unset => default,
+                Frame::HaproxyHello(hello) => default + hello.size(),
+                Frame::AgentHello(hello) => default + hello.size(),
+                Frame::HaproxyDisconnect(d) | Frame::AgentDisconnect(d) => default + d.size(),
+                Frame::HaproxyNotify(notify) => notify.size(),
+                Frame::AgentAck(ack) => ack.size(),
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use bytes::Bytes;
+
+    use crate::{
+        data::BufMutExt as _,
+        frame::{BufMutExt as _, Frame, Message},
+        Action, Capability, Error, Scope, Typed, Version,
+    };
+
+    use super::*;
+
+    const IDS: &[u64] = &[1, 0xef, 0xf0, 0x8ef, 0x8f0, 0x408f0, 0x20408f0, u64::MAX];
+
+    fn values() -> Vec<Typed> {
+        let mut values = vec![
+            Typed::Null,
+            Typed::Boolean(true),
+            Typed::Boolean(false),
+            Typed::Int32(-1),
+            Typed::Int32(i32::MAX),
+            Typed::Uint32(u32::MAX),
+            Typed::Int64(i64::MIN),
+            Typed::Ipv4(Ipv4Addr::LOCALHOST),
+            Typed::Ipv6(Ipv6Addr::LOCALHOST),
+            Typed::String(String::new()),
+            Typed::Binary(Bytes::new()),
+        ];
+
+        values.extend(IDS.iter().map(|&n| Typed::Uint64(n)));
+        values.extend(
+            [0, 1, 0xef, 0xf0, 0x8f0, 0x10000]
+                .into_iter()
+                .flat_map(|n| [Typed::String("x".repeat(n)), Typed::from(vec![0u8; n])]),
+        );
+        values
+    }
+
+    fn frames() -> Vec<Frame> {
+        let args = values()
+            .into_iter()
+            .enumerate()
+            .map(|(i, v)| (format!("arg{i}"), v))
+            .collect::<Vec<_>>();
+        let actions = values()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(i, v)| {
+                [
+                    Action::set_var(Scope::Transaction, format!("var{i}"), v),
+                    Action::unset_var(Scope::Request, format!("var{i}")),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut frames = vec![
+            Frame::Unset,
+            Frame::HaproxyHello(haproxy::Hello {
+                supported_versions: vec![Version::V2_0, Version::V2_1],
+                max_frame_size: 16380,
+                capabilities: vec![Capability::Pipelining, Capability::Async],
+                healthcheck: Some(true),
+                engine_id: Some("engine".into()),
+            }),
+            Frame::AgentHello(agent::Hello {
+                version: Version::V2_0,
+                max_frame_size: 16380,
+                capabilities: vec![],
+            }),
+            Frame::haproxy_disconnect(Error::TooBig, "frame is too big"),
+            Frame::agent_disconnect(Error::Normal, ""),
+        ];
+
+        for &id in IDS {
+            frames.push(Frame::notify(id, id, [Message::new("msg", args.clone())]));
+            frames.push(Frame::notify(id, 1, Vec::<Message>::new()));
+            frames.push(Frame::ack(id, id, actions.clone()));
+        }
+
+        frames
+    }
+
+    #[test]
+    fn test_typed_size() {
+        for v in values() {
+            let mut buf = Vec::new();
+            buf.put_typed(v.clone());
+
+            assert_eq!(v.size(), buf.len(), "size of {v:?}");
+        }
+    }
+
+    #[test]
+    fn test_frame_size() {
+        for f in frames() {
+            let mut buf = Vec::new();
+            buf.put_frame(f.clone());
+
+            assert_eq!(f.size(), buf.len(), "size of {:?}", f.frame_type());
+        }
+    }
+}
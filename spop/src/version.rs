@@ -1,7 +1,10 @@
-use parse_display::{Display, FromStr};
+use std::str::FromStr;
+
+use parse_display::Display;
+use thiserror::Error;
 
 /// The SPOP version.
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Display, FromStr)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Display)]
 #[display("{major}.{minor}")]
 pub struct Version {
     pub major: u8,
@@ -19,9 +22,65 @@ impl Version {
     pub const SUPPORTED: &[Version] = &[Self::V2_0];
     /// The SPOP 2.0 version.
     pub const V2_0: Version = Version { major: 2, minor: 0 };
+    /// The SPOP 2.1 version.
+    pub const V2_1: Version = Version { major: 2, minor: 1 };
 
     /// Create a new SPOP version.
     pub const fn new(major: u8, minor: u8) -> Self {
         Version { major, minor }
     }
 }
+
+/// The error of parsing a malformed version.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("invalid SPOP version `{0}`")]
+pub struct ParseVersionError(String);
+
+impl FromStr for Version {
+    type Err = ParseVersionError;
+
+    /// Parse a version in the strict `<major>.<minor>` form.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn number(s: &str) -> Option<u8> {
+            if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+                None
+            } else {
+                s.parse().ok()
+            }
+        }
+
+        s.split_once('.')
+            .and_then(|(major, minor)| Some(Version::new(number(major)?, number(minor)?)))
+            .ok_or_else(|| ParseVersionError(s.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("2.0".parse(), Ok(Version::V2_0));
+        assert_eq!("2.1".parse(), Ok(Version::V2_1));
+        assert_eq!("10.255".parse(), Ok(Version::new(10, 255)));
+
+        for s in [
+            "", "2", "2.", ".0", "2.0.1", "+2.0", "2.-1", " 2.0", "2.256", "a.b",
+        ] {
+            assert!(s.parse::<Version>().is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn test_ord() {
+        assert!(Version::V2_0 < Version::V2_1);
+        assert!(Version::V2_1 < Version::new(3, 0));
+        assert_eq!(
+            [Version::V2_1, Version::new(1, 9), Version::V2_0]
+                .into_iter()
+                .max(),
+            Some(Version::V2_1)
+        );
+    }
+}
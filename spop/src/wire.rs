@@ -0,0 +1,143 @@
+//! The wire format of the SPOP frames, as specified by the HAProxy `SPOE.txt` document.
+//!
+//! This module gathers the primitives shared by all the implementations of the protocol,
+//! e.g. the agents written in other languages or the packet dissectors, which may rely on
+//! them as a reference:
+//!
+//! - the layout of the frames and the codes of their types and flags;
+//! - the [`varint`] encoding of the integers and the lengths;
+//! - the typed data, with their [`DataType`] and [`DataFlags`];
+//! - the keys of the KV-lists of the HELLO and DISCONNECT frames;
+//! - the codes of the actions, the variable scopes and the status codes.
+//!
+//! Unlike the rest of the crate, the items of this module follow the specification:
+//! they only change in a breaking way with a new SPOP version, never with the runtime.
+//!
+//! ```text
+//! Frame          : <FRAME-LENGTH:4 bytes> <FRAME>
+//! FRAME          : <FRAME-TYPE:1 byte> <METADATA> <FRAME-PAYLOAD>
+//! METADATA       : <FLAGS:4 bytes> <STREAM-ID:varint> <FRAME-ID:varint>
+//! TYPED-DATA     : <TYPE:4 bits> <FLAGS:4 bits> <DATA>
+//! KV-LIST        : [ <KV-NAME:string> <KV-VALUE:typed-data> ... ]
+//! ```
+//!
+//! ```
+//! # use haproxy_spop::wire::{self, varint, BufExt as _, BufMutExt as _, Typed};
+//! let mut buf = vec![0, 0, 0, 0];
+//! buf.put_typed("ab");
+//! assert_eq!(&buf[wire::LENGTH_SIZE..], &[0x08, 0x02, b'a', b'b']);
+//! assert_eq!(varint::size_of(2288), 3);
+//!
+//! let mut payload = &buf[wire::LENGTH_SIZE..];
+//! assert_eq!(payload.typed(), Some(Typed::from("ab")));
+//! ```
+
+pub use crate::action::{Scope, Type as ActionType};
+pub use crate::data::{
+    varint, BufExt, BufMutExt, Flags as DataFlags, KeyValue, Type as DataType, Typed,
+};
+pub use crate::error::Error as Status;
+pub use crate::frame::kv::{
+    CAPABILITIES_KEY, ENGINE_ID_KEY, HEALTHCHECK_KEY, MAX_FRAME_SIZE_KEY, MSG_KEY, STATUS_CODE_KEY,
+    SUPPORTED_VERSIONS_KEY, VERSION_KEY,
+};
+pub use crate::frame::{Flags as FrameFlags, FrameId, StreamId, Type as FrameType};
+pub use crate::frame::{MAX_FRAME_SIZE, MIN_USER_STATUS};
+pub use crate::version::Version;
+
+/// The size of the length prefix of the frames, in big-endian.
+pub const LENGTH_SIZE: usize = 4;
+
+/// The size of the type of the frames.
+pub const TYPE_SIZE: usize = 1;
+
+/// The size of the flags of the frames, in big-endian.
+pub const FLAGS_SIZE: usize = 4;
+
+/// The mask of the type in the first byte of a typed data.
+pub const DATA_TYPE_MASK: u8 = DataType::MASK;
+
+/// The mask of the flags in the first byte of a typed data.
+pub const DATA_FLAGS_MASK: u8 = DataFlags::MASK;
+
+/// The separator of the lists of versions and capabilities in the HELLO frames.
+pub const LIST_SEPARATOR: char = ',';
+
+/// Returns the length of the frame starting the buffer, without its length prefix,
+/// or `None` when the length prefix is incomplete.
+pub fn frame_len(buf: &[u8]) -> Option<usize> {
+    let prefix = buf.get(..LENGTH_SIZE)?;
+
+    Some(u32::from_be_bytes(prefix.try_into().ok()?) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use bytes::BytesMut;
+
+    use crate::{Action, BufMutExt as _, Frame};
+
+    use super::*;
+
+    #[test]
+    fn test_varint() {
+        for (n, encoded) in [
+            (0, &[0x00][..]),
+            (239, &[0xEF]),
+            (240, &[0xF0, 0x00]),
+            (2287, &[0xFF, 0x7F]),
+            (2288, &[0xF0, 0x80, 0x00]),
+            (264432, &[0xF0, 0x80, 0x80, 0x00]),
+        ] {
+            let mut buf = BytesMut::new();
+
+            assert_eq!(varint::put(&mut buf, n), encoded.len(), "{n}");
+            assert_eq!(&buf[..], encoded, "{n}");
+            assert_eq!(varint::size_of(n), encoded.len(), "{n}");
+            assert_eq!(varint::get(encoded), Some(n), "{n}");
+        }
+    }
+
+    #[test]
+    fn test_typed_data() {
+        for (value, encoded) in [
+            (Typed::Null, &[0x00][..]),
+            (Typed::Boolean(false), &[0x01]),
+            (Typed::Boolean(true), &[0x11]),
+            (Typed::Int32(1), &[0x02, 0x01]),
+            (Typed::Uint32(240), &[0x03, 0xF0, 0x00]),
+            (
+                Typed::Ipv4(Ipv4Addr::new(10, 0, 0, 1)),
+                &[0x06, 10, 0, 0, 1],
+            ),
+            (Typed::from("ab"), &[0x08, 0x02, b'a', b'b']),
+        ] {
+            let mut buf = BytesMut::new();
+            buf.put_typed(value.clone());
+
+            assert_eq!(&buf[..], encoded, "{value:?}");
+            assert_eq!((&buf[..]).typed(), Some(value));
+        }
+
+        assert_eq!(0x11 & DATA_TYPE_MASK, DataType::Boolean as u8);
+        assert_eq!(0x11 & DATA_FLAGS_MASK, DataFlags::TRUE.bits());
+    }
+
+    #[test]
+    fn test_frame_layout() {
+        let mut buf = BytesMut::new();
+        buf.put_frame(Frame::ack(1, 2, Vec::<Action>::new()));
+
+        assert_eq!(buf[0], FrameType::AGENT_ACK);
+        assert_eq!(
+            &buf[TYPE_SIZE..TYPE_SIZE + FLAGS_SIZE],
+            FrameFlags::FIN.bits().to_be_bytes()
+        );
+        assert_eq!(&buf[TYPE_SIZE + FLAGS_SIZE..], &[1, 2]);
+
+        assert_eq!(frame_len(&[0, 0, 0x01, 0x02, 0xFF]), Some(0x102));
+        assert_eq!(frame_len(&[0, 0, 1]), None);
+    }
+}